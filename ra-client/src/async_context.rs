@@ -0,0 +1,198 @@
+use std::io::ErrorKind;
+use std::convert::TryInto;
+use std::mem::size_of;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt};
+use tokio::task::spawn_blocking;
+use sgx_isa::Report;
+use sgx_crypto::cmac::MacTag;
+use sgx_crypto::key_exchange::DHKEPublicKey;
+use ra_common::msg::{Gid, Quote, RaMsg1, RaMsg2, RaMsg3, RaMsg4, RaAbort, AbortReason,
+                     WireMessage, WireError};
+use crate::context::ClientRaContext;
+use crate::error::ClientRaError;
+use crate::provisioning::{init_quote_with_provisioning, is_epid_unprovisioned};
+use crate::ClientRaResult;
+
+const READ_CHUNK_LEN: usize = 0x1000;
+
+/// `ClientRaContext` for async applications. Talks to the SP and the enclave
+/// through tokio streams and runs the blocking AESM calls on tokio's blocking
+/// thread pool, so the attestation never blocks a runtime thread. Must be used
+/// from within a tokio runtime.
+pub struct AsyncClientRaContext {
+    inner: ClientRaContext,
+    // Bytes received from the SP but not yet decoded
+    sp_buf: Vec<u8>,
+}
+
+impl AsyncClientRaContext {
+    pub async fn init() -> ClientRaResult<Self> {
+        let inner = spawn_blocking(ClientRaContext::init).await??;
+        Ok(Self { inner, sp_buf: Vec::new() })
+    }
+
+    pub async fn init_with_tenant(tenant: &str) -> ClientRaResult<Self> {
+        let mut context = Self::init().await?;
+        context.inner.tenant = Some(tenant.to_owned());
+        Ok(context)
+    }
+
+    pub async fn do_attestation<E, S>(mut self, enclave_stream: &mut E, sp_stream: &mut S)
+        -> ClientRaResult<()>
+        where E: AsyncRead + AsyncWrite + Unpin, S: AsyncRead + AsyncWrite + Unpin {
+            let msg0 = self.inner.get_extended_epid_group_id();
+            write_msg(&msg0, sp_stream).await?;
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG0 sent");
+            }
+
+            let mut g_a: DHKEPublicKey = [0u8; size_of::<DHKEPublicKey>()];
+            enclave_stream.read_exact(&mut g_a[..]).await?;
+            let gid: Gid = self.inner.quote_info.gid().try_into().unwrap();
+            write_msg(&RaMsg1 { gid, g_a }, sp_stream).await?;
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG1 sent");
+            }
+
+            let msg2: RaMsg2 = self.read_from_sp(sp_stream, enclave_stream).await?;
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG2 received");
+            }
+
+            let msg3 = match self.process_msg_2(msg2, g_a, enclave_stream).await {
+                Ok(msg3) => msg3,
+                Err(e) => {
+                    let abort = RaAbort::new(AbortReason::Internal, None);
+                    let _r = write_msg(&abort, sp_stream).await;
+                    return Err(e);
+                },
+            };
+            write_msg(&msg3, sp_stream).await?;
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG3 sent");
+            }
+
+            let msg4: RaMsg4 = self.read_from_sp(sp_stream, enclave_stream).await?;
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG4 received");
+            }
+            write_msg(&msg4, enclave_stream).await?;
+
+            if !msg4.is_enclave_trusted {
+                return Err(ClientRaError::EnclaveNotTrusted);
+            }
+            match msg4.is_pse_manifest_trusted {
+                Some(t) => if !t { return Err(ClientRaError::PseNotTrusted); },
+                None => {},
+            }
+            Ok(())
+        }
+
+    async fn process_msg_2<E>(&self, msg2: RaMsg2, g_a: DHKEPublicKey, enclave_stream: &mut E)
+        -> ClientRaResult<RaMsg3>
+        where E: AsyncRead + AsyncWrite + Unpin {
+            write_msg(&msg2, enclave_stream).await?;
+            let sig_rl = msg2.sig_rl.unwrap_or_default();
+            let spid = (&msg2.spid[..]).to_owned();
+
+            // Get report for local attestation with QE from enclave
+            let aesm_client = self.inner.aesm_client.clone();
+            let policy = self.inner.provisioning_retry.clone();
+            let quote_info = spawn_blocking(move || {
+                init_quote_with_provisioning(&aesm_client, &policy)
+            }).await??;
+            enclave_stream.write_all(quote_info.target_info()).await?;
+            let mut report = vec![0u8; Report::UNPADDED_SIZE];
+            enclave_stream.read_exact(&mut report[..]).await?;
+
+            // Get a quote and QE report from QE and send them to enclave
+            let aesm_client = self.inner.aesm_client.clone();
+            let policy = self.inner.provisioning_retry.clone();
+            let _quote = spawn_blocking(move || -> ClientRaResult<_> {
+                match aesm_client.get_quote(&quote_info, report.clone(),
+                                            spid.clone(), sig_rl.clone()) {
+                    Err(ref e) if is_epid_unprovisioned(e) => {
+                        let quote_info = init_quote_with_provisioning(&aesm_client, &policy)?;
+                        Ok(aesm_client.get_quote(&quote_info, report, spid, sig_rl)?)
+                    },
+                    r => Ok(r?),
+                }
+            }).await??;
+            enclave_stream.write_all(_quote.quote()).await?;
+            enclave_stream.write_all(_quote.qe_report()).await?;
+            let mut quote = [0u8; size_of::<Quote>()];
+            quote.copy_from_slice(_quote.quote());
+
+            // Read MAC for msg3 from enclave
+            let mut mac = [0u8; size_of::<MacTag>()];
+            enclave_stream.read_exact(&mut mac).await?;
+
+            Ok(RaMsg3 {
+                g_a,
+                mac,
+                ps_sec_prop: None,
+                quote,
+            })
+        }
+
+    /// Same as `ClientRaContext::read_from_sp`: an abort from the SP is
+    /// forwarded to the enclave.
+    async fn read_from_sp<M, S, E>(&mut self, sp_stream: &mut S, enclave_stream: &mut E)
+        -> ClientRaResult<M>
+        where M: WireMessage, S: AsyncRead + Unpin, E: AsyncWrite + Unpin {
+            match read_msg(sp_stream, &mut self.sp_buf).await {
+                Ok(msg) => Ok(msg),
+                Err(WireError::Aborted(abort)) => {
+                    if cfg!(feature = "verbose") {
+                        eprintln!("Attestation aborted by SP: {:?}", abort.reason);
+                    }
+                    let _r = write_msg(&abort, enclave_stream).await;
+                    Err(ClientRaError::Aborted(abort.reason))
+                },
+                Err(e) => Err(e.into()),
+            }
+        }
+}
+
+async fn write_msg<M: WireMessage>(msg: &M, w: &mut (impl AsyncWrite + Unpin))
+    -> ClientRaResult<()> {
+        let mut buf = Vec::new();
+        msg.write_to(&mut buf)?;
+        w.write_all(&buf[..]).await?;
+        w.flush().await?;
+        Ok(())
+    }
+
+/// Decode the next message, reading from `r` until `buf` holds all of it.
+/// Bytes past the end of the message stay in `buf` for the next call.
+async fn read_msg<M: WireMessage>(r: &mut (impl AsyncRead + Unpin), buf: &mut Vec<u8>)
+    -> Result<M, WireError> {
+        loop {
+            let mut rest = &buf[..];
+            let result = M::read_from(&mut rest);
+            match result {
+                Err(WireError::Serialization(ref e)) if is_eof(e) => {},
+                _ => {
+                    let consumed = buf.len() - rest.len();
+                    buf.drain(..consumed);
+                    return result;
+                },
+            }
+
+            let mut chunk = [0u8; READ_CHUNK_LEN];
+            let n = r.read(&mut chunk[..]).await
+                .map_err(|e| WireError::Serialization(Box::new(bincode::ErrorKind::Io(e))))?;
+            if n == 0 {
+                return Err(WireError::Serialization(Box::new(
+                            bincode::ErrorKind::Io(ErrorKind::UnexpectedEof.into()))));
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+fn is_eof(e: &bincode::Error) -> bool {
+    match e.as_ref() {
+        bincode::ErrorKind::Io(e) => e.kind() == ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
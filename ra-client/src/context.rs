@@ -1,31 +1,59 @@
 use std::io::{Read, Write};
 use std::convert::TryInto;
 use std::mem::size_of;
+use std::thread::sleep;
 use aesm_client::{AesmClient, QuoteInfo};
 use sgx_isa::Report;
 use sgx_crypto::cmac::MacTag;
 use sgx_crypto::key_exchange::DHKEPublicKey;
-use ra_common::msg::{Gid, Quote, RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4};
+use ra_common::msg::{Gid, Quote, RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4, RaAbort, AbortReason, WireMessage, WireError};
+#[cfg(feature = "intel-compat")]
+use ra_common::msg::PsSecPropDesc;
+#[cfg(feature = "intel-compat")]
+use ra_common::compat::{self, IntelMsg01, IntelMsg2, IntelMsg3, IntelMsg4};
 use crate::error::ClientRaError;
+use crate::retry::RetryPolicy;
+use crate::provisioning::{init_quote_with_provisioning, is_epid_unprovisioned};
 use crate::ClientRaResult;
 
 pub struct ClientRaContext {
     pub aesm_client: AesmClient,
     pub quote_info: QuoteInfo,
     pub g_a: Option<DHKEPublicKey>,
+    pub tenant: Option<String>,
+    /// How often AESM may try to provision the platform's EPID key when it
+    /// has none, see `init_quote_with_provisioning`.
+    pub provisioning_retry: RetryPolicy,
 }
 
 impl ClientRaContext {
     pub fn init() -> ClientRaResult<Self>  {
+        Self::init_with_provisioning_retry(RetryPolicy::default())
+    }
+
+    /// Same as `init`, but retries EPID provisioning according to `policy`,
+    /// e.g. with more attempts on a machine that is attested right after it
+    /// was set up.
+    pub fn init_with_provisioning_retry(policy: RetryPolicy) -> ClientRaResult<Self> {
         let aesm_client = AesmClient::new();
-        let quote_info = aesm_client.init_quote()?;
+        let quote_info = init_quote_with_provisioning(&aesm_client, &policy)?;
         Ok(Self {
             aesm_client, 
             quote_info,
             g_a: None,
+            tenant: None,
+            provisioning_retry: policy,
         })
     }
 
+    /// Same as `init`, but asks a multi-tenant SP to attest with the
+    /// credentials registered under `tenant`.
+    pub fn init_with_tenant(tenant: &str) -> ClientRaResult<Self>  {
+        let mut context = Self::init()?;
+        context.tenant = Some(tenant.to_owned());
+        Ok(context)
+    }
+
     pub fn do_attestation(mut self, mut enclave_stream: &mut (impl Read+Write), 
                           mut sp_stream: &mut (impl Read+Write)) -> ClientRaResult<()> {
         let msg0 = self.get_extended_epid_group_id(); 
@@ -33,7 +61,7 @@ impl ClientRaContext {
             eprintln!("MSG0 generated");
         }
 
-        bincode::serialize_into(&mut sp_stream, &msg0)?;
+        msg0.write_to(&mut sp_stream)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG0 sent");
         }
@@ -43,32 +71,180 @@ impl ClientRaContext {
             eprintln!("MSG1 generated");
         }
 
-        bincode::serialize_into(&mut sp_stream, &msg1)?;
+        msg1.write_to(&mut sp_stream)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG1 sent");
         }
 
-        let msg2: RaMsg2 = bincode::deserialize_from(&mut sp_stream)?;
+        let msg2 = Self::read_from_sp(&mut sp_stream, enclave_stream)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG2 received");
         }
 
-        let msg3 = self.process_msg_2(msg2, enclave_stream)?;
+        self.finish(msg2, enclave_stream, sp_stream)
+    }
+
+    /// Like `do_attestation`, but connects to the SP with `connect_sp` and
+    /// starts over from MSG0 on a new connection if the connection fails or
+    /// IAS is unavailable before MSG2 arrives. The enclave's g_a is reused,
+    /// so the enclave does not notice the retries. Failures after MSG2 are
+    /// not retried, since the enclave has already consumed its key pair.
+    pub fn do_attestation_with_retry<S, F>(mut self,
+                                           enclave_stream: &mut (impl Read+Write),
+                                           mut connect_sp: F,
+                                           policy: &RetryPolicy) -> ClientRaResult<()>
+        where S: Read + Write, F: FnMut() -> std::io::Result<S> {
+            let msg1 = self.get_msg_1(enclave_stream);
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG1 generated");
+            }
+
+            let mut attempt = 1;
+            let mut backoff = policy.initial_backoff;
+            loop {
+                match self.start_sp_session(&mut connect_sp, &msg1) {
+                    Ok((mut sp_stream, msg2)) => {
+                        return self.finish(msg2, enclave_stream, &mut sp_stream);
+                    },
+                    Err(ref e) if e.is_transient() && attempt < policy.max_attempts => {
+                        if cfg!(feature = "verbose") {
+                            eprintln!("Attempt {} failed ({:?}), retrying in {:?}",
+                                      attempt, e, backoff);
+                        }
+                        sleep(backoff);
+                        backoff = policy.next_backoff(backoff);
+                        attempt += 1;
+                    },
+                    Err(e) => {
+                        let reason = match e {
+                            ClientRaError::Aborted(reason) => reason,
+                            _ => AbortReason::Internal,
+                        };
+                        let _r = RaAbort::new(reason, None).write_to(enclave_stream);
+                        return Err(e);
+                    },
+                }
+            }
+        }
+
+    /// Like `do_attestation`, but with the SP of Intel's sgx-ra-sample, or
+    /// any SP speaking its protocol (see `ra_common::compat`). The enclave
+    /// must run `ra_enclave::compat::IntelEnclaveRaContext`, which checks MSG2
+    /// against the SP's ECDSA key; MSG2 is relayed to it as the SP sent it.
+    #[cfg(feature = "intel-compat")]
+    pub fn do_intel_attestation(mut self, mut enclave_stream: &mut (impl Read+Write),
+                                sp_stream: &mut (impl Read+Write)) -> ClientRaResult<()> {
+        let msg1 = self.get_msg_1(enclave_stream);
+        let msg01 = IntelMsg01::from_msg1(self.get_extended_epid_group_id().exgid, &msg1);
+        compat::write_msg(sp_stream, &msg01.encode()[..])?;
+        if cfg!(feature = "verbose") {
+            eprintln!("MSG0 and MSG1 sent");
+        }
+
+        let msg2 = compat::read_msg(sp_stream)?;
+        if cfg!(feature = "verbose") {
+            eprintln!("MSG2 received");
+        }
+        compat::write_msg(enclave_stream, &msg2[..])?;
+        let msg2 = IntelMsg2::decode(&msg2[..])?;
+
+        // Get a Quote and send it to enclave to sign
+        let quote = Self::get_quote_with_provisioning(&self.aesm_client,
+                                                      msg2.spid.to_vec(),
+                                                      msg2.sig_rl,
+                                                      enclave_stream,
+                                                      &self.provisioning_retry)?;
+
+        // Read MAC for msg3 from enclave
+        let mut mac = [0u8; size_of::<MacTag>()];
+        enclave_stream.read_exact(&mut mac)?;
+        let msg3 = IntelMsg3 {
+            mac,
+            g_a: compat::key_to_intel(&self.g_a.take().unwrap()),
+            ps_sec_prop: [0u8; size_of::<PsSecPropDesc>()],
+            quote: quote.to_vec(),
+        };
+        compat::write_msg(sp_stream, &msg3.encode()[..])?;
+        if cfg!(feature = "verbose") {
+            eprintln!("MSG3 sent");
+        }
+
+        let msg4 = IntelMsg4::decode(&compat::read_msg(sp_stream)?[..])?.to_msg4();
+        if cfg!(feature = "verbose") {
+            eprintln!("MSG4 received");
+        }
+        msg4.write_to(&mut enclave_stream)?;
+
+        if !msg4.is_enclave_trusted {
+            return Err(ClientRaError::EnclaveNotTrusted);
+        }
+        Ok(())
+    }
+
+    /// Client side of a re-attestation over the live SecureChannel between
+    /// the enclave and the SP, see `ra_enclave::reattest::respond`. The
+    /// enclave forwards the messages to the SP itself, so the client hands it
+    /// MSG0 and MSG1 and gets it the quote for MSG2's SPID and SigRL.
+    pub fn do_reattestation(mut self, mut enclave_stream: &mut (impl Read+Write))
+        -> ClientRaResult<()> {
+            let msg1 = self.get_msg_1(enclave_stream);
+            self.get_extended_epid_group_id().write_to(&mut enclave_stream)?;
+            msg1.write_to(&mut enclave_stream)?;
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG0 and MSG1 handed to enclave");
+            }
+
+            let msg2 = RaMsg2::read_from(&mut enclave_stream)?;
+            let sig_rl = msg2.sig_rl.unwrap_or_default();
+            Self::get_quote_with_provisioning(&self.aesm_client,
+                                              msg2.spid.to_vec(),
+                                              sig_rl,
+                                              enclave_stream,
+                                              &self.provisioning_retry)?;
+            if cfg!(feature = "verbose") {
+                eprintln!("Quote handed to enclave");
+            }
+            Ok(())
+        }
+
+    /// Connect to the SP and run the protocol up to MSG2.
+    fn start_sp_session<S, F>(&self, connect_sp: &mut F, msg1: &RaMsg1)
+        -> ClientRaResult<(S, RaMsg2)>
+        where S: Read + Write, F: FnMut() -> std::io::Result<S> {
+            let mut sp_stream = connect_sp()?;
+            self.get_extended_epid_group_id().write_to(&mut sp_stream)?;
+            msg1.write_to(&mut sp_stream)?;
+            let msg2 = RaMsg2::read_from(&mut sp_stream)?;
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG2 received");
+            }
+            Ok((sp_stream, msg2))
+        }
+
+    fn finish(mut self, msg2: RaMsg2, mut enclave_stream: &mut (impl Read+Write),
+              mut sp_stream: &mut (impl Read+Write)) -> ClientRaResult<()> {
+        let msg3 = match self.process_msg_2(msg2, enclave_stream) {
+            Ok(msg3) => msg3,
+            Err(e) => {
+                let _r = RaAbort::new(AbortReason::Internal, None).write_to(&mut sp_stream);
+                return Err(e);
+            },
+        };
         if cfg!(feature = "verbose") {
             eprintln!("MSG3 generated");
         }
 
-        bincode::serialize_into(&mut sp_stream, &msg3)?;
+        msg3.write_to(&mut sp_stream)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG3 sent");
         }
 
-        let msg4: RaMsg4 = bincode::deserialize_from(&mut sp_stream)?;
+        let msg4: RaMsg4 = Self::read_from_sp(&mut sp_stream, enclave_stream)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG4 received");
         }
 
-        bincode::serialize_into(&mut enclave_stream, &msg4).unwrap();
+        msg4.write_to(&mut enclave_stream)?;
 
         if !msg4.is_enclave_trusted {
             return Err(ClientRaError::EnclaveNotTrusted);
@@ -80,10 +256,27 @@ impl ClientRaContext {
         Ok(())
     }
 
+    /// Read the next message from the SP. If the SP aborted instead, the
+    /// abort is forwarded to the enclave so that it stops waiting as well.
+    fn read_from_sp<M: WireMessage>(sp_stream: &mut impl Read,
+                                    enclave_stream: &mut impl Write) -> ClientRaResult<M> {
+        match M::read_from(sp_stream) {
+            Ok(msg) => Ok(msg),
+            Err(WireError::Aborted(abort)) => {
+                if cfg!(feature = "verbose") {
+                    eprintln!("Attestation aborted by SP: {:?}", abort.reason);
+                }
+                let _r = abort.write_to(enclave_stream);
+                Err(ClientRaError::Aborted(abort.reason))
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
     /// ExGID = 0 means IAS will be used for remote attestation. This function only 
     /// returns 0 for now.
     pub fn get_extended_epid_group_id(&self) -> RaMsg0 {
-        RaMsg0 { exgid: 0 }
+        RaMsg0 { exgid: 0, tenant: self.tenant.clone() }
     }
 
     pub fn get_msg_1(&mut self, 
@@ -98,7 +291,7 @@ impl ClientRaContext {
     pub fn process_msg_2(&mut self, msg2: RaMsg2, 
                          mut enclave_stream: &mut (impl Read+Write)) 
         -> ClientRaResult<RaMsg3> {
-            bincode::serialize_into(&mut enclave_stream, &msg2).unwrap();
+            msg2.write_to(&mut enclave_stream)?;
 
             let sig_rl = match msg2.sig_rl {
                 Some(sig_rl) => sig_rl.to_owned(),
@@ -107,10 +300,11 @@ impl ClientRaContext {
             let spid = (&msg2.spid[..]).to_owned();
 
             // Get a Quote and send it to enclave to sign
-            let quote = Self::get_quote(&self.aesm_client,
-                                        spid,
-                                        sig_rl,
-                                        enclave_stream)?;
+            let quote = Self::get_quote_with_provisioning(&self.aesm_client,
+                                                          spid,
+                                                          sig_rl,
+                                                          enclave_stream,
+                                                          &self.provisioning_retry)?;
 
             // Read MAC for msg3 from enclave
             let mut mac = [0u8; size_of::<MacTag>()];
@@ -129,7 +323,19 @@ impl ClientRaContext {
                      spid: Vec<u8>,
                      sig_rl: Vec<u8>,
                      enclave_stream: &mut (impl Read+Write)) -> ClientRaResult<Quote> {
-        let quote_info = aesm_client.init_quote()?;
+        Self::get_quote_with_provisioning(aesm_client, spid, sig_rl, enclave_stream,
+                                          &RetryPolicy::default())
+    }
+
+    /// Same as `get_quote`, but if the platform turns out to have no usable
+    /// EPID key, has AESM provision it according to `policy` and asks for
+    /// the quote again.
+    pub fn get_quote_with_provisioning(aesm_client: &AesmClient,
+                                       spid: Vec<u8>,
+                                       sig_rl: Vec<u8>,
+                                       enclave_stream: &mut (impl Read+Write),
+                                       policy: &RetryPolicy) -> ClientRaResult<Quote> {
+        let quote_info = init_quote_with_provisioning(aesm_client, policy)?;
 
         // Get report for local attestation with QE from enclave
         enclave_stream.write_all(quote_info.target_info()).unwrap();
@@ -137,11 +343,20 @@ impl ClientRaContext {
         enclave_stream.read_exact(&mut report[..]).unwrap();
 
         // Get a quote and QE report from QE and send them to enclave
-        let _quote = aesm_client.get_quote(
-            &quote_info,
-            report,
-            spid,
-            sig_rl)?;
+        let _quote = match aesm_client.get_quote(&quote_info, report.clone(),
+                                                 spid.clone(), sig_rl.clone()) {
+            Ok(quote) => quote,
+            Err(ref e) if is_epid_unprovisioned(e) => {
+                if cfg!(feature = "verbose") {
+                    eprintln!("EPID key unusable ({:?}), provisioning", e);
+                }
+                // The QE keeps its identity, so the enclave's report still
+                // targets it
+                let quote_info = init_quote_with_provisioning(aesm_client, policy)?;
+                aesm_client.get_quote(&quote_info, report, spid, sig_rl)?
+            },
+            Err(e) => return Err(e.into()),
+        };
         enclave_stream.write_all(_quote.quote()).unwrap();
         enclave_stream.write_all(_quote.qe_report()).unwrap();
 
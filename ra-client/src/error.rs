@@ -1,9 +1,40 @@
+use ra_common::msg::{AbortReason, WireError};
+use crate::platform::PlatformProblem;
+
 #[derive(Debug)]
 pub enum ClientRaError {
     IO(std::boxed::Box<bincode::ErrorKind>),
     Aesm(aesm_client::Error),
+    /// AESM could not provision the platform's EPID key, e.g. because
+    /// Intel's provisioning service is unreachable from this machine.
+    EpidProvisioningFailed {
+        attempts: u32,
+        error: aesm_client::Error,
+    },
     EnclaveNotTrusted,
     PseNotTrusted,
+    /// The SP aborted the attestation.
+    Aborted(AbortReason),
+    /// The host cannot attest, see `PlatformInfo::problems`.
+    PlatformUnsupported(Vec<PlatformProblem>),
+    /// An AESM call that `AsyncClientRaContext` ran on tokio's blocking
+    /// thread pool panicked or was cancelled, with the reason.
+    BlockingTaskFailed(String),
+}
+
+impl ClientRaError {
+    /// Whether starting over on a new SP connection may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ClientRaError::IO(e) => match e.as_ref() {
+                bincode::ErrorKind::Io(_) => true,
+                _ => false,
+            },
+            ClientRaError::Aborted(AbortReason::IasUnavailable) |
+                ClientRaError::Aborted(AbortReason::ShuttingDown) => true,
+            _ => false,
+        }
+    }
 }
 
 impl std::convert::From<aesm_client::Error> for ClientRaError {
@@ -14,3 +45,20 @@ impl std::convert::From<std::boxed::Box<bincode::ErrorKind>> for ClientRaError {
     fn from(e: std::boxed::Box<bincode::ErrorKind>) -> Self { Self::IO(e) }
 }
 
+impl std::convert::From<std::io::Error> for ClientRaError {
+    fn from(e: std::io::Error) -> Self { Self::IO(Box::new(bincode::ErrorKind::Io(e))) }
+}
+
+#[cfg(feature = "async")]
+impl std::convert::From<tokio::task::JoinError> for ClientRaError {
+    fn from(e: tokio::task::JoinError) -> Self { Self::BlockingTaskFailed(e.to_string()) }
+}
+
+impl std::convert::From<WireError> for ClientRaError {
+    fn from(e: WireError) -> Self {
+        match e {
+            WireError::Serialization(e) => Self::IO(e),
+            WireError::Aborted(abort) => Self::Aborted(abort.reason),
+        }
+    }
+}
@@ -1,7 +1,20 @@
 mod error;
 mod context;
+mod retry;
+mod provisioning;
+pub mod platform;
+#[cfg(feature = "async")]
+mod async_context;
+#[cfg(all(feature = "sdk-bridge", unix))]
+pub mod sdk_bridge;
+#[cfg(all(feature = "urts", unix))]
+pub mod urts;
 
 pub use crate::error::*;
 pub use crate::context::*;
+pub use crate::retry::*;
+pub use crate::provisioning::*;
+#[cfg(feature = "async")]
+pub use crate::async_context::*;
 
 pub type ClientRaResult<T> = Result<T, ClientRaError>;
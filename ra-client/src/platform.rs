@@ -0,0 +1,381 @@
+// What the host offers for SGX, detected at runtime, so that a client can
+// tell its user what to fix before it tries to attest: the CPU's SGX support
+// (CPUID), the driver the kernel exposes it through, and AESM, which runs the
+// quoting enclave.
+//
+// On Windows, the driver and AESM come with Intel's PSW for Windows: there is
+// no device node to open, AESM is the AESMService service, and aesm-client
+// reaches it through the PSW's sgx_uae_service.dll rather than a socket.
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use aesm_client::AesmClient;
+use crate::error::ClientRaError;
+use crate::provisioning::is_epid_unprovisioned;
+use crate::ClientRaResult;
+
+const AESM_SOCKET_PATH: &str = "/var/run/aesmd/aesm.socket";
+const AESM_WINDOWS_SERVICE: &str = "AESMService";
+// Device nodes of each driver, and how to tell the out-of-tree ones apart:
+// the in-kernel driver is built in and has no module of its own
+const IN_KERNEL_DEVICE: &str = "/dev/sgx_enclave";
+const DCAP_DEVICE: &str = "/dev/sgx/enclave";
+const DCAP_MODULE: &str = "/sys/module/intel_sgx";
+const LEGACY_DEVICE: &str = "/dev/isgx";
+// The PSW for Windows installs the driver along with the library enclaves are
+// loaded through
+#[cfg(windows)]
+const WINDOWS_URTS_DLL: &str = "sgx_urts.dll";
+// Packages of Intel's PSW, as named in Intel's Debian and RPM repositories.
+// AESM does not report its own version, so it is read from dpkg's database.
+const DPKG_STATUS_PATH: &str = "/var/lib/dpkg/status";
+const AESM_PACKAGE: &str = "sgx-aesm-service";
+const AESM_PLUGIN_PREFIX: &str = "libsgx-aesm-";
+const EPID_PLUGIN: &str = "libsgx-aesm-epid-plugin";
+const ECDSA_PLUGIN: &str = "libsgx-aesm-ecdsa-plugin";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SgxDriver {
+    /// The driver of Linux 5.11 and later, /dev/sgx_enclave.
+    InKernel,
+    /// Intel's out-of-tree DCAP driver, intel_sgx.
+    DcapOot,
+    /// Intel's out-of-tree legacy driver, isgx, for CPUs without FLC.
+    LegacyOot,
+    /// Intel's driver for Windows, which enclaves are loaded through with the
+    /// PSW's sgx_urts.dll, and which needs no FLC.
+    Windows,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlatformInfo {
+    /// The CPU implements SGX.
+    pub cpu_sgx: bool,
+    /// SGX1 instructions are enabled, i.e. not disabled by the BIOS.
+    pub sgx1: bool,
+    /// SGX2 (EDMM) instructions are enabled.
+    pub sgx2: bool,
+    /// Flexible Launch Control, which the in-kernel and DCAP drivers need.
+    pub flc: bool,
+    pub driver: Option<SgxDriver>,
+    /// Path of the driver's device node, if there is a driver. None on
+    /// Windows, whose driver has no device node.
+    pub device: Option<String>,
+    /// This process can open the device node for reading and writing.
+    pub device_accessible: bool,
+    /// AESM accepts connections on its socket, or its service is running on
+    /// Windows.
+    pub aesm: bool,
+}
+
+/// Something that keeps the platform from attesting, with what to do about
+/// it as its `Display`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlatformProblem {
+    NoCpuSupport,
+    SgxDisabled,
+    /// No driver, and the CPU has no FLC, so that only the legacy driver
+    /// would work.
+    NoDriverWithoutFlc,
+    NoDriver,
+    DeviceInaccessible(String),
+    AesmUnavailable,
+}
+
+impl fmt::Display for PlatformProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlatformProblem::NoCpuSupport =>
+                write!(f, "The CPU does not support SGX"),
+            PlatformProblem::SgxDisabled =>
+                write!(f, "SGX is disabled; set it to \"Enabled\" in the BIOS setup"),
+            PlatformProblem::NoDriver if cfg!(windows) =>
+                write!(f, "No SGX driver; install Intel's SGX Platform Software for \
+                           Windows"),
+            PlatformProblem::AesmUnavailable if cfg!(windows) =>
+                write!(f, "AESM is not running; start the {} service", AESM_WINDOWS_SERVICE),
+            PlatformProblem::NoDriverWithoutFlc =>
+                write!(f, "No SGX driver, and the CPU lacks Flexible Launch Control; \
+                           install Intel's legacy isgx driver"),
+            PlatformProblem::NoDriver =>
+                write!(f, "No SGX driver; use Linux 5.11 or later, or install Intel's \
+                           DCAP driver"),
+            PlatformProblem::DeviceInaccessible(device) =>
+                write!(f, "Cannot open {}; add the user to the group that owns it, \
+                           e.g. sgx", device),
+            PlatformProblem::AesmUnavailable =>
+                write!(f, "AESM is not running; install and start aesmd ({} not \
+                           accepting connections)", AESM_SOCKET_PATH),
+        }
+    }
+}
+
+impl PlatformInfo {
+    pub fn detect() -> Self {
+        let (cpu_sgx, flc, sgx1, sgx2) = cpuid_sgx();
+        let (driver, device) = detect_driver();
+        let device_accessible = device.as_ref().map_or(false, |device| {
+            OpenOptions::new().read(true).write(true).open(device).is_ok()
+        });
+        let info = Self {
+            cpu_sgx,
+            sgx1,
+            sgx2,
+            flc,
+            driver,
+            device,
+            device_accessible,
+            aesm: is_aesm_running(),
+        };
+        if cfg!(feature = "verbose") {
+            eprintln!("{:#?}", info);
+        }
+        info
+    }
+
+    /// Everything that keeps this platform from attesting, most fundamental
+    /// first.
+    pub fn problems(&self) -> Vec<PlatformProblem> {
+        let mut problems = Vec::new();
+        if !self.cpu_sgx {
+            problems.push(PlatformProblem::NoCpuSupport);
+        } else if !self.sgx1 {
+            problems.push(PlatformProblem::SgxDisabled);
+        }
+        match self.device.as_ref() {
+            None if self.driver == Some(SgxDriver::Windows) => {},
+            None if self.cpu_sgx && !self.flc && !cfg!(windows) =>
+                problems.push(PlatformProblem::NoDriverWithoutFlc),
+            None => problems.push(PlatformProblem::NoDriver),
+            Some(device) if !self.device_accessible =>
+                problems.push(PlatformProblem::DeviceInaccessible(device.clone())),
+            Some(_) => {},
+        }
+        if !self.aesm {
+            problems.push(PlatformProblem::AesmUnavailable);
+        }
+        problems
+    }
+
+    /// Fails with `PlatformUnsupported` if there is any of `problems()`.
+    pub fn check(&self) -> ClientRaResult<()> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(ClientRaError::PlatformUnsupported(problems))
+    }
+}
+
+/// (SGX, FLC, SGX1 enabled, SGX2 enabled) from CPUID leaves 7 and 0x12.
+#[cfg(target_arch = "x86_64")]
+fn cpuid_sgx() -> (bool, bool, bool, bool) {
+    use std::arch::x86_64::{__cpuid_count, __get_cpuid_max};
+    // CPUID is always available on x86_64
+    unsafe {
+        let (max_leaf, _) = __get_cpuid_max(0);
+        if max_leaf < 0x7 {
+            return (false, false, false, false);
+        }
+        let leaf7 = __cpuid_count(0x7, 0);
+        let sgx = leaf7.ebx & (1 << 2) != 0;
+        let flc = leaf7.ecx & (1 << 30) != 0;
+        if !sgx || max_leaf < 0x12 {
+            return (sgx, flc, false, false);
+        }
+        let leaf12 = __cpuid_count(0x12, 0);
+        (sgx, flc, leaf12.eax & 0x1 != 0, leaf12.eax & 0x2 != 0)
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cpuid_sgx() -> (bool, bool, bool, bool) {
+    (false, false, false, false)
+}
+
+#[cfg(not(windows))]
+fn detect_driver() -> (Option<SgxDriver>, Option<String>) {
+    let exists = |path: &str| Path::new(path).exists();
+    let (driver, device) = if exists(LEGACY_DEVICE) {
+        (SgxDriver::LegacyOot, LEGACY_DEVICE)
+    } else if exists(DCAP_MODULE) {
+        let device = if exists(IN_KERNEL_DEVICE) { IN_KERNEL_DEVICE } else { DCAP_DEVICE };
+        (SgxDriver::DcapOot, device)
+    } else if exists(IN_KERNEL_DEVICE) {
+        (SgxDriver::InKernel, IN_KERNEL_DEVICE)
+    } else {
+        return (None, None);
+    };
+    if !exists(device) {
+        return (None, None);
+    }
+    (Some(driver), Some(device.to_owned()))
+}
+
+#[cfg(unix)]
+fn is_aesm_running() -> bool {
+    std::os::unix::net::UnixStream::connect(AESM_SOCKET_PATH).is_ok()
+}
+
+#[cfg(windows)]
+fn detect_driver() -> (Option<SgxDriver>, Option<String>) {
+    use winapi::um::libloaderapi::{FreeLibrary, LoadLibraryW};
+    let name = wide(WINDOWS_URTS_DLL);
+    // Safe since the name is NUL-terminated, and the library is freed again
+    let loaded = unsafe {
+        let module = LoadLibraryW(name.as_ptr());
+        if !module.is_null() {
+            FreeLibrary(module);
+        }
+        !module.is_null()
+    };
+    if !loaded {
+        return (None, None);
+    }
+    (Some(SgxDriver::Windows), None)
+}
+
+#[cfg(windows)]
+fn is_aesm_running() -> bool {
+    use std::ptr::null;
+    use winapi::um::winsvc::{CloseServiceHandle, OpenSCManagerW, OpenServiceW,
+                             QueryServiceStatus, SC_MANAGER_CONNECT,
+                             SERVICE_QUERY_STATUS, SERVICE_RUNNING, SERVICE_STATUS};
+    let name = wide(AESM_WINDOWS_SERVICE);
+    // Safe since the name is NUL-terminated, and every handle opened is closed
+    unsafe {
+        let manager = OpenSCManagerW(null(), null(), SC_MANAGER_CONNECT);
+        if manager.is_null() {
+            return false;
+        }
+        let service = OpenServiceW(manager, name.as_ptr(), SERVICE_QUERY_STATUS);
+        let mut status: SERVICE_STATUS = std::mem::zeroed();
+        let running = !service.is_null()
+            && QueryServiceStatus(service, &mut status) != 0
+            && status.dwCurrentState == SERVICE_RUNNING;
+        if !service.is_null() {
+            CloseServiceHandle(service);
+        }
+        CloseServiceHandle(manager);
+        running
+    }
+}
+
+// NUL-terminated UTF-16, as the W functions of the Windows API take
+#[cfg(windows)]
+fn wide(s: &str) -> Vec<u16> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_aesm_running() -> bool {
+    false
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttestationKeyType {
+    /// EPID, whose quotes IAS verifies.
+    Epid,
+    /// ECDSA, whose quotes are verified with Intel's DCAP collateral.
+    Ecdsa,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EpidStatus {
+    /// AESM initialized the quoting enclave; the platform is in this EPID
+    /// group.
+    Ready { gid: [u8; 4] },
+    /// The platform has no EPID key yet, see `init_quote_with_provisioning`.
+    Unprovisioned,
+    /// AESM cannot make EPID quotes, with its error.
+    Unavailable(String),
+}
+
+/// What the AESM service on the host can do, to tell why quote generation
+/// fails on a machine, e.g. from a customer's bug report.
+#[derive(Debug, Clone)]
+pub struct AesmInfo {
+    /// AESM accepts connections on its socket, or its service is running on
+    /// Windows.
+    pub running: bool,
+    /// Version of the installed sgx-aesm-service package. None if AESM was
+    /// not installed with dpkg, e.g. from RPMs or Intel's installer.
+    pub version: Option<String>,
+    /// Installed AESM plugin packages and their versions, e.g.
+    /// ("libsgx-aesm-ecdsa-plugin", "2.13.100.4-bionic1"), if known.
+    pub plugins: Vec<(String, String)>,
+    /// Attestation key types whose AESM plugin package is installed, as
+    /// read from dpkg; EPID also counts if AESM initialized its quoting
+    /// enclave. AESM itself is not asked which key types it supports, which
+    /// aesm-client cannot do, so a plugin that fails to load is still listed.
+    pub installed_key_types: Vec<AttestationKeyType>,
+    pub epid: EpidStatus,
+}
+
+impl AesmInfo {
+    /// Ask AESM to initialize the EPID quoting enclave, and read what is
+    /// installed of AESM. Initializing the quoting enclave may have AESM
+    /// provision the platform, which can take seconds.
+    pub fn query() -> Self {
+        let running = is_aesm_running();
+        let epid = if running {
+            match AesmClient::new().init_quote() {
+                Ok(quote_info) => {
+                    let mut gid = [0u8; 4];
+                    gid.copy_from_slice(&quote_info.gid()[..4]);
+                    EpidStatus::Ready { gid }
+                },
+                Err(ref e) if is_epid_unprovisioned(e) => EpidStatus::Unprovisioned,
+                Err(e) => EpidStatus::Unavailable(format!("{:?}", e)),
+            }
+        } else {
+            EpidStatus::Unavailable("AESM is not running".to_owned())
+        };
+
+        let packages = installed_packages();
+        let version = packages.iter()
+            .find(|(name, _)| name == AESM_PACKAGE)
+            .map(|(_, version)| version.clone());
+        let plugins: Vec<(String, String)> = packages.into_iter()
+            .filter(|(name, _)| name.starts_with(AESM_PLUGIN_PREFIX))
+            .collect();
+        let has_plugin = |plugin: &str| plugins.iter().any(|(name, _)| name == plugin);
+
+        let mut installed_key_types = Vec::new();
+        match epid {
+            EpidStatus::Unavailable(_) if !has_plugin(EPID_PLUGIN) => {},
+            _ => installed_key_types.push(AttestationKeyType::Epid),
+        }
+        if has_plugin(ECDSA_PLUGIN) {
+            installed_key_types.push(AttestationKeyType::Ecdsa);
+        }
+
+        let info = Self { running, version, plugins, installed_key_types, epid };
+        if cfg!(feature = "verbose") {
+            eprintln!("{:#?}", info);
+        }
+        info
+    }
+}
+
+// (name, version) of the packages dpkg has installed, none without dpkg, e.g.
+// on Windows
+fn installed_packages() -> Vec<(String, String)> {
+    let status = match fs::read_to_string(DPKG_STATUS_PATH) {
+        Ok(status) => status,
+        Err(_) => return Vec::new(),
+    };
+    let mut packages = Vec::new();
+    for paragraph in status.split("\n\n") {
+        let field = |name: &str| paragraph.lines()
+            .find_map(|line| line.strip_prefix(name))
+            .map(|value| value.trim().to_owned());
+        let installed = field("Status:").map_or(false, |s| s.ends_with(" installed"));
+        if let (true, Some(name), Some(version)) = (installed, field("Package:"), field("Version:")) {
+            packages.push((name, version));
+        }
+    }
+    packages
+}
@@ -0,0 +1,73 @@
+// EPID provisioning. A platform has no EPID key until AESM provisions it from
+// Intel's provisioning service, which AESM attempts whenever the quoting
+// enclave is initialized without one. On a fresh machine, or one whose EPID
+// blob was lost or revoked, the first quote therefore fails until
+// provisioning succeeds, typically because the provisioning service was not
+// reachable yet.
+use std::thread::sleep;
+use aesm_client::{AesmClient, AesmError, QuoteInfo};
+use crate::error::ClientRaError;
+use crate::retry::RetryPolicy;
+use crate::ClientRaResult;
+
+/// Whether `e` means that the platform has no usable EPID key, i.e. needs to
+/// be provisioned.
+pub fn is_epid_unprovisioned(e: &aesm_client::Error) -> bool {
+    match e {
+        aesm_client::Error::AesmCode(AesmError::EpidblobError_4) |
+            aesm_client::Error::AesmCode(AesmError::SgxProvisionFailed_16) => true,
+        _ => false,
+    }
+}
+
+// Whether provisioning failed because the provisioning service or AESM could
+// not be reached for now
+fn is_provisioning_transient(e: &aesm_client::Error) -> bool {
+    match e {
+        aesm_client::Error::AesmCode(AesmError::NetworkError_12) |
+            aesm_client::Error::AesmCode(AesmError::NetworkBusyError_13) |
+            aesm_client::Error::AesmCode(AesmError::ProxySettingAssist_14) |
+            aesm_client::Error::AesmCode(AesmError::Busy_18) |
+            aesm_client::Error::AesmCode(AesmError::BackendServerBusy_19) |
+            aesm_client::Error::AesmCode(AesmError::ServiceUnavailable_30) => true,
+        _ => false,
+    }
+}
+
+/// Initialize the quoting enclave, having AESM provision the platform again
+/// while `is_epid_unprovisioned` or the provisioning service cannot be
+/// reached, up to `policy.max_attempts` times in total. Fails with
+/// `EpidProvisioningFailed` if the platform is still not provisioned.
+pub fn init_quote_with_provisioning(aesm_client: &AesmClient, policy: &RetryPolicy)
+    -> ClientRaResult<QuoteInfo> {
+        let mut attempt = 1;
+        let mut backoff = policy.initial_backoff;
+        loop {
+            let e = match aesm_client.init_quote() {
+                Ok(quote_info) => {
+                    if cfg!(feature = "verbose") && attempt > 1 {
+                        eprintln!("EPID provisioned after {} attempts", attempt);
+                    }
+                    return Ok(quote_info);
+                },
+                Err(e) => e,
+            };
+            let provisioning = is_epid_unprovisioned(&e) || is_provisioning_transient(&e);
+            if !provisioning {
+                return Err(e.into());
+            }
+            if attempt >= policy.max_attempts {
+                return Err(ClientRaError::EpidProvisioningFailed {
+                    attempts: attempt,
+                    error: e,
+                });
+            }
+            if cfg!(feature = "verbose") {
+                eprintln!("EPID provisioning attempt {} failed ({:?}), retrying in {:?}",
+                          attempt, e, backoff);
+            }
+            sleep(backoff);
+            backoff = policy.next_backoff(backoff);
+            attempt += 1;
+        }
+    }
@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+/// How `ClientRaContext::do_attestation_with_retry` retries transient
+/// failures: up to `max_attempts` connections in total, waiting
+/// `initial_backoff` before the first retry and `multiplier` times longer
+/// before each further one, capped at `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            multiplier: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn none() -> Self {
+        Self { max_attempts: 1, ..Default::default() }
+    }
+
+    pub(crate) fn next_backoff(&self, backoff: Duration) -> Duration {
+        std::cmp::min(backoff * self.multiplier, self.max_backoff)
+    }
+}
@@ -0,0 +1,45 @@
+// Host side of ra-enclave's Intel SGX SDK bridge (edl/ra_bridge.edl). An ecall
+// runs on a dedicated thread whose ocalls are served from one end of a socket
+// pair; the other end is the `enclave_stream` for `ClientRaContext`.
+use std::cell::RefCell;
+use std::io::{Read, Write, Result};
+use std::os::unix::net::UnixStream;
+use std::slice;
+use std::thread::{self, JoinHandle};
+
+thread_local! {
+    static ENCLAVE_SIDE: RefCell<Option<UnixStream>> = RefCell::new(None);
+}
+
+/// Run `ecall`, e.g. the edger8r-generated untrusted proxy of
+/// `ecall_ra_do_attestation`, on a new thread. Returns the stream to pass to
+/// `ClientRaContext::do_attestation` and a handle to the ecall's status.
+pub fn spawn_ecall<F>(ecall: F) -> Result<(UnixStream, JoinHandle<u32>)>
+    where F: FnOnce() -> u32 + Send + 'static {
+        let (host_side, enclave_side) = UnixStream::pair()?;
+        let handle = thread::spawn(move || {
+            ENCLAVE_SIDE.with(|s| *s.borrow_mut() = Some(enclave_side));
+            let status = ecall();
+            ENCLAVE_SIDE.with(|s| s.borrow_mut().take());
+            status
+        });
+        Ok((host_side, handle))
+    }
+
+#[no_mangle]
+pub unsafe extern "C" fn ra_ocall_read(buf: *mut u8, len: usize) -> i64 {
+    let buf = slice::from_raw_parts_mut(buf, len);
+    ENCLAVE_SIDE.with(|s| match s.borrow_mut().as_mut() {
+        Some(stream) => stream.read(buf).map(|n| n as i64).unwrap_or(-1),
+        None => -1,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ra_ocall_write(buf: *const u8, len: usize) -> i64 {
+    let buf = slice::from_raw_parts(buf, len);
+    ENCLAVE_SIDE.with(|s| match s.borrow_mut().as_mut() {
+        Some(stream) => stream.write_all(buf).map(|_| len as i64).unwrap_or(-1),
+        None => -1,
+    })
+}
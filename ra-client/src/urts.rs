@@ -0,0 +1,194 @@
+// Bindings to the Intel SGX SDK's untrusted runtime, libsgx_urts, so that a
+// Rust host can load an SDK enclave and run ra-enclave's bridge ecall without
+// linking the C proxies generated by edger8r. The marshalling structs and
+// ocall bridges below follow edger8r's output for edl/ra_bridge.edl.
+//
+// edger8r numbers ecalls and ocalls in the order they appear in the enclave's
+// complete EDL, imports included, so the index of `ecall_ra_do_attestation`
+// and the position of the bridge's ocalls depend on the enclave; look them up
+// in the generated Enclave_u.c if the EDL declares more than ra_bridge.edl.
+// Link against libsgx_urts_sim instead for enclaves built in simulation mode,
+// e.g. with `-l sgx_urts_sim` in RUSTFLAGS.
+use std::ffi::{c_void, CString};
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use crate::sdk_bridge::{self, spawn_ecall};
+
+const SGX_SUCCESS: u32 = 0;
+const LAUNCH_TOKEN_LEN: usize = 1024;
+
+#[link(name = "sgx_urts")]
+extern "C" {
+    fn sgx_create_enclave(file_name: *const c_char,
+                          debug: c_int,
+                          launch_token: *mut [u8; LAUNCH_TOKEN_LEN],
+                          launch_token_updated: *mut c_int,
+                          enclave_id: *mut u64,
+                          misc_attr: *mut c_void) -> u32;
+
+    fn sgx_destroy_enclave(enclave_id: u64) -> u32;
+
+    fn sgx_ecall(enclave_id: u64,
+                 index: c_int,
+                 ocall_table: *const c_void,
+                 ms: *mut c_void) -> u32;
+}
+
+/// An `sgx_status_t` other than `SGX_SUCCESS`, e.g. 0x2001
+/// (`SGX_ERROR_INVALID_ENCLAVE`) for a file that is not a signed enclave.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SgxStatus(pub u32);
+
+impl std::convert::From<SgxStatus> for io::Error {
+    fn from(e: SgxStatus) -> Self {
+        io::Error::new(io::ErrorKind::Other, format!("sgx_status_t {:#x}", e.0))
+    }
+}
+
+/// An enclave loaded by the untrusted runtime. Destroyed when dropped.
+pub struct SgxEnclave {
+    id: u64,
+}
+
+impl SgxEnclave {
+    /// Load the signed enclave at `path`, e.g. enclave.signed.so. Debug
+    /// enclaves only load with `debug` set.
+    pub fn create(path: &Path, debug: bool) -> io::Result<Self> {
+        let path = path.to_str()
+            .and_then(|p| CString::new(p).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                                          "Enclave path is not a C string"))?;
+        // Launch tokens are ignored on platforms with flexible launch control
+        let mut launch_token = [0u8; LAUNCH_TOKEN_LEN];
+        let mut launch_token_updated = 0;
+        let mut id = 0u64;
+        let r = unsafe {
+            sgx_create_enclave(path.as_ptr(),
+                               debug as c_int,
+                               &mut launch_token,
+                               &mut launch_token_updated,
+                               &mut id,
+                               ptr::null_mut())
+        };
+        if r != SGX_SUCCESS {
+            return Err(SgxStatus(r).into());
+        }
+        if cfg!(feature = "verbose") {
+            eprintln!("Enclave {:#x} created", id);
+        }
+        Ok(Self { id })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Make ecall `index` with marshalling struct `ms`.
+    ///
+    /// # Safety
+    /// `ms` must point to the marshalling struct edger8r generates for the
+    /// ecall at `index`, and `ocalls` must list the enclave's ocalls.
+    pub unsafe fn ecall(&self, index: i32, ocalls: &OcallTable, ms: *mut c_void)
+        -> Result<(), SgxStatus> {
+            let r = sgx_ecall(self.id, index, ocalls.as_ptr(), ms);
+            if r != SGX_SUCCESS {
+                return Err(SgxStatus(r));
+            }
+            Ok(())
+        }
+}
+
+impl Drop for SgxEnclave {
+    fn drop(&mut self) {
+        let _r = unsafe { sgx_destroy_enclave(self.id) };
+    }
+}
+
+/// An ocall bridge as generated by edger8r: unpacks the marshalling struct
+/// and calls the ocall.
+pub type OcallBridge = unsafe extern "C" fn(ms: *mut c_void) -> u32;
+
+/// The enclave's ocall table, in the order of the EDL's untrusted functions.
+pub struct OcallTable {
+    // nr_ocall followed by the bridges, like the struct edger8r generates
+    raw: Vec<usize>,
+}
+
+impl OcallTable {
+    pub fn new(bridges: &[OcallBridge]) -> Self {
+        let mut raw = Vec::with_capacity(1 + bridges.len());
+        raw.push(bridges.len());
+        raw.extend(bridges.iter().map(|b| *b as usize));
+        Self { raw }
+    }
+
+    /// For an enclave whose only untrusted functions are those of
+    /// ra_bridge.edl.
+    pub fn ra_bridge() -> Self {
+        Self::new(&RA_BRIDGE_OCALLS)
+    }
+
+    fn as_ptr(&self) -> *const c_void {
+        self.raw.as_ptr() as *const c_void
+    }
+}
+
+/// `ra_ocall_read` and `ra_ocall_write`, for enclaves that declare further
+/// ocalls and thus need a table of their own.
+pub const RA_BRIDGE_OCALLS: [OcallBridge; 2] = [ra_ocall_read_bridge, ra_ocall_write_bridge];
+
+#[repr(C)]
+struct MsRaOcallRead {
+    retval: i64,
+    buf: *mut u8,
+    len: usize,
+}
+
+#[repr(C)]
+struct MsRaOcallWrite {
+    retval: i64,
+    buf: *const u8,
+    len: usize,
+}
+
+#[repr(C)]
+struct MsEcallRaDoAttestation {
+    retval: u32,
+}
+
+unsafe extern "C" fn ra_ocall_read_bridge(ms: *mut c_void) -> u32 {
+    let ms = &mut *(ms as *mut MsRaOcallRead);
+    ms.retval = sdk_bridge::ra_ocall_read(ms.buf, ms.len);
+    SGX_SUCCESS
+}
+
+unsafe extern "C" fn ra_ocall_write_bridge(ms: *mut c_void) -> u32 {
+    let ms = &mut *(ms as *mut MsRaOcallWrite);
+    ms.retval = sdk_bridge::ra_ocall_write(ms.buf, ms.len);
+    SGX_SUCCESS
+}
+
+/// Run `ecall_ra_do_attestation`, whose index is `ecall_index` (0 if it is
+/// the enclave's first ecall), on a new thread. Returns the stream to pass to
+/// `ClientRaContext::do_attestation` and a handle to the ecall's result: the
+/// status returned by ra-enclave's `sdk_bridge::do_attestation`, or the
+/// runtime's error if the ecall could not be made.
+pub fn spawn_attestation(enclave: Arc<SgxEnclave>, ecall_index: i32, ocalls: OcallTable)
+    -> io::Result<(UnixStream, JoinHandle<u32>)> {
+        spawn_ecall(move || {
+            let mut ms = MsEcallRaDoAttestation { retval: 0 };
+            let r = unsafe {
+                enclave.ecall(ecall_index, &ocalls,
+                              &mut ms as *mut MsEcallRaDoAttestation as *mut c_void)
+            };
+            match r {
+                Ok(()) => ms.retval,
+                Err(SgxStatus(status)) => status,
+            }
+        })
+    }
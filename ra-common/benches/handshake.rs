@@ -0,0 +1,58 @@
+// Compute cost of the attestation handshake on the SP and enclave sides, from
+// the key exchange to the MSG3 MAC. Getting the quote from the QE and having
+// IAS verify it need SGX hardware and the network and are left out, as if IAS
+// answered instantly; ra-sp's attestation bench covers the full handshake
+// against its mock IAS.
+use std::mem::size_of;
+use criterion::{criterion_group, criterion_main, Criterion};
+use sgx_crypto::random::RandomState;
+use sgx_crypto::key_exchange::OneWayAuthenticatedDHKE;
+use sgx_crypto::signature::{SigningKey, VerificationKey};
+use sgx_crypto::cmac::Cmac;
+use ra_common::derive_secret_keys;
+use ra_common::msg::{Quote, RaMsg2, RaMsg3};
+use ra_common::DEFAULT_KDF_ID;
+
+const SP_PRIVATE_KEY_PEM: &str = include_str!("../../sample-sp/data/sp-keys/private_key.pem");
+const SP_PUBLIC_KEY_PEM: &str = include_str!("../../sample-sp/data/sp-keys/public_key.pem");
+
+fn handshake(c: &mut Criterion) {
+    let rng = RandomState::new();
+    let signing_key = SigningKey::new_from_pem(SP_PRIVATE_KEY_PEM).unwrap();
+    let verification_key = VerificationKey::new_from_pem(SP_PUBLIC_KEY_PEM).unwrap();
+    let quote = [0u8; size_of::<Quote>()];
+
+    c.bench_function("handshake", |b| b.iter(|| {
+        // Enclave: MSG1
+        let enclave_ke = OneWayAuthenticatedDHKE::generate_keypair(&rng).unwrap();
+        let g_a = enclave_ke.get_public_key().to_owned();
+
+        // SP: MSG2
+        let sp_ke = OneWayAuthenticatedDHKE::generate_keypair(&rng).unwrap();
+        let g_b = sp_ke.get_public_key().to_owned();
+        let (kdk, sign_gb_ga) = sp_ke.sign_and_derive(&g_a, &signing_key, &rng).unwrap();
+        let (smk, _sk, _mk, _vk) = derive_secret_keys(&Cmac::new(&kdk));
+        let sp_smk = Cmac::new(&smk);
+        let msg2 = RaMsg2::new(&sp_smk, g_b, [0u8; 16], 1, DEFAULT_KDF_ID,
+                               sign_gb_ga, None, None);
+
+        // Enclave: verify MSG2, MSG3
+        let kdk = enclave_ke.verify_and_derive(&msg2.g_b, &msg2.sign_gb_ga,
+                                               &verification_key).unwrap();
+        let (smk, _sk, _mk, _vk) = derive_secret_keys(&Cmac::new(&kdk));
+        let enclave_smk = Cmac::new(&smk);
+        msg2.verify_mac(&enclave_smk).unwrap();
+        let msg3 = RaMsg3::new(&enclave_smk, g_a, None, quote);
+
+        // SP: verify MSG3
+        msg3.verify_mac(&sp_smk).unwrap();
+    }));
+}
+
+fn key_derivation(c: &mut Criterion) {
+    let kdk = Cmac::new(&[7u8; 16]);
+    c.bench_function("derive_secret_keys", |b| b.iter(|| derive_secret_keys(&kdk)));
+}
+
+criterion_group!(benches, handshake, key_derivation);
+criterion_main!(benches);
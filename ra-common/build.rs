@@ -0,0 +1,7 @@
+fn main() {
+    #[cfg(feature = "protobuf")]
+    {
+        println!("cargo:rerun-if-changed=proto/ra.proto");
+        prost_build::compile_protos(&["proto/ra.proto"], &["proto/"]).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+// Encryption of data an enclave keeps in untrusted storage, e.g. files on the
+// host, under keys derived from an attested session. The blob is
+//   magic || label length (u16) || label || KDF nonce || AES-GCM(data)
+// in little-endian order, with everything before the ciphertext authenticated
+// as additional data. The key is derived from the session's storage key in the
+// manner of `derive_secret_keys`, with the label and the KDF nonce as context,
+// so every blob is encrypted under a key of its own and a blob written under
+// one label cannot be read as another.
+//
+// Both ends of the session can decrypt the blobs, i.e. the enclave and the SP.
+// Unlike `ra_enclave::sealing`, the data is not bound to the platform, and it
+// can only be read back by a party that still holds the session's keys.
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use sgx_crypto::aead::{self, AeadError, AeadKey};
+use sgx_crypto::cmac::{Cmac, MacTag};
+use sgx_crypto::random::RandomState;
+use crate::session_keys::SessionKeys;
+
+const MAGIC: [u8; 4] = *b"RABL";
+pub const KDF_NONCE_LEN: usize = 16;
+
+#[derive(Debug)]
+pub enum BlobError {
+    IO(io::Error),
+    Aead(AeadError),
+    Malformed,
+    /// The blob was encrypted under another label.
+    LabelMismatched(String),
+}
+
+impl std::convert::From<io::Error> for BlobError {
+    fn from(e: io::Error) -> Self { Self::IO(e) }
+}
+
+impl std::convert::From<AeadError> for BlobError {
+    fn from(e: AeadError) -> Self { Self::Aead(e) }
+}
+
+/// Header of an encrypted blob, which tells how its key was derived.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobHeader {
+    /// What the blob holds, e.g. "user-db" or "checkpoint/42".
+    pub label: String,
+    pub kdf_nonce: [u8; KDF_NONCE_LEN],
+}
+
+impl BlobHeader {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + 2 + self.label.len() + KDF_NONCE_LEN);
+        bytes.extend_from_slice(&MAGIC[..]);
+        // Can unwrap since writing to a Vec does not fail
+        bytes.write_u16::<LittleEndian>(self.label.len() as u16).unwrap();
+        bytes.extend_from_slice(self.label.as_bytes());
+        bytes.extend_from_slice(&self.kdf_nonce[..]);
+        bytes
+    }
+
+    /// Decode the header at the start of `blob` and return it with its
+    /// length.
+    fn decode(blob: &[u8]) -> Result<(Self, usize), BlobError> {
+        let mut reader = blob;
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic[..]).map_err(|_| BlobError::Malformed)?;
+        if magic != MAGIC {
+            return Err(BlobError::Malformed);
+        }
+        let label_len = reader.read_u16::<LittleEndian>()
+            .map_err(|_| BlobError::Malformed)? as usize;
+        if reader.len() < label_len + KDF_NONCE_LEN {
+            return Err(BlobError::Malformed);
+        }
+        let label = String::from_utf8(reader[..label_len].to_vec())
+            .map_err(|_| BlobError::Malformed)?;
+        let mut kdf_nonce = [0u8; KDF_NONCE_LEN];
+        kdf_nonce.copy_from_slice(&reader[label_len..label_len + KDF_NONCE_LEN]);
+        Ok((Self { label, kdf_nonce }, 4 + 2 + label_len + KDF_NONCE_LEN))
+    }
+
+    fn key(&self, storage_key: &MacTag) -> AeadKey {
+        let mut data = Vec::with_capacity(1 + 4 + 1 + self.label.len() + KDF_NONCE_LEN + 2);
+        data.push(0x01);
+        data.extend_from_slice(b"BLOB");
+        data.push(0x00);
+        data.extend_from_slice(self.label.as_bytes());
+        data.extend_from_slice(&self.kdf_nonce[..]);
+        data.extend_from_slice(&[0x80, 0x00]);
+        Cmac::new(storage_key).sign(&data[..])
+    }
+}
+
+/// Encrypt `plaintext` under a fresh key derived from the storage key of
+/// `keys` and `label`. Labels must be shorter than 64 KiB.
+pub fn encrypt_blob(keys: &SessionKeys, label: &str, plaintext: &[u8])
+    -> Result<Vec<u8>, BlobError> {
+        if label.len() > u16::max_value() as usize {
+            return Err(BlobError::Malformed);
+        }
+        let rng = RandomState::new();
+        let mut kdf_nonce = [0u8; KDF_NONCE_LEN];
+        rng.fill(&mut kdf_nonce[..]);
+        let header = BlobHeader { label: label.to_owned(), kdf_nonce };
+
+        let header_bytes = header.encode();
+        let ciphertext = aead::seal(&header.key(&keys.storage_key()), &header_bytes[..],
+                                    plaintext, &rng)?;
+        let mut blob = header_bytes;
+        blob.extend_from_slice(&ciphertext[..]);
+        Ok(blob)
+    }
+
+/// Decrypt a blob produced by `encrypt_blob`, which must carry `label`.
+pub fn decrypt_blob(keys: &SessionKeys, label: &str, blob: &[u8])
+    -> Result<Vec<u8>, BlobError> {
+        let (header, header_len) = BlobHeader::decode(blob)?;
+        if header.label != label {
+            return Err(BlobError::LabelMismatched(header.label));
+        }
+        let (header_bytes, ciphertext) = blob.split_at(header_len);
+        Ok(aead::open(&header.key(&keys.storage_key()), header_bytes, ciphertext)?)
+    }
+
+/// The header of `blob`, e.g. to find out its label before decrypting it.
+/// Not authenticated until the blob is decrypted.
+pub fn blob_header(blob: &[u8]) -> Result<BlobHeader, BlobError> {
+    BlobHeader::decode(blob).map(|(header, _)| header)
+}
+
+/// `encrypt_blob` and write the blob to `writer`.
+pub fn write_encrypted<W: Write>(mut writer: W, keys: &SessionKeys, label: &str,
+                                 plaintext: &[u8]) -> Result<(), BlobError> {
+    writer.write_all(&encrypt_blob(keys, label, plaintext)?[..])?;
+    Ok(writer.flush()?)
+}
+
+/// Read a blob from `reader` to its end and `decrypt_blob` it.
+pub fn read_encrypted<R: Read>(mut reader: R, keys: &SessionKeys, label: &str)
+    -> Result<Vec<u8>, BlobError> {
+        let mut blob = Vec::new();
+        reader.read_to_end(&mut blob)?;
+        decrypt_blob(keys, label, &blob[..])
+    }
+
+/// Encrypt `plaintext` to the file at `path`, replacing it. Not available to
+/// enclaves, which have no file system; they can hand the blob of
+/// `encrypt_blob` to the host instead.
+pub fn encrypt_file(path: &Path, keys: &SessionKeys, label: &str, plaintext: &[u8])
+    -> Result<(), BlobError> {
+        write_encrypted(fs::File::create(path)?, keys, label, plaintext)
+    }
+
+pub fn decrypt_file(path: &Path, keys: &SessionKeys, label: &str)
+    -> Result<Vec<u8>, BlobError> {
+        read_encrypted(fs::File::open(path)?, keys, label)
+    }
@@ -0,0 +1,460 @@
+// Wire format and handshake of Intel's sgx-ra-sample
+// (https://github.com/intel/sgx-ra-sample), for `SpRaContext` and
+// `ClientRaContext` to attest the sample's C enclaves and to be attested by
+// its C SP.
+// Every message is sent as a single line of lowercase hex (msgio.cpp) holding
+// the packed little-endian C structs from sgx_key_exchange.h. Public keys are
+// raw (gx, gy) pairs in little-endian order rather than uncompressed SEC1
+// points.
+//
+// The handshake differs from this crate's own: the SP signs g_b || g_a with an
+// ECDSA P-256 key whose public key is compiled into the enclave rather than
+// with its RSA key, the KDK is derived from the little-endian shared secret,
+// MSG0 travels with MSG1, the quote's REPORTDATA holds no bound data or
+// nonce, and there are no abort messages.
+use std::io::{Read, Write, Result, Error, ErrorKind};
+use std::convert::TryInto;
+use std::mem::size_of;
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use sgx_crypto::cmac::{Cmac, MacError, MacTag};
+use sgx_crypto::digest::{sha256, Sha256Digest};
+use sgx_crypto::key_exchange::{derive_kdk, DHKEPublicKey, SharedSecret, KDK};
+use sgx_crypto::random::RandomState;
+use sgx_crypto::signature::{EcdsaSigningKey, EcdsaVerificationKey, SigError};
+use ra_verify::asn1;
+use crate::msg::{Gid, Spid, PsSecPropDesc, RaMsg1, RaMsg4};
+
+const COORD_LEN: usize = 32;
+const MAX_LINE_LEN: usize = 0x100000;
+
+/// `sgx_ec256_public_t`: gx || gy, each little-endian.
+pub type IntelPublicKey = [u8; 2 * COORD_LEN];
+/// `sgx_ec256_signature_t`: x || y, each eight little-endian u32 words.
+pub type IntelSignature = [u8; 2 * COORD_LEN];
+/// `sgx_platform_info_t`
+pub type PlatformInfoBlob = [u8; 101];
+
+/// Send `msg` the way msgio does: hex encoded and newline terminated.
+pub fn write_msg(w: &mut impl Write, msg: &[u8]) -> Result<()> {
+    let mut line = String::with_capacity(2 * msg.len() + 1);
+    for b in msg.iter() {
+        line.push_str(&format!("{:02x}", b));
+    }
+    line.push('\n');
+    w.write_all(line.as_bytes())?;
+    w.flush()
+}
+
+/// Receive one msgio line and decode it.
+pub fn read_msg(r: &mut impl Read) -> Result<Vec<u8>> {
+    let mut line = Vec::new();
+    loop {
+        let c = r.read_u8()?;
+        match c {
+            b'\n' => break,
+            b'\r' => {},
+            _ if line.len() >= MAX_LINE_LEN => return Err(invalid("Message too long")),
+            _ => line.push(c),
+        }
+    }
+    if line.len() % 2 != 0 {
+        return Err(invalid("Odd-length hex message"));
+    }
+    line.chunks(2)
+        .map(|pair| {
+            // from_str_radix would also take a sign, e.g. "+f"
+            if !pair.iter().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid("Bad hex"));
+            }
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid("Bad hex"))?;
+            u8::from_str_radix(pair, 16).map_err(|_| invalid("Bad hex"))
+        })
+        .collect()
+}
+
+/// Convert an uncompressed SEC1 point (0x04 || X || Y, big-endian) to
+/// `sgx_ec256_public_t`.
+pub fn key_to_intel(key: &DHKEPublicKey) -> IntelPublicKey {
+    let mut out = [0u8; 2 * COORD_LEN];
+    for i in 0..COORD_LEN {
+        out[i] = key[COORD_LEN - i];
+        out[COORD_LEN + i] = key[2 * COORD_LEN - i];
+    }
+    out
+}
+
+pub fn key_from_intel(key: &IntelPublicKey) -> DHKEPublicKey {
+    let mut out = [0u8; size_of::<DHKEPublicKey>()];
+    out[0] = 0x04;
+    for i in 0..COORD_LEN {
+        out[COORD_LEN - i] = key[i];
+        out[2 * COORD_LEN - i] = key[COORD_LEN + i];
+    }
+    out
+}
+
+/// KDK = AES-CMAC(0^128, shared secret) with the shared secret in
+/// little-endian order, as `sgx_ra_proc_msg2` and the sample's SP derive it.
+pub fn derive_intel_kdk(shared_secret: &SharedSecret) -> KDK {
+    let mut shared_secret = *shared_secret;
+    shared_secret.reverse();
+    derive_kdk(&shared_secret)
+}
+
+/// The SP's signature of g_b || g_a in MSG2.
+pub fn sign_gb_ga(g_b: &IntelPublicKey, g_a: &IntelPublicKey,
+                  key: &EcdsaSigningKey, rng: &RandomState)
+    -> std::result::Result<IntelSignature, SigError> {
+        let signature = key.sign(&[&g_b[..], &g_a[..]].concat(), rng)?;
+        signature_to_intel(&signature[..]).ok_or(SigError::BadSignature)
+    }
+
+pub fn verify_gb_ga(g_b: &IntelPublicKey, g_a: &IntelPublicKey, sign_gb_ga: &IntelSignature,
+                    key: &EcdsaVerificationKey)
+    -> std::result::Result<(), SigError> {
+        key.verify(&[&g_b[..], &g_a[..]].concat(), &signature_from_intel(sign_gb_ga)[..])
+    }
+
+/// The SP key as the sample compiles it into its enclave
+/// (`sgx_ec256_public_t`).
+pub fn verification_key_from_intel(key: &IntelPublicKey) -> EcdsaVerificationKey {
+    EcdsaVerificationKey::new_from_sec1(&key_from_intel(key)[..])
+}
+
+/// What the quote's REPORTDATA starts with: SHA-256(g_a || g_b || VK). The
+/// rest of REPORTDATA is zero.
+pub fn verification_digest(g_a: &IntelPublicKey, g_b: &IntelPublicKey, vk: &MacTag)
+    -> Sha256Digest {
+        sha256(&[&g_a[..], &g_b[..], &vk[..]].concat())
+    }
+
+// Ecdsa-Sig-Value ::= SEQUENCE { r INTEGER, s INTEGER } to r || s, each
+// little-endian
+fn signature_to_intel(der: &[u8]) -> Option<IntelSignature> {
+    let mut der = der;
+    let mut content = asn1::expect(&mut der, asn1::TAG_SEQUENCE)?;
+    let mut out = [0u8; 2 * COORD_LEN];
+    for half in out.chunks_mut(COORD_LEN) {
+        let int = asn1::expect(&mut content, asn1::TAG_INTEGER)?;
+        let skip = int.iter().take_while(|b| **b == 0).count();
+        let int = &int[skip..];
+        if int.len() > COORD_LEN {
+            return None;
+        }
+        for (i, b) in int.iter().rev().enumerate() {
+            half[i] = *b;
+        }
+    }
+    if !der.is_empty() || !content.is_empty() {
+        return None;
+    }
+    Some(out)
+}
+
+fn signature_from_intel(signature: &IntelSignature) -> Vec<u8> {
+    let mut raw = *signature;
+    for half in raw.chunks_mut(COORD_LEN) {
+        half.reverse();
+    }
+    asn1::ecdsa_sig_to_der(&raw[..])
+}
+
+/// MSG0 and MSG1, which the sample client sends as one message.
+pub struct IntelMsg01 {
+    pub exgid: u32,
+    pub g_a: IntelPublicKey,
+    pub gid: Gid,
+}
+
+impl IntelMsg01 {
+    pub fn from_msg1(exgid: u32, msg1: &RaMsg1) -> Self {
+        Self { exgid, g_a: key_to_intel(&msg1.g_a), gid: msg1.gid }
+    }
+
+    pub fn to_msg1(&self) -> RaMsg1 {
+        RaMsg1 { gid: self.gid, g_a: key_from_intel(&self.g_a) }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.write_u32::<LittleEndian>(self.exgid).unwrap();
+        out.write_all(&self.g_a[..]).unwrap();
+        out.write_all(&self.gid[..]).unwrap();
+        out
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self> {
+        let exgid = data.read_u32::<LittleEndian>()?;
+        let mut g_a = [0u8; size_of::<IntelPublicKey>()];
+        data.read_exact(&mut g_a[..])?;
+        let mut gid = [0u8; size_of::<Gid>()];
+        data.read_exact(&mut gid[..])?;
+        Ok(Self { exgid, g_a, gid })
+    }
+}
+
+/// `sgx_ra_msg2_t` followed by the SigRL.
+pub struct IntelMsg2 {
+    pub g_b: IntelPublicKey,
+    pub spid: Spid,
+    pub quote_type: u16,
+    pub kdf_id: u16,
+    pub sign_gb_ga: IntelSignature,
+    pub mac: MacTag,
+    pub sig_rl: Vec<u8>,
+}
+
+impl IntelMsg2 {
+    pub fn new(smk: &Cmac,
+               g_b: IntelPublicKey,
+               spid: Spid,
+               quote_type: u16,
+               kdf_id: u16,
+               sign_gb_ga: IntelSignature,
+               sig_rl: Vec<u8>) -> Self {
+        let mut msg2 = Self {
+            g_b,
+            spid,
+            quote_type,
+            kdf_id,
+            sign_gb_ga,
+            mac: [0u8; size_of::<MacTag>()],
+            sig_rl,
+        };
+        msg2.mac = smk.sign(&msg2.mac_data()[..]);
+        msg2
+    }
+
+    pub fn verify_mac(&self, smk: &Cmac) -> std::result::Result<(), MacError> {
+        smk.verify(&self.mac_data()[..], &self.mac)
+    }
+
+    /// The bytes covered by `mac`: everything from g_b up to the signature.
+    pub fn mac_data(&self) -> Vec<u8> {
+        let mut a = Vec::new();
+        a.write_all(&self.g_b[..]).unwrap();
+        a.write_all(&self.spid[..]).unwrap();
+        a.write_u16::<LittleEndian>(self.quote_type).unwrap();
+        a.write_u16::<LittleEndian>(self.kdf_id).unwrap();
+        a.write_all(&self.sign_gb_ga[..]).unwrap();
+        a
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.mac_data();
+        out.write_all(&self.mac[..]).unwrap();
+        out.write_u32::<LittleEndian>(self.sig_rl.len() as u32).unwrap();
+        out.write_all(&self.sig_rl[..]).unwrap();
+        out
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self> {
+        let mut g_b = [0u8; size_of::<IntelPublicKey>()];
+        data.read_exact(&mut g_b[..])?;
+        let mut spid = [0u8; size_of::<Spid>()];
+        data.read_exact(&mut spid[..])?;
+        let quote_type = data.read_u16::<LittleEndian>()?;
+        let kdf_id = data.read_u16::<LittleEndian>()?;
+        let mut sign_gb_ga = [0u8; size_of::<IntelSignature>()];
+        data.read_exact(&mut sign_gb_ga[..])?;
+        let mut mac = [0u8; size_of::<MacTag>()];
+        data.read_exact(&mut mac[..])?;
+        let sig_rl_len = data.read_u32::<LittleEndian>()? as usize;
+        if data.len() != sig_rl_len {
+            return Err(invalid("SigRL length mismatch"));
+        }
+        Ok(Self { g_b, spid, quote_type, kdf_id, sign_gb_ga, mac, sig_rl: data.to_vec() })
+    }
+}
+
+/// `sgx_ra_msg3_t`. The quote is variable length in the sample.
+pub struct IntelMsg3 {
+    pub mac: MacTag,
+    pub g_a: IntelPublicKey,
+    pub ps_sec_prop: PsSecPropDesc,
+    pub quote: Vec<u8>,
+}
+
+impl IntelMsg3 {
+    /// MSG3 without a PSE security property descriptor.
+    pub fn new(smk: &Cmac, g_a: IntelPublicKey, quote: Vec<u8>) -> Self {
+        let mut msg3 = Self {
+            mac: [0u8; size_of::<MacTag>()],
+            g_a,
+            ps_sec_prop: [0u8; size_of::<PsSecPropDesc>()],
+            quote,
+        };
+        msg3.mac = smk.sign(&msg3.mac_data()[..]);
+        msg3
+    }
+
+    pub fn verify_mac(&self, smk: &Cmac) -> std::result::Result<(), MacError> {
+        smk.verify(&self.mac_data()[..], &self.mac)
+    }
+
+    /// The bytes covered by `mac`: everything after it.
+    pub fn mac_data(&self) -> Vec<u8> {
+        let mut a = Vec::new();
+        a.write_all(&self.g_a[..]).unwrap();
+        a.write_all(&self.ps_sec_prop[..]).unwrap();
+        a.write_all(&self.quote[..]).unwrap();
+        a
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.write_all(&self.mac[..]).unwrap();
+        out.write_all(&self.mac_data()[..]).unwrap();
+        out
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self> {
+        let mut mac = [0u8; size_of::<MacTag>()];
+        data.read_exact(&mut mac[..])?;
+        let mut g_a = [0u8; size_of::<IntelPublicKey>()];
+        data.read_exact(&mut g_a[..])?;
+        if data.len() < size_of::<PsSecPropDesc>() {
+            return Err(invalid("Truncated message"));
+        }
+        let mut ps_sec_prop = [0u8; size_of::<PsSecPropDesc>()];
+        ps_sec_prop.copy_from_slice(&data[..size_of::<PsSecPropDesc>()]);
+        let quote = data[size_of::<PsSecPropDesc>()..].to_vec();
+        Ok(Self { mac, g_a, ps_sec_prop, quote })
+    }
+}
+
+/// `attestation_status_t` of the sample's `ra_msg4_t`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntelAttestationStatus {
+    NotTrusted = 0,
+    NotTrustedItsComplicated = 1,
+    TrustedItsComplicated = 2,
+    Trusted = 3,
+}
+
+/// The sample's `ra_msg4_t`.
+pub struct IntelMsg4 {
+    pub status: IntelAttestationStatus,
+    pub pse_status: IntelAttestationStatus,
+    pub platform_info_blob: Option<PlatformInfoBlob>,
+}
+
+impl IntelMsg4 {
+    /// The platform info blob is decoded from the hex string IAS returned in
+    /// `msg4.pib`, if any.
+    pub fn from_msg4(msg4: &RaMsg4) -> Self {
+        let status = |trusted| if trusted {
+            IntelAttestationStatus::Trusted
+        } else {
+            IntelAttestationStatus::NotTrusted
+        };
+        let platform_info_blob = msg4.pib.as_ref()
+            .and_then(|pib| decode_hex(pib))
+            .and_then(|pib| pib.as_slice().try_into().ok());
+        Self {
+            status: status(msg4.is_enclave_trusted),
+            pse_status: status(msg4.is_pse_manifest_trusted.unwrap_or(false)),
+            platform_info_blob,
+        }
+    }
+
+    /// The sample's SP trusts an enclave as `Trusted` or
+    /// `TrustedItsComplicated`, e.g. with an out-of-date TCB its policy
+    /// accepts. The PSE status is only reported if MSG3 had a PSE descriptor,
+    /// which `IntelMsg3::new` never sends.
+    pub fn to_msg4(&self) -> RaMsg4 {
+        let is_enclave_trusted = match self.status {
+            IntelAttestationStatus::Trusted |
+                IntelAttestationStatus::TrustedItsComplicated => true,
+            _ => false,
+        };
+        let pib = self.platform_info_blob.as_ref()
+            .map(|pib| pib.iter().map(|b| format!("{:02X}", b)).collect());
+        RaMsg4 { is_enclave_trusted, is_pse_manifest_trusted: None, pib }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.write_u32::<LittleEndian>(self.status as u32).unwrap();
+        out.write_u32::<LittleEndian>(self.pse_status as u32).unwrap();
+        match self.platform_info_blob.as_ref() {
+            Some(pib) => out.write_all(&pib[..]).unwrap(),
+            None => out.write_all(&[0u8; size_of::<PlatformInfoBlob>()]).unwrap(),
+        }
+        out
+    }
+
+    /// An all-zero platform info blob, which the sample's SP sends when IAS
+    /// returned none, is read as none.
+    pub fn decode(mut data: &[u8]) -> Result<Self> {
+        let status = decode_status(data.read_u32::<LittleEndian>()?)?;
+        let pse_status = decode_status(data.read_u32::<LittleEndian>()?)?;
+        let mut pib = [0u8; size_of::<PlatformInfoBlob>()];
+        data.read_exact(&mut pib[..])?;
+        let platform_info_blob = if pib.iter().all(|b| *b == 0) {
+            None
+        } else {
+            Some(pib)
+        };
+        Ok(Self { status, pse_status, platform_info_blob })
+    }
+}
+
+fn decode_status(status: u32) -> Result<IntelAttestationStatus> {
+    match status {
+        0 => Ok(IntelAttestationStatus::NotTrusted),
+        1 => Ok(IntelAttestationStatus::NotTrustedItsComplicated),
+        2 => Ok(IntelAttestationStatus::TrustedItsComplicated),
+        3 => Ok(IntelAttestationStatus::Trusted),
+        _ => Err(invalid("Unknown attestation status")),
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..(i + 2))?, 16).ok())
+        .collect()
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::INTEL_KEY_SCHEDULE;
+
+    #[test]
+    fn derives_kdk_from_little_endian_shared_secret() {
+        // The vector holds the shared secret in the order the KDK is derived
+        // from, while the crypto libraries return it big-endian
+        let mut shared_secret = INTEL_KEY_SCHEDULE.shared_secret;
+        shared_secret.reverse();
+        assert_eq!(derive_intel_kdk(&shared_secret), INTEL_KEY_SCHEDULE.kdk);
+    }
+
+    #[test]
+    fn rejects_signs_in_hex() {
+        assert_eq!(read_msg(&mut &b"0a1b\n"[..]).unwrap(), vec![0x0a, 0x1b]);
+        assert!(read_msg(&mut &b"+a1b\n"[..]).is_err());
+        assert!(read_msg(&mut &b"0a-1\n"[..]).is_err());
+    }
+
+    #[test]
+    fn converts_signatures() {
+        let mut signature = [0u8; 2 * COORD_LEN];
+        for (i, b) in signature.iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        // High bits set and leading zeros, which DER encodes differently
+        signature[COORD_LEN - 1] = 0x80;
+        signature[2 * COORD_LEN - 1] = 0x00;
+        signature[2 * COORD_LEN - 2] = 0x00;
+        let der = signature_from_intel(&signature);
+        assert_eq!(signature_to_intel(&der[..]), Some(signature));
+    }
+}
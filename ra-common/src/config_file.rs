@@ -0,0 +1,101 @@
+// Config files in JSON, TOML, or YAML, told apart by their extension. Every
+// format is read into the same serde model, so that a config type supports
+// all three without knowing about them. Any of them may be encrypted, see
+// `encrypted_config`.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use crate::encrypted_config::{is_encrypted_config, read_encrypted_config, EnvConfigKey};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    /// Needs the toml-config feature.
+    Toml,
+    /// Needs the yaml-config feature.
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// `.toml` is TOML, `.yaml` and `.yml` are YAML, and anything else is
+    /// JSON, as all configs were before TOML and YAML were supported.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_ref().map(|e| e.as_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "JSON",
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Yaml => "YAML",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigFileError {
+    IO(io::Error),
+    /// The file is not valid in its format, or does not fit the config
+    /// type, with the parser's message.
+    Syntax(ConfigFormat, String),
+    /// Support for the format was not built in.
+    FormatNotSupported(ConfigFormat),
+    /// An encrypted config cannot be decrypted, e.g. because its key is
+    /// missing or wrong, with the reason.
+    Encryption(String),
+}
+
+impl fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFileError::IO(e) => write!(f, "cannot be read: {}", e),
+            ConfigFileError::Syntax(format, message) =>
+                write!(f, "is not valid {}: {}", format.name(), message),
+            ConfigFileError::FormatNotSupported(ConfigFormat::Toml) =>
+                write!(f, "is TOML, which needs the toml-config feature"),
+            ConfigFileError::FormatNotSupported(ConfigFormat::Yaml) =>
+                write!(f, "is YAML, which needs the yaml-config feature"),
+            ConfigFileError::FormatNotSupported(format) =>
+                write!(f, "is {}, which is not supported", format.name()),
+            ConfigFileError::Encryption(reason) =>
+                write!(f, "cannot be decrypted: {}", reason),
+        }
+    }
+}
+
+impl std::convert::From<io::Error> for ConfigFileError {
+    fn from(e: io::Error) -> Self { Self::IO(e) }
+}
+
+/// Read the config at `path` in the format of its extension. A `.enc` file
+/// is decrypted with the key in the `RA_CONFIG_KEY` environment variable;
+/// use `read_encrypted_config` for other keys.
+pub fn read_config<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigFileError> {
+    if is_encrypted_config(path) {
+        return read_encrypted_config(path, &EnvConfigKey::default());
+    }
+    parse_config(&fs::read_to_string(path)?, ConfigFormat::from_path(path))
+}
+
+pub fn parse_config<T: DeserializeOwned>(text: &str, format: ConfigFormat)
+    -> Result<T, ConfigFileError> {
+        let syntax = |e: &dyn fmt::Display| ConfigFileError::Syntax(format, e.to_string());
+        match format {
+            ConfigFormat::Json => serde_json::from_str(text).map_err(|e| syntax(&e)),
+            #[cfg(feature = "toml-config")]
+            ConfigFormat::Toml => toml::from_str(text).map_err(|e| syntax(&e)),
+            #[cfg(feature = "yaml-config")]
+            ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|e| syntax(&e)),
+            #[allow(unreachable_patterns)]
+            _ => Err(ConfigFileError::FormatNotSupported(format)),
+        }
+    }
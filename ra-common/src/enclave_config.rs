@@ -0,0 +1,75 @@
+use std::env;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::config_file::{read_config, ConfigFileError};
+use crate::msg::WireMessage;
+
+/// Prefix of the variables read by `EnclaveConfig::from_env`.
+pub const ENCLAVE_CONFIG_ENV_PREFIX: &str = "RA_ENCLAVE_";
+
+#[derive(Debug)]
+pub enum EnvConfigError {
+    /// Name of a required variable that is not set.
+    Missing(String),
+    /// Name of a variable whose value could not be parsed or decoded.
+    Invalid(String),
+}
+
+/// Trust anchors and policy of an enclave, provisioned at runtime instead of
+/// being compiled in. The SP sends a new config over the secure channel after
+/// a successful attestation, and the enclave keeps it sealed on disk (see
+/// `ra_enclave::sealing`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnclaveConfig {
+    /// Must increase with every update. An enclave refuses a config whose
+    /// version is not newer than the one it currently uses.
+    pub version: u64,
+    /// PEM-encoded RSA public key the SP signs (g_b, g_a) with.
+    pub sp_vkey_pem: String,
+    /// Only accept MSG2s carrying an SP challenge nonce.
+    pub require_challenge_nonce: bool,
+}
+
+impl WireMessage for EnclaveConfig {}
+
+impl EnclaveConfig {
+    /// Read a config in JSON, TOML, or YAML, by the file's extension, e.g.
+    /// for the SP to provision or for `sealing::seal_config` tooling.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigFileError> {
+        read_config(path)
+    }
+
+    /// Assemble a config from `RA_ENCLAVE_VERSION`,
+    /// `RA_ENCLAVE_SP_VKEY_PEM_BASE64` (base64 of the PEM file), and the
+    /// optional `RA_ENCLAVE_REQUIRE_CHALLENGE_NONCE` (`true`/`false`/`1`/`0`),
+    /// e.g. in the container that provisions the enclave. The environment is
+    /// controlled by the host, so an enclave must not trust a config read
+    /// this way; have it delivered and sealed as usual instead.
+    pub fn from_env() -> Result<Self, EnvConfigError> {
+        Self::from_env_with_prefix(ENCLAVE_CONFIG_ENV_PREFIX)
+    }
+
+    pub fn from_env_with_prefix(prefix: &str) -> Result<Self, EnvConfigError> {
+        let var = |field: &str| env::var(format!("{}{}", prefix, field));
+        let name = |field: &str| format!("{}{}", prefix, field);
+
+        let version = var("VERSION").map_err(|_| EnvConfigError::Missing(name("VERSION")))?
+            .parse()
+            .map_err(|_| EnvConfigError::Invalid(name("VERSION")))?;
+        let sp_vkey_pem = var("SP_VKEY_PEM_BASE64")
+            .map_err(|_| EnvConfigError::Missing(name("SP_VKEY_PEM_BASE64")))?;
+        let sp_vkey_pem = base64::decode(&sp_vkey_pem).ok()
+            .and_then(|pem| String::from_utf8(pem).ok())
+            .ok_or_else(|| EnvConfigError::Invalid(name("SP_VKEY_PEM_BASE64")))?;
+        let require_challenge_nonce = match var("REQUIRE_CHALLENGE_NONCE") {
+            Err(_) => false,
+            Ok(value) => match value.as_str() {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => return Err(EnvConfigError::Invalid(name("REQUIRE_CHALLENGE_NONCE"))),
+            },
+        };
+
+        Ok(Self { version, sp_vkey_pem, require_challenge_nonce })
+    }
+}
@@ -0,0 +1,138 @@
+// Config files encrypted at rest, so that SPIDs, subscription keys, and the
+// paths of private keys are not stored in cleartext. A file is
+//   magic || wrapped key length (u16) || wrapped key || AES-GCM(config)
+// in little-endian order, with everything before the ciphertext authenticated
+// as additional data. The config inside is in the format of the file name
+// without its `.enc` extension, e.g. settings.json.enc holds JSON.
+//
+// The wrapped key is empty if the config is encrypted under the key of the
+// `ConfigKeySource` itself. Otherwise it is a data key wrapped by the source,
+// e.g. by a KMS, which the source unwraps when the config is read.
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::Path;
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use serde::de::DeserializeOwned;
+use sgx_crypto::aead::{self, AeadKey};
+use sgx_crypto::key_wrap::KeyWrap;
+use sgx_crypto::random::RandomState;
+use crate::config_file::{parse_config, ConfigFileError, ConfigFormat};
+
+const MAGIC: [u8; 4] = *b"RACE";
+/// Extension of encrypted configs, after that of the format inside.
+pub const ENCRYPTED_CONFIG_EXTENSION: &str = "enc";
+/// Variable `EnvConfigKey::default` reads the key from.
+pub const CONFIG_KEY_ENV_VAR: &str = "RA_CONFIG_KEY";
+
+/// Where the key of an encrypted config comes from, e.g. an environment
+/// variable or a KMS.
+pub trait ConfigKeySource {
+    /// The key of a config whose file carries `wrapped_key`, which is empty
+    /// if the config is encrypted under the source's own key. Fails with a
+    /// message for the user, e.g. that the KMS is unreachable.
+    fn config_key(&self, wrapped_key: &[u8]) -> Result<AeadKey, String>;
+}
+
+/// A key in an environment variable, as 32 hex digits. A wrapped key is
+/// unwrapped with it as the KEK of an RFC 3394 key wrap.
+pub struct EnvConfigKey {
+    var: String,
+}
+
+impl EnvConfigKey {
+    pub fn new(var: &str) -> Self {
+        Self { var: var.to_owned() }
+    }
+}
+
+impl Default for EnvConfigKey {
+    fn default() -> Self {
+        Self::new(CONFIG_KEY_ENV_VAR)
+    }
+}
+
+impl ConfigKeySource for EnvConfigKey {
+    fn config_key(&self, wrapped_key: &[u8]) -> Result<AeadKey, String> {
+        let value = env::var(&self.var)
+            .map_err(|_| format!("{} is not set", self.var))?;
+        let key = key_from_hex(value.trim())
+            .ok_or_else(|| format!("{} is not 32 hexadecimal digits", self.var))?;
+        if wrapped_key.is_empty() {
+            return Ok(key);
+        }
+        let data_key = KeyWrap::new(&key).unwrap_key(wrapped_key)
+            .map_err(|_| format!("the data key cannot be unwrapped with {}", self.var))?;
+        data_key.as_slice().try_into()
+            .map_err(|_| "the data key is not 16 bytes".to_owned())
+    }
+}
+
+/// Whether `path` is an encrypted config by its extension.
+pub fn is_encrypted_config(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some(ENCRYPTED_CONFIG_EXTENSION)
+}
+
+/// Encrypt `config` under `key`. `wrapped_key` is stored with it for the
+/// `ConfigKeySource` to get `key` back, and is empty if the source has `key`
+/// itself.
+pub fn encrypt_config(config: &[u8], key: &AeadKey, wrapped_key: &[u8])
+    -> Result<Vec<u8>, ConfigFileError> {
+        if wrapped_key.len() > u16::max_value() as usize {
+            return Err(ConfigFileError::Encryption("the wrapped key is too long".to_owned()));
+        }
+        let mut header = Vec::with_capacity(4 + 2 + wrapped_key.len());
+        header.extend_from_slice(&MAGIC[..]);
+        // Can unwrap since writing to a Vec does not fail
+        header.write_u16::<LittleEndian>(wrapped_key.len() as u16).unwrap();
+        header.extend_from_slice(wrapped_key);
+        let ciphertext = aead::seal(key, &header[..], config, &RandomState::new())
+            .map_err(|e| ConfigFileError::Encryption(format!("{:?}", e)))?;
+        header.extend_from_slice(&ciphertext[..]);
+        Ok(header)
+    }
+
+/// Reverse `encrypt_config`, with the key from `source`.
+pub fn decrypt_config(encrypted: &[u8], source: &dyn ConfigKeySource)
+    -> Result<Vec<u8>, ConfigFileError> {
+        let malformed = || ConfigFileError::Encryption("not an encrypted config".to_owned());
+        let mut reader = encrypted;
+        if reader.len() < MAGIC.len() || reader[..MAGIC.len()] != MAGIC[..] {
+            return Err(malformed());
+        }
+        reader = &reader[MAGIC.len()..];
+        let wrapped_len = reader.read_u16::<LittleEndian>().map_err(|_| malformed())? as usize;
+        if reader.len() < wrapped_len {
+            return Err(malformed());
+        }
+        let header_len = MAGIC.len() + 2 + wrapped_len;
+        let (header, ciphertext) = encrypted.split_at(header_len);
+
+        let key = source.config_key(&reader[..wrapped_len])
+            .map_err(ConfigFileError::Encryption)?;
+        aead::open(&key, header, ciphertext)
+            .map_err(|_| ConfigFileError::Encryption(
+                    "the key is wrong or the file was modified".to_owned()))
+    }
+
+/// Read the encrypted config at `path`, in the format of its name without
+/// `.enc`.
+pub fn read_encrypted_config<T: DeserializeOwned>(path: &Path, source: &dyn ConfigKeySource)
+    -> Result<T, ConfigFileError> {
+        let config = decrypt_config(&fs::read(path)?[..], source)?;
+        let format = ConfigFormat::from_path(Path::new(path.file_stem().unwrap_or_default()));
+        let text = String::from_utf8(config)
+            .map_err(|_| ConfigFileError::Syntax(format, "not UTF-8".to_owned()))?;
+        parse_config(&text, format)
+    }
+
+fn key_from_hex(hex: &str) -> Option<AeadKey> {
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut key = [0u8; 16];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
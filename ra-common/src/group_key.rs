@@ -0,0 +1,55 @@
+// Keys shared by several attested enclaves, e.g. to encrypt state that any
+// member of a cluster must be able to read. The SP picks the group key and
+// sends it to each member wrapped (RFC 3394) under a key-encryption key
+// derived from that member's master key, and picks a new one whenever the
+// membership changes.
+use serde::{Serialize, Deserialize};
+use sgx_crypto::cmac::{Cmac, MacTag};
+use sgx_crypto::key_wrap::{KeyWrap, KeyWrapError, Kek};
+use crate::msg::WireMessage;
+
+pub type GroupKey = [u8; 16];
+
+const EPOCH_LEN: usize = 8;
+
+/// Derive the key-encryption key for group keys from a session's master
+/// key, in the manner of `derive_secret_keys`.
+pub fn derive_group_kek(master_key: &MacTag) -> Kek {
+    let gkek_data = [0x01, 'G' as u8, 'K' as u8, 'E' as u8, 'K' as u8, 0x00, 0x80, 0x00];
+    Cmac::new(master_key).sign(&gkek_data)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GroupKeyUpdate {
+    /// Incremented on every change of the group key. A member must ignore
+    /// updates whose epoch is not above the one it holds, so that an old key
+    /// cannot be replayed.
+    pub epoch: u64,
+    /// Wrap of key || epoch (big-endian), so the epoch cannot be altered.
+    pub wrapped_key: Vec<u8>,
+}
+
+impl GroupKeyUpdate {
+    pub fn wrap(kek: &Kek, epoch: u64, key: &GroupKey) -> Self {
+        let mut key_data = [0u8; 16 + EPOCH_LEN];
+        key_data[..16].copy_from_slice(&key[..]);
+        key_data[16..].copy_from_slice(&epoch.to_be_bytes());
+        // Can unwrap since the key data is 24 bytes long
+        let wrapped_key = KeyWrap::new(kek).wrap_key(&key_data[..]).unwrap();
+        Self { epoch, wrapped_key }
+    }
+
+    /// Fails if the update was not wrapped under `kek` or was altered.
+    pub fn unwrap(&self, kek: &Kek) -> Result<GroupKey, KeyWrapError> {
+        let key_data = KeyWrap::new(kek).unwrap_key(&self.wrapped_key[..])?;
+        if key_data.len() != 16 + EPOCH_LEN ||
+            key_data[16..] != self.epoch.to_be_bytes()[..] {
+                return Err(KeyWrapError::IntegrityError);
+            }
+        let mut key = [0u8; 16];
+        key.copy_from_slice(&key_data[..16]);
+        Ok(key)
+    }
+}
+
+impl WireMessage for GroupKeyUpdate {}
@@ -0,0 +1,56 @@
+// Continuous attestation. For long-lived sessions that must not outlive the
+// enclave's trustworthiness, the enclave sends the SP a fresh quote over the
+// session's channel every so often, and the SP revokes the session when a
+// heartbeat is late or IAS no longer vouches for the platform, e.g. after a
+// TCB recovery turned its status into GROUP_OUT_OF_DATE.
+//
+// The quote's REPORTDATA is
+//   SHA-256(heartbeat key || sequence number (u64 LE)) || zeros
+// with the heartbeat key derived from the session's MK, so a heartbeat proves
+// that the enclave holding the session keys is still running on a platform
+// IAS trusts, and cannot be replayed in another session or out of order.
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use sgx_crypto::cmac::MacTag;
+use sgx_crypto::digest::sha256;
+use crate::msg::WireMessage;
+use crate::session_keys::{KeyPurpose, SessionKeys};
+
+/// A reasonable default interval between heartbeats. Each heartbeat costs an
+/// IAS request, so intervals much shorter than this are seldom worthwhile.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Heartbeat {
+    /// Starts at 1 and increases with every heartbeat of the session.
+    pub seq: u64,
+    pub quote: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HeartbeatAck {
+    pub seq: u64,
+    /// False if the SP revoked the session; the enclave should then stop
+    /// using its keys.
+    pub is_enclave_trusted: bool,
+    /// The IAS quote status of the heartbeat, if IAS was asked.
+    pub quote_status: Option<String>,
+}
+
+impl WireMessage for Heartbeat {}
+impl WireMessage for HeartbeatAck {}
+
+/// Key that binds heartbeats to a session. Both ends derive it.
+pub fn heartbeat_key(keys: &SessionKeys) -> MacTag {
+    keys.derive(KeyPurpose::Heartbeat)
+}
+
+/// REPORTDATA of the quote of heartbeat `seq`.
+pub fn heartbeat_report_data(heartbeat_key: &MacTag, seq: u64) -> [u8; 64] {
+    let mut data = Vec::with_capacity(heartbeat_key.len() + 8);
+    data.extend_from_slice(&heartbeat_key[..]);
+    data.extend_from_slice(&seq.to_le_bytes());
+    let mut report_data = [0u8; 64];
+    report_data[..32].copy_from_slice(&sha256(&data[..])[..]);
+    report_data
+}
@@ -1,7 +1,31 @@
 pub mod msg;
 pub mod tcp;
+pub mod memory;
+pub mod listener;
+pub mod quote;
+pub mod enclave_config;
+pub mod config_file;
+pub mod encrypted_config;
+pub mod tls_psk;
+pub mod group_key;
+pub mod mq;
+pub mod secrets;
+pub mod session_keys;
+pub mod heartbeat;
+pub mod nonblocking;
+pub mod test_vectors;
+pub mod blob;
+#[cfg(feature = "intel-compat")]
+pub mod compat;
+#[cfg(feature = "protobuf")]
+pub mod proto;
 
 use sgx_crypto::cmac::{Cmac, MacTag};
+use sgx_crypto::key_exchange::SharedSecret;
+
+/// KDF ID of `derive_secret_keys`, the only KDF supported by `sgx_ra_init`.
+pub const DEFAULT_KDF_ID: u16 = 0x0001;
+
 /// Derive SMK, SK, MK, and VK according to 
 /// https://software.intel.com/en-us/articles/code-sample-intel-software-guard-extensions-remote-attestation-end-to-end-example
 pub fn derive_secret_keys(kdk: &Cmac) -> (MacTag, MacTag, MacTag, MacTag) {
@@ -19,3 +43,14 @@ pub fn derive_secret_keys(kdk: &Cmac) -> (MacTag, MacTag, MacTag, MacTag) {
 
     (smk, sk, mk, vk)
 }
+
+/// A custom key derivation, the counterpart of the callback passed to
+/// `sgx_ra_init_ex`. The SP announces `kdf_id` in MSG2 and both sides derive
+/// (SMK, SK, MK, VK) with `derive_secret_keys` from the ECDH shared secret,
+/// as the SDK callback does. The shared secret is big-endian, while the SDK
+/// passes it little-endian, so reverse it to match a KDF written for the SDK.
+pub trait KeyDerivation: Send + Sync {
+    fn kdf_id(&self) -> u16;
+
+    fn derive_secret_keys(&self, shared_secret: &SharedSecret) -> (MacTag, MacTag, MacTag, MacTag);
+}
@@ -0,0 +1,176 @@
+use std::io::{Result, Read, Write, ErrorKind};
+use std::net::{TcpListener, TcpStream, SocketAddr};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::thread::sleep;
+use crate::tcp::bind_or_inherit;
+
+const ACCEPT_SLEEP_TIME_MILLIS: u64 = 10;
+
+struct Shared {
+    shutdown: AtomicBool,
+    in_flight: Mutex<usize>,
+    drained: Condvar,
+}
+
+/// Used to ask a `GracefulListener` to stop accepting, possibly from another
+/// thread.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shared: Arc<Shared>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shared.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+/// A TCP listener that can be shut down without dropping in-flight
+/// connections. Every accepted `Connection` counts as in flight until it is
+/// dropped; `close` waits for all of them up to a deadline.
+pub struct GracefulListener {
+    listener: TcpListener,
+    shared: Arc<Shared>,
+}
+
+impl GracefulListener {
+    /// Under systemd socket activation, the inherited socket is used instead
+    /// of binding to `bind_addr:port`, see `tcp::bind_or_inherit`.
+    pub fn bind(bind_addr: &str, port: u16) -> Result<Self> {
+        Self::from_listener(bind_or_inherit(bind_addr, port)?)
+    }
+
+    pub fn from_listener(listener: TcpListener) -> Result<Self> {
+        // Poll so that a shutdown request is noticed without a new connection.
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            shared: Arc::new(Shared {
+                shutdown: AtomicBool::new(false),
+                in_flight: Mutex::new(0),
+                drained: Condvar::new(),
+            }),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { shared: self.shared.clone() }
+    }
+
+    /// Block until a new connection arrives. Returns `None` once shutdown has
+    /// been requested.
+    pub fn accept(&self) -> Result<Option<Connection>> {
+        loop {
+            if self.shared.shutdown.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+            if let Some(connection) = self.try_accept()? {
+                return Ok(Some(connection));
+            }
+            sleep(Duration::from_millis(ACCEPT_SLEEP_TIME_MILLIS));
+        }
+    }
+
+    /// Accept the connections that are already waiting, up to `max`, without
+    /// blocking. None are accepted once shutdown has been requested.
+    pub fn accept_pending(&self, max: usize) -> Result<Vec<Connection>> {
+        let mut connections = Vec::new();
+        while connections.len() < max && !self.shared.shutdown.load(Ordering::SeqCst) {
+            match self.try_accept()? {
+                Some(connection) => connections.push(connection),
+                None => break,
+            }
+        }
+        Ok(connections)
+    }
+
+    fn try_accept(&self) -> Result<Option<Connection>> {
+        match self.listener.accept() {
+            Ok((stream, peer_addr)) => {
+                stream.set_nonblocking(false)?;
+                *self.shared.in_flight.lock().unwrap() += 1;
+                Ok(Some(Connection {
+                    stream,
+                    peer_addr,
+                    shared: self.shared.clone(),
+                }))
+            },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stop accepting and wait for in-flight connections to be dropped, but no
+    /// longer than `deadline`. Returns the number of connections still open
+    /// when the deadline passed.
+    pub fn close(self, deadline: Duration) -> usize {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        drop(self.listener);
+
+        let start = Instant::now();
+        let mut in_flight = self.shared.in_flight.lock().unwrap();
+        while *in_flight > 0 {
+            let elapsed = start.elapsed();
+            if elapsed >= deadline {
+                break;
+            }
+            in_flight = self.shared.drained
+                .wait_timeout(in_flight, deadline - elapsed).unwrap().0;
+        }
+        *in_flight
+    }
+}
+
+/// A connection accepted by a `GracefulListener`. It is considered in flight
+/// until dropped.
+pub struct Connection {
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl Connection {
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut in_flight = self.shared.in_flight.lock().unwrap();
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            self.shared.drained.notify_all();
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// In-memory transport, e.g. to run both ends of an attestation in one process
+// for tests. A pair of `MemoryStream`s behaves like a connected socket pair:
+// reads block until the peer writes, and once one end is dropped the other
+// reads the remaining bytes, then end of stream, and fails to write.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use sgx_crypto::secure_channel::TransportTimeouts;
+
+struct Pipe {
+    buf: VecDeque<u8>,
+    closed: bool,
+}
+
+struct Shared {
+    pipe: Mutex<Pipe>,
+    cond: Condvar,
+}
+
+impl Shared {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            pipe: Mutex::new(Pipe { buf: VecDeque::new(), closed: false }),
+            cond: Condvar::new(),
+        })
+    }
+
+    fn close(&self) {
+        self.pipe.lock().unwrap().closed = true;
+        self.cond.notify_all();
+    }
+}
+
+pub struct MemoryStream {
+    incoming: Arc<Shared>,
+    outgoing: Arc<Shared>,
+    read_timeout: Option<Duration>,
+}
+
+impl MemoryStream {
+    /// Two streams connected to each other.
+    pub fn pair() -> (Self, Self) {
+        let a_to_b = Shared::new();
+        let b_to_a = Shared::new();
+        let a = Self {
+            incoming: b_to_a.clone(),
+            outgoing: a_to_b.clone(),
+            read_timeout: None,
+        };
+        let b = Self {
+            incoming: a_to_b,
+            outgoing: b_to_a,
+            read_timeout: None,
+        };
+        (a, b)
+    }
+
+    /// Like `TcpStream::set_read_timeout`: reads fail with `TimedOut` after
+    /// waiting `timeout` for the peer. `None` waits forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+}
+
+// Writes never block, so there is no write timeout to set
+impl TransportTimeouts for MemoryStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        MemoryStream::set_read_timeout(self, timeout);
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = self.read_timeout.map(|t| Instant::now() + t);
+        let mut pipe = self.incoming.pipe.lock().unwrap();
+        while pipe.buf.is_empty() && !pipe.closed {
+            pipe = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut,
+                                                  "Peer did not write in time"));
+                    }
+                    self.incoming.cond.wait_timeout(pipe, deadline - now).unwrap().0
+                },
+                None => self.incoming.cond.wait(pipe).unwrap(),
+            };
+        }
+        let n = buf.len().min(pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.outgoing.pipe.lock().unwrap();
+        if pipe.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Peer is gone"));
+        }
+        pipe.buf.extend(buf);
+        self.outgoing.cond.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.outgoing.close();
+        self.incoming.close();
+    }
+}
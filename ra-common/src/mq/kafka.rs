@@ -0,0 +1,71 @@
+// `MessageTransport` over Kafka topics. The SP consumes a request topic and
+// produces to a reply topic, clients the other way around; the correlation ID
+// is the record key. Every client consumes the reply topic in its own
+// consumer group and skips the replies meant for others.
+use std::collections::VecDeque;
+use std::io::{self, Error, ErrorKind};
+use std::time::{Duration, Instant};
+use ::kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
+use ::kafka::producer::{Producer, Record, RequiredAcks};
+use super::MessageTransport;
+
+pub struct KafkaTransport {
+    producer: Producer,
+    consumer: Consumer,
+    out_topic: String,
+    pending: VecDeque<(String, Vec<u8>)>,
+}
+
+impl KafkaTransport {
+    /// Consume `in_topic` as member of `group` and produce to `out_topic`.
+    /// Only records produced after the consumer joined are received.
+    pub fn new(hosts: Vec<String>, in_topic: &str, out_topic: &str, group: &str)
+        -> io::Result<Self> {
+            let producer = Producer::from_hosts(hosts.clone())
+                .with_required_acks(RequiredAcks::One)
+                .create()
+                .map_err(kafka_error)?;
+            let consumer = Consumer::from_hosts(hosts)
+                .with_topic(in_topic.to_owned())
+                .with_group(group.to_owned())
+                .with_fallback_offset(FetchOffset::Latest)
+                .with_offset_storage(GroupOffsetStorage::Kafka)
+                .create()
+                .map_err(kafka_error)?;
+            Ok(Self {
+                producer,
+                consumer,
+                out_topic: out_topic.to_owned(),
+                pending: VecDeque::new(),
+            })
+        }
+}
+
+impl MessageTransport for KafkaTransport {
+    fn send(&mut self, correlation_id: &str, payload: &[u8]) -> io::Result<()> {
+        let record = Record::from_key_value(&self.out_topic, correlation_id.as_bytes(), payload);
+        self.producer.send(&record).map_err(kafka_error)
+    }
+
+    fn recv(&mut self, timeout: Duration) -> io::Result<Option<(String, Vec<u8>)>> {
+        let deadline = Instant::now() + timeout;
+        while self.pending.is_empty() && Instant::now() < deadline {
+            let message_sets = self.consumer.poll().map_err(kafka_error)?;
+            for message_set in message_sets.iter() {
+                for m in message_set.messages() {
+                    match String::from_utf8(m.key.to_vec()) {
+                        Ok(id) => self.pending.push_back((id, m.value.to_vec())),
+                        Err(_) => continue, // not one of ours
+                    }
+                }
+                self.consumer.consume_messageset(message_set).map_err(kafka_error)?;
+            }
+            self.consumer.commit_consumed().map_err(kafka_error)?;
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+fn kafka_error(e: ::kafka::error::Error) -> Error {
+    Error::new(ErrorKind::Other, format!("Kafka: {}", e))
+}
@@ -0,0 +1,204 @@
+// Attestation over message brokers, for enclave hosts that cannot accept
+// connections. Each party only publishes and consumes messages; the messages
+// of one attestation share a correlation ID, and a `QueueStream` turns them
+// back into the byte stream that the contexts expect. Every protocol message
+// is flushed on its own, so it travels as one broker message.
+#[cfg(feature = "mq-kafka")]
+pub mod kafka;
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write, Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sgx_crypto::random::RandomState;
+
+// Longest a stream holds the transport while waiting, so that streams of
+// other correlation IDs get their turn
+const POLL_SLICE_MILLIS: u64 = 100;
+// Finished attestations remembered, so that their late messages are not
+// taken for new ones
+const MAX_CLOSED: usize = 1024;
+
+/// A broker connection that both sends and receives messages tagged with a
+/// correlation ID. With AMQP, for example, the ID goes in the
+/// `correlation_id` property and the SP answers to the `reply_to` queue of
+/// the request it saw last for that ID.
+pub trait MessageTransport: Send {
+    fn send(&mut self, correlation_id: &str, payload: &[u8]) -> io::Result<()>;
+
+    /// The next message for any correlation ID, or None if none arrived
+    /// within `timeout`.
+    fn recv(&mut self, timeout: Duration) -> io::Result<Option<(String, Vec<u8>)>>;
+}
+
+struct Hub<T: MessageTransport> {
+    transport: T,
+    // Messages received for open streams and not read yet
+    inbox: HashMap<String, VecDeque<Vec<u8>>>,
+    // Correlation IDs seen for the first time, for `accept`
+    new_ids: VecDeque<String>,
+    closed: VecDeque<String>,
+    accepting: bool,
+}
+
+impl<T: MessageTransport> Hub<T> {
+    fn pump(&mut self, timeout: Duration) -> io::Result<()> {
+        let (id, payload) = match self.transport.recv(timeout)? {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        match self.inbox.get_mut(&id) {
+            Some(queue) => queue.push_back(payload),
+            // Messages of unknown attestations are dropped, unless this side
+            // serves attestations
+            None if self.accepting && !self.closed.contains(&id) => {
+                self.inbox.insert(id.clone(), vec![payload].into());
+                self.new_ids.push_back(id);
+            },
+            None => {},
+        }
+        Ok(())
+    }
+}
+
+/// Streams of many attestations over one `MessageTransport`.
+pub struct MessageQueue<T: MessageTransport> {
+    hub: Arc<Mutex<Hub<T>>>,
+    timeout: Duration,
+}
+
+impl<T: MessageTransport> MessageQueue<T> {
+    /// `timeout` bounds every wait for a message, in place of a read timeout.
+    pub fn new(transport: T, timeout: Duration) -> Self {
+        Self {
+            hub: Arc::new(Mutex::new(Hub {
+                transport,
+                inbox: HashMap::new(),
+                new_ids: VecDeque::new(),
+                closed: VecDeque::new(),
+                accepting: false,
+            })),
+            timeout,
+        }
+    }
+
+    /// Start an attestation under a new correlation ID, e.g. to pass as the
+    /// `sp_stream` of `ClientRaContext::do_attestation`.
+    pub fn connect(&self) -> QueueStream<T> {
+        let mut id = [0u8; 16];
+        RandomState::new().fill(&mut id);
+        let id: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+        self.hub.lock().unwrap().inbox.insert(id.clone(), VecDeque::new());
+        self.stream(id)
+    }
+
+    /// Wait for a message with a correlation ID not seen before and return
+    /// its stream, e.g. to pass as the `client_stream` of
+    /// `SpRaContext::do_attestation`. Fails with `TimedOut` if none arrives.
+    pub fn accept(&self) -> io::Result<QueueStream<T>> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let mut hub = self.hub.lock().unwrap();
+            hub.accepting = true;
+            if let Some(id) = hub.new_ids.pop_front() {
+                drop(hub);
+                return Ok(self.stream(id));
+            }
+            hub.pump(time_left(deadline)?)?;
+        }
+    }
+
+    fn stream(&self, correlation_id: String) -> QueueStream<T> {
+        QueueStream {
+            hub: self.hub.clone(),
+            correlation_id,
+            timeout: self.timeout,
+            out: Vec::new(),
+            buf: Vec::new(),
+            cursor: 0,
+        }
+    }
+}
+
+/// The messages of one attestation as a byte stream. Written data is sent
+/// as one message on every flush.
+pub struct QueueStream<T: MessageTransport> {
+    hub: Arc<Mutex<Hub<T>>>,
+    correlation_id: String,
+    timeout: Duration,
+    out: Vec<u8>,
+    buf: Vec<u8>,
+    cursor: usize,
+}
+
+impl<T: MessageTransport> QueueStream<T> {
+    pub fn correlation_id(&self) -> &str {
+        &self.correlation_id
+    }
+
+    fn next_message(&mut self) -> io::Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let mut hub = self.hub.lock().unwrap();
+            let message = hub.inbox.get_mut(&self.correlation_id)
+                .and_then(|queue| queue.pop_front());
+            if let Some(message) = message {
+                self.buf = message;
+                self.cursor = 0;
+                return Ok(());
+            }
+            hub.pump(time_left(deadline)?)?;
+        }
+    }
+}
+
+impl<T: MessageTransport> Read for QueueStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.cursor == self.buf.len() {
+            self.next_message()?;
+        }
+        let n = usize::min(buf.len(), self.buf.len() - self.cursor);
+        buf[..n].copy_from_slice(&self.buf[self.cursor..(self.cursor + n)]);
+        self.cursor += n;
+        Ok(n)
+    }
+}
+
+impl<T: MessageTransport> Write for QueueStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        self.hub.lock().unwrap().transport.send(&self.correlation_id, &self.out[..])?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+impl<T: MessageTransport> Drop for QueueStream<T> {
+    fn drop(&mut self) {
+        let _r = self.flush();
+        let mut hub = self.hub.lock().unwrap();
+        hub.inbox.remove(&self.correlation_id);
+        if hub.closed.len() == MAX_CLOSED {
+            hub.closed.pop_front();
+        }
+        hub.closed.push_back(self.correlation_id.clone());
+    }
+}
+
+fn time_left(deadline: Instant) -> io::Result<Duration> {
+    let now = Instant::now();
+    if now >= deadline {
+        return Err(Error::new(ErrorKind::TimedOut, "No message before the timeout"));
+    }
+    Ok(Duration::min(deadline - now, Duration::from_millis(POLL_SLICE_MILLIS)))
+}
@@ -1,6 +1,7 @@
-use std::io::Write;
+use std::io::{Read, Write};
 use std::mem::size_of;
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 use serde_big_array::big_array;
 use byteorder::{WriteBytesExt, LittleEndian};
 use sgx_crypto::signature::Signature;
@@ -11,15 +12,108 @@ pub type Gid = [u8; 4];
 pub type Spid = [u8; 16];
 pub type PsSecPropDesc = [u8; 256];
 pub type Quote = [u8; 1116]; // 436 + quote.signature_len for version 2
+pub type Nonce = [u8; 16];
 
 big_array! { 
     BigArray; 
     +size_of::<DHKEPublicKey>(), size_of::<Quote>(),
 }
 
+// Every message on the wire is preceded by one of these tags, so that a peer
+// can abort the protocol in place of any message.
+const FRAME_MSG: u8 = 0;
+const FRAME_ABORT: u8 = 1;
+
+/// Largest encoded message accepted from a peer, so that a malformed length
+/// prefix cannot make the reader allocate without bound.
+pub const MAX_MESSAGE_LEN: u64 = 16 << 20;
+
+#[derive(Debug)]
+pub enum WireError {
+    Serialization(bincode::Error),
+    /// The peer sent an abort message instead of the expected message.
+    Aborted(RaAbort),
+}
+
+impl std::convert::From<bincode::Error> for WireError {
+    fn from(e: bincode::Error) -> Self { Self::Serialization(e) }
+}
+
+/// A protocol message with a symmetric encoding over any byte stream, so that
+/// every party reads exactly what its peer wrote.
+pub trait WireMessage: Serialize + DeserializeOwned {
+    /// Tag of the frames of this message. Only `RaAbort` has one of its own.
+    const FRAME: u8 = FRAME_MSG;
+
+    fn write_to<W: Write>(&self, mut writer: W) -> bincode::Result<()> {
+        bincode::serialize_into(&mut writer, &Self::FRAME)?;
+        bincode::serialize_into(&mut writer, self)?;
+        // Buffered streams, e.g. a SecureChannel, must not hold it back
+        Ok(writer.flush()?)
+    }
+
+    fn read_from<R: Read>(mut reader: R) -> Result<Self, WireError> {
+        let frame: u8 = bincode::deserialize_from(&mut reader)?;
+        let mut config = bincode::config();
+        config.limit(MAX_MESSAGE_LEN);
+        match frame {
+            f if f == Self::FRAME => Ok(config.deserialize_from(reader)?),
+            FRAME_ABORT => Err(WireError::Aborted(config.deserialize_from(reader)?)),
+            _ => Err(WireError::Serialization(Box::new(
+                        bincode::ErrorKind::Custom("Unknown frame type".to_owned())))),
+        }
+    }
+}
+
+/// Coarse reason for aborting an attestation, sent to the peer so that it can
+/// log and react to the failure instead of seeing a dropped connection.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum AbortReason {
+    /// A message failed its MAC or consistency checks.
+    IntegrityError = 1,
+    /// The peer asked for something this party does not serve, e.g. an
+    /// unknown tenant.
+    Unsupported = 2,
+    /// IAS could not be reached or refused the request.
+    IasUnavailable = 3,
+    /// The quote or the enclave identity was rejected.
+    QuoteRejected = 4,
+    /// Any other local failure.
+    Internal = 5,
+    /// The sender is shutting down; a new attempt may succeed.
+    ShuttingDown = 6,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RaAbort {
+    pub reason: AbortReason,
+    /// CMAC of the reason under SMK, when the sender already derived it.
+    pub mac: Option<MacTag>,
+}
+
+impl RaAbort {
+    pub fn new(reason: AbortReason, smk: Option<&Cmac>) -> Self {
+        Self {
+            reason,
+            mac: smk.map(|smk| smk.sign(&[reason as u8])),
+        }
+    }
+
+    /// Fails if the abort carries no MAC or the MAC is wrong.
+    pub fn verify_mac(&self, smk: &Cmac) -> Result<(), MacError> {
+        match self.mac.as_ref() {
+            Some(mac) => smk.verify(&[self.reason as u8], mac),
+            None => Err(MacError),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RaMsg0 {
     pub exgid: u32,
+    /// Lets an SP serving several customers pick the SPID and IAS
+    /// credentials for this connection. `None` selects the SP's default.
+    pub tenant: Option<String>,
 }
 
 
@@ -36,9 +130,14 @@ pub struct RaMsg2 {
     pub g_b: DHKEPublicKey,
     pub spid: Spid,
     pub quote_type: u16, /* unlinkable Quote(0) or linkable Quote(1) */
+    /// KDF both sides use to derive the session keys, `DEFAULT_KDF_ID` unless
+    /// the SP was set up with a custom `KeyDerivation`.
+    pub kdf_id: u16,
     pub sign_gb_ga: Signature, 
     pub mac: MacTag, 
     pub sig_rl: Option<Vec<u8>>,
+    /// SP challenge that the enclave must hash into REPORTDATA.
+    pub nonce: Option<Nonce>,
 }
 
 impl RaMsg2 {
@@ -46,15 +145,19 @@ impl RaMsg2 {
                g_b: DHKEPublicKey, 
                spid: Spid, 
                quote_type: u16,
+               kdf_id: u16,
                sign_gb_ga: Signature, 
-               sig_rl: Option<Vec<u8>>) -> Self {
+               sig_rl: Option<Vec<u8>>,
+               nonce: Option<Nonce>) -> Self {
         let mut msg2 = Self {
             g_b,
             spid,
             quote_type,
+            kdf_id,
             sign_gb_ga,
             mac: [0u8; size_of::<MacTag>()],
             sig_rl,
+            nonce,
         };
         let a = msg2.get_a();
         msg2.mac = smk.sign(&a[..]);
@@ -66,12 +169,21 @@ impl RaMsg2 {
         smk.verify(&a[..], &self.mac)
     }
 
+    // Every parameter the SP picks for the session must be covered here, so
+    // that a man-in-the-middle cannot swap it for a weaker one the enclave
+    // also accepts. The protocol has no version or cipher negotiation; the
+    // KDF is its only choice. If a negotiation is added, the lists offered by
+    // each side must be covered as well, not only the outcome.
     fn get_a(&self) -> Vec<u8> {
         let mut a = Vec::new();
         a.write_all(&self.g_b[..]).unwrap();
         a.write_all(&self.spid[..]).unwrap();
         a.write_u16::<LittleEndian>(self.quote_type).unwrap();
+        a.write_u16::<LittleEndian>(self.kdf_id).unwrap();
         a.write_all(&self.sign_gb_ga[..]).unwrap();
+        if let Some(nonce) = self.nonce.as_ref() {
+            a.write_all(&nonce[..]).unwrap();
+        }
         a
     }
 }
@@ -131,3 +243,15 @@ pub struct RaMsg4 {
     pub is_pse_manifest_trusted: Option<bool>,
     pub pib: Option<String>,
 }
+
+impl WireMessage for RaMsg0 {}
+impl WireMessage for RaMsg1 {}
+impl WireMessage for RaMsg2 {}
+impl WireMessage for RaMsg3 {}
+impl WireMessage for RaMsg4 {}
+
+/// Sent in place of any message, which then fails to read with
+/// `WireError::Aborted`. Reading an `RaAbort` itself returns it.
+impl WireMessage for RaAbort {
+    const FRAME: u8 = FRAME_ABORT;
+}
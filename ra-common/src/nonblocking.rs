@@ -0,0 +1,141 @@
+// Building blocks to drive the attestation handshakes from an event loop,
+// e.g. mio or epoll, on non-blocking streams. A handshake is advanced
+// whenever its stream is ready; it reads and writes what it can without
+// blocking, keeps partial messages in buffers, and reports what it waits for.
+use std::io::{self, ErrorKind, Read, Write};
+use crate::msg::{WireError, WireMessage};
+
+const READ_CHUNK_LEN: usize = 0x1000;
+
+/// What a handshake waits for after a call to `advance`.
+#[derive(Debug)]
+pub enum HandshakeStatus<T> {
+    /// Call `advance` again once the stream is readable.
+    NeedsRead,
+    /// Call `advance` again once the stream is writable.
+    NeedsWrite,
+    /// Waiting on something other than the stream, e.g. IAS. The waker
+    /// passed to `advance` is woken when it is done.
+    Pending,
+    /// The handshake is over.
+    Done(T),
+}
+
+impl<T> HandshakeStatus<T> {
+    pub fn is_done(&self) -> bool {
+        match self {
+            HandshakeStatus::Done(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Bytes read from a non-blocking stream but not yet decoded, and bytes
+/// encoded but not yet written.
+#[derive(Default)]
+pub struct NonBlockingIo {
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    written: usize,
+}
+
+impl NonBlockingIo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `msg` to be written by `flush`.
+    pub fn queue_msg<M: WireMessage>(&mut self, msg: &M) -> bincode::Result<()> {
+        msg.write_to(&mut self.outgoing)
+    }
+
+    /// Queue raw bytes, e.g. the parts of the client-enclave exchange that
+    /// are not `WireMessage`s.
+    pub fn queue_bytes(&mut self, bytes: &[u8]) {
+        self.outgoing.extend_from_slice(bytes);
+    }
+
+    /// Whether queued bytes are still to be written.
+    pub fn has_pending_writes(&self) -> bool {
+        self.written < self.outgoing.len()
+    }
+
+    /// Write what is queued. Returns false if the stream would block before
+    /// everything was written.
+    pub fn flush(&mut self, w: &mut impl Write) -> io::Result<bool> {
+        while self.written < self.outgoing.len() {
+            match w.write(&self.outgoing[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+        self.outgoing.clear();
+        self.written = 0;
+        match w.flush() {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Decode the next message, reading what the stream has. None if the
+    /// stream would block before the message is complete. Bytes past the
+    /// end of the message stay buffered for the next call.
+    pub fn read_msg<M: WireMessage>(&mut self, r: &mut impl Read)
+        -> Result<Option<M>, WireError> {
+            loop {
+                let mut rest = &self.incoming[..];
+                let result = M::read_from(&mut rest);
+                match result {
+                    Err(WireError::Serialization(ref e)) if is_eof(e) => {},
+                    _ => {
+                        let consumed = self.incoming.len() - rest.len();
+                        self.incoming.drain(..consumed);
+                        return result.map(Some);
+                    },
+                }
+                if !self.fill(r).map_err(|e| WireError::Serialization(Box::new(
+                            bincode::ErrorKind::Io(e))))? {
+                    return Ok(None);
+                }
+            }
+        }
+
+    /// Take the next `len` bytes, reading what the stream has. None if the
+    /// stream would block before there are `len` bytes.
+    pub fn read_exact(&mut self, r: &mut impl Read, len: usize) -> io::Result<Option<Vec<u8>>> {
+        while self.incoming.len() < len {
+            if !self.fill(r)? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.incoming.drain(..len).collect()))
+    }
+
+    // Read one chunk. Returns false if the stream would block.
+    fn fill(&mut self, r: &mut impl Read) -> io::Result<bool> {
+        let mut chunk = [0u8; READ_CHUNK_LEN];
+        loop {
+            match r.read(&mut chunk[..]) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    self.incoming.extend_from_slice(&chunk[..n]);
+                    return Ok(true);
+                },
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn is_eof(e: &bincode::Error) -> bool {
+    match e.as_ref() {
+        bincode::ErrorKind::Io(e) => e.kind() == ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
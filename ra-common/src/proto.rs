@@ -0,0 +1,312 @@
+// Protobuf encoding of the protocol messages, following proto/ra.proto, so
+// that peers written in other languages can take part in the attestation.
+// Each message is sent as a varint length followed by an `Envelope`, which is
+// what protobuf's writeDelimitedTo/parseDelimitedFrom produce and expect.
+//
+// Only the framing differs from `WireMessage`: MACs and signatures cover the
+// same bytes, so a message can be verified whichever codec carried it.
+use std::io::{Read, Write};
+use std::convert::{TryFrom, TryInto};
+use std::mem::size_of;
+use prost::Message;
+use sgx_crypto::cmac::MacTag;
+use sgx_crypto::key_exchange::DHKEPublicKey;
+use crate::msg::*;
+
+pub mod pb {
+    include!(concat!(env!("OUT_DIR"), "/ra.rs"));
+}
+
+use pb::envelope::Body;
+
+#[derive(Debug)]
+pub enum ProtoError {
+    IO(std::io::Error),
+    Decode(prost::DecodeError),
+    /// A field is missing or does not fit the corresponding Rust type.
+    Invalid(&'static str),
+    /// The envelope holds another message than the one expected.
+    UnexpectedMessage,
+    /// The peer sent an abort message instead of the expected message.
+    Aborted(RaAbort),
+}
+
+impl std::convert::From<std::io::Error> for ProtoError {
+    fn from(e: std::io::Error) -> Self { Self::IO(e) }
+}
+
+impl std::convert::From<prost::DecodeError> for ProtoError {
+    fn from(e: prost::DecodeError) -> Self { Self::Decode(e) }
+}
+
+/// A protocol message that can travel in an `Envelope`.
+pub trait ProtoMessage: Sized {
+    fn to_body(&self) -> Body;
+
+    fn from_body(body: Body) -> Result<Self, ProtoError>;
+
+    fn write_proto<W: Write>(&self, writer: W) -> Result<(), ProtoError> {
+        write_envelope(writer, self.to_body())
+    }
+
+    fn read_proto<R: Read>(reader: R) -> Result<Self, ProtoError> {
+        match read_envelope(reader)?.body {
+            Some(Body::Abort(abort)) => Err(ProtoError::Aborted(abort.try_into()?)),
+            Some(body) => Self::from_body(body),
+            None => Err(ProtoError::Invalid("Empty envelope")),
+        }
+    }
+}
+
+/// Abort the protocol in place of the next message.
+pub fn write_abort<W: Write>(writer: W, abort: &RaAbort) -> Result<(), ProtoError> {
+    write_envelope(writer, Body::Abort(abort.into()))
+}
+
+fn write_envelope<W: Write>(mut writer: W, body: Body) -> Result<(), ProtoError> {
+    let envelope = pb::Envelope { body: Some(body) };
+    let mut buf = Vec::with_capacity(envelope.encoded_len() + 10);
+    envelope.encode_length_delimited(&mut buf).unwrap();
+    writer.write_all(&buf[..])?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_envelope<R: Read>(mut reader: R) -> Result<pb::Envelope, ProtoError> {
+    let mut len = 0usize;
+    let mut shift = 0;
+    loop {
+        let mut b = [0u8; 1];
+        reader.read_exact(&mut b)?;
+        len |= ((b[0] & 0x7f) as usize) << shift;
+        if b[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 28 {
+            return Err(ProtoError::Invalid("Length prefix too long"));
+        }
+    }
+    if len as u64 > MAX_MESSAGE_LEN {
+        return Err(ProtoError::Invalid("Message too long"));
+    }
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf[..])?;
+    Ok(pb::Envelope::decode(&buf[..])?)
+}
+
+fn copy_into(dst: &mut [u8], src: &[u8], field: &'static str) -> Result<(), ProtoError> {
+    if dst.len() != src.len() {
+        return Err(ProtoError::Invalid(field));
+    }
+    dst.copy_from_slice(src);
+    Ok(())
+}
+
+fn to_public_key(src: &[u8], field: &'static str) -> Result<DHKEPublicKey, ProtoError> {
+    let mut key = [0u8; size_of::<DHKEPublicKey>()];
+    copy_into(&mut key[..], src, field)?;
+    Ok(key)
+}
+
+fn to_mac(src: &[u8], field: &'static str) -> Result<MacTag, ProtoError> {
+    let mut mac = [0u8; size_of::<MacTag>()];
+    copy_into(&mut mac[..], src, field)?;
+    Ok(mac)
+}
+
+impl From<&RaMsg0> for pb::Msg0 {
+    fn from(msg: &RaMsg0) -> Self {
+        Self {
+            exgid: msg.exgid,
+            tenant: msg.tenant.clone(),
+        }
+    }
+}
+
+impl TryFrom<pb::Msg0> for RaMsg0 {
+    type Error = ProtoError;
+
+    fn try_from(msg: pb::Msg0) -> Result<Self, ProtoError> {
+        Ok(Self {
+            exgid: msg.exgid,
+            tenant: msg.tenant,
+        })
+    }
+}
+
+impl From<&RaMsg1> for pb::Msg1 {
+    fn from(msg: &RaMsg1) -> Self {
+        Self {
+            gid: msg.gid.to_vec(),
+            g_a: msg.g_a.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<pb::Msg1> for RaMsg1 {
+    type Error = ProtoError;
+
+    fn try_from(msg: pb::Msg1) -> Result<Self, ProtoError> {
+        let mut gid = [0u8; size_of::<Gid>()];
+        copy_into(&mut gid[..], &msg.gid[..], "gid")?;
+        Ok(Self {
+            gid,
+            g_a: to_public_key(&msg.g_a[..], "g_a")?,
+        })
+    }
+}
+
+impl From<&RaMsg2> for pb::Msg2 {
+    fn from(msg: &RaMsg2) -> Self {
+        Self {
+            g_b: msg.g_b.to_vec(),
+            spid: msg.spid.to_vec(),
+            quote_type: msg.quote_type as u32,
+            kdf_id: msg.kdf_id as u32,
+            sign_gb_ga: msg.sign_gb_ga.clone(),
+            mac: msg.mac.to_vec(),
+            sig_rl: msg.sig_rl.clone(),
+            nonce: msg.nonce.map(|nonce| nonce.to_vec()),
+        }
+    }
+}
+
+impl TryFrom<pb::Msg2> for RaMsg2 {
+    type Error = ProtoError;
+
+    fn try_from(msg: pb::Msg2) -> Result<Self, ProtoError> {
+        let mut spid = [0u8; size_of::<Spid>()];
+        copy_into(&mut spid[..], &msg.spid[..], "spid")?;
+        let nonce = match msg.nonce {
+            Some(v) => {
+                let mut nonce = [0u8; size_of::<Nonce>()];
+                copy_into(&mut nonce[..], &v[..], "nonce")?;
+                Some(nonce)
+            },
+            None => None,
+        };
+        Ok(Self {
+            g_b: to_public_key(&msg.g_b[..], "g_b")?,
+            spid,
+            quote_type: u16::try_from(msg.quote_type)
+                .map_err(|_| ProtoError::Invalid("quote_type"))?,
+            kdf_id: u16::try_from(msg.kdf_id)
+                .map_err(|_| ProtoError::Invalid("kdf_id"))?,
+            sign_gb_ga: msg.sign_gb_ga,
+            mac: to_mac(&msg.mac[..], "mac")?,
+            sig_rl: msg.sig_rl,
+            nonce,
+        })
+    }
+}
+
+impl From<&RaMsg3> for pb::Msg3 {
+    fn from(msg: &RaMsg3) -> Self {
+        Self {
+            mac: msg.mac.to_vec(),
+            g_a: msg.g_a.to_vec(),
+            ps_sec_prop: msg.ps_sec_prop.as_ref().map(|p| p.inner.to_vec()),
+            quote: msg.quote.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<pb::Msg3> for RaMsg3 {
+    type Error = ProtoError;
+
+    fn try_from(msg: pb::Msg3) -> Result<Self, ProtoError> {
+        let ps_sec_prop = match msg.ps_sec_prop {
+            Some(v) => {
+                let mut inner = [0u8; size_of::<PsSecPropDesc>()];
+                copy_into(&mut inner[..], &v[..], "ps_sec_prop")?;
+                Some(PsSecPropDescInternal { inner })
+            },
+            None => None,
+        };
+        let mut quote = [0u8; size_of::<Quote>()];
+        copy_into(&mut quote[..], &msg.quote[..], "quote")?;
+        Ok(Self {
+            mac: to_mac(&msg.mac[..], "mac")?,
+            g_a: to_public_key(&msg.g_a[..], "g_a")?,
+            ps_sec_prop,
+            quote,
+        })
+    }
+}
+
+impl From<&RaMsg4> for pb::Msg4 {
+    fn from(msg: &RaMsg4) -> Self {
+        Self {
+            is_enclave_trusted: msg.is_enclave_trusted,
+            is_pse_manifest_trusted: msg.is_pse_manifest_trusted,
+            pib: msg.pib.clone(),
+        }
+    }
+}
+
+impl TryFrom<pb::Msg4> for RaMsg4 {
+    type Error = ProtoError;
+
+    fn try_from(msg: pb::Msg4) -> Result<Self, ProtoError> {
+        Ok(Self {
+            is_enclave_trusted: msg.is_enclave_trusted,
+            is_pse_manifest_trusted: msg.is_pse_manifest_trusted,
+            pib: msg.pib,
+        })
+    }
+}
+
+impl From<&RaAbort> for pb::Abort {
+    fn from(abort: &RaAbort) -> Self {
+        // The discriminants of AbortReason match the enum values in ra.proto
+        Self {
+            reason: abort.reason as i32,
+            mac: abort.mac.map(|mac| mac.to_vec()),
+        }
+    }
+}
+
+impl TryFrom<pb::Abort> for RaAbort {
+    type Error = ProtoError;
+
+    fn try_from(abort: pb::Abort) -> Result<Self, ProtoError> {
+        let reason = match pb::AbortReason::from_i32(abort.reason) {
+            Some(pb::AbortReason::IntegrityError) => AbortReason::IntegrityError,
+            Some(pb::AbortReason::Unsupported) => AbortReason::Unsupported,
+            Some(pb::AbortReason::IasUnavailable) => AbortReason::IasUnavailable,
+            Some(pb::AbortReason::QuoteRejected) => AbortReason::QuoteRejected,
+            Some(pb::AbortReason::Internal) => AbortReason::Internal,
+            Some(pb::AbortReason::ShuttingDown) => AbortReason::ShuttingDown,
+            None => return Err(ProtoError::Invalid("reason")),
+        };
+        let mac = match abort.mac {
+            Some(v) => Some(to_mac(&v[..], "mac")?),
+            None => None,
+        };
+        Ok(Self { reason, mac })
+    }
+}
+
+macro_rules! impl_proto_message {
+    ($msg:ty, $variant:ident) => {
+        impl ProtoMessage for $msg {
+            fn to_body(&self) -> Body {
+                Body::$variant(self.into())
+            }
+
+            fn from_body(body: Body) -> Result<Self, ProtoError> {
+                match body {
+                    Body::$variant(msg) => msg.try_into(),
+                    _ => Err(ProtoError::UnexpectedMessage),
+                }
+            }
+        }
+    }
+}
+
+impl_proto_message!(RaMsg0, Msg0);
+impl_proto_message!(RaMsg1, Msg1);
+impl_proto_message!(RaMsg2, Msg2);
+impl_proto_message!(RaMsg3, Msg3);
+impl_proto_message!(RaMsg4, Msg4);
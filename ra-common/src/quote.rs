@@ -0,0 +1,2 @@
+// Shared with no_std relying parties through ra-verify
+pub use ra_verify::quote::*;
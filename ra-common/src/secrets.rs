@@ -0,0 +1,43 @@
+// Delivery of secrets, e.g. keys, tokens, or configuration, from the SP to an
+// attested enclave over their SecureChannel. The SP sends one SecretBundle and
+// the enclave acknowledges with the names of the secrets it accepted, so that
+// the SP knows which secrets reached the enclave.
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+use serde::{Serialize, Deserialize};
+use crate::msg::WireMessage;
+
+#[derive(Serialize, Deserialize)]
+pub struct Secret {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        for b in self.value.iter_mut() {
+            unsafe { ptr::write_volatile(b, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "Secret {{ name: {:?}, .. }}", self.name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SecretBundle {
+    pub secrets: Vec<Secret>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SecretAck {
+    /// Names of the secrets the enclave accepted, in the order received.
+    pub accepted: Vec<String>,
+}
+
+impl WireMessage for SecretBundle {}
+impl WireMessage for SecretAck {}
@@ -0,0 +1,101 @@
+// Keys for each use of an attested session, derived from MK in the manner of
+// `derive_secret_keys` with the purpose as label, so that no key is used in
+// two contexts: a key leaked or misused in one of them, e.g. a token-signing
+// key handed to a web service, reveals nothing about the others. MK itself
+// is only used for key derivation.
+use std::io;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+use sgx_crypto::cmac::{Cmac, MacTag};
+use sgx_crypto::key_wrap::Kek;
+use crate::group_key::derive_group_kek;
+use crate::tls_psk::ExternalPsk;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyPurpose {
+    /// Key of `SecureChannel`, whose AES-GCM records need no separate MAC key.
+    ChannelEncrypt,
+    /// For application protocols that authenticate messages without
+    /// encrypting them.
+    ChannelMac,
+    /// Encryption of data kept in untrusted storage, see `blob`.
+    Storage,
+    /// MACs of tokens issued to the enclave, see `ra_sp::TokenIssuer`.
+    TokenSigning,
+    /// Binds the quotes of heartbeats to the session, see `heartbeat`.
+    Heartbeat,
+}
+
+impl KeyPurpose {
+    pub fn label(&self) -> &'static [u8] {
+        match self {
+            KeyPurpose::ChannelEncrypt => b"CHANNEL-ENC",
+            KeyPurpose::ChannelMac => b"CHANNEL-MAC",
+            KeyPurpose::Storage => b"STORAGE",
+            KeyPurpose::TokenSigning => b"TOKEN-SIGN",
+            KeyPurpose::Heartbeat => b"HEARTBEAT",
+        }
+    }
+}
+
+/// The keys of an attested session. The SP and the enclave derive the same
+/// keys from the MK they agreed on.
+pub struct SessionKeys {
+    mk: MacTag,
+}
+
+impl SessionKeys {
+    pub fn new(master_key: &MacTag) -> Self {
+        Self { mk: *master_key }
+    }
+
+    pub fn derive(&self, purpose: KeyPurpose) -> MacTag {
+        let label = purpose.label();
+        let mut data = Vec::with_capacity(1 + label.len() + 1 + 2);
+        data.push(0x01);
+        data.extend_from_slice(label);
+        data.push(0x00);
+        data.extend_from_slice(&[0x80, 0x00]);
+        Cmac::new(&self.mk).sign(&data[..])
+    }
+
+    /// Key to pass to `SecureChannel::new` and `SecureChannel::rekey`.
+    pub fn channel_key(&self) -> MacTag {
+        self.derive(KeyPurpose::ChannelEncrypt)
+    }
+
+    pub fn channel_mac_key(&self) -> MacTag {
+        self.derive(KeyPurpose::ChannelMac)
+    }
+
+    pub fn storage_key(&self) -> MacTag {
+        self.derive(KeyPurpose::Storage)
+    }
+
+    pub fn token_signing_key(&self) -> MacTag {
+        self.derive(KeyPurpose::TokenSigning)
+    }
+
+    /// Key-encryption key of group key updates, see `group_key`.
+    pub fn group_kek(&self) -> Kek {
+        derive_group_kek(&self.mk)
+    }
+
+    /// A TLS 1.3 external PSK for `identity`, see `tls_psk`.
+    pub fn tls_psk(&self, identity: &[u8]) -> io::Result<ExternalPsk> {
+        ExternalPsk::derive(&self.mk, identity)
+    }
+}
+
+impl Drop for SessionKeys {
+    fn drop(&mut self) {
+        unsafe { ptr::write_volatile(&mut self.mk, [0u8; 16]) };
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl std::fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str("SessionKeys(..)")
+    }
+}
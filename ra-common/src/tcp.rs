@@ -1,9 +1,72 @@
 use std::io::{Result, Error, ErrorKind};
-use std::net::{TcpListener, TcpStream};
+use std::net::{TcpListener, TcpStream, SocketAddr};
 use std::time::{Duration, Instant};
 use std::thread::sleep;
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
 
 const CONNECT_SLEEP_TIME_MILLIS: u64 = 10;
+/// First file descriptor passed by systemd socket activation (SD_LISTEN_FDS_START).
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Tuning applied to connected sockets. The default disables Nagle's
+/// algorithm, since the handshake is a sequence of small request/response
+/// messages, and leaves everything else to the OS.
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    pub nodelay: bool,
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    /// Idle time before TCP keepalive probes are sent; `None` leaves keepalive
+    /// as configured by the OS.
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            keepalive: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Buffer sizes and keepalive are not available inside an SGX enclave and
+    /// setting them there results in an error.
+    pub fn apply(&self, stream: TcpStream) -> Result<TcpStream> {
+        stream.set_nodelay(self.nodelay)?;
+        if self.send_buffer_size.is_none() && self.recv_buffer_size.is_none() &&
+            self.keepalive.is_none() {
+                return Ok(stream);
+            }
+        self.apply_extended(stream)
+    }
+
+    #[cfg(not(target_env = "sgx"))]
+    fn apply_extended(&self, stream: TcpStream) -> Result<TcpStream> {
+        let socket = socket2::Socket::from(stream);
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if self.keepalive.is_some() {
+            socket.set_keepalive(self.keepalive)?;
+        }
+        Ok(socket.into_tcp_stream())
+    }
+
+    #[cfg(target_env = "sgx")]
+    fn apply_extended(&self, _stream: TcpStream) -> Result<TcpStream> {
+        Err(Error::new(ErrorKind::Other,
+                       "Socket buffer and keepalive options are not supported in SGX"))
+    }
+}
 
 pub fn tcp_connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream> {
     let start = Instant::now();
@@ -13,7 +76,7 @@ pub fn tcp_connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream
                 return Ok(s); 
             },
             Err(e) => { 
-                if start.elapsed() == timeout {
+                if start.elapsed() >= timeout {
                     return Err(Error::new(ErrorKind::TimedOut, e));
                 }
             }
@@ -22,7 +85,81 @@ pub fn tcp_connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream
     }
 }
 
+pub fn tcp_connect_with(host: &str, port: u16, timeout: Duration,
+                        options: &SocketOptions) -> Result<TcpStream> {
+    options.apply(tcp_connect(host, port, timeout)?)
+}
+
 pub fn tcp_accept(port: u16) -> Result<TcpStream> {
-    let listener = TcpListener::bind(("localhost", port))?;
-    Ok(listener.accept()?.0)
+    Ok(tcp_accept_on("localhost", port)?.0)
+}
+
+/// Accept a single connection on `bind_addr:port` and return it along with the
+/// peer address. `bind_addr` is a host name or a bare IPv4/IPv6 address, e.g.
+/// "0.0.0.0" or "::". On dual-stack hosts "::" also accepts IPv4 peers, which
+/// then show up as IPv4-mapped IPv6 addresses.
+///
+/// Under systemd socket activation, the connection is accepted on the
+/// inherited socket instead and `bind_addr` and `port` are ignored. The
+/// socket stays open, so that it can be used again by the next call.
+pub fn tcp_accept_on(bind_addr: &str, port: u16) -> Result<(TcpStream, SocketAddr)> {
+    #[cfg(unix)]
+    {
+        if let Some(fd) = systemd_listen_fd()? {
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            let result = listener.accept();
+            let _fd = listener.into_raw_fd();
+            return result;
+        }
+    }
+    let listener = TcpListener::bind((bind_addr, port))?;
+    listener.accept()
+}
+
+/// Take the listening socket passed by systemd socket activation, if the
+/// process was started that way (LISTEN_PID and LISTEN_FDS are set for this
+/// process). Only the first socket is used. The socket must be taken once,
+/// since dropping the listener closes it.
+#[cfg(unix)]
+pub fn systemd_listener() -> Result<Option<TcpListener>> {
+    Ok(systemd_listen_fd()?.map(|fd| unsafe { TcpListener::from_raw_fd(fd) }))
+}
+
+#[cfg(not(unix))]
+pub fn systemd_listener() -> Result<Option<TcpListener>> {
+    Ok(None)
 }
+
+/// Bind to `bind_addr:port`, unless a listening socket was inherited from
+/// systemd, in which case that one is returned.
+pub fn bind_or_inherit(bind_addr: &str, port: u16) -> Result<TcpListener> {
+    match systemd_listener()? {
+        Some(listener) => Ok(listener),
+        None => TcpListener::bind((bind_addr, port)),
+    }
+}
+
+#[cfg(unix)]
+fn systemd_listen_fd() -> Result<Option<RawFd>> {
+    let pid = match std::env::var("LISTEN_PID") {
+        Ok(pid) => pid,
+        Err(_) => return Ok(None),
+    };
+    // The variables are inherited by children, which must not use the socket
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(None);
+    }
+    let fds: u32 = std::env::var("LISTEN_FDS").ok()
+        .and_then(|fds| fds.parse().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid LISTEN_FDS"))?;
+    if fds == 0 {
+        return Ok(None);
+    }
+    Ok(Some(LISTEN_FDS_START))
+}
+
+pub fn tcp_accept_with(bind_addr: &str, port: u16, options: &SocketOptions)
+    -> Result<(TcpStream, SocketAddr)> {
+        let (stream, peer_addr) = tcp_accept_on(bind_addr, port)?;
+        Ok((options.apply(stream)?, peer_addr))
+    }
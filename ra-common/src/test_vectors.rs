@@ -0,0 +1,267 @@
+// Known-answer vectors for the key schedule: a fixed pair of ECDH P-256 key
+// pairs, their shared secret, and the keys derived from it. They were computed
+// independently of this crate, so that an implementation of the other side of
+// the protocol, or another build of this one, e.g. with a different crypto
+// backend, can check that it derives the same keys.
+//
+// `KEY_SCHEDULE` follows this crate, which derives the KDK from the shared
+// secret in big-endian order as the crypto libraries return it.
+// `INTEL_KEY_SCHEDULE` follows the Intel SGX SDK's sgx_ra_proc_msg2, which
+// derives it from the shared secret in little-endian order
+// (sgx_ec256_dh_shared_t). Public keys are uncompressed SEC1 points in both;
+// see `compat` for Intel's encoding.
+//
+// Sources, all in github.com/intel/linux-sgx unless noted:
+// - ECDH over P-256 as in sgx_ecc256_compute_shared_dhkey
+//   (sdk/tlibcrypto), i.e. the x-coordinate of the shared point.
+// - KDK = AES-CMAC(0^128, shared secret) and each key =
+//   AES-CMAC(KDK, 0x01 || label || 0x00 || 0x80 0x00), as in derive_key
+//   (common/src/ecp.cpp), with the labels "SMK", "SK", "MK", and "VK" of
+//   sgx_ra_proc_msg2_trusted (sdk/tkey_exchange/tkey_exchange.cpp) and of
+//   Intel's sgx-ra-sample (github.com/intel/sgx-ra-sample, sp.cpp).
+// - `SESSION_KEYS` has no Intel counterpart: it applies the same derive_key
+//   construction to MK with the labels of `KeyPurpose`.
+// The values were computed with pyca/cryptography's ECDH and AES-CMAC.
+use sgx_crypto::cmac::{Cmac, MacTag};
+use crate::derive_secret_keys;
+use crate::session_keys::SessionKeys;
+
+pub struct KeyScheduleVector {
+    /// The enclave's (g_a) and the SP's (g_b) private scalars, big-endian.
+    pub g_a_private: [u8; 32],
+    pub g_a: [u8; 65],
+    pub g_b_private: [u8; 32],
+    pub g_b: [u8; 65],
+    /// x-coordinate of the shared point, in the order the KDK is derived from.
+    pub shared_secret: [u8; 32],
+    /// AES-CMAC of `shared_secret` under the all-zero key.
+    pub kdk: MacTag,
+    pub smk: MacTag,
+    pub sk: MacTag,
+    pub mk: MacTag,
+    pub vk: MacTag,
+}
+
+/// Keys `SessionKeys` derives from `mk`.
+pub struct SessionKeysVector {
+    pub mk: MacTag,
+    pub channel_key: MacTag,
+    pub channel_mac_key: MacTag,
+    pub storage_key: MacTag,
+    pub token_signing_key: MacTag,
+}
+
+pub const KEY_SCHEDULE: KeyScheduleVector = KeyScheduleVector {
+    g_a_private: [
+        0x02, 0xf1, 0xc5, 0xad, 0x7e, 0x1b, 0x0d, 0x3c, 0x4a, 0x59, 0x68, 0x77,
+        0x86, 0x95, 0xa4, 0xb3, 0xc2, 0xd1, 0xe0, 0xf1, 0x01, 0x11, 0x21, 0x31,
+        0x41, 0x51, 0x61, 0x71, 0x81, 0x92, 0x02, 0x12,
+    ],
+    g_a: [
+        0x04, 0x09, 0xe0, 0xdd, 0x5a, 0x58, 0x5f, 0x38, 0x5c, 0x0a, 0x7b, 0x0e,
+        0xa2, 0xd7, 0xd1, 0x5a, 0x59, 0x24, 0x09, 0xc3, 0x9a, 0x5f, 0xb2, 0x76,
+        0x4d, 0xb2, 0x7f, 0xc4, 0x04, 0x01, 0x33, 0x8c, 0x44, 0x9d, 0x6a, 0x57,
+        0xb3, 0x1f, 0x59, 0x66, 0x53, 0x02, 0x00, 0xe1, 0x4a, 0x5a, 0x5b, 0x45,
+        0x08, 0x2f, 0x86, 0x74, 0xad, 0x17, 0xc3, 0xb8, 0x58, 0x53, 0x15, 0x89,
+        0x50, 0x42, 0x01, 0x8b, 0x10,
+    ],
+    g_b_private: [
+        0x4b, 0x6f, 0x1e, 0x2d, 0x3c, 0x5a, 0x69, 0x78, 0x87, 0x96, 0xa5, 0xb4,
+        0xc3, 0xd2, 0xe1, 0xf0, 0x01, 0x12, 0x23, 0x34, 0x45, 0x56, 0x67, 0x78,
+        0x89, 0x9a, 0xab, 0xbc, 0xcd, 0xde, 0xef, 0xf0,
+    ],
+    g_b: [
+        0x04, 0x7b, 0x83, 0x8a, 0xa4, 0x4c, 0xa1, 0xc4, 0x05, 0xd9, 0x53, 0x94,
+        0xf0, 0x88, 0xbe, 0x37, 0x70, 0x2c, 0x83, 0x66, 0x8a, 0xbb, 0x75, 0x1d,
+        0xb3, 0xbb, 0x3d, 0x6c, 0x73, 0xcb, 0xd5, 0x49, 0xd1, 0xf3, 0x9c, 0x2d,
+        0x81, 0xfb, 0x9e, 0xa6, 0xe4, 0xc9, 0x73, 0x1a, 0x09, 0xf8, 0x5b, 0x13,
+        0xfb, 0xe6, 0xd2, 0x97, 0x42, 0xe9, 0xb0, 0x3b, 0x57, 0x74, 0x2c, 0xbb,
+        0xe2, 0x73, 0x18, 0x17, 0xac,
+    ],
+    shared_secret: [
+        0xf3, 0xcc, 0x07, 0x49, 0x84, 0x0b, 0x4e, 0xb5, 0xbf, 0xcc, 0x05, 0x49,
+        0x57, 0x6b, 0x37, 0xec, 0x0f, 0x42, 0x84, 0xb9, 0x22, 0x50, 0x36, 0x51,
+        0x32, 0xdf, 0x37, 0x25, 0xfd, 0xd9, 0xbf, 0x61,
+    ],
+    kdk: [
+        0xc3, 0x66, 0x2a, 0x31, 0xf7, 0x85, 0xcb, 0x29, 0x67, 0xa5, 0x22, 0xc4,
+        0x58, 0x59, 0x05, 0xc2,
+    ],
+    smk: [
+        0xd3, 0xc5, 0xc2, 0x8b, 0xa4, 0x4b, 0x97, 0xaa, 0x5c, 0x4a, 0xea, 0x6e,
+        0xa5, 0xb8, 0x3f, 0xaf,
+    ],
+    sk: [
+        0x0e, 0x63, 0x4f, 0x62, 0x33, 0x50, 0x88, 0x0f, 0x14, 0xa4, 0x23, 0x42,
+        0xe8, 0x2d, 0x52, 0x13,
+    ],
+    mk: [
+        0x2c, 0x9c, 0x51, 0xbc, 0x67, 0x50, 0xcd, 0xe3, 0xee, 0x7f, 0xc1, 0xd2,
+        0x29, 0x9e, 0x0a, 0xc9,
+    ],
+    vk: [
+        0x5b, 0x44, 0x01, 0x95, 0x83, 0xa7, 0xeb, 0x60, 0x43, 0x8e, 0x75, 0xb1,
+        0x71, 0xa8, 0x47, 0x6a,
+    ],
+};
+
+pub const INTEL_KEY_SCHEDULE: KeyScheduleVector = KeyScheduleVector {
+    g_a_private: [
+        0x02, 0xf1, 0xc5, 0xad, 0x7e, 0x1b, 0x0d, 0x3c, 0x4a, 0x59, 0x68, 0x77,
+        0x86, 0x95, 0xa4, 0xb3, 0xc2, 0xd1, 0xe0, 0xf1, 0x01, 0x11, 0x21, 0x31,
+        0x41, 0x51, 0x61, 0x71, 0x81, 0x92, 0x02, 0x12,
+    ],
+    g_a: [
+        0x04, 0x09, 0xe0, 0xdd, 0x5a, 0x58, 0x5f, 0x38, 0x5c, 0x0a, 0x7b, 0x0e,
+        0xa2, 0xd7, 0xd1, 0x5a, 0x59, 0x24, 0x09, 0xc3, 0x9a, 0x5f, 0xb2, 0x76,
+        0x4d, 0xb2, 0x7f, 0xc4, 0x04, 0x01, 0x33, 0x8c, 0x44, 0x9d, 0x6a, 0x57,
+        0xb3, 0x1f, 0x59, 0x66, 0x53, 0x02, 0x00, 0xe1, 0x4a, 0x5a, 0x5b, 0x45,
+        0x08, 0x2f, 0x86, 0x74, 0xad, 0x17, 0xc3, 0xb8, 0x58, 0x53, 0x15, 0x89,
+        0x50, 0x42, 0x01, 0x8b, 0x10,
+    ],
+    g_b_private: [
+        0x4b, 0x6f, 0x1e, 0x2d, 0x3c, 0x5a, 0x69, 0x78, 0x87, 0x96, 0xa5, 0xb4,
+        0xc3, 0xd2, 0xe1, 0xf0, 0x01, 0x12, 0x23, 0x34, 0x45, 0x56, 0x67, 0x78,
+        0x89, 0x9a, 0xab, 0xbc, 0xcd, 0xde, 0xef, 0xf0,
+    ],
+    g_b: [
+        0x04, 0x7b, 0x83, 0x8a, 0xa4, 0x4c, 0xa1, 0xc4, 0x05, 0xd9, 0x53, 0x94,
+        0xf0, 0x88, 0xbe, 0x37, 0x70, 0x2c, 0x83, 0x66, 0x8a, 0xbb, 0x75, 0x1d,
+        0xb3, 0xbb, 0x3d, 0x6c, 0x73, 0xcb, 0xd5, 0x49, 0xd1, 0xf3, 0x9c, 0x2d,
+        0x81, 0xfb, 0x9e, 0xa6, 0xe4, 0xc9, 0x73, 0x1a, 0x09, 0xf8, 0x5b, 0x13,
+        0xfb, 0xe6, 0xd2, 0x97, 0x42, 0xe9, 0xb0, 0x3b, 0x57, 0x74, 0x2c, 0xbb,
+        0xe2, 0x73, 0x18, 0x17, 0xac,
+    ],
+    shared_secret: [
+        0x61, 0xbf, 0xd9, 0xfd, 0x25, 0x37, 0xdf, 0x32, 0x51, 0x36, 0x50, 0x22,
+        0xb9, 0x84, 0x42, 0x0f, 0xec, 0x37, 0x6b, 0x57, 0x49, 0x05, 0xcc, 0xbf,
+        0xb5, 0x4e, 0x0b, 0x84, 0x49, 0x07, 0xcc, 0xf3,
+    ],
+    kdk: [
+        0x12, 0x8c, 0xc3, 0x0f, 0x45, 0x88, 0x08, 0xfe, 0xd5, 0xf6, 0x9e, 0xb0,
+        0xa7, 0x93, 0x4d, 0x0f,
+    ],
+    smk: [
+        0xc8, 0x51, 0x79, 0x8c, 0x79, 0x23, 0x24, 0x61, 0xdc, 0x01, 0x05, 0x20,
+        0xde, 0x23, 0x32, 0xe2,
+    ],
+    sk: [
+        0x39, 0xf8, 0x2a, 0xc7, 0xc9, 0xf0, 0xd5, 0x84, 0x12, 0x24, 0xf9, 0xb8,
+        0x6f, 0x8f, 0x3a, 0xc0,
+    ],
+    mk: [
+        0x08, 0xdf, 0x45, 0x8c, 0xb8, 0x70, 0x37, 0xf6, 0x54, 0xc0, 0x09, 0x3e,
+        0x43, 0xb4, 0x7b, 0x6b,
+    ],
+    vk: [
+        0xef, 0xb6, 0x3d, 0xe4, 0x7c, 0x64, 0xcd, 0xc7, 0x55, 0x42, 0xf1, 0xa4,
+        0x56, 0x20, 0x67, 0xc0,
+    ],
+};
+
+pub const SESSION_KEYS: SessionKeysVector = SessionKeysVector {
+    mk: [
+        0x2c, 0x9c, 0x51, 0xbc, 0x67, 0x50, 0xcd, 0xe3, 0xee, 0x7f, 0xc1, 0xd2,
+        0x29, 0x9e, 0x0a, 0xc9,
+    ],
+    channel_key: [
+        0x8a, 0xae, 0xa3, 0x2c, 0x1d, 0x17, 0xd2, 0x69, 0xa9, 0xa3, 0xe7, 0x67,
+        0xca, 0xe7, 0x8d, 0xce,
+    ],
+    channel_mac_key: [
+        0x20, 0x6e, 0x8a, 0xf8, 0x45, 0x07, 0x1b, 0x79, 0xb0, 0xbc, 0x1b, 0xf1,
+        0xb1, 0xc8, 0xf9, 0xee,
+    ],
+    storage_key: [
+        0x59, 0xe1, 0x1d, 0x4a, 0x39, 0xb3, 0x8c, 0xcc, 0xe6, 0x04, 0xb9, 0xeb,
+        0x76, 0xce, 0xe0, 0xf6,
+    ],
+    token_signing_key: [
+        0xaf, 0x13, 0x41, 0x93, 0x87, 0xcc, 0x4a, 0xea, 0xd2, 0xc0, 0xe6, 0x0f,
+        0x47, 0x61, 0x0d, 0x02,
+    ],
+};
+
+/// Derive the KDK and the session keys of `vector` from its shared secret and
+/// compare them with the expected ones. Fails with the name of the first key
+/// that differs. The ECDH step is not checked, since the crypto backends only
+/// agree on ephemeral keys; the tests below check it with fixed keys.
+pub fn check_key_schedule(vector: &KeyScheduleVector) -> Result<(), &'static str> {
+    let kdk = Cmac::new(&[0u8; 16]).sign(&vector.shared_secret[..]);
+    if kdk != vector.kdk {
+        return Err("KDK");
+    }
+    let (smk, sk, mk, vk) = derive_secret_keys(&Cmac::new(&kdk));
+    for (name, key, expected) in [("SMK", smk, vector.smk),
+                                  ("SK", sk, vector.sk),
+                                  ("MK", mk, vector.mk),
+                                  ("VK", vk, vector.vk)].iter() {
+        if key != expected {
+            return Err(*name);
+        }
+    }
+    Ok(())
+}
+
+/// Like `check_key_schedule`, for the keys of `SessionKeys`.
+pub fn check_session_keys(vector: &SessionKeysVector) -> Result<(), &'static str> {
+    let keys = SessionKeys::new(&vector.mk);
+    for (name, key, expected) in [("channel", keys.channel_key(), vector.channel_key),
+                                  ("channel MAC", keys.channel_mac_key(), vector.channel_mac_key),
+                                  ("storage", keys.storage_key(), vector.storage_key),
+                                  ("token signing", keys.token_signing_key(),
+                                   vector.token_signing_key)].iter() {
+        if key != expected {
+            return Err(*name);
+        }
+    }
+    Ok(())
+}
+
+/// Check all of the vectors above against this build.
+pub fn check_all() -> Result<(), &'static str> {
+    check_key_schedule(&KEY_SCHEDULE)?;
+    check_key_schedule(&INTEL_KEY_SCHEDULE)?;
+    check_session_keys(&SESSION_KEYS)
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::{ecdh, PublicKey, SecretKey};
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+    use super::*;
+
+    // The key pair of `private`, which must have the public key `public`
+    fn key_pair(private: &[u8; 32], public: &[u8; 65]) -> SecretKey {
+        let secret_key = SecretKey::from_bytes(&private[..]).unwrap();
+        assert_eq!(secret_key.public_key().to_encoded_point(false).as_bytes(), &public[..]);
+        secret_key
+    }
+
+    // x-coordinate of the point both sides of `vector` agree on, big-endian
+    fn shared_secret(vector: &KeyScheduleVector) -> [u8; 32] {
+        let g_a_private = key_pair(&vector.g_a_private, &vector.g_a);
+        let g_b_private = key_pair(&vector.g_b_private, &vector.g_b);
+        let g_a = PublicKey::from_sec1_bytes(&vector.g_a[..]).unwrap();
+        let g_b = PublicKey::from_sec1_bytes(&vector.g_b[..]).unwrap();
+        let enclave = ecdh::diffie_hellman(g_a_private.secret_scalar(), g_b.as_affine());
+        let sp = ecdh::diffie_hellman(g_b_private.secret_scalar(), g_a.as_affine());
+        assert_eq!(enclave.as_bytes(), sp.as_bytes());
+        let mut x = [0u8; 32];
+        x.copy_from_slice(enclave.as_bytes());
+        x
+    }
+
+    #[test]
+    fn vectors() {
+        check_all().unwrap()
+    }
+
+    #[test]
+    fn ecdh_shared_secret() {
+        assert_eq!(shared_secret(&KEY_SCHEDULE), KEY_SCHEDULE.shared_secret);
+        let mut little_endian = shared_secret(&INTEL_KEY_SCHEDULE);
+        little_endian.reverse();
+        assert_eq!(little_endian, INTEL_KEY_SCHEDULE.shared_secret);
+    }
+}
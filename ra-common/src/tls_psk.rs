@@ -0,0 +1,48 @@
+// TLS 1.3 external PSKs (RFC 8446, section 4.2.11) keyed by an attestation,
+// so that application traffic can use a standard TLS stack instead of
+// `SecureChannel` without losing the attestation as its root of trust.
+//
+// The PSK is derived from MK the same way `derive_secret_keys` derives the
+// session keys from the KDK (AES-CMAC in counter mode, NIST SP 800-108),
+// with the PSK identity as context. A PSK is thus only valid under the
+// identity it was derived for, and neither can be computed without MK.
+//
+// rustls does not accept external PSKs; OpenSSL 1.1.1 does, through
+// SSL_CTX_set_psk_use_session_callback on the client and
+// SSL_CTX_set_psk_find_session_callback on the server. Both sides must
+// restrict the handshake to a SHA-256 cipher suite, e.g.
+// TLS_AES_128_GCM_SHA256, since the PSK is bound to that hash.
+use std::io;
+use sgx_crypto::cmac::{Cmac, MacTag};
+use sgx_crypto::locked::Locked;
+
+const LABEL: &[u8] = b"TLS13-PSK";
+pub const PSK_LEN: usize = 32;
+
+pub struct ExternalPsk {
+    pub identity: Vec<u8>,
+    pub key: Locked<[u8; PSK_LEN]>,
+}
+
+impl ExternalPsk {
+    /// Derive the PSK for `identity` from the master key of an attestation.
+    /// The SP and the enclave get the same PSK for the same identity, e.g. a
+    /// session ID both of them know.
+    pub fn derive(master_key: &MacTag, identity: &[u8]) -> io::Result<Self> {
+        let mk = Cmac::new(master_key);
+        let mut key = Locked::new([0u8; PSK_LEN])?;
+        for (i, block) in key.chunks_mut(16).enumerate() {
+            let mut data = Vec::with_capacity(1 + LABEL.len() + 1 + identity.len() + 2);
+            data.push((i + 1) as u8);
+            data.extend_from_slice(LABEL);
+            data.push(0x00);
+            data.extend_from_slice(identity);
+            data.extend_from_slice(&((PSK_LEN * 8) as u16).to_le_bytes());
+            block.copy_from_slice(&mk.sign(&data[..])[..]);
+        }
+        Ok(Self {
+            identity: identity.to_vec(),
+            key,
+        })
+    }
+}
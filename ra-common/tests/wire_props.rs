@@ -0,0 +1,228 @@
+// Property-based tests of the wire format, to harden the decoders against
+// malformed peers. For every `WireMessage`, any message decodes from its
+// encoding and re-encodes to the same bytes, and no input, whether random or
+// a truncated or corrupted encoding, makes a decoder panic. The same holds
+// for the quote parser.
+use std::mem::size_of;
+use proptest::prelude::*;
+use proptest::collection::vec;
+use proptest::option;
+use proptest::sample::Index;
+use ra_common::msg::*;
+use ra_common::enclave_config::EnclaveConfig;
+use ra_common::group_key::GroupKeyUpdate;
+use ra_common::secrets::{Secret, SecretAck, SecretBundle};
+use ra_common::heartbeat::{Heartbeat, HeartbeatAck};
+use ra_common::quote::{QuoteBody, QUOTE_BODY_LEN};
+
+// Byte arrays of any length; std only implements Arbitrary up to 32 bytes
+macro_rules! array {
+    ($len:expr) => {
+        vec(any::<u8>(), $len).prop_map(|v| {
+            let mut a = [0u8; $len];
+            a.copy_from_slice(&v[..]);
+            a
+        })
+    };
+}
+
+fn arb_abort_reason() -> impl Strategy<Value = AbortReason> {
+    prop_oneof![
+        Just(AbortReason::IntegrityError),
+        Just(AbortReason::Unsupported),
+        Just(AbortReason::IasUnavailable),
+        Just(AbortReason::QuoteRejected),
+        Just(AbortReason::Internal),
+        Just(AbortReason::ShuttingDown),
+    ]
+}
+
+fn arb_abort() -> impl Strategy<Value = RaAbort> {
+    (arb_abort_reason(), option::of(array!(16)))
+        .prop_map(|(reason, mac)| RaAbort { reason, mac })
+}
+
+fn arb_msg0() -> impl Strategy<Value = RaMsg0> {
+    (any::<u32>(), option::of(".{0,32}"))
+        .prop_map(|(exgid, tenant)| RaMsg0 { exgid, tenant })
+}
+
+fn arb_msg1() -> impl Strategy<Value = RaMsg1> {
+    (array!(4), array!(65)).prop_map(|(gid, g_a)| RaMsg1 { gid, g_a })
+}
+
+fn arb_msg2() -> impl Strategy<Value = RaMsg2> {
+    (array!(65), array!(16), any::<u16>(), any::<u16>(), vec(any::<u8>(), 0..512),
+     array!(16), option::of(vec(any::<u8>(), 0..256)), option::of(array!(16)))
+        .prop_map(|(g_b, spid, quote_type, kdf_id, sign_gb_ga, mac, sig_rl, nonce)| RaMsg2 {
+            g_b, spid, quote_type, kdf_id, sign_gb_ga, mac, sig_rl, nonce,
+        })
+}
+
+fn arb_quote() -> impl Strategy<Value = Quote> {
+    array!(size_of::<Quote>())
+}
+
+fn arb_msg3() -> impl Strategy<Value = RaMsg3> {
+    (array!(16), array!(65), option::of(array!(256)), arb_quote())
+        .prop_map(|(mac, g_a, ps_sec_prop, quote)| RaMsg3 {
+            mac,
+            g_a,
+            ps_sec_prop: ps_sec_prop.map(|inner| PsSecPropDescInternal { inner }),
+            quote,
+        })
+}
+
+fn arb_msg4() -> impl Strategy<Value = RaMsg4> {
+    (any::<bool>(), option::of(any::<bool>()), option::of("[0-9A-F]{0,420}"))
+        .prop_map(|(is_enclave_trusted, is_pse_manifest_trusted, pib)| RaMsg4 {
+            is_enclave_trusted, is_pse_manifest_trusted, pib,
+        })
+}
+
+fn arb_enclave_config() -> impl Strategy<Value = EnclaveConfig> {
+    (any::<u64>(), ".{0,512}", any::<bool>())
+        .prop_map(|(version, sp_vkey_pem, require_challenge_nonce)| EnclaveConfig {
+            version, sp_vkey_pem, require_challenge_nonce,
+        })
+}
+
+fn arb_group_key_update() -> impl Strategy<Value = GroupKeyUpdate> {
+    (any::<u64>(), vec(any::<u8>(), 0..64))
+        .prop_map(|(epoch, wrapped_key)| GroupKeyUpdate { epoch, wrapped_key })
+}
+
+fn arb_secret_bundle() -> impl Strategy<Value = SecretBundle> {
+    vec((".{0,32}", vec(any::<u8>(), 0..128)), 0..8)
+        .prop_map(|secrets| SecretBundle {
+            secrets: secrets.into_iter()
+                .map(|(name, value)| Secret { name, value })
+                .collect(),
+        })
+}
+
+fn arb_secret_ack() -> impl Strategy<Value = SecretAck> {
+    vec(".{0,32}", 0..8).prop_map(|accepted| SecretAck { accepted })
+}
+
+fn arb_heartbeat() -> impl Strategy<Value = Heartbeat> {
+    (any::<u64>(), vec(any::<u8>(), 0..size_of::<Quote>() + 1))
+        .prop_map(|(seq, quote)| Heartbeat { seq, quote })
+}
+
+fn arb_heartbeat_ack() -> impl Strategy<Value = HeartbeatAck> {
+    (any::<u64>(), any::<bool>(), option::of("[A-Z_]{0,40}"))
+        .prop_map(|(seq, is_enclave_trusted, quote_status)| HeartbeatAck {
+            seq, is_enclave_trusted, quote_status,
+        })
+}
+
+fn encode<M: WireMessage>(msg: &M) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    // Can unwrap since writing to a Vec does not fail
+    msg.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+// Encodings of messages from `strategy`, so that failing cases can be shown
+// even for messages that are not `Debug`
+fn encodings<M, S>(strategy: S) -> impl Strategy<Value = Vec<u8>>
+    where M: WireMessage, S: Strategy<Value = M> {
+        strategy.prop_map(|msg| encode(&msg))
+    }
+
+// `bytes` decodes, and re-encodes to the same bytes
+fn check_round_trip<M: WireMessage>(bytes: &[u8]) -> Result<(), TestCaseError> {
+    let msg = M::read_from(bytes)
+        .map_err(|e| TestCaseError::fail(format!("{:?}", e)))?;
+    prop_assert_eq!(encode(&msg), bytes);
+    Ok(())
+}
+
+// Truncations of `bytes` fail to decode, and a corruption of it fails to
+// decode or decodes to some message, but does not panic
+fn check_malformed<M: WireMessage>(bytes: &[u8], cut: Index, flip: Index, mask: u8)
+    -> Result<(), TestCaseError> {
+        let cut = cut.index(bytes.len());
+        prop_assert!(M::read_from(&bytes[..cut]).is_err());
+
+        let mut corrupted = bytes.to_vec();
+        let i = flip.index(corrupted.len());
+        corrupted[i] ^= mask;
+        let _r = M::read_from(&corrupted[..]);
+        Ok(())
+    }
+
+// A module of tests for each message type
+macro_rules! wire_props {
+    ($($name:ident: $msg:ty = $strategy:expr;)*) => {
+        $(
+            mod $name {
+                use super::*;
+
+                proptest! {
+                    #[test]
+                    fn round_trip(bytes in encodings($strategy)) {
+                        check_round_trip::<$msg>(&bytes)?;
+                    }
+
+                    #[test]
+                    fn malformed(bytes in encodings($strategy), cut in any::<Index>(),
+                                 flip in any::<Index>(), mask in any::<u8>()) {
+                        check_malformed::<$msg>(&bytes, cut, flip, mask)?;
+                    }
+
+                    #[test]
+                    fn arbitrary_bytes(bytes in vec(any::<u8>(), 0..2048)) {
+                        let _r = <$msg as WireMessage>::read_from(&bytes[..]);
+                    }
+                }
+            }
+        )*
+    };
+}
+
+wire_props! {
+    msg0: RaMsg0 = arb_msg0();
+    msg1: RaMsg1 = arb_msg1();
+    msg2: RaMsg2 = arb_msg2();
+    msg3: RaMsg3 = arb_msg3();
+    msg4: RaMsg4 = arb_msg4();
+    enclave_config: EnclaveConfig = arb_enclave_config();
+    group_key_update: GroupKeyUpdate = arb_group_key_update();
+    secret_bundle: SecretBundle = arb_secret_bundle();
+    secret_ack: SecretAck = arb_secret_ack();
+    heartbeat: Heartbeat = arb_heartbeat();
+    heartbeat_ack: HeartbeatAck = arb_heartbeat_ack();
+    abort: RaAbort = arb_abort();
+}
+
+proptest! {
+    // An abort in place of any message decodes as `WireError::Aborted` with
+    // the same contents
+    #[test]
+    fn aborts(abort in arb_abort()) {
+        let bytes = encode(&abort);
+        match RaMsg4::read_from(&bytes[..]) {
+            Err(WireError::Aborted(decoded)) => {
+                prop_assert_eq!(decoded.reason, abort.reason);
+                prop_assert_eq!(decoded.mac, abort.mac);
+            },
+            r => prop_assert!(false, "decoded as {:?}", r),
+        }
+    }
+
+    // `QuoteBody::parse` accepts exactly the inputs of at least
+    // `QUOTE_BODY_LEN` bytes, ignores what follows the body, and never panics
+    #[test]
+    fn quote_parser(bytes in vec(any::<u8>(), 0..QUOTE_BODY_LEN + 64)) {
+        let body = QuoteBody::parse(&bytes[..]);
+        prop_assert_eq!(body.is_some(), bytes.len() >= QUOTE_BODY_LEN);
+        if let Some(body) = body {
+            let again = QuoteBody::parse(&bytes[..QUOTE_BODY_LEN]).unwrap();
+            prop_assert_eq!(body.mr_enclave, again.mr_enclave);
+            prop_assert_eq!(&body.report_data[..], &again.report_data[..]);
+            prop_assert_eq!(&bytes[112..144], &body.mr_enclave[..]);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use std::io::{Read, Write};
+use ra_verify::rats::{Attester, TeeType};
+use crate::context::EnclaveRaContext;
+use crate::error::EnclaveRaError;
+
+/// RATS attester for SGX enclaves. The evidence is a quote, obtained through
+/// a client running `ClientRaContext::get_quote`; it becomes verifiable
+/// evidence for `ra_verify::rats::IasReportVerifier` once the SP has it
+/// verified by IAS.
+pub struct SgxAttester<'a, S: Read + Write> {
+    pub client_stream: &'a mut S,
+}
+
+impl<'a, S: Read + Write> Attester for SgxAttester<'a, S> {
+    type Error = EnclaveRaError;
+
+    fn tee_type(&self) -> TeeType {
+        TeeType::Sgx
+    }
+
+    fn evidence(&mut self, report_data: &[u8; 64]) -> Result<Vec<u8>, Self::Error> {
+        let quote = EnclaveRaContext::get_quote(&report_data[..], self.client_stream)?;
+        Ok(quote.to_vec())
+    }
+}
@@ -0,0 +1,74 @@
+// Enclave side of the handshake of Intel's sgx-ra-sample, see
+// `ra_common::compat`, so that the sample's C SP can attest a Rust enclave
+// through `ClientRaContext::do_intel_attestation`. The SP's key is the ECDSA
+// P-256 key the sample compiles into its enclave rather than an RSA key, and
+// the quote binds neither data nor a challenge nonce.
+use std::io::{Read, Write};
+use sgx_crypto::random::RandomState;
+use sgx_crypto::key_exchange::DHKE;
+use sgx_crypto::signature::EcdsaVerificationKey;
+use sgx_crypto::cmac::{Cmac, MacTag};
+use ra_common::{derive_secret_keys, DEFAULT_KDF_ID};
+use ra_common::compat::{self, IntelMsg2, IntelMsg3, IntelPublicKey};
+use ra_common::msg::{RaMsg4, WireMessage};
+use ra_common::session_keys::SessionKeys;
+use crate::context::EnclaveRaContext;
+use crate::error::EnclaveRaError;
+use crate::EnclaveRaResult;
+
+pub struct IntelEnclaveRaContext {
+    pub key_exchange: Option<DHKE>,
+    pub sp_vkey: EcdsaVerificationKey,
+}
+
+impl IntelEnclaveRaContext {
+    /// `sp_public_key` is the SP's key as the sample's enclave holds it
+    /// (`sgx_ec256_public_t`, e.g. its `def_service_public_key`).
+    pub fn init(sp_public_key: &IntelPublicKey) -> EnclaveRaResult<Self> {
+        let rng = RandomState::new();
+        let key_exchange = DHKE::generate_keypair(&rng)?;
+        Ok(Self {
+            key_exchange: Some(key_exchange),
+            sp_vkey: compat::verification_key_from_intel(sp_public_key),
+        })
+    }
+
+    /// Returns the signing key and the keys of the session, as
+    /// `EnclaveRaContext::do_attestation` does.
+    pub fn do_attestation(mut self, mut client_stream: &mut (impl Read+Write))
+        -> EnclaveRaResult<(MacTag, SessionKeys)> {
+            let key_exchange = self.key_exchange.take().unwrap();
+            let g_a = key_exchange.get_public_key().to_owned();
+            client_stream.write_all(&g_a[..])?;
+
+            // The client relays MSG2 as the SP sent it
+            let msg2 = IntelMsg2::decode(&compat::read_msg(client_stream)?[..])?;
+            let g_a = compat::key_to_intel(&g_a);
+            compat::verify_gb_ga(&msg2.g_b, &g_a, &msg2.sign_gb_ga, &self.sp_vkey)?;
+            if msg2.kdf_id != DEFAULT_KDF_ID {
+                return Err(EnclaveRaError::UnsupportedKdf(msg2.kdf_id));
+            }
+
+            // Derive KDK from the little-endian shared secret and then the
+            // other secret keys
+            let shared_secret = key_exchange.agree(&compat::key_from_intel(&msg2.g_b))?;
+            let (smk, sk, mk, vk) =
+                derive_secret_keys(&Cmac::new(&compat::derive_intel_kdk(&shared_secret)));
+            let smk = Cmac::new(&smk);
+            msg2.verify_mac(&smk).map_err(|_| EnclaveRaError::IntegrityError)?;
+
+            // REPORTDATA = SHA-256(g_a || g_b || vk), in Intel's encoding
+            let report_data = compat::verification_digest(&g_a, &msg2.g_b, &vk);
+            let quote = EnclaveRaContext::get_quote(&report_data[..], client_stream)?;
+
+            // Send MAC for msg3 to client
+            let msg3 = IntelMsg3::new(&smk, g_a, quote.to_vec());
+            client_stream.write_all(&msg3.mac)?;
+
+            let msg4 = RaMsg4::read_from(&mut client_stream)?;
+            if !msg4.is_enclave_trusted {
+                return Err(EnclaveRaError::EnclaveNotTrusted);
+            }
+            Ok((sk, SessionKeys::new(&mk)))
+        }
+}
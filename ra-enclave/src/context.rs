@@ -2,19 +2,47 @@ use std::io::{Read, Write};
 use std::mem::size_of;
 use sgx_isa::{Targetinfo, Report};
 use sgx_crypto::random::RandomState;
-use sgx_crypto::key_exchange::OneWayAuthenticatedDHKE;
+use sgx_crypto::key_exchange::{derive_kdk, OneWayAuthenticatedDHKE, DHKEPublicKey};
 use sgx_crypto::signature::VerificationKey;
 use sgx_crypto::cmac::{Cmac, MacTag};
-use sgx_crypto::digest::sha256;
-use ra_common::derive_secret_keys;
-use ra_common::msg::{Quote, RaMsg2, RaMsg3, RaMsg4};
+use sgx_crypto::digest::{sha256, Sha256Digest};
+use ra_common::{derive_secret_keys, KeyDerivation, DEFAULT_KDF_ID};
+use ra_common::enclave_config::EnclaveConfig;
+use ra_common::session_keys::SessionKeys;
+use ra_common::msg::{Quote, RaMsg2, RaMsg3, RaMsg4, WireMessage, WireError};
 use crate::error::EnclaveRaError;
 use crate::EnclaveRaResult;
+#[cfg(not(feature = "occlum"))]
 use crate::local_attestation;
+#[cfg(feature = "occlum")]
+use crate::occlum::SgxDevice;
+#[cfg(feature = "occlum")]
+use crate::error::LocalAttestationError;
+
+// Length of the QE report the client sends along with the quote
+pub(crate) const QE_REPORT_LEN: usize = 432;
+
+// The error of reading a message after MSG2. Only the SP holds SMK, so an
+// abort without its MAC may come from anyone on the path
+pub(crate) fn authenticate_abort(e: WireError, smk: &Cmac) -> EnclaveRaError {
+    match e {
+        WireError::Aborted(abort) => match abort.verify_mac(smk) {
+            Ok(()) => EnclaveRaError::Aborted(abort.reason),
+            Err(_) => EnclaveRaError::IntegrityError,
+        },
+        e => e.into(),
+    }
+}
 
 pub struct EnclaveRaContext {
     pub key_exchange: Option<OneWayAuthenticatedDHKE>,
     pub sp_vkey: VerificationKey,
+    /// Further SP keys accepted for MSG2, e.g. the next key of an SP that is
+    /// rotating its signing key.
+    pub extra_sp_vkeys: Vec<VerificationKey>,
+    pub bound_data_digest: Option<Sha256Digest>,
+    pub key_derivation: Option<Box<dyn KeyDerivation>>,
+    pub require_challenge_nonce: bool,
 }
 
 impl EnclaveRaContext {
@@ -23,14 +51,50 @@ impl EnclaveRaContext {
         let key_exchange = OneWayAuthenticatedDHKE::generate_keypair(&rng)?;
         Ok(Self {
             sp_vkey: VerificationKey::new_from_pem(sp_vkey_pem)?,
+            extra_sp_vkeys: Vec::new(),
             key_exchange: Some(key_exchange),
+            bound_data_digest: None,
+            key_derivation: None,
+            require_challenge_nonce: false,
         })
     }
 
+    /// Take the SP key and policy from a provisioned config, e.g. one returned
+    /// by `sealing::unseal_config`.
+    pub fn init_with_config(config: &EnclaveConfig) -> EnclaveRaResult<Self> {
+        let mut context = Self::init(&config.sp_vkey_pem)?;
+        context.require_challenge_nonce = config.require_challenge_nonce;
+        Ok(context)
+    }
+
+    /// Also accept MSG2s signed with `sp_vkey_pem`, so that the enclave keeps
+    /// working when the SP switches to that key after a rotation.
+    pub fn trust_sp_key(&mut self, sp_vkey_pem: &str) -> EnclaveRaResult<()> {
+        self.extra_sp_vkeys.push(VerificationKey::new_from_pem(sp_vkey_pem)?);
+        Ok(())
+    }
+
+    /// Put SHA-256(`data`) in the second half of the quote's REPORTDATA, e.g.
+    /// the public key of a TLS keypair generated in the enclave, so that the SP
+    /// can check that the attested enclave owns it. Must be called before
+    /// `do_attestation`.
+    pub fn bind_data(&mut self, data: &[u8]) {
+        self.bound_data_digest = Some(sha256(data));
+    }
+
+    /// Accept MSG2s that announce `kdf`'s KDF ID, in addition to the
+    /// default KDF, like `sgx_ra_init_ex`.
+    pub fn set_key_derivation(&mut self, kdf: Box<dyn KeyDerivation>) {
+        self.key_derivation = Some(kdf);
+    }
+
+    /// Returns the signing key and the keys of the session, e.g.
+    /// `keys.channel_key()` for the `SecureChannel` to the SP.
     pub fn do_attestation(mut self, mut client_stream: &mut (impl Read+Write))
-        -> EnclaveRaResult<(MacTag, MacTag)> {
-            let (sk, mk) = self.process_msg_2(client_stream).unwrap();
-            let msg4: RaMsg4 = bincode::deserialize_from(&mut client_stream).unwrap();
+        -> EnclaveRaResult<(MacTag, SessionKeys)> {
+            let (smk, sk, mk) = self.exchange_msg_2_3(client_stream)?;
+            let msg4 = RaMsg4::read_from(&mut client_stream)
+                .map_err(|e| authenticate_abort(e, &smk))?;
             if !msg4.is_enclave_trusted {
                 return Err(EnclaveRaError::EnclaveNotTrusted);
             }
@@ -40,49 +104,88 @@ impl EnclaveRaContext {
                 },
                 None => {},
             }
-            Ok((sk, mk))
+            Ok((sk, SessionKeys::new(&mk)))
         }
 
     // Return (signing key, master key)
     pub fn process_msg_2(&mut self, 
-                         mut client_stream: &mut (impl Read+Write)) 
+                         client_stream: &mut (impl Read+Write)) 
         -> EnclaveRaResult<(MacTag, MacTag)> {
+            let (_smk, sk, mk) = self.exchange_msg_2_3(client_stream)?;
+            Ok((sk, mk))
+        }
+
+    // Return (SMK, signing key, master key)
+    fn exchange_msg_2_3(&mut self, mut client_stream: &mut (impl Read+Write))
+        -> EnclaveRaResult<(Cmac, MacTag, MacTag)> {
             let g_a = self.key_exchange.as_ref().unwrap().get_public_key().to_owned();
-            client_stream.write_all(&g_a[..]).unwrap();
+            client_stream.write_all(&g_a[..])?;
+
+            let msg2 = RaMsg2::read_from(&mut client_stream)?;
+            let (smk, sk, mk, report_data) = self.verify_msg_2(&msg2, &g_a)?;
 
-            let msg2: RaMsg2 = bincode::deserialize_from(&mut client_stream).unwrap();
+            // Obtain Quote
+            let quote = Self::get_quote(&report_data[..], client_stream)?;
+
+            // Send MAC for msg3 to client
+            let msg3 = RaMsg3::new(&smk, 
+                                   g_a,
+                                   None, 
+                                   quote);
+            client_stream.write_all(&msg3.mac)?;
+
+            Ok((smk, sk, mk))
+        }
+
+    // Verify MSG2 and derive the keys of the session. Returns (SMK, signing
+    // key, master key, REPORTDATA).
+    pub(crate) fn verify_msg_2(&mut self, msg2: &RaMsg2, g_a: &DHKEPublicKey)
+        -> EnclaveRaResult<(Cmac, MacTag, MacTag, Vec<u8>)> {
+            if self.require_challenge_nonce && msg2.nonce.is_none() {
+                return Err(EnclaveRaError::MissingChallengeNonce);
+            }
+
+            // Pick the trusted SP key that signed (g_b, g_a), if any
+            let mut gb_ga = Vec::new();
+            gb_ga.write_all(&msg2.g_b).unwrap();
+            gb_ga.write_all(&g_a[..]).unwrap();
+            let sp_vkey = self.extra_sp_vkeys.iter()
+                .find(|k| k.verify(&gb_ga[..], &msg2.sign_gb_ga[..]).is_ok())
+                .unwrap_or(&self.sp_vkey);
 
             // Verify and derive KDK and then other secret keys 
-            let kdk = self.key_exchange.take().unwrap()
-                .verify_and_derive(&msg2.g_b,
-                                   &msg2.sign_gb_ga,
-                                   &self.sp_vkey)
-                .unwrap();
-            let kdk_cmac = Cmac::new(&kdk);
-            let (smk, sk, mk, vk) = derive_secret_keys(&kdk_cmac);
+            let shared_secret = self.key_exchange.take().unwrap()
+                .verify_and_agree(&msg2.g_b,
+                                  &msg2.sign_gb_ga,
+                                  sp_vkey)?;
+            let (smk, sk, mk, vk) = match self.key_derivation.as_ref() {
+                _ if msg2.kdf_id == DEFAULT_KDF_ID =>
+                    derive_secret_keys(&Cmac::new(&derive_kdk(&shared_secret))),
+                Some(kdf) if kdf.kdf_id() == msg2.kdf_id => kdf.derive_secret_keys(&shared_secret),
+                _ => return Err(EnclaveRaError::UnsupportedKdf(msg2.kdf_id)),
+            };
             let smk = Cmac::new(&smk);
 
             // Verify MAC tag of MSG2
             msg2.verify_mac(&smk).map_err(|_| EnclaveRaError::IntegrityError)?;
 
-            // Obtain SHA-256(g_a || g_b || vk) 
+            // Obtain SHA-256(g_a || g_b || vk [|| nonce])
             let mut verification_msg = Vec::new();
-            verification_msg.write_all(g_a.as_ref()).unwrap();
+            verification_msg.write_all(&g_a[..]).unwrap();
             verification_msg.write_all(&msg2.g_b).unwrap();
             verification_msg.write_all(&vk).unwrap();
+            if let Some(nonce) = msg2.nonce.as_ref() {
+                verification_msg.write_all(&nonce[..]).unwrap();
+            }
             let verification_digest = sha256(&verification_msg[..]);
 
-            // Obtain Quote
-            let quote = Self::get_quote(&verification_digest[..], client_stream)?;
-
-            // Send MAC for msg3 to client
-            let msg3 = RaMsg3::new(&smk, 
-                                   g_a,
-                                   None, 
-                                   quote);
-            client_stream.write_all(&msg3.mac).unwrap();
-
-            Ok((sk, mk))
+            // REPORTDATA = SHA-256(g_a || g_b || vk) || SHA-256(bound data)
+            let mut report_data = Vec::new();
+            report_data.write_all(&verification_digest[..]).unwrap();
+            if let Some(digest) = self.bound_data_digest.as_ref() {
+                report_data.write_all(&digest[..]).unwrap();
+            }
+            Ok((smk, sk, mk, report_data))
         }
 
     /// Get quote from Quote Enclave. The length of report_data must be <= 64 bytes.
@@ -94,24 +197,63 @@ impl EnclaveRaContext {
 
         // Obtain QE's target info to build a report for local attestation. 
         // Then, send the report back to client.
-        let mut _report_data = [0u8; 64];
-        (&mut _report_data[..(report_data.len())]).copy_from_slice(report_data);
         let mut target_info = [0u8; Targetinfo::UNPADDED_SIZE];
-        client_stream.read_exact(&mut target_info).unwrap();
-        let target_info = Targetinfo::try_copy_from(&target_info).unwrap();
-        let report = Report::for_target(&target_info, &_report_data);
-        client_stream.write_all(report.as_ref()).unwrap();
+        client_stream.read_exact(&mut target_info)?;
+        let report = Self::report_for_qe(report_data, &target_info)?;
+        client_stream.write_all(report.as_ref())?;
 
         // Obtain quote and QE report from client 
         let mut quote = [0u8; size_of::<Quote>()];
-        client_stream.read_exact(&mut quote[..]).unwrap();
-        let qe_report_len = 432usize;
-        let mut qe_report = vec![0u8; qe_report_len];
-        client_stream.read_exact(&mut qe_report[..]).unwrap();
+        client_stream.read_exact(&mut quote[..])?;
+        let mut qe_report = vec![0u8; QE_REPORT_LEN];
+        client_stream.read_exact(&mut qe_report[..])?;
 
         // Verify that the report is generated by QE
-        local_attestation::verify_local_attest(&qe_report[..])
-            .map_err(|e| EnclaveRaError::LocalAttestation(e))?;
+        verify_qe_report(&qe_report[..])?;
         Ok(quote)
     }
+
+    // Report for local attestation with the QE whose target info was sent
+    pub(crate) fn report_for_qe(report_data: &[u8], target_info: &[u8])
+        -> EnclaveRaResult<Report> {
+            let mut _report_data = [0u8; 64];
+            (&mut _report_data[..(report_data.len())]).copy_from_slice(report_data);
+            let target_info = Targetinfo::try_copy_from(target_info).unwrap();
+            create_report(&target_info, &_report_data)
+        }
+
+    // Check the QE report that came with `quote`
+    pub(crate) fn check_quote(quote: &[u8], qe_report: &[u8]) -> EnclaveRaResult<Quote> {
+        verify_qe_report(qe_report)?;
+        let mut q = [0u8; size_of::<Quote>()];
+        q.copy_from_slice(quote);
+        Ok(q)
+    }
+}
+
+#[cfg(not(feature = "occlum"))]
+fn create_report(target_info: &Targetinfo, report_data: &[u8; 64]) -> EnclaveRaResult<Report> {
+    Ok(Report::for_target(target_info, report_data))
+}
+
+#[cfg(not(feature = "occlum"))]
+fn verify_qe_report(qe_report: &[u8]) -> EnclaveRaResult<()> {
+    local_attestation::verify_local_attest(qe_report)
+        .map_err(|e| EnclaveRaError::LocalAttestation(e))
+}
+
+#[cfg(feature = "occlum")]
+fn create_report(target_info: &Targetinfo, report_data: &[u8; 64]) -> EnclaveRaResult<Report> {
+    SgxDevice::open()
+        .and_then(|device| device.create_report(Some(target_info), report_data))
+        .map_err(|e| EnclaveRaError::SgxDevice(e))
+}
+
+#[cfg(feature = "occlum")]
+fn verify_qe_report(qe_report: &[u8]) -> EnclaveRaResult<()> {
+    let qe_report = Report::try_copy_from(qe_report)
+        .ok_or(EnclaveRaError::LocalAttestation(LocalAttestationError::IncorrectReportLength))?;
+    let device = SgxDevice::open().map_err(|e| EnclaveRaError::SgxDevice(e))?;
+    device.verify_report(&qe_report)
+        .map_err(|_| EnclaveRaError::LocalAttestation(LocalAttestationError::IntegrityError))
 }
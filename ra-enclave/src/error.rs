@@ -1,3 +1,5 @@
+use ra_common::msg::{AbortReason, WireError};
+
 #[derive(Debug)]
 pub enum EnclaveRaError {
     KeyExchange(sgx_crypto::key_exchange::KeError),
@@ -7,6 +9,17 @@ pub enum EnclaveRaError {
     LocalAttestation(LocalAttestationError),
     EnclaveNotTrusted,
     PseNotTrusted,
+    UnsupportedKdf(u16),
+    MissingChallengeNonce,
+    /// The system clock is set before 1970.
+    InvalidTime,
+    Serialization(bincode::Error),
+    /// The SP or the client aborted the attestation. Once MSG2 was received,
+    /// only aborts with a valid MAC under SMK, i.e. from the SP, are reported
+    /// as such; others fail with `IntegrityError`.
+    Aborted(AbortReason),
+    /// Occlum's /dev/sgx failed.
+    SgxDevice(std::io::Error),
 }
 
 impl std::convert::From<sgx_crypto::key_exchange::KeError> for EnclaveRaError {
@@ -17,6 +30,23 @@ impl std::convert::From<sgx_crypto::signature::SigError> for EnclaveRaError {
     fn from(e: sgx_crypto::signature::SigError) -> Self { Self::Signature(e) }
 }
 
+impl std::convert::From<bincode::Error> for EnclaveRaError {
+    fn from(e: bincode::Error) -> Self { Self::Serialization(e) }
+}
+
+impl std::convert::From<std::io::Error> for EnclaveRaError {
+    fn from(e: std::io::Error) -> Self { Self::Serialization(Box::new(bincode::ErrorKind::Io(e))) }
+}
+
+impl std::convert::From<WireError> for EnclaveRaError {
+    fn from(e: WireError) -> Self {
+        match e {
+            WireError::Serialization(e) => Self::Serialization(e),
+            WireError::Aborted(abort) => Self::Aborted(abort.reason),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum LocalAttestationError {
     IncorrectReportLength,
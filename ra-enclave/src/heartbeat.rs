@@ -0,0 +1,72 @@
+// Enclave side of continuous attestation: send the SP's HeartbeatMonitor a
+// fresh quote bound to the session every so often, see ra_common::heartbeat.
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use sgx_crypto::cmac::MacTag;
+use sgx_crypto::secure_channel::{SecureChannel, SecureTransport};
+use ra_common::heartbeat::{heartbeat_key, heartbeat_report_data, Heartbeat, HeartbeatAck};
+use ra_common::msg::WireMessage;
+use ra_common::session_keys::SessionKeys;
+use crate::context::EnclaveRaContext;
+use crate::error::EnclaveRaError;
+use crate::EnclaveRaResult;
+
+pub struct HeartbeatSender {
+    key: MacTag,
+    interval: Duration,
+    seq: u64,
+    last_sent: Instant,
+}
+
+impl HeartbeatSender {
+    /// For the session of `keys`, with a heartbeat due every `interval`,
+    /// counting from now.
+    pub fn new(keys: &SessionKeys, interval: Duration) -> Self {
+        Self {
+            key: heartbeat_key(keys),
+            interval,
+            seq: 0,
+            last_sent: Instant::now(),
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last_sent.elapsed() >= self.interval
+    }
+
+    /// Get a quote through the client at `client_stream`, send it to the SP
+    /// over `channel`, and wait for the SP's verdict. The client must answer
+    /// with `ClientRaContext::get_quote`, for the SPID and SigRL of the
+    /// session's MSG2. Fails with `EnclaveNotTrusted` if the SP revoked the
+    /// session.
+    pub fn send<T, S>(&mut self, channel: &mut SecureChannel<T>, client_stream: &mut S)
+        -> EnclaveRaResult<HeartbeatAck>
+        where T: SecureTransport, S: Read + Write {
+            self.seq += 1;
+            let report_data = heartbeat_report_data(&self.key, self.seq);
+            let quote = EnclaveRaContext::get_quote(&report_data[..], client_stream)?;
+            Heartbeat { seq: self.seq, quote: quote.to_vec() }.write_to(&mut *channel)?;
+            let ack = HeartbeatAck::read_from(&mut *channel)?;
+            if ack.seq != self.seq {
+                return Err(EnclaveRaError::IntegrityError);
+            }
+            self.last_sent = Instant::now();
+            if cfg!(feature = "verbose") {
+                eprintln!("Heartbeat {} acknowledged: {:?}", ack.seq, ack.quote_status);
+            }
+            if !ack.is_enclave_trusted {
+                return Err(EnclaveRaError::EnclaveNotTrusted);
+            }
+            Ok(ack)
+        }
+
+    /// `send` if the interval has elapsed.
+    pub fn send_if_due<T, S>(&mut self, channel: &mut SecureChannel<T>, client_stream: &mut S)
+        -> EnclaveRaResult<Option<HeartbeatAck>>
+        where T: SecureTransport, S: Read + Write {
+            if !self.is_due() {
+                return Ok(None);
+            }
+            self.send(channel, client_stream).map(Some)
+        }
+}
@@ -1,4 +1,19 @@
+#[cfg(not(feature = "occlum"))]
 pub mod local_attestation;
+#[cfg(not(feature = "occlum"))]
+pub mod sealing;
+#[cfg(feature = "occlum")]
+pub mod occlum;
+pub mod ra_tls;
+pub mod attester;
+pub mod secrets;
+pub mod heartbeat;
+pub mod reattest;
+pub mod nonblocking;
+#[cfg(feature = "sdk-bridge")]
+pub mod sdk_bridge;
+#[cfg(feature = "intel-compat")]
+pub mod compat;
 mod error;
 mod context;
 
@@ -0,0 +1,151 @@
+// The enclave's side of the attestation as a state machine, for enclaves
+// that talk to the client over a non-blocking stream and cannot spare a
+// thread per attestation.
+use std::io::{Read, Write};
+use std::mem::size_of;
+use sgx_isa::Targetinfo;
+use sgx_crypto::cmac::{Cmac, MacTag};
+use sgx_crypto::key_exchange::DHKEPublicKey;
+use ra_common::msg::{Quote, RaMsg2, RaMsg3, RaMsg4};
+use ra_common::nonblocking::{HandshakeStatus, NonBlockingIo};
+use ra_common::session_keys::SessionKeys;
+use crate::context::{EnclaveRaContext, QE_REPORT_LEN, authenticate_abort};
+use crate::error::EnclaveRaError;
+use crate::EnclaveRaResult;
+
+#[derive(Clone, Copy)]
+enum State {
+    SendGa,
+    ReadMsg2,
+    ReadTargetInfo,
+    SendReport,
+    ReadQuote,
+    SendMac,
+    ReadMsg4,
+    Done,
+}
+
+/// `EnclaveRaContext::do_attestation` on a non-blocking stream. Call
+/// `advance` whenever the stream is ready, until it returns `Done` or fails.
+pub struct EnclaveHandshake {
+    context: EnclaveRaContext,
+    state: State,
+    io: NonBlockingIo,
+    g_a: Option<DHKEPublicKey>,
+    // SMK, signing key, and master key, once MSG2 is verified
+    keys: Option<(Cmac, MacTag, MacTag)>,
+    report_data: Vec<u8>,
+}
+
+impl EnclaveHandshake {
+    pub fn new(context: EnclaveRaContext) -> Self {
+        Self {
+            context,
+            state: State::SendGa,
+            io: NonBlockingIo::new(),
+            g_a: None,
+            keys: None,
+            report_data: Vec::new(),
+        }
+    }
+
+    /// Read and write what `stream` allows without blocking. Returns the
+    /// signing key and the keys of the session when done, like
+    /// `do_attestation`. Panics if called after the handshake finished.
+    pub fn advance(&mut self, stream: &mut (impl Read+Write))
+        -> EnclaveRaResult<HandshakeStatus<(MacTag, SessionKeys)>> {
+            let r = self.step(stream);
+            match r {
+                Ok(HandshakeStatus::Done(_)) | Err(_) => self.state = State::Done,
+                _ => {},
+            }
+            r
+        }
+
+    fn step(&mut self, stream: &mut (impl Read+Write))
+        -> EnclaveRaResult<HandshakeStatus<(MacTag, SessionKeys)>> {
+            loop {
+                match self.state {
+                    State::SendGa => {
+                        if self.g_a.is_none() {
+                            let g_a = self.context.key_exchange.as_ref().unwrap()
+                                .get_public_key().to_owned();
+                            self.io.queue_bytes(&g_a[..]);
+                            self.g_a = Some(g_a);
+                        }
+                        if !self.io.flush(stream)? {
+                            return Ok(HandshakeStatus::NeedsWrite);
+                        }
+                        self.state = State::ReadMsg2;
+                    },
+                    State::ReadMsg2 => {
+                        let msg2: Option<RaMsg2> = self.io.read_msg(stream)?;
+                        let msg2 = match msg2 {
+                            Some(msg2) => msg2,
+                            None => return Ok(HandshakeStatus::NeedsRead),
+                        };
+                        let g_a = self.g_a.unwrap();
+                        let (smk, sk, mk, report_data) = self.context.verify_msg_2(&msg2, &g_a)?;
+                        self.keys = Some((smk, sk, mk));
+                        self.report_data = report_data;
+                        self.state = State::ReadTargetInfo;
+                    },
+                    State::ReadTargetInfo => {
+                        let target_info = self.io.read_exact(stream, Targetinfo::UNPADDED_SIZE)?;
+                        let target_info = match target_info {
+                            Some(target_info) => target_info,
+                            None => return Ok(HandshakeStatus::NeedsRead),
+                        };
+                        let report = EnclaveRaContext::report_for_qe(&self.report_data[..],
+                                                                     &target_info[..])?;
+                        self.io.queue_bytes(report.as_ref());
+                        self.state = State::SendReport;
+                    },
+                    State::SendReport => {
+                        if !self.io.flush(stream)? {
+                            return Ok(HandshakeStatus::NeedsWrite);
+                        }
+                        self.state = State::ReadQuote;
+                    },
+                    State::ReadQuote => {
+                        let quote_len = size_of::<Quote>();
+                        let quote = self.io.read_exact(stream, quote_len + QE_REPORT_LEN)?;
+                        let quote = match quote {
+                            Some(quote) => quote,
+                            None => return Ok(HandshakeStatus::NeedsRead),
+                        };
+                        let quote = EnclaveRaContext::check_quote(&quote[..quote_len],
+                                                                  &quote[quote_len..])?;
+                        let smk = &self.keys.as_ref().unwrap().0;
+                        let msg3 = RaMsg3::new(smk, self.g_a.unwrap(), None, quote);
+                        self.io.queue_bytes(&msg3.mac[..]);
+                        self.state = State::SendMac;
+                    },
+                    State::SendMac => {
+                        if !self.io.flush(stream)? {
+                            return Ok(HandshakeStatus::NeedsWrite);
+                        }
+                        self.state = State::ReadMsg4;
+                    },
+                    State::ReadMsg4 => {
+                        let smk = &self.keys.as_ref().unwrap().0;
+                        let msg4: Option<RaMsg4> = self.io.read_msg(stream)
+                            .map_err(|e| authenticate_abort(e, smk))?;
+                        let msg4 = match msg4 {
+                            Some(msg4) => msg4,
+                            None => return Ok(HandshakeStatus::NeedsRead),
+                        };
+                        if !msg4.is_enclave_trusted {
+                            return Err(EnclaveRaError::EnclaveNotTrusted);
+                        }
+                        if msg4.is_pse_manifest_trusted == Some(false) {
+                            return Err(EnclaveRaError::PseNotTrusted);
+                        }
+                        let (_, sk, mk) = self.keys.take().unwrap();
+                        return Ok(HandshakeStatus::Done((sk, SessionKeys::new(&mk))));
+                    },
+                    State::Done => panic!("EnclaveHandshake advanced after it finished"),
+                }
+            }
+        }
+}
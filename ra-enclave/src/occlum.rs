@@ -0,0 +1,124 @@
+// Access to SGX from inside the Occlum LibOS. Occlum runs enclave code as an
+// ordinary musl process and does not let it execute ENCLU, so reports and
+// quotes are obtained through the ioctls of its /dev/sgx device instead
+// (occlum/src/libos/src/fs/dev_fs/dev_sgx).
+use std::fs::File;
+use std::io::{Error, Result};
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use sgx_isa::{Targetinfo, Report};
+use ra_common::msg::{Gid, Quote, Spid};
+
+const SGX_DEVICE_PATH: &str = "/dev/sgx";
+
+const IOC_WRITE: u64 = 1;
+const IOC_READ: u64 = 2;
+
+// _IOC(dir, 's', nr, size) as in Linux's asm-generic/ioctl.h
+const fn ioc(dir: u64, nr: u64, size: usize) -> u64 {
+    (dir << 30) | ((size as u64) << 16) | ((b's' as u64) << 8) | nr
+}
+
+const SGXIOC_GET_EPID_GROUP_ID: u64 = ioc(IOC_READ, 1, size_of::<Gid>());
+const SGXIOC_GEN_QUOTE: u64 = ioc(IOC_READ | IOC_WRITE, 2, size_of::<GenQuoteArg>());
+const SGXIOC_SELF_TARGET: u64 = ioc(IOC_READ, 3, Targetinfo::UNPADDED_SIZE);
+const SGXIOC_CREATE_REPORT: u64 = ioc(IOC_READ | IOC_WRITE, 4, size_of::<CreateReportArg>());
+const SGXIOC_VERIFY_REPORT: u64 = ioc(IOC_WRITE, 5, Report::UNPADDED_SIZE);
+
+// sgxioc_gen_epid_quote_arg_t
+#[repr(C)]
+struct GenQuoteArg {
+    report_data: [u8; 64],
+    quote_type: u32,
+    spid: Spid,
+    nonce: [u8; 16],
+    sig_rl: *const u8,
+    sig_rl_len: u32,
+    quote_buf_len: u32,
+    quote_buf: *mut u8,
+}
+
+// sgxioc_create_report_arg_t
+#[repr(C)]
+struct CreateReportArg {
+    target_info: *const u8,
+    report_data: *const u8,
+    report: *mut u8,
+}
+
+/// Handle to Occlum's /dev/sgx.
+pub struct SgxDevice {
+    file: File,
+}
+
+impl SgxDevice {
+    pub fn open() -> Result<Self> {
+        Ok(Self { file: File::open(SGX_DEVICE_PATH)? })
+    }
+
+    pub fn epid_group_id(&self) -> Result<Gid> {
+        let mut gid = [0u8; size_of::<Gid>()];
+        self.ioctl(SGXIOC_GET_EPID_GROUP_ID, gid.as_mut_ptr())?;
+        Ok(gid)
+    }
+
+    pub fn self_target(&self) -> Result<Targetinfo> {
+        let mut target_info = vec![0u8; Targetinfo::UNPADDED_SIZE];
+        self.ioctl(SGXIOC_SELF_TARGET, target_info.as_mut_ptr())?;
+        // Can unwrap since the length is correct
+        Ok(Targetinfo::try_copy_from(&target_info[..]).unwrap())
+    }
+
+    /// Same as `Report::for_target`. A report for the enclave itself is
+    /// created if `target_info` is None.
+    pub fn create_report(&self, target_info: Option<&Targetinfo>, report_data: &[u8; 64])
+        -> Result<Report> {
+            let mut report = vec![0u8; Report::UNPADDED_SIZE];
+            let mut arg = CreateReportArg {
+                target_info: target_info.map_or(ptr::null(), |ti| ti.as_ref().as_ptr()),
+                report_data: report_data.as_ptr(),
+                report: report.as_mut_ptr(),
+            };
+            self.ioctl(SGXIOC_CREATE_REPORT, &mut arg as *mut _ as *mut u8)?;
+            // Can unwrap since the length is correct
+            Ok(Report::try_copy_from(&report[..]).unwrap())
+        }
+
+    /// Check that `report` was created for this enclave on this CPU, like
+    /// `local_attestation::verify_report`.
+    pub fn verify_report(&self, report: &Report) -> Result<()> {
+        let report: &[u8] = report.as_ref();
+        let mut report = report[..Report::UNPADDED_SIZE].to_vec();
+        self.ioctl(SGXIOC_VERIFY_REPORT, report.as_mut_ptr())
+    }
+
+    /// Have Occlum obtain a quote from the QE through its own AESM
+    /// connection, without a `ClientRaContext` relaying the report. The QE
+    /// report is verified by Occlum.
+    pub fn gen_quote(&self, report_data: &[u8; 64], linkable: bool, spid: &Spid,
+                     nonce: &[u8; 16], sig_rl: Option<&[u8]>) -> Result<Quote> {
+        let mut quote = [0u8; size_of::<Quote>()];
+        let sig_rl = sig_rl.unwrap_or(&[]);
+        let mut arg = GenQuoteArg {
+            report_data: *report_data,
+            quote_type: linkable as u32,
+            spid: *spid,
+            nonce: *nonce,
+            sig_rl: if sig_rl.is_empty() { ptr::null() } else { sig_rl.as_ptr() },
+            sig_rl_len: sig_rl.len() as u32,
+            quote_buf_len: quote.len() as u32,
+            quote_buf: quote.as_mut_ptr(),
+        };
+        self.ioctl(SGXIOC_GEN_QUOTE, &mut arg as *mut _ as *mut u8)?;
+        Ok(quote)
+    }
+
+    fn ioctl(&self, request: u64, arg: *mut u8) -> Result<()> {
+        let ret = unsafe { libc::ioctl(self.file.as_raw_fd(), request as _, arg) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+// Self-signed X.509 certificates for RA-TLS: the certificate carries a quote
+// whose REPORTDATA starts with SHA-256 of the certificate's
+// SubjectPublicKeyInfo, so a TLS peer that verifies the quote knows the key
+// belongs to the attested enclave.
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sgx_crypto::random::RandomState;
+use sgx_crypto::signature::EcdsaSigningKey;
+use sgx_crypto::digest::sha256;
+use ra_common::msg::Quote;
+use crate::context::EnclaveRaContext;
+use crate::error::EnclaveRaError;
+use crate::EnclaveRaResult;
+
+/// Extension holding the raw quote, as in Intel's sgx-ra-tls.
+pub const QUOTE_OID: &[u64] = &[1, 2, 840, 113741, 1337, 6];
+const ECDSA_WITH_SHA256_OID: &[u64] = &[1, 2, 840, 10045, 4, 3, 2];
+const EC_PUBLIC_KEY_OID: &[u64] = &[1, 2, 840, 10045, 2, 1];
+const PRIME256V1_OID: &[u64] = &[1, 2, 840, 10045, 3, 1, 7];
+const COMMON_NAME_OID: &[u64] = &[2, 5, 4, 3];
+const COMMON_NAME: &str = "RA-TLS";
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_UTF8_STRING: u8 = 0x0c;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_EXPLICIT_0: u8 = 0xa0;
+const TAG_EXPLICIT_3: u8 = 0xa3;
+
+pub struct RaTlsCertificate {
+    pub key: EcdsaSigningKey,
+    pub cert_der: Vec<u8>,
+    pub quote: Quote,
+}
+
+/// Generate a key pair, have it quoted, and wrap both in a self-signed
+/// certificate valid from now for `valid_for`. The quote is obtained through
+/// `client_stream` like in `EnclaveRaContext::get_quote`, so the client must
+/// run `ClientRaContext::get_quote` on its side. The enclave's clock is
+/// untrusted, so verifiers should rely on the quote rather than the validity
+/// period.
+pub fn generate_certificate(client_stream: &mut (impl Read+Write), valid_for: Duration)
+    -> EnclaveRaResult<RaTlsCertificate> {
+        let rng = RandomState::new();
+        let key = EcdsaSigningKey::generate(&rng)?;
+        let spki = subject_public_key_info(key.public_key());
+        let quote = EnclaveRaContext::get_quote(&sha256(&spki[..])[..], client_stream)?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_err(|_| EnclaveRaError::InvalidTime)?;
+        let mut serial = [0u8; 8];
+        rng.fill(&mut serial[..]);
+        // Keep it positive and minimally encoded
+        serial[0] = (serial[0] & 0x7f) | 0x01;
+
+        let algorithm = der(TAG_SEQUENCE, &der_oid(ECDSA_WITH_SHA256_OID));
+        let name = der(TAG_SEQUENCE,
+                       &der(TAG_SET,
+                            &der(TAG_SEQUENCE,
+                                 &[der_oid(COMMON_NAME_OID),
+                                   der(TAG_UTF8_STRING, COMMON_NAME.as_bytes())].concat())));
+        let validity = der(TAG_SEQUENCE,
+                           &[der_time(now.as_secs()),
+                             der_time((now + valid_for).as_secs())].concat());
+        let extension = der(TAG_SEQUENCE,
+                            &[der_oid(QUOTE_OID),
+                              der(TAG_OCTET_STRING, &quote[..])].concat());
+        let extensions = der(TAG_EXPLICIT_3, &der(TAG_SEQUENCE, &extension));
+
+        let tbs = der(TAG_SEQUENCE, &[
+                      der(TAG_EXPLICIT_0, &der(TAG_INTEGER, &[2])), // v3
+                      der(TAG_INTEGER, &serial[..]),
+                      algorithm.clone(),
+                      name.clone(),
+                      validity,
+                      name,
+                      spki,
+                      extensions,
+        ].concat());
+        let signature = key.sign(&tbs[..], &rng)?;
+        let cert_der = der(TAG_SEQUENCE, &[
+                           tbs,
+                           algorithm,
+                           der_bit_string(&signature[..]),
+        ].concat());
+
+        Ok(RaTlsCertificate { key, cert_der, quote })
+    }
+
+fn subject_public_key_info(public_key: &[u8]) -> Vec<u8> {
+    let algorithm = der(TAG_SEQUENCE,
+                        &[der_oid(EC_PUBLIC_KEY_OID), der_oid(PRIME256V1_OID)].concat());
+    der(TAG_SEQUENCE, &[algorithm, der_bit_string(public_key)].concat())
+}
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let len_bytes = (len as u64).to_be_bytes();
+        let skip = len_bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (len_bytes.len() - skip) as u8);
+        out.extend_from_slice(&len_bytes[skip..]);
+    }
+    out.write_all(content).unwrap();
+    out
+}
+
+fn der_bit_string(bits: &[u8]) -> Vec<u8> {
+    let mut content = vec![0u8]; // no unused bits
+    content.extend_from_slice(bits);
+    der(TAG_BIT_STRING, &content[..])
+}
+
+fn der_oid(oid: &[u64]) -> Vec<u8> {
+    let mut content = Vec::new();
+    let mut arcs = vec![oid[0] * 40 + oid[1]];
+    arcs.extend_from_slice(&oid[2..]);
+    for arc in arcs {
+        let mut base128 = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            base128.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        content.extend(base128.iter().rev());
+    }
+    der(TAG_OID, &content[..])
+}
+
+/// UTCTime until 2049 and GeneralizedTime afterwards, as RFC 5280 requires.
+fn der_time(unix_secs: u64) -> Vec<u8> {
+    let (year, month, day) = civil_from_days((unix_secs / 86400) as i64);
+    let secs = unix_secs % 86400;
+    let time = format!("{:02}{:02}{:02}{:02}{:02}Z",
+                       month, day, secs / 3600, (secs / 60) % 60, secs % 60);
+    if year < 2050 {
+        der(TAG_UTC_TIME, format!("{:02}{}", year % 100, time).as_bytes())
+    } else {
+        der(TAG_GENERALIZED_TIME, format!("{:04}{}", year, time).as_bytes())
+    }
+}
+
+// Howard Hinnant's days-to-civil algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
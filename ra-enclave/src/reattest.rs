@@ -0,0 +1,73 @@
+// Enclave side of re-attestation: answer the SP's `Reattestation` over the
+// live SecureChannel of the session and switch the channel to the new keys.
+// The enclave forwards the protocol messages itself, so the client only
+// supplies what needs AESM, see `ClientRaContext::do_reattestation`.
+use std::io::{Read, Write};
+use sgx_crypto::cmac::MacTag;
+use sgx_crypto::secure_channel::{SecureChannel, SecureTransport};
+use ra_common::msg::{RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4, WireMessage, WireError};
+use ra_common::session_keys::SessionKeys;
+use crate::context::{EnclaveRaContext, authenticate_abort};
+use crate::error::EnclaveRaError;
+use crate::EnclaveRaResult;
+
+/// Run the attestation again with `context` over `channel`, when the
+/// application protocol says the SP starts one, e.g. after a request of its
+/// own. The client at `client_stream` must answer with
+/// `ClientRaContext::do_reattestation`. Returns the signing key and the keys
+/// of the new session, like `EnclaveRaContext::do_attestation`; the channel
+/// has already switched to their channel key. If the attestation fails, the
+/// channel keeps its keys, but the SP will close the session.
+pub fn respond<T, S>(mut context: EnclaveRaContext, channel: &mut SecureChannel<T>,
+                     client_stream: &mut S) -> EnclaveRaResult<(MacTag, SessionKeys)>
+    where T: SecureTransport, S: Read + Write {
+        let g_a = context.key_exchange.as_ref().unwrap().get_public_key().to_owned();
+        client_stream.write_all(&g_a[..])?;
+
+        // MSG0 and MSG1 come from the client, which knows the EPID group
+        let msg0 = RaMsg0::read_from(&mut *client_stream)?;
+        let msg1 = RaMsg1::read_from(&mut *client_stream)?;
+        if msg1.g_a[..] != g_a[..] {
+            return Err(EnclaveRaError::IntegrityError);
+        }
+        msg0.write_to(&mut *channel)?;
+        msg1.write_to(&mut *channel)?;
+        if cfg!(feature = "verbose") {
+            eprintln!("Re-attestation MSG0 and MSG1 sent");
+        }
+
+        let msg2 = match RaMsg2::read_from(&mut *channel) {
+            Ok(msg2) => msg2,
+            Err(WireError::Aborted(abort)) => {
+                // The client is waiting for MSG2
+                let _r = abort.write_to(&mut *client_stream);
+                return Err(EnclaveRaError::Aborted(abort.reason));
+            },
+            Err(e) => return Err(e.into()),
+        };
+        let (smk, sk, mk, report_data) = context.verify_msg_2(&msg2, &g_a)?;
+
+        // The client needs the SPID and SigRL of MSG2 for the quote
+        msg2.write_to(&mut *client_stream)?;
+        let quote = EnclaveRaContext::get_quote(&report_data[..], client_stream)?;
+        RaMsg3::new(&smk, g_a, None, quote).write_to(&mut *channel)?;
+        if cfg!(feature = "verbose") {
+            eprintln!("Re-attestation MSG3 sent");
+        }
+
+        let msg4 = RaMsg4::read_from(&mut *channel)
+            .map_err(|e| authenticate_abort(e, &smk))?;
+        if !msg4.is_enclave_trusted {
+            return Err(EnclaveRaError::EnclaveNotTrusted);
+        }
+        if msg4.is_pse_manifest_trusted == Some(false) {
+            return Err(EnclaveRaError::PseNotTrusted);
+        }
+        // The SP switches right after sending MSG4
+        let keys = SessionKeys::new(&mk);
+        channel.rekey(&keys.channel_key())?;
+        if cfg!(feature = "verbose") {
+            eprintln!("Re-attestation succeeded, channel rekeyed");
+        }
+        Ok((sk, keys))
+    }
@@ -0,0 +1,103 @@
+// Glue for hosting EnclaveRaContext in an Intel SGX SDK enclave, where the
+// enclave cannot open sockets and talks to the client through the ocalls
+// declared in edl/ra_bridge.edl. Reports and keys still come from sgx-isa, so
+// the enclave's Rust std must provide the ENCLU intrinsics that sgx-isa's
+// `sgxstd` feature relies on.
+use std::io::{Read, Write, Result, Error, ErrorKind};
+use sgx_crypto::cmac::MacTag;
+use ra_common::session_keys::SessionKeys;
+use crate::context::EnclaveRaContext;
+use crate::error::EnclaveRaError;
+
+const SGX_SUCCESS: u32 = 0;
+
+// Trusted proxies generated by edger8r
+extern "C" {
+    fn ra_ocall_read(retval: *mut i64, buf: *mut u8, len: usize) -> u32;
+    fn ra_ocall_write(retval: *mut i64, buf: *const u8, len: usize) -> u32;
+}
+
+/// The connection to the client, relayed by the host through ocalls.
+pub struct OcallStream;
+
+impl Read for OcallStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut retval = -1i64;
+        let status = unsafe { ra_ocall_read(&mut retval, buf.as_mut_ptr(), buf.len()) };
+        ocall_result(status, retval, buf.len())
+    }
+}
+
+impl Write for OcallStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut retval = -1i64;
+        let status = unsafe { ra_ocall_write(&mut retval, buf.as_ptr(), buf.len()) };
+        ocall_result(status, retval, buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// The host is untrusted, so its return value is checked against the buffer.
+fn ocall_result(status: u32, retval: i64, len: usize) -> Result<usize> {
+    if status != SGX_SUCCESS {
+        return Err(Error::new(ErrorKind::Other, "ocall failed"));
+    }
+    if retval < 0 || retval as u64 > len as u64 {
+        return Err(Error::new(ErrorKind::BrokenPipe, "Client connection failed"));
+    }
+    Ok(retval as usize)
+}
+
+/// Status code returned to the host by `ecall_ra_do_attestation`.
+pub fn error_code(e: &EnclaveRaError) -> u32 {
+    match e {
+        EnclaveRaError::KeyExchange(_) => 1,
+        EnclaveRaError::Signature(_) => 2,
+        EnclaveRaError::IntegrityError => 3,
+        EnclaveRaError::ReportDataLongerThan64Bytes => 4,
+        EnclaveRaError::LocalAttestation(_) => 5,
+        EnclaveRaError::EnclaveNotTrusted => 6,
+        EnclaveRaError::PseNotTrusted => 7,
+        EnclaveRaError::UnsupportedKdf(_) => 8,
+        EnclaveRaError::MissingChallengeNonce => 9,
+        EnclaveRaError::InvalidTime => 10,
+        EnclaveRaError::Serialization(_) => 11,
+        EnclaveRaError::Aborted(_) => 12,
+        EnclaveRaError::SgxDevice(_) => 13,
+    }
+}
+
+/// Run an attestation over `OcallStream` and hand the signing key and the
+/// session keys to `on_success`. The keys never leave the enclave.
+pub fn do_attestation<F>(sp_vkey_pem: &str, on_success: F) -> u32
+    where F: FnOnce(MacTag, SessionKeys) {
+        let result = EnclaveRaContext::init(sp_vkey_pem)
+            .and_then(|context| context.do_attestation(&mut OcallStream));
+        match result {
+            Ok((signing_key, keys)) => {
+                on_success(signing_key, keys);
+                0
+            },
+            Err(e) => error_code(&e),
+        }
+    }
+
+/// Define `ecall_ra_do_attestation` as declared in edl/ra_bridge.edl.
+/// `$on_success` receives the signing key and session keys, e.g. to store
+/// them in the enclave's state:
+///
+/// ```ignore
+/// ra_enclave::ra_ecalls!(SP_VKEY_PEM, |sk, keys| *KEYS.lock().unwrap() = Some((sk, keys)));
+/// ```
+#[macro_export]
+macro_rules! ra_ecalls {
+    ($sp_vkey_pem:expr, $on_success:expr) => {
+        #[no_mangle]
+        pub extern "C" fn ecall_ra_do_attestation() -> u32 {
+            $crate::sdk_bridge::do_attestation($sp_vkey_pem, $on_success)
+        }
+    };
+}
@@ -0,0 +1,136 @@
+// Sealed storage of the enclave's configuration. The blob is
+//   magic || version (u64) || isvsvn (u16) || cpusvn || keyid || AES-GCM(config)
+// in little-endian order, with everything before the ciphertext authenticated
+// as additional data. The seal key is bound to MRSIGNER, so that a new build
+// of the enclave signed by the same key can read the configs of older ones.
+use std::io::{Read, Write};
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use sgx_isa::{Keyname, Keypolicy, Keyrequest, Report};
+use sgx_crypto::random::RandomState;
+use sgx_crypto::aead::{self, AeadError};
+use ra_common::enclave_config::EnclaveConfig;
+
+const MAGIC: [u8; 4] = *b"RAEC";
+const CPUSVN_LEN: usize = 16;
+const KEYID_LEN: usize = 32;
+const HEADER_LEN: usize = 4 + 8 + 2 + CPUSVN_LEN + KEYID_LEN;
+// Same defaults as the Intel SGX SDK: the seal key depends on the INIT, DEBUG,
+// and MODE64BIT attributes and on the reserved attribute and MISCSELECT bits.
+const ATTRIBUTE_FLAGS_MASK: u64 = 0xff00_0000_0000_000b;
+const MISC_MASK: u32 = 0xf000_0000;
+
+#[derive(Debug)]
+pub enum SealError {
+    Serialization(bincode::Error),
+    Aead(AeadError),
+    /// EGETKEY failed, e.g. because the blob was sealed by a newer SVN.
+    KeyDerivation,
+    Malformed,
+    /// The blob is not newer than the config currently in use.
+    Rollback { current: u64, found: u64 },
+}
+
+impl std::convert::From<bincode::Error> for SealError {
+    fn from(e: bincode::Error) -> Self { Self::Serialization(e) }
+}
+
+impl std::convert::From<AeadError> for SealError {
+    fn from(e: AeadError) -> Self { Self::Aead(e) }
+}
+
+struct Header {
+    version: u64,
+    isvsvn: u16,
+    cpusvn: [u8; CPUSVN_LEN],
+    keyid: [u8; KEYID_LEN],
+}
+
+impl Header {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN);
+        out.write_all(&MAGIC[..]).unwrap();
+        out.write_u64::<LittleEndian>(self.version).unwrap();
+        out.write_u16::<LittleEndian>(self.isvsvn).unwrap();
+        out.write_all(&self.cpusvn[..]).unwrap();
+        out.write_all(&self.keyid[..]).unwrap();
+        out
+    }
+
+    fn decode(mut data: &[u8]) -> Result<Self, SealError> {
+        let mut magic = [0u8; 4];
+        data.read_exact(&mut magic[..]).map_err(|_| SealError::Malformed)?;
+        if magic != MAGIC {
+            return Err(SealError::Malformed);
+        }
+        let version = data.read_u64::<LittleEndian>().map_err(|_| SealError::Malformed)?;
+        let isvsvn = data.read_u16::<LittleEndian>().map_err(|_| SealError::Malformed)?;
+        let mut cpusvn = [0u8; CPUSVN_LEN];
+        data.read_exact(&mut cpusvn[..]).map_err(|_| SealError::Malformed)?;
+        let mut keyid = [0u8; KEYID_LEN];
+        data.read_exact(&mut keyid[..]).map_err(|_| SealError::Malformed)?;
+        Ok(Self { version, isvsvn, cpusvn, keyid })
+    }
+
+    fn seal_key(&self) -> Result<[u8; 16], SealError> {
+        let request = Keyrequest {
+            keyname: Keyname::Seal as _,
+            keypolicy: Keypolicy::MRSIGNER,
+            isvsvn: self.isvsvn,
+            cpusvn: self.cpusvn,
+            attributemask: [ATTRIBUTE_FLAGS_MASK, 0],
+            keyid: self.keyid,
+            miscmask: MISC_MASK,
+            ..Default::default()
+        };
+        request.egetkey().map_err(|_| SealError::KeyDerivation)
+    }
+}
+
+/// Seal `config` to this enclave's signer on this platform.
+pub fn seal_config(config: &EnclaveConfig) -> Result<Vec<u8>, SealError> {
+    let report = Report::for_self();
+    let rng = RandomState::new();
+    let mut keyid = [0u8; KEYID_LEN];
+    rng.fill(&mut keyid[..]);
+    let header = Header {
+        version: config.version,
+        isvsvn: report.isvsvn,
+        cpusvn: report.cpusvn,
+        keyid,
+    };
+
+    let header_bytes = header.encode();
+    let plaintext = bincode::serialize(config)?;
+    let ciphertext = aead::seal(&header.seal_key()?, &header_bytes[..],
+                                &plaintext[..], &rng)?;
+
+    let mut blob = header_bytes;
+    blob.extend_from_slice(&ciphertext[..]);
+    Ok(blob)
+}
+
+/// Unseal a blob produced by `seal_config`.
+pub fn unseal_config(blob: &[u8]) -> Result<EnclaveConfig, SealError> {
+    if blob.len() < HEADER_LEN {
+        return Err(SealError::Malformed);
+    }
+    let (header_bytes, ciphertext) = blob.split_at(HEADER_LEN);
+    let header = Header::decode(header_bytes)?;
+    let plaintext = aead::open(&header.seal_key()?, header_bytes, ciphertext)?;
+    let config: EnclaveConfig = bincode::deserialize(&plaintext[..])?;
+    if config.version != header.version {
+        return Err(SealError::Malformed);
+    }
+    Ok(config)
+}
+
+/// Unseal an updated config, refusing to go back to an older or the same
+/// version. Only protects against rollback while `current` is known: a
+/// restarted enclave has to start from the newest blob it is given.
+pub fn unseal_update(current: &EnclaveConfig, blob: &[u8]) -> Result<EnclaveConfig, SealError> {
+    let update = unseal_config(blob)?;
+    if update.version <= current.version {
+        return Err(SealError::Rollback { current: current.version, found: update.version });
+    }
+    Ok(update)
+}
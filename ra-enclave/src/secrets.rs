@@ -0,0 +1,32 @@
+// Enclave side of secret provisioning: receive the SecretBundle that the SP's
+// SecretProvisioner sends after a successful attestation and acknowledge it.
+use sgx_crypto::secure_channel::{SecureChannel, SecureTransport};
+use ra_common::msg::WireMessage;
+use ra_common::secrets::{SecretAck, SecretBundle};
+use crate::EnclaveRaResult;
+
+/// Receive the secrets sent by the SP over `channel`, keyed with the master
+/// key of the attestation, and acknowledge all of them. Secrets are zeroed
+/// when dropped.
+pub fn receive_secrets<T: SecureTransport>(channel: &mut SecureChannel<T>)
+    -> EnclaveRaResult<SecretBundle> {
+        receive_secrets_with(channel, |_| true)
+    }
+
+/// Like `receive_secrets`, but only keeps and acknowledges the secrets for
+/// which `accept` holds, e.g. those the enclave has a use for. The SP then
+/// fails the provisioning with the names of the others.
+pub fn receive_secrets_with<T, F>(channel: &mut SecureChannel<T>, accept: F)
+    -> EnclaveRaResult<SecretBundle>
+    where T: SecureTransport, F: Fn(&str) -> bool {
+        let mut bundle = SecretBundle::read_from(&mut *channel)?;
+        bundle.secrets.retain(|s| accept(&s.name));
+        let ack = SecretAck {
+            accepted: bundle.secrets.iter().map(|s| s.name.clone()).collect(),
+        };
+        ack.write_to(&mut *channel)?;
+        if cfg!(feature = "verbose") {
+            eprintln!("{} secrets received", bundle.secrets.len());
+        }
+        Ok(bundle)
+    }
@@ -0,0 +1,138 @@
+// Offline checks of attestation artifacts, for auditors and operators who
+// have the artifact but not the SP that produced it.
+//
+//   ra-inspect verify <bundle> --policy <policy.json> --ca <root CA>
+//                     [--at <seconds since the Unix epoch>]
+//
+// The bundle is the DER of an `Evidence`, e.g. from
+// `AttestationResponse::to_evidence`. The root CA, in PEM or DER, is Intel's
+// attestation report signing CA; the certificates in the bundle are not
+// trusted by themselves. `--at` checks the report signing certificate, and the
+// report's age against `max_report_age_secs`, as of that time instead of now,
+// e.g. the time the evidence was archived.
+//
+// The bundle does not carry the advisory IDs IAS reported with the report, so
+// a policy with `allowed_advisory_ids` rejects every quote status but "OK",
+// see `Policy::allowed_advisory_ids`.
+//
+// Exits with 0 if the evidence is trusted, 1 if it is rejected, and 2 if an
+// input cannot be read.
+mod policy_file;
+
+use std::path::Path;
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+use ra_verify::evidence::Evidence;
+use ra_verify::report::trust_anchor_from_der;
+use ra_verify::VerifyError;
+use crate::policy_file::read_policy;
+
+const USAGE: &str = "usage: ra-inspect verify <bundle> --policy <policy.json> --ca <root CA> \
+                     [--at <seconds since the Unix epoch>]";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2);
+}
+
+// Exit with 2 for an input that is not usable
+fn fail(path: &str, message: &str) -> ! {
+    eprintln!("{}: {}", path, message);
+    exit(2);
+}
+
+// DER of the first certificate of a PEM file, or the file itself if it is DER
+fn certificate_der(pem_or_der: Vec<u8>) -> Option<Vec<u8>> {
+    let pem = match std::str::from_utf8(&pem_or_der[..]) {
+        Ok(pem) if pem.contains("-----BEGIN CERTIFICATE-----") => pem,
+        _ => return Some(pem_or_der),
+    };
+    let b64: String = pem.lines()
+        .skip_while(|l| !l.starts_with("-----BEGIN CERTIFICATE-----"))
+        .skip(1)
+        .take_while(|l| !l.starts_with("-----END CERTIFICATE-----"))
+        .collect();
+    base64::decode(&b64).ok()
+}
+
+fn verify(args: &[String]) -> ! {
+    let mut bundle_path = None;
+    let mut policy_path = None;
+    let mut ca_path = None;
+    let mut time = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--policy" => policy_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--ca" => ca_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--at" => time = Some(args.next()
+                                  .and_then(|t| t.parse::<u64>().ok())
+                                  .unwrap_or_else(|| usage())),
+            _ if bundle_path.is_none() && !arg.starts_with("--") => bundle_path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let (bundle_path, policy_path, ca_path) = match (bundle_path, policy_path, ca_path) {
+        (Some(bundle), Some(policy), Some(ca)) => (bundle, policy, ca),
+        _ => usage(),
+    };
+    let time = time.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH)
+                                   .unwrap().as_secs());
+
+    let policy = read_policy(Path::new(policy_path))
+        .unwrap_or_else(|e| fail(policy_path, &e));
+    let ca = std::fs::read(ca_path)
+        .unwrap_or_else(|e| fail(ca_path, &format!("cannot be read: {}", e)));
+    let ca = certificate_der(ca)
+        .unwrap_or_else(|| fail(ca_path, "is not a valid PEM certificate"));
+    let trust_anchor = trust_anchor_from_der(&ca[..])
+        .unwrap_or_else(|_| fail(ca_path, "is not a valid certificate"));
+    let bundle = std::fs::read(bundle_path)
+        .unwrap_or_else(|e| fail(bundle_path, &format!("cannot be read: {}", e)));
+    let evidence = Evidence::from_der(&bundle[..])
+        .unwrap_or_else(|_| fail(bundle_path, "is not a DER evidence bundle"));
+
+    let verified = match evidence.verify(&[trust_anchor], time, &policy) {
+        Ok(verified) => verified,
+        Err(e) => {
+            let reason = match e {
+                VerifyError::InvalidCertificate =>
+                    "the report signing certificate does not chain to the root CA \
+                     or is not valid at the given time",
+                VerifyError::BadSignature => "the report signature is invalid",
+                VerifyError::MalformedReport => "the report is malformed",
+                VerifyError::MalformedQuote => "the quote is malformed",
+                VerifyError::MissingEndorsement => "the bundle has no report signing certificate",
+                VerifyError::Rejected(reason) => reason,
+                _ => "the evidence cannot be verified",
+            };
+            println!("{}: REJECTED: {}", bundle_path, reason);
+            exit(1);
+        },
+    };
+
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    let (quote, report) = (&verified.quote, &verified.report);
+    println!("{}: TRUSTED", bundle_path);
+    println!("report_id:      {}", report.id);
+    println!("timestamp:      {}", report.timestamp);
+    println!("quote_status:   {}", report.isv_enclave_quote_status);
+    println!("mr_enclave:     {}", hex(&quote.mr_enclave[..]));
+    println!("mr_signer:      {}", hex(&quote.mr_signer[..]));
+    println!("isv_prod_id:    {}", quote.isv_prod_id);
+    println!("isv_svn:        {}", quote.isv_svn);
+    println!("cpu_svn:        {}", hex(&quote.cpu_svn[..]));
+    println!("debug:          {}", quote.is_debug());
+    if evidence.channel_binding.is_some() {
+        println!("channel_binding matches REPORTDATA");
+    }
+    exit(0);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|a| a.as_str()) {
+        Some("verify") => verify(&args[1..]),
+        _ => usage(),
+    }
+}
@@ -0,0 +1,108 @@
+// A ra_verify::policy::Policy written down as JSON, with measurements in hex
+// and attributes by name:
+//
+//   {"mr_enclave": "<64 hex digits>", "mr_signer": "<64 hex digits>",
+//    "isv_prod_id": 0, "min_isv_svn": 1, "allow_debug": false,
+//    "min_cpu_svn": [{"epid_group_ids": ["00000b1c"],
+//                     "min_cpu_svn": "<32 hex digits>"}],
+//    "denied_attributes": ["PROVISIONKEY", "EINITTOKENKEY"],
+//    "quote_trust_options": ["GROUP_OUT_OF_DATE"],
+//    "allowed_advisory_ids": ["INTEL-SA-00334"],
+//    "max_report_age_secs": 86400}
+//
+// Every field is optional, as in Policy. Unknown fields are rejected, so that
+// a misspelled field does not silently leave a check out.
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use ra_verify::policy::{Policy, CpuSvnRequirement};
+use ra_verify::quote::AttributeFlags;
+
+const ATTRIBUTES: &[(&str, AttributeFlags)] = &[
+    ("INIT", AttributeFlags::INIT),
+    ("DEBUG", AttributeFlags::DEBUG),
+    ("MODE64BIT", AttributeFlags::MODE64BIT),
+    ("PROVISIONKEY", AttributeFlags::PROVISIONKEY),
+    ("EINITTOKENKEY", AttributeFlags::EINITTOKENKEY),
+    ("CET", AttributeFlags::CET),
+    ("KSS", AttributeFlags::KSS),
+];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    mr_enclave: Option<String>,
+    mr_signer: Option<String>,
+    isv_prod_id: Option<u16>,
+    min_isv_svn: Option<u16>,
+    #[serde(default)]
+    allow_debug: bool,
+    #[serde(default)]
+    min_cpu_svn: Vec<CpuSvnFile>,
+    #[serde(default)]
+    denied_attributes: Vec<String>,
+    #[serde(default)]
+    quote_trust_options: Vec<String>,
+    allowed_advisory_ids: Option<Vec<String>>,
+    max_report_age_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CpuSvnFile {
+    #[serde(default)]
+    epid_group_ids: Vec<String>,
+    min_cpu_svn: String,
+}
+
+/// Read the policy at `path`, or say what is wrong with it.
+pub fn read_policy(path: &Path) -> Result<Policy, String> {
+    let json = fs::read(path).map_err(|e| format!("cannot be read: {}", e))?;
+    let file: PolicyFile = serde_json::from_slice(&json[..])
+        .map_err(|e| format!("is not a valid policy: {}", e))?;
+
+    let mut min_cpu_svn = Vec::new();
+    for (i, requirement) in file.min_cpu_svn.iter().enumerate() {
+        let mut epid_group_ids = Vec::new();
+        for gid in requirement.epid_group_ids.iter() {
+            epid_group_ids.push(from_hex(&format!("min_cpu_svn[{}].epid_group_ids", i), gid)?);
+        }
+        min_cpu_svn.push(CpuSvnRequirement {
+            epid_group_ids,
+            min_cpu_svn: from_hex(&format!("min_cpu_svn[{}].min_cpu_svn", i),
+                                  &requirement.min_cpu_svn)?,
+        });
+    }
+
+    let mut denied_attributes = AttributeFlags::empty();
+    for name in file.denied_attributes.iter() {
+        let flag = ATTRIBUTES.iter()
+            .find(|(known, _)| known == name)
+            .ok_or_else(|| format!("denied_attributes: unknown attribute {:?}", name))?;
+        denied_attributes |= flag.1;
+    }
+
+    Ok(Policy {
+        mr_enclave: file.mr_enclave.map(|m| from_hex("mr_enclave", &m)).transpose()?,
+        mr_signer: file.mr_signer.map(|m| from_hex("mr_signer", &m)).transpose()?,
+        isv_prod_id: file.isv_prod_id,
+        min_isv_svn: file.min_isv_svn,
+        allow_debug: file.allow_debug,
+        min_cpu_svn,
+        denied_attributes,
+        quote_trust_options: file.quote_trust_options,
+        allowed_advisory_ids: file.allowed_advisory_ids,
+        max_report_age_secs: file.max_report_age_secs,
+    })
+}
+
+fn from_hex<T>(field: &str, hex: &str) -> Result<T, String>
+    where for<'a> &'a [u8]: TryInto<T> {
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+            .collect::<Option<Vec<u8>>>();
+        bytes.and_then(|b| b.as_slice().try_into().ok())
+            .ok_or_else(|| format!("{}: {:?} is not hex of the right length", field, hex))
+    }
@@ -0,0 +1,19 @@
+// Latency of a full attestation as the SP sees it, from MSG0 to MSG4, with
+// IAS answered by `MockIas` on localhost and the enclave simulated in the
+// same process. It includes the SP's SigRL and report requests and the
+// verification of the signed report, but not Intel's response times or the
+// quoting enclave.
+use criterion::{criterion_group, criterion_main, Criterion};
+use ra_sp::{loopback_config, Loopback};
+
+fn attestation(c: &mut Criterion) {
+    let loopback = Loopback::start(loopback_config()).unwrap();
+    c.bench_function("attestation_mock_ias", |b| b.iter(|| {
+        let (sp, enclave) = loopback.attest(loopback.enclave());
+        sp.unwrap();
+        enclave.unwrap();
+    }));
+}
+
+criterion_group!(benches, attestation);
+criterion_main!(benches);
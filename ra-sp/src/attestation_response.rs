@@ -1,16 +1,25 @@
 use serde::Deserialize;
 use hyper::header::{HeaderMap, HeaderValue};
-use regex::Regex;
 use serde_json::Value;
 use sgx_crypto::certificate::X509Cert;
-use crate::error::AttestationError;
+use ra_common::msg::Quote;
+use ra_verify::evidence::Evidence;
+use ra_verify::report::parse_timestamp;
+use ra_verify::VerifyError;
+use crate::error::{AttestationError, SpRaError};
+use crate::SpRaResult;
 
-#[derive(Deserialize, Debug)]
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                            "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct AttestationResponse {
     // header
     pub advisory_url: Option<String>, 
     pub advisory_ids: Option<String>, 
     pub request_id: String,
+    /// When IAS sent the response, by IAS's clock, as an HTTP date.
+    pub date: Option<String>,
     // body
     pub id: String,
     pub timestamp: String,
@@ -23,13 +32,22 @@ pub struct AttestationResponse {
     pub platform_info_blob: Option<String>,
     pub nonce: Option<String>,
     pub epid_pseudonym: Option<String>,
+    // what the report was verified from, see `to_evidence`
+    #[serde(skip)]
+    pub raw_body: Vec<u8>,
+    #[serde(skip)]
+    pub signature: Vec<u8>,
+    #[serde(skip)]
+    pub certificates: Vec<Vec<u8>>,
 }
 
 impl AttestationResponse {
     pub fn from_response(root_ca_cert: &X509Cert,
                          headers: &HeaderMap, 
                          body: Vec<u8>) -> Result<Self, AttestationError> {
-        Self::verify_response(root_ca_cert, &headers, &body[..])?;
+        let (signature, certificates) =
+            Self::verify_response(root_ca_cert, &headers, &body[..])?;
+        let raw_body = body.clone();
 
         let body: Value = {
             let body = String::from_utf8(body).unwrap();
@@ -44,6 +62,7 @@ impl AttestationResponse {
                 advisory_ids: headers.get("advisory-ids").map(h),
                 advisory_url: headers.get("advisory-url").map(h),
                 request_id: headers.get("request-id").map(h).unwrap(),
+                date: headers.get("date").map(h),
                 // body
                 id: body["id"].as_str().unwrap().to_owned(),
                 timestamp: body["timestamp"].as_str().unwrap().to_owned(),
@@ -58,28 +77,72 @@ impl AttestationResponse {
                         platform_info_blob: body["platformInfoBlob"].as_str().map(b),
                         nonce: body["nonce"].as_str().map(b),
                         epid_pseudonym: body["epidPseudonym"].as_str().map(b),
+                        raw_body,
+                        signature,
+                        certificates,
             })
     }
 
+    /// Bundle the report with `quote` for relying parties that verify it
+    /// themselves, e.g. as `Evidence::to_der` in a certificate extension.
+    /// `channel_binding` is the data bound into REPORTDATA, if any.
+    pub fn to_evidence(&self, quote: &Quote, channel_binding: Option<&[u8]>) -> Evidence {
+        Evidence {
+            quote: quote.to_vec(),
+            report: self.raw_body.clone(),
+            report_signature: self.signature.clone(),
+            certificates: self.certificates.clone(),
+            channel_binding: channel_binding.map(|b| b.to_vec()),
+        }
+    }
+
+    /// The advisory IDs reported by IAS, e.g. "INTEL-SA-00334".
+    pub fn advisory_id_list(&self) -> Vec<&str> {
+        match self.advisory_ids.as_ref() {
+            Some(ids) => ids.split(',')
+                .map(|id| id.trim())
+                .filter(|id| !id.is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Check that IAS made the report within `max_skew_secs` of `now`, in
+    /// seconds since the Unix epoch. Fails with `ClockSkew` rather than
+    /// `ReportOutOfDate` if it is the SP's clock that appears wrong: IAS's
+    /// `Date` is as far off, or the report is from the future.
+    pub fn check_timestamp(&self, now: u64, max_skew_secs: u64) -> SpRaResult<()> {
+        let offset = |secs: u64| now as i64 - secs as i64;
+        let ias_offset = self.date.as_ref().and_then(|date| parse_http_date(date)).map(offset);
+        if let Some(ias_offset) = ias_offset {
+            if ias_offset.abs() as u64 > max_skew_secs {
+                return Err(SpRaError::ClockSkew(ias_offset));
+            }
+        }
+        let report_offset = parse_timestamp(&self.timestamp)
+            .map(offset)
+            .ok_or(SpRaError::Verify(VerifyError::MalformedReport))?;
+        match report_offset {
+            o if o.abs() as u64 <= max_skew_secs => Ok(()),
+            o if o < 0 && ias_offset.is_none() => Err(SpRaError::ClockSkew(o)),
+            _ => Err(SpRaError::ReportOutOfDate(self.timestamp.clone())),
+        }
+    }
+
     fn verify_response(root_ca_cert: &X509Cert, headers: &HeaderMap, 
-                       body: &[u8]) -> Result<(), AttestationError> {
+                       body: &[u8]) -> Result<(Vec<u8>, Vec<Vec<u8>>), AttestationError> {
         // Split certificates
-        let re = Regex::new("(-----BEGIN .*-----\\n)\
-                            ((([A-Za-z0-9+/]{4})*\
-                              ([A-Za-z0-9+/]{2}==|[A-Za-z0-9+/]{3}=)*\\n)+)\
-                            (-----END .*-----)").unwrap();
         let (certificate, ca_certificate) =  {
             let c = headers.get("x-iasreport-signing-certificate")
                 .unwrap().to_str().unwrap();
             let c = percent_encoding::percent_decode_str(c).decode_utf8().unwrap();
-            let c = re.find_iter(&c)
-                .map(|m| m.as_str().to_owned())
-                .collect::<Vec<String>>();
-            let mut c_iter = c.into_iter();
-            let certificate = c_iter.next().unwrap();
-            let certificate = X509Cert::new_from_pem(&certificate).unwrap();
-            let ca_certificate = c_iter.next().unwrap();
-            let ca_certificate = X509Cert::new_from_pem(&ca_certificate).unwrap();
+            let mut c_iter = X509Cert::new_chain_from_pem(&c)
+                .map_err(|_| AttestationError::InvalidIASCertificate)?
+                .into_iter();
+            let certificate = c_iter.next()
+                .ok_or(AttestationError::InvalidIASCertificate)?;
+            let ca_certificate = c_iter.next()
+                .ok_or(AttestationError::InvalidIASCertificate)?;
             (certificate, ca_certificate)
         };
 
@@ -98,6 +161,45 @@ impl AttestationResponse {
             headers.get("x-iasreport-signature").unwrap().to_str().unwrap()).unwrap();
         verification_key.verify(body, &signature[..])
             .map_err(|_| AttestationError::BadSignature)?;
-        Ok(())
+        let certificates = vec![certificate.as_ref().to_vec(), ca_certificate.as_ref().to_vec()];
+        Ok((signature, certificates))
+    }
+}
+
+// Seconds since the Unix epoch of an HTTP date (RFC 7231's IMF-fixdate), e.g.
+// "Sun, 06 Nov 1994 08:49:37 GMT"
+fn parse_http_date(date: &str) -> Option<u64> {
+    let fields: Vec<&str> = date.split_whitespace().collect();
+    if fields.len() != 6 || fields[5] != "GMT" {
+        return None;
+    }
+    let month = MONTHS.iter().position(|m| *m == fields[2])? + 1;
+    parse_timestamp(&format!("{}-{:02}-{:0>2}T{}", fields[3], month, fields[1], fields[4]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_http_dates() {
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784_111_777));
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+    }
+
+    #[test]
+    fn counts_leap_days() {
+        assert_eq!(parse_http_date("Sat, 29 Feb 2020 23:59:59 GMT"), Some(1_583_020_799));
+        assert_eq!(parse_http_date("Sun, 01 Mar 2020 00:00:00 GMT"), Some(1_583_020_800));
+    }
+
+    #[test]
+    fn rejects_other_date_formats() {
+        // Only IMF-fixdate, not the obsolete RFC 850 and asctime formats
+        for date in &["", "Sun, 06 Nov 1994 08:49:37 UTC", "Sunday, 06-Nov-94 08:49:37 GMT",
+                      "Sun Nov  6 08:49:37 1994", "Sun, 06 Foo 1994 08:49:37 GMT",
+                      "Sun, 06 Nov 1994 08:49 GMT"] {
+            assert_eq!(parse_http_date(date), None, "{}", date);
+        }
     }
 }
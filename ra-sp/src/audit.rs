@@ -0,0 +1,196 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::{json, Value};
+use ra_common::msg::{Gid, RaMsg1, Quote};
+use ra_common::quote::QuoteBody;
+use crate::attestation_response::AttestationResponse;
+use crate::error::{SpRaError, IasError};
+use crate::hooks::{AttestationHooks, HookResult};
+use crate::{AttestationResult, EnclaveIdentity, SessionId, SpRaResult};
+
+/// Audit trail of attestations as JSON lines, one event per line, for
+/// ingestion by a SIEM. Unlike the `verbose` feature's debug output, the
+/// events have a fixed shape and contain no secrets:
+///
+/// ```text
+/// {"time":1700000000,"session":7,"event":"attestation_started","gid":"..."}
+/// {"time":...,"event":"quote_received","enclave":{"mr_enclave":"...",...}}
+/// {"time":...,"event":"report_verified","report_id":"...","quote_status":"OK",...}
+/// {"time":...,"event":"attestation_succeeded","verdict":"trusted",...}
+/// {"time":...,"event":"attestation_failed","verdict":"rejected","error":"..."}
+/// ```
+///
+/// Events of one attestation share the `session` field, its
+/// `SpRaContext::session_id`. Install with `SpIdentity::set_hooks`; other
+/// hooks are still called through `with_hooks`.
+///
+/// An attestation whose start, quote, or report cannot be audited is vetoed,
+/// so that no enclave is trusted without a trace. Events that are lost
+/// anyway, e.g. of failures, are counted by `lost_events`, and the SP reports
+/// itself as not ready while the last event could not be written, see
+/// `AttestationHooks::check_ready`.
+pub struct AuditLog {
+    sink: Mutex<Box<dyn Write + Send>>,
+    lost_events: AtomicU64,
+    failing: AtomicBool,
+    inner: Option<Arc<dyn AttestationHooks>>,
+}
+
+impl AuditLog {
+    pub fn new(sink: impl Write + Send + 'static) -> Self {
+        Self {
+            sink: Mutex::new(Box::new(sink)),
+            lost_events: AtomicU64::new(0),
+            failing: AtomicBool::new(false),
+            inner: None,
+        }
+    }
+
+    /// Events that could not be written to the sink.
+    pub fn lost_events(&self) -> u64 {
+        self.lost_events.load(Ordering::SeqCst)
+    }
+
+    /// Append events to the file at `path`, creating it if needed.
+    pub fn to_file(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self::new(file))
+    }
+
+    /// Forward every callback to `hooks` after auditing it. A veto by `hooks`
+    /// is audited as the attestation's failure.
+    pub fn with_hooks(mut self, hooks: Arc<dyn AttestationHooks>) -> Self {
+        self.inner = Some(hooks);
+        self
+    }
+
+    fn emit(&self, session: SessionId, event: &str, mut fields: Value) -> std::io::Result<()> {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        fields["time"] = json!(time);
+        fields["session"] = json!(session);
+        fields["event"] = json!(event);
+        let mut line = fields.to_string();
+        line.push('\n');
+        let mut sink = self.sink.lock().unwrap();
+        let r = sink.write_all(line.as_bytes()).and_then(|_| sink.flush());
+        if r.is_err() {
+            self.lost_events.fetch_add(1, Ordering::SeqCst);
+        }
+        self.failing.store(r.is_err(), Ordering::SeqCst);
+        r
+    }
+
+    // `emit` for hooks that can veto the attestation
+    fn emit_or_veto(&self, session: SessionId, event: &str, fields: Value) -> HookResult {
+        self.emit(session, event, fields)
+            .map_err(|e| format!("Cannot write audit event: {}", e))
+    }
+}
+
+impl AttestationHooks for AuditLog {
+    fn on_msg1(&self, session: SessionId, msg1: &RaMsg1) -> HookResult {
+        self.emit_or_veto(session, "attestation_started",
+                          json!({ "gid": hex::encode(&msg1.gid[..]) }))?;
+        self.inner.as_ref().map_or(Ok(()), |h| h.on_msg1(session, msg1))
+    }
+
+    fn on_revocation_data_unavailable(&self, session: SessionId, gid: &Gid, error: &IasError) {
+        let _r = self.emit(session, "revocation_data_unavailable", json!({
+            "gid": hex::encode(&gid[..]),
+            "error": format!("{:?}", error),
+        }));
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_revocation_data_unavailable(session, gid, error);
+        }
+    }
+
+    fn on_quote_received(&self, session: SessionId, quote: &Quote) -> HookResult {
+        if let Some(body) = QuoteBody::parse(&quote[..]) {
+            self.emit_or_veto(session, "quote_received", json!({
+                "enclave": EnclaveIdentity::from_quote_body(&body),
+            }))?;
+        }
+        self.inner.as_ref().map_or(Ok(()), |h| h.on_quote_received(session, quote))
+    }
+
+    fn on_report_verified(&self, session: SessionId, report: &AttestationResponse) -> HookResult {
+        self.emit_or_veto(session, "report_verified", json!({
+            "report_id": report.id,
+            "quote_status": report.isv_enclave_quote_status,
+            "advisory_ids": report.advisory_id_list(),
+        }))?;
+        self.inner.as_ref().map_or(Ok(()), |h| h.on_report_verified(session, report))
+    }
+
+    fn on_complete(&self, result: &AttestationResult) {
+        let _r = self.emit(result.session_id, "attestation_succeeded", json!({
+            "verdict": "trusted",
+            "enclave": result.enclave,
+            "quote_status": result.quote_status,
+            "advisory_ids": result.advisory_ids,
+            "report_id": result.report_id,
+        }));
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_complete(result);
+        }
+    }
+
+    fn on_failure(&self, session: SessionId, error: &SpRaError) {
+        let verdict = match error {
+            SpRaError::EnclaveNotTrusted |
+                SpRaError::RejectedByVerifier(_) |
+                SpRaError::SigstructMismatched |
+                SpRaError::EnclaveInDebugMode |
+                SpRaError::CpuSvnTooLow => "rejected",
+            SpRaError::Vetoed(_) => "vetoed",
+            _ => "error",
+        };
+        let _r = self.emit(session, "attestation_failed", json!({
+            "verdict": verdict,
+            "error": format!("{:?}", error),
+        }));
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_failure(session, error);
+        }
+    }
+
+    fn on_secret_delivered(&self, name: &str, result: &AttestationResult) {
+        let _r = self.emit(result.session_id, "secret_delivered", json!({
+            "secret": name,
+            "enclave": result.enclave,
+            "report_id": result.report_id,
+        }));
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_secret_delivered(name, result);
+        }
+    }
+
+    fn on_shutdown(&self) {
+        let _r = self.sink.lock().unwrap().flush();
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_shutdown();
+        }
+    }
+
+    fn on_reload(&self, result: &SpRaResult<()>) {
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_reload(result);
+        }
+    }
+
+    fn check_ready(&self) -> Result<(), String> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(format!("audit log failing, {} events lost", self.lost_events()));
+        }
+        self.inner.as_ref().map_or(Ok(()), |h| h.check_ready())
+    }
+}
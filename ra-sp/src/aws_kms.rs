@@ -0,0 +1,79 @@
+// Keys of encrypted configs from AWS KMS. The config is encrypted under a
+// data key generated by KMS, and the file carries that key as wrapped by the
+// KMS key; reading the config asks KMS to unwrap it, so the key only ever
+// leaves KMS for the process that is allowed to decrypt.
+use std::convert::TryInto;
+use tokio::runtime::{Builder, Runtime};
+use rusoto_core::Region;
+use rusoto_kms::{DecryptRequest, GenerateDataKeyRequest, Kms, KmsClient};
+use sgx_crypto::aead::AeadKey;
+use ra_common::encrypted_config::ConfigKeySource;
+
+/// `ConfigKeySource` unwrapping the data key of a config with AWS KMS.
+/// Credentials come from the usual AWS chain, e.g. `AWS_ACCESS_KEY_ID` or
+/// the instance profile. Calls to KMS block, so a source must not be used
+/// from async code.
+pub struct AwsKmsConfigKey {
+    client: KmsClient,
+}
+
+impl AwsKmsConfigKey {
+    pub fn new(region: Region) -> Self {
+        Self { client: KmsClient::new(region) }
+    }
+
+    /// Generate a data key under the KMS key `key_id`, e.g. an alias such as
+    /// "alias/ra-config". Returns the key to pass to
+    /// `ra_common::encrypted_config::encrypt_config` along with the wrapped
+    /// key to store with it.
+    pub fn generate_data_key(&self, key_id: &str) -> Result<(AeadKey, Vec<u8>), String> {
+        let request = GenerateDataKeyRequest {
+            key_id: key_id.to_owned(),
+            number_of_bytes: Some(16),
+            ..Default::default()
+        };
+        let response = runtime()?.block_on(self.client.generate_data_key(request))
+            .map_err(|e| format!("KMS cannot generate a data key: {}", e))?;
+        let key = response.plaintext
+            .ok_or_else(|| "KMS returned no data key".to_owned())?;
+        let wrapped_key = response.ciphertext_blob
+            .ok_or_else(|| "KMS returned no wrapped data key".to_owned())?;
+        let key = key.as_ref().try_into()
+            .map_err(|_| "the data key is not 16 bytes".to_owned())?;
+        Ok((key, wrapped_key.to_vec()))
+    }
+}
+
+impl Default for AwsKmsConfigKey {
+    /// In the region of `AWS_DEFAULT_REGION` or `AWS_REGION`.
+    fn default() -> Self {
+        Self::new(Region::default())
+    }
+}
+
+impl ConfigKeySource for AwsKmsConfigKey {
+    fn config_key(&self, wrapped_key: &[u8]) -> Result<AeadKey, String> {
+        if wrapped_key.is_empty() {
+            return Err("the config has no data key for KMS to unwrap".to_owned());
+        }
+        // KMS finds the KMS key from the wrapped key itself
+        let request = DecryptRequest {
+            ciphertext_blob: wrapped_key.to_vec().into(),
+            ..Default::default()
+        };
+        let response = runtime()?.block_on(self.client.decrypt(request))
+            .map_err(|e| format!("KMS cannot unwrap the data key: {}", e))?;
+        let key = response.plaintext
+            .ok_or_else(|| "KMS returned no data key".to_owned())?;
+        key.as_ref().try_into()
+            .map_err(|_| "the data key is not 16 bytes".to_owned())
+    }
+}
+
+fn runtime() -> Result<Runtime, String> {
+    Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("cannot start a runtime for KMS: {}", e))
+}
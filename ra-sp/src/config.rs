@@ -1,16 +1,675 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
 use serde::Deserialize;
+use serde_json::{Map, Value};
+use ra_verify::sigstruct::Sigstruct;
+use ra_verify::policy::CpuSvnRequirement;
+use sgx_crypto::signature::SigningKey;
+use sgx_crypto::certificate::X509Cert;
+use http::StatusCode;
+use tokio::runtime::Builder;
+use ra_common::config_file::read_config;
+use ra_common::encrypted_config::{read_encrypted_config, ConfigKeySource};
+use crate::error::{SpRaError, IasError};
+use crate::credentials::{Spid, SubscriptionKey};
+use crate::ias_pin::IasCertPin;
+use crate::ias::{IasClient, DEFAULT_IAS_BASE_URI};
+use crate::SpRaResult;
+
+/// Prefix of the variables read by `SpConfig::from_env`.
+pub const SP_CONFIG_ENV_PREFIX: &str = "RA_SP_";
+/// Default of `SpConfig::report_max_skew_secs`.
+pub const DEFAULT_REPORT_MAX_SKEW_SECS: u64 = 300;
+// Comma-separated in environment variables
+const LIST_FIELDS: &[&str] = &["quote_trust_options", "pse_trust_options",
+                                "allowed_advisory_ids", "ias_signing_cert_pins"];
+const BOOL_FIELDS: &[&str] = &["linkable", "random_nonce", "use_platform_service",
+                                "challenge_nonce", "prewarm_ias_connection",
+                                "allow_debug_enclaves"];
+// Unsigned integers in environment variables
+const NUMBER_FIELDS: &[&str] = &["verdict_cache_secs", "report_max_skew_secs",
+                                  "ias_max_in_flight"];
+// JSON in environment variables
+const JSON_FIELDS: &[&str] = &["tenants", "min_cpu_svn"];
+// Quote statuses of IAS that `quote_trust_options` may accept
+const TRUSTABLE_QUOTE_STATUSES: &[&str] = &["OK", "GROUP_OUT_OF_DATE",
+                                            "CONFIGURATION_NEEDED", "SW_HARDENING_NEEDED",
+                                            "CONFIGURATION_AND_SW_HARDENING_NEEDED"];
+const UNTRUSTABLE_QUOTE_STATUSES: &[&str] = &["SIGNATURE_INVALID", "GROUP_REVOKED",
+                                              "SIGNATURE_REVOKED", "KEY_REVOKED",
+                                              "SIGRL_VERSION_MISMATCH"];
+// Fields of the JSON config, their type, and whether they are required
+const FIELDS: &[(&str, FieldKind, bool)] = &[
+    ("linkable", FieldKind::Bool, true),
+    ("random_nonce", FieldKind::Bool, true),
+    ("use_platform_service", FieldKind::Bool, true),
+    ("spid", FieldKind::Spid, true),
+    ("primary_subscription_key", FieldKind::SubscriptionKey, true),
+    ("secondary_subscription_key", FieldKind::SubscriptionKey, true),
+    ("quote_trust_options", FieldKind::StringList, true),
+    ("pse_trust_options", FieldKind::StringList, false),
+    ("allowed_advisory_ids", FieldKind::StringList, false),
+    ("sp_private_key_pem_path", FieldKind::String, false),
+    ("ias_root_cert_pem_path", FieldKind::String, false),
+    ("sigstruct_path", FieldKind::String, false),
+    ("sp_private_key_pem_base64", FieldKind::String, false),
+    ("ias_root_cert_pem_base64", FieldKind::String, false),
+    ("sigstruct_base64", FieldKind::String, false),
+    ("tenants", FieldKind::Tenants, false),
+    ("challenge_nonce", FieldKind::Bool, false),
+    ("prewarm_ias_connection", FieldKind::Bool, false),
+    ("allow_debug_enclaves", FieldKind::Bool, false),
+    ("min_cpu_svn", FieldKind::CpuSvnFloors, false),
+    ("ias_base_uri", FieldKind::String, false),
+    ("ias_signing_cert_pins", FieldKind::IasCertPins, false),
+    ("report_max_skew_secs", FieldKind::Number, false),
+    ("verdict_cache_secs", FieldKind::Number, false),
+    ("revocation_check", FieldKind::RevocationCheck, false),
+    ("ias_max_in_flight", FieldKind::Number, false),
+];
+const TENANT_FIELDS: &[(&str, FieldKind)] = &[
+    ("name", FieldKind::String),
+    ("spid", FieldKind::Spid),
+    ("primary_subscription_key", FieldKind::SubscriptionKey),
+    ("secondary_subscription_key", FieldKind::SubscriptionKey),
+];
+// Never echoed in problems
+const SECRET_FIELDS: &[&str] = &["primary_subscription_key", "secondary_subscription_key",
+                                 "sp_private_key_pem_base64"];
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct SpConfig {
     pub linkable: bool, 
     pub random_nonce: bool,  
     pub use_platform_service: bool,
-    pub spid: String,
-    pub primary_subscription_key: String,
-    pub secondary_subscription_key: String,
+    pub spid: Spid,
+    pub primary_subscription_key: SubscriptionKey,
+    pub secondary_subscription_key: SubscriptionKey,
     pub quote_trust_options: Vec<String>, 
     pub pse_trust_options: Option<Vec<String>>,
+    /// If set, a quote status accepted through `quote_trust_options` is only
+    /// trusted when every advisory ID reported by IAS is in this list.
+    pub allowed_advisory_ids: Option<Vec<String>>,
+    #[serde(default)]
     pub sp_private_key_pem_path: String,
+    #[serde(default)]
     pub ias_root_cert_pem_path: String,
+    #[serde(default)]
     pub sigstruct_path: String,
+    /// Base64 of the file contents, used instead of the corresponding path
+    /// when set, e.g. when the config comes from environment variables.
+    pub sp_private_key_pem_base64: Option<String>,
+    pub ias_root_cert_pem_base64: Option<String>,
+    pub sigstruct_base64: Option<String>,
+    /// Additional SPIDs and subscription keys, selected per connection by the
+    /// tenant name the client sends in msg0.
+    pub tenants: Option<Vec<TenantConfig>>,
+    /// Send a fresh nonce in MSG2 that the enclave must hash into the quote's
+    /// REPORTDATA, as explicit proof that the quote was made for this session.
+    #[serde(default)]
+    pub challenge_nonce: bool,
+    /// Connect to IAS and check the subscription keys in `SpIdentity::init`,
+    /// so that the first attestation does not pay for DNS and the TLS
+    /// handshake, and unreachable IAS or bad keys are reported at startup.
+    #[serde(default)]
+    pub prewarm_ias_connection: bool,
+    /// Trust enclaves whose quote has the DEBUG attribute, whose memory the
+    /// host can read. Only for development.
+    #[serde(default)]
+    pub allow_debug_enclaves: bool,
+    /// CPUSVN floors the platform must meet, e.g. to require a microcode
+    /// update that ISVSVN cannot reflect. Every floor whose EPID groups
+    /// include the quote's must be met. No floor if unset.
+    pub min_cpu_svn: Option<Vec<CpuSvnFloor>>,
+    /// Base URI of the IAS API, e.g. the production API or a mock like
+    /// `MockIas`. Defaults to IAS's development API.
+    pub ias_base_uri: Option<String>,
+    /// Pins of IAS's report signing certificate, of which the certificate
+    /// must match one besides chaining to the root CA; see `IasCertPin` for
+    /// the format. List the new certificate's pin along with the current one
+    /// before IAS rotates it. No pinning if unset.
+    pub ias_signing_cert_pins: Option<Vec<IasCertPin>>,
+    /// How far, in seconds, the timestamp of a report fresh from IAS may be
+    /// from the SP's clock, allowing for skew between the clocks and for the
+    /// time the request took. Defaults to `DEFAULT_REPORT_MAX_SKEW_SECS`.
+    pub report_max_skew_secs: Option<u64>,
+    /// Reuse the IAS report of a trusted quote for this many seconds for
+    /// quotes of the same enclave, EPID group, and TCB, see `VerdictCache`
+    /// for what this gives up. Off if unset.
+    pub verdict_cache_secs: Option<u64>,
+    /// What to do when the SigRL of the client's EPID group cannot be
+    /// fetched. Defaults to `hard`.
+    #[serde(default)]
+    pub revocation_check: RevocationCheck,
+    /// Most IAS requests the SP sends at a time, so that a burst of
+    /// attestations does not trip Intel's rate limits. Requests beyond it
+    /// wait, taking turns across sessions. Unlimited if unset.
+    pub ias_max_in_flight: Option<u64>,
+}
+
+/// Strictness of the SP's revocation checks, i.e. of fetching SigRLs, which
+/// the platform needs to prove in its quote that it is not revoked. Only EPID
+/// SigRLs are covered: the PCK CRLs of ECDSA (DCAP) quotes are fetched by the
+/// QVL, see the `dcap-qvl` feature, and those of TD quotes come with the
+/// `TdPolicy` of the `TdxVerifier`. Both fail without their CRLs whatever
+/// this is set to.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RevocationCheck {
+    /// Fail the attestation if the SigRL cannot be fetched.
+    Hard,
+    /// Go on with the last cached SigRL, or none, and report the failure
+    /// to `AttestationHooks::on_revocation_data_unavailable`. IAS still
+    /// rejects a quote made with an outdated SigRL, with quote status
+    /// SIGRL_VERSION_MISMATCH, so this only helps groups without revoked
+    /// platforms, which are most.
+    Soft,
+    /// Never fetch SigRLs. Only for tests, e.g. against a mock IAS.
+    Skip,
+}
+
+impl Default for RevocationCheck {
+    fn default() -> Self {
+        RevocationCheck::Hard
+    }
+}
+
+/// A minimum CPUSVN for the platforms of some EPID groups, e.g.
+/// `{"epid_group_ids": ["00000b6a"], "min_cpu_svn": "0e0e0204ff8000000000000000000000"}`.
+/// CPUSVN is only comparable within a platform family, so it is compared
+/// component by component, see `ra_verify::policy::CpuSvnRequirement`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct CpuSvnFloor {
+    /// EPID group IDs of the family, 8 hex digits each, in the byte order of
+    /// the quote. Empty, or left out, to apply to every quote.
+    #[serde(default)]
+    pub epid_group_ids: Vec<String>,
+    /// 32 hex digits.
+    pub min_cpu_svn: String,
+}
+
+impl CpuSvnFloor {
+    /// The floor to check quotes against, or None if a field is not hex of
+    /// the right length.
+    pub fn requirement(&self) -> Option<CpuSvnRequirement> {
+        let mut min_cpu_svn = [0u8; 16];
+        hex::decode_to_slice(&self.min_cpu_svn, &mut min_cpu_svn).ok()?;
+        let epid_group_ids = self.epid_group_ids.iter()
+            .map(|gid| {
+                let mut bytes = [0u8; 4];
+                hex::decode_to_slice(gid, &mut bytes).ok().map(|_| bytes)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(CpuSvnRequirement { epid_group_ids, min_cpu_svn })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TenantConfig {
+    pub name: String,
+    pub spid: Spid,
+    pub primary_subscription_key: SubscriptionKey,
+    pub secondary_subscription_key: SubscriptionKey,
+}
+
+impl SpConfig {
+    /// Read a config in JSON, TOML, or YAML, by the file's extension; see
+    /// `ra_common::config_file` for the features TOML and YAML need. Fails
+    /// with `InvalidConfig` listing every field that is missing, unknown, or
+    /// of the wrong type, or naming the file's syntax error.
+    /// A `.enc` file, e.g. settings.json.enc, is decrypted with the key in
+    /// `RA_CONFIG_KEY`, see `ra_common::encrypted_config`.
+    pub fn from_file(path: &Path) -> SpRaResult<Self> {
+        Self::from_json(read_config(path)?)
+    }
+
+    /// Read an encrypted config with the key from `source`, e.g.
+    /// `AwsKmsConfigKey` with the aws-kms feature.
+    pub fn from_encrypted_file(path: &Path, source: &dyn ConfigKeySource) -> SpRaResult<Self> {
+        Self::from_json(read_encrypted_config(path, source)?)
+    }
+
+    /// Check `value` against the fields of `SpConfig` before deserializing
+    /// it, so that every mistake is reported with the field it is in.
+    pub fn from_json(value: Value) -> SpRaResult<Self> {
+        let problems = schema_problems(&value);
+        if !problems.is_empty() {
+            return Err(SpRaError::InvalidConfig(problems));
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Assemble the config from `RA_SP_<FIELD>` environment variables, e.g.
+    /// `RA_SP_SPID` or `RA_SP_SIGSTRUCT_BASE64`, for deployments where
+    /// mounting a settings file is awkward. List fields are comma-separated,
+    /// booleans are `true`/`false`/`1`/`0`, and `RA_SP_TENANTS` is JSON.
+    pub fn from_env() -> SpRaResult<Self> {
+        Self::from_env_with_prefix(SP_CONFIG_ENV_PREFIX)
+    }
+
+    pub fn from_env_with_prefix(prefix: &str) -> SpRaResult<Self> {
+        let mut fields = Map::new();
+        for (name, value) in env::vars() {
+            let field = match name.strip_prefix(prefix) {
+                Some(field) => field.to_lowercase(),
+                None => continue,
+            };
+            // Other variables may share the prefix
+            if !FIELDS.iter().any(|(known, _, _)| *known == field) {
+                continue;
+            }
+            let value = if LIST_FIELDS.contains(&field.as_str()) {
+                Value::Array(value.split(',')
+                             .map(|v| v.trim())
+                             .filter(|v| !v.is_empty())
+                             .map(|v| Value::String(v.to_owned()))
+                             .collect())
+            } else if BOOL_FIELDS.contains(&field.as_str()) {
+                match value.as_str() {
+                    "true" | "1" => Value::Bool(true),
+                    "false" | "0" => Value::Bool(false),
+                    _ => return Err(SpRaError::InvalidConfigValue(
+                            format!("{} is {:?}, expected true, false, 1, or 0", name, value))),
+                }
+            } else if NUMBER_FIELDS.contains(&field.as_str()) {
+                match value.parse::<u64>() {
+                    Ok(n) => Value::from(n),
+                    Err(_) => return Err(SpRaError::InvalidConfigValue(
+                            format!("{} is {:?}, expected a non-negative integer", name, value))),
+                }
+            } else if JSON_FIELDS.contains(&field.as_str()) {
+                // Not echoed, tenants hold subscription keys
+                serde_json::from_str(&value)
+                    .map_err(|e| SpRaError::InvalidConfigValue(
+                            format!("{} is not valid JSON: {}", name, e)))?
+            } else {
+                Value::String(value)
+            };
+            fields.insert(field, value);
+        }
+        Self::from_json(Value::Object(fields))
+    }
+
+    /// Load every key, certificate, and SIGSTRUCT the config points to and
+    /// check the config for consistency, so that a bad config is rejected
+    /// before it is put in use. Fails with `InvalidConfig` listing all of
+    /// `problems()`.
+    pub fn validate(&self) -> SpRaResult<()> {
+        let problems = self.problems();
+        if !problems.is_empty() {
+            return Err(SpRaError::InvalidConfig(problems));
+        }
+        Ok(())
+    }
+
+    /// Everything `validate` would reject, without contacting IAS.
+    pub fn problems(&self) -> Vec<ConfigProblem> {
+        // SpIdentity::init refuses these
+        let mut problems = self.unsupported_options();
+        let mut report = |field: &str, message: String| problems.push(ConfigProblem {
+            field: field.to_owned(),
+            message,
+        });
+
+        let sources = [
+            ("sp_private_key_pem_path", &self.sp_private_key_pem_path,
+             "sp_private_key_pem_base64", &self.sp_private_key_pem_base64,
+             self.sp_private_key().err()),
+            ("ias_root_cert_pem_path", &self.ias_root_cert_pem_path,
+             "ias_root_cert_pem_base64", &self.ias_root_cert_pem_base64,
+             self.ias_root_cert().err()),
+            ("sigstruct_path", &self.sigstruct_path,
+             "sigstruct_base64", &self.sigstruct_base64,
+             self.sigstruct().err()),
+        ];
+        for (path_field, path, base64_field, base64, error) in sources.iter() {
+            match (path.is_empty(), base64.is_some()) {
+                (false, true) => report(*path_field,
+                                        format!("and {} are mutually exclusive", base64_field)),
+                (true, false) => report(*path_field, format!("is missing, or set {}", base64_field)),
+                (false, false) if !Path::new(path.as_str()).is_file() =>
+                    report(*path_field, format!("{:?} is not a file", path)),
+                _ => if let Some(e) = error {
+                    let field = if base64.is_some() { *base64_field } else { *path_field };
+                    report(field, format!("cannot be loaded: {:?}", e));
+                },
+            }
+        }
+
+        for (i, tenant) in self.tenants.iter().flatten().enumerate() {
+            let field = format!("tenants[{}]", i);
+            if tenant.name.is_empty() {
+                report(&field, "has an empty name".to_owned());
+            }
+            if self.tenants.iter().flatten().take(i).any(|t| t.name == tenant.name) {
+                report(&field, format!("duplicates tenant {:?}", tenant.name));
+            }
+        }
+
+        for (i, floor) in self.min_cpu_svn.iter().flatten().enumerate() {
+            if floor.requirement().is_none() {
+                report(&format!("min_cpu_svn[{}]", i),
+                       "needs 8 hex digits per EPID group and 32 for the CPUSVN".to_owned());
+            }
+        }
+        if self.pse_trust_options.is_some() && !self.use_platform_service {
+            report("pse_trust_options", "has no effect without use_platform_service".to_owned());
+        }
+        for status in self.quote_trust_options.iter() {
+            if UNTRUSTABLE_QUOTE_STATUSES.contains(&status.as_str()) {
+                report("quote_trust_options", format!("trusts revoked or invalid quotes ({})", status));
+            } else if !TRUSTABLE_QUOTE_STATUSES.contains(&status.as_str()) {
+                report("quote_trust_options", format!("unknown quote status {:?}", status));
+            }
+        }
+        if self.allowed_advisory_ids.is_some() && self.quote_trust_options.is_empty() {
+            report("allowed_advisory_ids", "has no effect without quote_trust_options".to_owned());
+        }
+        if self.revocation_check == RevocationCheck::Skip && !self.allow_debug_enclaves {
+            report("revocation_check", "skip is only for tests, with allow_debug_enclaves".to_owned());
+        }
+        if let Some(uri) = self.ias_base_uri.as_ref() {
+            if !is_http_uri(uri) {
+                report("ias_base_uri", format!("{:?} is not an http or https URL", uri));
+            }
+        }
+        if self.verdict_cache_secs == Some(0) {
+            report("verdict_cache_secs", "is 0, leave it unset to disable the cache".to_owned());
+        }
+        if self.ias_max_in_flight == Some(0) {
+            report("ias_max_in_flight", "is 0, leave it unset for no limit".to_owned());
+        }
+        problems
+    }
+
+    // Options of the protocol that the SP does not implement
+    pub(crate) fn unsupported_options(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let mut report = |field: &str, message: &str| problems.push(ConfigProblem {
+            field: field.to_owned(),
+            message: message.to_owned(),
+        });
+        if !self.linkable {
+            report("linkable", "only linkable quotes are supported");
+        }
+        if self.random_nonce {
+            report("random_nonce", "is not supported");
+        }
+        if self.use_platform_service {
+            report("use_platform_service", "is not supported");
+        }
+        problems
+    }
+
+    /// Check with IAS that every subscription key, including those of the
+    /// tenants, is accepted. Blocks until IAS answered.
+    pub fn ias_problems(&self) -> SpRaResult<Vec<ConfigProblem>> {
+        let mut runtime = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()?;
+        let ias_client = self.ias_client()?;
+        let mut keys = vec![
+            ("primary_subscription_key".to_owned(), &self.primary_subscription_key),
+            ("secondary_subscription_key".to_owned(), &self.secondary_subscription_key),
+        ];
+        for (i, tenant) in self.tenants.iter().flatten().enumerate() {
+            keys.push((format!("tenants[{}].primary_subscription_key", i),
+                       &tenant.primary_subscription_key));
+            keys.push((format!("tenants[{}].secondary_subscription_key", i),
+                       &tenant.secondary_subscription_key));
+        }
+        let mut problems = Vec::new();
+        for (field, key) in keys {
+            let r = runtime.block_on(ias_client.warm_up(key, key));
+            let message = match r {
+                Ok(()) => continue,
+                Err(IasError::SigRLError(status)) if status == StatusCode::UNAUTHORIZED =>
+                    "is rejected by IAS".to_owned(),
+                Err(e) => format!("cannot be checked, IAS unreachable: {:?}", e),
+            };
+            problems.push(ConfigProblem { field, message });
+        }
+        Ok(problems)
+    }
+
+    pub(crate) fn sp_private_key(&self) -> SpRaResult<SigningKey> {
+        Ok(match self.sp_private_key_pem_base64.as_ref() {
+            Some(b64) => {
+                let mut pem = decode_pem("sp_private_key_pem_base64", b64)?;
+                let key = SigningKey::new_from_pem(&pem);
+                zero_string(&mut pem);
+                key?
+            },
+            None => SigningKey::new_from_pem_file(Path::new(&self.sp_private_key_pem_path))?,
+        })
+    }
+
+    pub(crate) fn ias_root_cert(&self) -> SpRaResult<X509Cert> {
+        Ok(match self.ias_root_cert_pem_base64.as_ref() {
+            Some(b64) => X509Cert::new_from_pem(
+                &decode_pem("ias_root_cert_pem_base64", b64)?)?,
+            None => X509Cert::new_from_pem_file(Path::new(&self.ias_root_cert_pem_path))?,
+        })
+    }
+
+    pub(crate) fn ias_client(&self) -> SpRaResult<IasClient> {
+        let base_uri = self.ias_base_uri.as_ref()
+            .map(|uri| uri.as_str())
+            .unwrap_or(DEFAULT_IAS_BASE_URI);
+        let mut client = IasClient::with_base_uri(self.ias_root_cert()?, base_uri)?;
+        if let Some(pins) = self.ias_signing_cert_pins.as_ref() {
+            client.set_signing_cert_pins(pins.clone());
+        }
+        Ok(client)
+    }
+
+    pub(crate) fn sigstruct(&self) -> SpRaResult<Sigstruct> {
+        let (field, bytes) = match self.sigstruct_base64.as_ref() {
+            Some(b64) => ("sigstruct_base64", base64::decode(b64)
+                .map_err(|_| SpRaError::InvalidConfigValue("sigstruct_base64".to_owned()))?),
+            None => {
+                let mut bytes = Vec::new();
+                File::open(Path::new(&self.sigstruct_path))?.read_to_end(&mut bytes)?;
+                ("sigstruct_path", bytes)
+            },
+        };
+        Sigstruct::parse(&bytes[..])
+            .ok_or_else(|| SpRaError::InvalidConfigValue(field.to_owned()))
+    }
+
+    /// Credentials for the given tenant, or the top-level ones for `None`.
+    pub fn tenant(&self, name: Option<&str>) -> Option<TenantConfig> {
+        match name {
+            None => Some(TenantConfig {
+                name: String::new(),
+                spid: self.spid,
+                primary_subscription_key: self.primary_subscription_key.clone(),
+                secondary_subscription_key: self.secondary_subscription_key.clone(),
+            }),
+            Some(name) => self.tenants.as_ref()?
+                .iter()
+                .find(|t| t.name == name)
+                .cloned(),
+        }
+    }
+}
+
+/// A config field that is invalid or inconsistent with the rest, e.g. as
+/// reported by `SpConfig::problems`.
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    pub field: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+        -> Result<(), std::fmt::Error> {
+            write!(f, "{} {}", self.field, self.message)
+        }
+}
+
+fn is_http_uri(uri: &str) -> bool {
+    match uri.parse::<http::Uri>() {
+        Ok(uri) => uri.authority().is_some() &&
+            (uri.scheme_str() == Some("http") || uri.scheme_str() == Some("https")),
+        Err(_) => false,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FieldKind {
+    Bool,
+    String,
+    StringList,
+    Number,
+    Spid,
+    SubscriptionKey,
+    Tenants,
+    RevocationCheck,
+    IasCertPins,
+    CpuSvnFloors,
+}
+
+impl FieldKind {
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            FieldKind::Bool => value.is_boolean(),
+            FieldKind::String => value.is_string(),
+            FieldKind::StringList => value.as_array()
+                .map_or(false, |list| list.iter().all(|v| v.is_string())),
+            FieldKind::Number => value.is_u64(),
+            FieldKind::Spid => value.as_str().map_or(false, |s| s.parse::<Spid>().is_ok()),
+            FieldKind::SubscriptionKey => value.as_str()
+                .map_or(false, |s| s.parse::<SubscriptionKey>().is_ok()),
+            // Checked field by field
+            FieldKind::Tenants => value.is_array(),
+            FieldKind::RevocationCheck => match value.as_str() {
+                Some("hard") | Some("soft") | Some("skip") => true,
+                _ => false,
+            },
+            FieldKind::IasCertPins => value.as_array()
+                .map_or(false, |list| list.iter().all(|v| v.as_str()
+                    .map_or(false, |s| s.parse::<IasCertPin>().is_ok()))),
+            FieldKind::CpuSvnFloors => serde_json::from_value::<Vec<CpuSvnFloor>>(value.clone())
+                .map_or(false, |floors| floors.iter().all(|f| f.requirement().is_some())),
+        }
+    }
+
+    fn expected(&self) -> &'static str {
+        match self {
+            FieldKind::Bool => "true or false",
+            FieldKind::String => "a string",
+            FieldKind::StringList => "a list of strings",
+            FieldKind::Number => "a non-negative integer",
+            FieldKind::Spid | FieldKind::SubscriptionKey => "32 hexadecimal digits",
+            FieldKind::Tenants => "a list of tenants",
+            FieldKind::RevocationCheck => "\"hard\", \"soft\", or \"skip\"",
+            FieldKind::IasCertPins =>
+                "a list of sha256/<base64 SPKI hash> or 64-hex-digit certificate hashes",
+            FieldKind::CpuSvnFloors =>
+                "a list of {\"epid_group_ids\": [8 hex digits, ...], \"min_cpu_svn\": 32 hex digits}",
+        }
+    }
+}
+
+// Missing, unknown, and mistyped fields of a JSON config
+fn schema_problems(value: &Value) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    let fields = match value.as_object() {
+        Some(fields) => fields,
+        None => {
+            problems.push(ConfigProblem {
+                field: "config".to_owned(),
+                message: "must be a JSON object".to_owned(),
+            });
+            return problems;
+        },
+    };
+    let mut report = |field: &str, message: String| problems.push(ConfigProblem {
+        field: field.to_owned(),
+        message,
+    });
+    for (name, kind, required) in FIELDS.iter() {
+        match fields.get(*name) {
+            None | Some(Value::Null) if *required => report(name, "is missing".to_owned()),
+            None | Some(Value::Null) => {},
+            Some(value) if !kind.matches(value) =>
+                report(name, format!("is {}, expected {}", shown(name, value), kind.expected())),
+            Some(_) => {},
+        }
+    }
+    for name in fields.keys() {
+        if !FIELDS.iter().any(|(field, _, _)| field == name) {
+            report(name, "is not a known field".to_owned());
+        }
+    }
+    let tenants = fields.get("tenants").and_then(|t| t.as_array());
+    for (i, tenant) in tenants.into_iter().flatten().enumerate() {
+        let tenant = match tenant.as_object() {
+            Some(tenant) => tenant,
+            None => {
+                report(&format!("tenants[{}]", i), "must be an object".to_owned());
+                continue;
+            },
+        };
+        for (name, kind) in TENANT_FIELDS.iter() {
+            let field = format!("tenants[{}].{}", i, name);
+            match tenant.get(*name) {
+                Some(value) if !kind.matches(value) =>
+                    report(&field, format!("is {}, expected {}", shown(name, value),
+                                           kind.expected())),
+                Some(_) => {},
+                None => report(&field, "is missing".to_owned()),
+            }
+        }
+        for name in tenant.keys() {
+            if !TENANT_FIELDS.iter().any(|(field, _)| field == name) {
+                report(&format!("tenants[{}].{}", i, name), "is not a known field".to_owned());
+            }
+        }
+    }
+    problems
+}
+
+// The value of a field as it appears in a problem
+fn shown(field: &str, value: &Value) -> String {
+    if SECRET_FIELDS.contains(&field) {
+        return match value {
+            Value::Null => "null",
+            Value::Bool(_) => "a boolean",
+            Value::Number(_) => "a number",
+            Value::String(_) => "a string",
+            Value::Array(_) => "a list",
+            Value::Object(_) => "an object",
+        }.to_owned();
+    }
+    let mut shown = value.to_string();
+    if shown.len() > 64 {
+        shown.truncate(shown.char_indices().nth(61).map_or(shown.len(), |(i, _)| i));
+        shown.push_str("...");
+    }
+    shown
+}
+
+/// Overwrite a string that held a secret, e.g. a private key in PEM, before it
+/// is freed.
+pub(crate) fn zero_string(s: &mut String) {
+    // Zeros are valid UTF-8
+    for b in unsafe { s.as_mut_vec() }.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+    s.clear();
+}
+
+fn decode_pem(field: &str, b64: &str) -> SpRaResult<String> {
+    base64::decode(b64).ok()
+        .and_then(|pem| String::from_utf8(pem).ok())
+        .ok_or_else(|| SpRaError::InvalidConfigValue(field.to_owned()))
 }
@@ -1,87 +1,248 @@
-use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
 use std::convert::TryInto;
-use byteorder::{ReadBytesExt, LittleEndian};
-use sgxs::sigstruct;
+use std::future::Future;
+use std::mem::size_of;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use sgx_crypto::random::RandomState;
-use sgx_crypto::key_exchange::{OneWayAuthenticatedDHKE, DHKEPublicKey};
+use sgx_crypto::key_exchange::{derive_kdk, OneWayAuthenticatedDHKE, DHKEPublicKey};
 use sgx_crypto::cmac::{Cmac, MacTag};
-use sgx_crypto::signature::SigningKey;
-use sgx_crypto::certificate::X509Cert;
 use sgx_crypto::digest::{sha256, Sha256Digest};
-use ra_common::msg::{Spid, RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4};
-use ra_common::derive_secret_keys;
-use crate::ias::{IasClient};
-use crate::config::SpConfig;
-use crate::error::SpRaError;
-use crate::{SpRaResult, AttestationResult};
+use sgx_crypto::locked::Locked;
+use ra_common::msg::{Gid, Nonce, Quote, RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4, RaAbort, WireMessage};
+#[cfg(feature = "intel-compat")]
+use ra_common::compat::{self, IntelMsg01, IntelMsg2, IntelMsg3, IntelMsg4};
+use ra_common::quote::QuoteBody;
+use ra_common::{derive_secret_keys, DEFAULT_KDF_ID};
+use ra_common::session_keys::SessionKeys;
+#[cfg(feature = "ring-backend")]
+use ra_verify::tdx::TdReportBody;
+#[cfg(feature = "ring-backend")]
+use ra_verify::VerifyError;
+use crate::attestation_response::AttestationResponse;
+use crate::sig_rl_cache::SigRlCache;
+use crate::verdict_cache::{VerdictCache, VerdictKey};
+use crate::ias_scheduler::{self, IasSlot};
+use crate::hooks::AttestationHooks;
+use crate::verifier::{ReportVerifier, Verdict};
+use crate::quorum::VerifierQuorum;
+use crate::config::{SpConfig, TenantConfig, RevocationCheck, DEFAULT_REPORT_MAX_SKEW_SECS};
+use crate::identity::SpIdentity;
+use crate::ra_tls::{RaTlsEvidence, RaTlsAttestation};
+use crate::error::{SpRaError, IasError};
+use crate::{SpRaResult, AttestationResult, AttestationTimings, EnclaveIdentity};
+
+/// Identifies one `SpRaContext`, e.g. to correlate the hook calls of an
+/// attestation. Unique within the process.
+pub type SessionId = u64;
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+// Take an id for a new attestation, e.g. for an `SpServer` connection whose
+// attempts share one
+pub(crate) fn next_session_id() -> SessionId {
+    NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed)
+}
 
+/// One attestation with one client. Cheap to create from a shared
+/// `SpIdentity`; use `SpRaContext::init` for a standalone context.
 pub struct SpRaContext {
-    config: SpConfig,
-    sigstruct: sigstruct::Sigstruct,
-    ias_client: IasClient, 
-    sp_private_key: SigningKey, 
+    pub(crate) identity: Arc<SpIdentity>,
+    session_id: SessionId,
+    tenant: Option<TenantConfig>,
+    sig_rl_cache: Option<SigRlCache>,
+    verdict_cache: Option<VerdictCache>,
+    // Queue of the session's IAS requests, if they are limited
+    ias_slot: Option<IasSlot>,
+    hooks: Option<Arc<dyn AttestationHooks>>,
+    verifier: Option<Arc<dyn ReportVerifier>>,
+    quorum: Option<Arc<VerifierQuorum>>,
+    rejection: Option<String>,
     rng: RandomState,
     key_exchange: Option<OneWayAuthenticatedDHKE>,
     g_a: Option<DHKEPublicKey>,
     verification_digest: Option<Sha256Digest>,
-    smk: Option<Cmac>,
-    sk_mk: Option<(MacTag, MacTag)>,
+    bound_data_digest: Option<Sha256Digest>,
+    // Quote and IAS report of the attestation, once verified
+    quote_body: Option<QuoteBody>,
+    report: Option<AttestationResponse>,
+    cached_verdict: bool,
+    smk: Option<Locked<Cmac>>,
+    sk_mk: Option<Locked<(MacTag, MacTag)>>,
+    // When the attestation started and MSG2 was sent, for the timings
+    started_at: Option<Instant>,
+    msg2_sent_at: Option<Instant>,
+    timings: AttestationTimings,
 }
 
 impl SpRaContext {
-    pub fn init(mut config: SpConfig) -> SpRaResult<Self> {
-        assert!(config.linkable, "Only Linkable Quote supported");
-        assert!(!config.random_nonce, "Random nonces not supported");
-        assert!(!config.use_platform_service, "Platform service not supported");
-        if cfg!(feature = "verbose") {
-            eprintln!("==================SP Config==================");
-            eprintln!("{:#?}", config);
-            eprintln!("=============================================");
-        }
-
-        // Preparing for binary search
-        config.quote_trust_options.sort();
-        config.pse_trust_options.as_mut().map(|v| v.sort());
-
-        let sp_private_key = SigningKey::new_from_pem_file(
-            Path::new(&config.sp_private_key_pem_path))?;
-
-        let cert = X509Cert::new_from_pem_file(
-            Path::new(&config.ias_root_cert_pem_path))?;
+    pub fn init(config: SpConfig) -> SpRaResult<Self> {
+        Self::new(Arc::new(SpIdentity::init(config)?))
+    }
 
+    pub fn new(identity: Arc<SpIdentity>) -> SpRaResult<Self> {
         let rng = RandomState::new();
         let key_exchange = OneWayAuthenticatedDHKE::generate_keypair(&rng)?;
 
-        let mut sigstruct = File::open(Path::new(&config.sigstruct_path))?;
-        let sigstruct = sigstruct::read(&mut sigstruct)?;
-
         Ok(Self {
-            config,
-            sigstruct,
-            ias_client: IasClient::new(cert),
-            sp_private_key,
+            session_id: next_session_id(),
+            tenant: None,
+            sig_rl_cache: identity.sig_rl_cache.clone(),
+            verdict_cache: identity.verdict_cache.clone(),
+            ias_slot: identity.ias_scheduler.as_ref().map(|s| s.new_slot()),
+            hooks: identity.hooks.clone(),
+            verifier: identity.verifier.clone(),
+            quorum: identity.quorum.clone(),
+            identity,
+            rejection: None,
             rng,
             key_exchange: Some(key_exchange),
             g_a: None,
             verification_digest: None, 
+            bound_data_digest: None,
+            quote_body: None,
+            report: None,
+            cached_verdict: false,
             smk: None,
             sk_mk: None,
+            started_at: None,
+            msg2_sent_at: None,
+            timings: AttestationTimings::default(),
         })
     }
 
-    #[tokio::main]
-    pub async fn do_attestation(mut self, 
-                                mut client_stream: &mut (impl Read+Write)) 
+    /// The ID of this attestation, as passed to the hooks.
+    pub fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+
+    /// Run the attestation on the identity's runtime, so that it reuses the
+    /// IAS connections pooled there. Blocks the calling thread.
+    pub fn do_attestation(mut self, 
+                          client_stream: &mut (impl Read+Write)) 
+        -> SpRaResult<AttestationResult> {
+            let identity = self.identity.clone();
+            identity.runtime.handle().enter(|| {
+                futures::executor::block_on(self.attest_and_report(client_stream))
+            })
+        }
+
+    /// Like `do_attestation`, but with a client of Intel's sgx-ra-sample, or
+    /// any client speaking its protocol (see `ra_common::compat`), e.g. to
+    /// keep attesting existing C enclave apps during a migration. MSG2 is
+    /// signed with the key of `SpIdentity::set_intel_compat_key` and carries
+    /// no challenge nonce, the identity's `KeyDerivation` is not used, and the
+    /// quote binds no data, so `bound_data_digest` is zero. The quote must be
+    /// as long as `Quote`, i.e. signed without SigRL entries. Nothing is sent
+    /// if the attestation fails before MSG4, since the sample has no abort
+    /// message. Blocks the calling thread.
+    #[cfg(feature = "intel-compat")]
+    pub fn do_intel_attestation(mut self,
+                                client_stream: &mut (impl Read+Write))
         -> SpRaResult<AttestationResult> {
-            // Not using MSG0 for now.
-            let _msg0: RaMsg0 = bincode::deserialize_from(&mut client_stream)?;
+            let identity = self.identity.clone();
+            identity.runtime.handle().enter(|| {
+                futures::executor::block_on(self.attest_intel_and_report(client_stream))
+            })
+        }
+
+    /// Verify the certificate a peer presented in a TLS handshake, e.g. one
+    /// from ra-enclave's `ra_tls` or from a Gramine workload, against the same
+    /// policy as `do_attestation`. The TLS handshake must have succeeded with
+    /// this certificate, which proves the peer holds its key. Blocks the
+    /// calling thread.
+    pub fn verify_ra_tls_certificate(mut self, cert_der: &[u8])
+        -> SpRaResult<RaTlsAttestation> {
+            let evidence = RaTlsEvidence::from_certificate(cert_der)?;
+            let identity = self.identity.clone();
+            let (report, is_enclave_trusted) = identity.runtime.handle().enter(|| {
+                futures::executor::block_on(
+                    self.verify_quote(&evidence.quote, &evidence.quote_body, true))
+            })?;
+            if !is_enclave_trusted {
+                return Err(match self.rejection.take() {
+                    Some(reason) => SpRaError::RejectedByVerifier(reason),
+                    None => SpRaError::EnclaveNotTrusted,
+                });
+            }
+            Ok(RaTlsAttestation { evidence, report })
+        }
+
+    /// Verify the TD quote of an Intel TDX VM with the identity's
+    /// `TdxVerifier`: its signature chain, its QE and platform TCB against
+    /// Intel's collateral, and the TD policy. `report_data` is what the TD
+    /// must have bound to the quote, e.g. a hash of a nonce sent to it and
+    /// its TLS key.
+    #[cfg(feature = "ring-backend")]
+    pub fn verify_td_quote(self, quote: &[u8], report_data: &[u8; 64])
+        -> SpRaResult<TdReportBody> {
+            let verifier = self.identity.tdx_verifier.as_ref()
+                .ok_or(SpRaError::TdxNotConfigured)?;
+            let report = verifier.verify(quote)?;
+            if report.report_data[..] != report_data[..] {
+                return Err(VerifyError::Rejected("REPORTDATA mismatch").into());
+            }
+            Ok(report)
+        }
+
+    /// Have IAS verify `quote`, sent by an already attested enclave, e.g. in
+    /// a heartbeat, and decide on it with the same policy as
+    /// `do_attestation`, but never from the `VerdictCache`. Returns the IAS
+    /// report and why the enclave is no longer trusted, if it is not. Blocks
+    /// the calling thread.
+    pub(crate) fn verify_fresh_quote(mut self, quote: &Quote, quote_body: &QuoteBody)
+        -> SpRaResult<(AttestationResponse, Option<SpRaError>)> {
+            let identity = self.identity.clone();
+            let (report, is_enclave_trusted) = identity.runtime.handle().enter(|| {
+                futures::executor::block_on(self.verify_quote(quote, quote_body, false))
+            })?;
+            if is_enclave_trusted {
+                return Ok((report, None));
+            }
+            let rejection = match self.rejection.take() {
+                Some(reason) => SpRaError::RejectedByVerifier(reason),
+                None => SpRaError::EnclaveNotTrusted,
+            };
+            Ok((report, Some(rejection)))
+        }
+
+    async fn attest_and_report(&mut self, client_stream: &mut (impl Read+Write))
+        -> SpRaResult<AttestationResult> {
+            let result = self.attest(client_stream).await;
+            if let Some(abort) = result.as_ref().err().and_then(|e| self.abort_for(e)) {
+                let _r = abort.write_to(&mut *client_stream);
+            }
+            self.report_outcome(&result);
+            result
+        }
+
+    /// The abort to send the client when the attestation fails with `e`.
+    pub(crate) fn abort_for(&self, e: &SpRaError) -> Option<RaAbort> {
+        e.abort_reason().map(|reason| RaAbort::new(reason, self.smk.as_deref()))
+    }
+
+    pub(crate) fn report_outcome(&self, result: &SpRaResult<AttestationResult>) {
+        if let Some(hooks) = self.hooks.as_ref() {
+            match result.as_ref() {
+                Ok(result) => hooks.on_complete(result),
+                Err(e) => hooks.on_failure(self.session_id, e),
+            }
+        }
+    }
+
+    async fn attest(&mut self, mut client_stream: &mut (impl Read+Write))
+        -> SpRaResult<AttestationResult> {
+            self.start_timing();
+            let msg0 = RaMsg0::read_from(&mut client_stream)?;
             if cfg!(feature = "verbose") {
                 eprintln!("MSG0 received ");
             }
 
-            let msg1: RaMsg1 = bincode::deserialize_from(&mut client_stream)?;
+            self.select_tenant(msg0.tenant.as_ref().map(|t| t.as_str()))?;
+
+            let msg1 = RaMsg1::read_from(&mut client_stream)?;
+            self.msg1_received();
             if cfg!(feature = "verbose") {
                 eprintln!("MSG1 received");
             }
@@ -91,12 +252,14 @@ impl SpRaContext {
                 eprintln!("MSG1 processed");
             }
 
-            bincode::serialize_into(&mut client_stream, &msg2)?;
+            msg2.write_to(&mut client_stream)?;
+            self.msg2_sent();
             if cfg!(feature = "verbose") {
                 eprintln!("MSG2 sent");
             }
 
-            let msg3: RaMsg3 = bincode::deserialize_from(&mut client_stream)?;
+            let msg3 = RaMsg3::read_from(&mut client_stream)?;
+            self.msg3_received();
             if cfg!(feature = "verbose") {
                 eprintln!("MSG3 received");
             }
@@ -106,13 +269,80 @@ impl SpRaContext {
                 eprintln!("MSG4 generated");
             }
 
-            bincode::serialize_into(&mut client_stream, &msg4)?;
+            msg4.write_to(&mut client_stream)?;
             if cfg!(feature = "verbose") {
                 eprintln!("MSG4 sent");
             }
+            self.finish(&msg4, epid_pseudonym)
+        }
 
+    #[cfg(feature = "intel-compat")]
+    async fn attest_intel_and_report(&mut self, client_stream: &mut (impl Read+Write))
+        -> SpRaResult<AttestationResult> {
+            let result = self.attest_intel(client_stream).await;
+            self.report_outcome(&result);
+            result
+        }
+
+    #[cfg(feature = "intel-compat")]
+    async fn attest_intel(&mut self, client_stream: &mut (impl Read+Write))
+        -> SpRaResult<AttestationResult> {
+            self.start_timing();
+            let msg01 = IntelMsg01::decode(&compat::read_msg(client_stream)?[..])?;
+            self.msg1_received();
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG0 and MSG1 received");
+            }
+
+            let msg2 = self.process_intel_msg_01(msg01).await?;
+            compat::write_msg(client_stream, &msg2.encode()[..])?;
+            self.msg2_sent();
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG2 sent");
+            }
+
+            let msg3 = IntelMsg3::decode(&compat::read_msg(client_stream)?[..])?;
+            self.msg3_received();
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG3 received");
+            }
+
+            let (msg4, epid_pseudonym) = self.process_intel_msg_3(msg3).await?;
+            compat::write_msg(client_stream, &IntelMsg4::from_msg4(&msg4).encode()[..])?;
+            if cfg!(feature = "verbose") {
+                eprintln!("MSG4 sent");
+            }
+            self.finish(&msg4, epid_pseudonym)
+        }
+
+    // Marks of the handshake for the timings, by both the blocking and the
+    // non-blocking attestation
+    pub(crate) fn start_timing(&mut self) {
+        if self.started_at.is_none() {
+            self.started_at = Some(Instant::now());
+        }
+    }
+
+    pub(crate) fn msg1_received(&mut self) {
+        self.timings.msg1_wait = since(self.started_at);
+    }
+
+    pub(crate) fn msg2_sent(&mut self) {
+        self.msg2_sent_at = Some(Instant::now());
+    }
+
+    pub(crate) fn msg3_received(&mut self) {
+        self.timings.msg3_wait = since(self.msg2_sent_at);
+    }
+
+    /// The outcome of the attestation once MSG4 is sent.
+    pub(crate) fn finish(&mut self, msg4: &RaMsg4, epid_pseudonym: Option<String>)
+        -> SpRaResult<AttestationResult> {
             if !msg4.is_enclave_trusted {
-                return Err(SpRaError::EnclaveNotTrusted);
+                return Err(match self.rejection.take() {
+                    Some(reason) => SpRaError::RejectedByVerifier(reason),
+                    None => SpRaError::EnclaveNotTrusted,
+                });
             }
             match msg4.is_pse_manifest_trusted {
                 Some(t) => if !t {
@@ -121,59 +351,243 @@ impl SpRaContext {
                 None => {},
             }
 
-            let (signing_key, master_key) = self.sk_mk.take().unwrap();
+            let sk_mk = self.sk_mk.take().unwrap();
+            let report = self.report.take().unwrap();
+            self.timings.total = since(self.started_at);
+            let attested_at = SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
 
             Ok(AttestationResult {
+                session_id: self.session_id,
                 epid_pseudonym,
-                signing_key,
-                master_key,
+                signing_key: Locked::new(sk_mk.0)?,
+                keys: Locked::new(SessionKeys::new(&sk_mk.1))?,
+                bound_data_digest: self.bound_data_digest.take().unwrap(),
+                enclave: EnclaveIdentity::from_quote_body(self.quote_body.as_ref().unwrap()),
+                advisory_ids: report.advisory_id_list().iter().map(|id| id.to_string()).collect(),
+                advisory_url: report.advisory_url,
+                quote_status: report.isv_enclave_quote_status,
+                report_id: report.id,
+                report_timestamp: report.timestamp,
+                attested_at,
+                cached_verdict: self.cached_verdict,
+                timings: self.timings.clone(),
             })
         }
 
+    /// Override the identity's hooks for this connection only.
+    pub fn set_hooks(&mut self, hooks: Arc<dyn AttestationHooks>) {
+        self.hooks = Some(hooks);
+    }
+
+    fn are_advisories_allowed(&self, attestation_result: &AttestationResponse) -> bool {
+        match self.identity.config.allowed_advisory_ids.as_ref() {
+            Some(allowed) => attestation_result.advisory_id_list()
+                .into_iter()
+                .all(|id| allowed.binary_search_by(|a| a.as_str().cmp(id)).is_ok()),
+            None => true,
+        }
+    }
+
+    /// Override the identity's report verifier for this connection only.
+    pub fn set_report_verifier(&mut self, verifier: Arc<dyn ReportVerifier>) {
+        self.verifier = Some(verifier);
+    }
+
+    fn run_hook<F>(&self, hook: F) -> SpRaResult<()>
+        where F: FnOnce(&dyn AttestationHooks) -> Result<(), String> {
+            match self.hooks.as_ref() {
+                Some(hooks) => hook(hooks.as_ref()).map_err(|reason| SpRaError::Vetoed(reason)),
+                None => Ok(()),
+            }
+        }
+
+    /// Override the identity's SigRL cache for this connection only, e.g.
+    /// with a cache of the connection's tenant. It is only used if it has
+    /// the tenant's subscription keys, see `SigRlCache`.
+    pub fn set_sig_rl_cache(&mut self, cache: SigRlCache) {
+        self.sig_rl_cache = Some(cache);
+    }
+
+    // Pass `id` to the hooks instead of the context's own, e.g. for every
+    // attempt of one connection
+    pub(crate) fn set_session_id(&mut self, id: SessionId) {
+        self.session_id = id;
+    }
+
+    /// Pick the SPID and IAS credentials for this connection. Defaults to the
+    /// top-level ones if never called.
+    pub fn select_tenant(&mut self, name: Option<&str>) -> SpRaResult<()> {
+        let tenant = self.identity.config.tenant(name)
+            .ok_or_else(|| SpRaError::UnknownTenant(name.unwrap_or("").to_owned()))?;
+        self.tenant = Some(tenant);
+        Ok(())
+    }
+
+    fn credentials(&mut self) -> &TenantConfig {
+        if self.tenant.is_none() {
+            self.tenant = self.identity.config.tenant(None);
+        }
+        self.tenant.as_ref().unwrap()
+    }
+
     pub async fn process_msg_1(&mut self, msg1: RaMsg1) -> SpRaResult<RaMsg2> {
-        // Get sigRL
-        let sig_rl = self.ias_client
-            .get_sig_rl(&msg1.gid, &self.config.primary_subscription_key);
+        let session = self.session_id;
+        self.run_hook(|h| h.on_msg1(session, &msg1))?;
+        let tenant = self.credentials().clone();
 
+        let key_derivation_started = Instant::now();
         let key_exchange = self.key_exchange.take().unwrap();
         let g_b = key_exchange.get_public_key().to_owned();
 
-        // Sign and derive KDK and other secret keys 
-        let (kdk, sign_gb_ga) = key_exchange.sign_and_derive(&msg1.g_a,
-                                                             &self.sp_private_key,
-                                                             &self.rng)
-            .unwrap();
-        let kdk_cmac = Cmac::new(&kdk);
-        let (smk, sk, mk, vk) = derive_secret_keys(&kdk_cmac);
-        let smk = Cmac::new(&smk);
-
-        // Obtain SHA-256(g_a || g_b || vk) 
+        // Sign and derive KDK and other secret keys. Fails if g_a is not a
+        // point on the curve.
+        let sp_private_key = self.identity.signing_keys.signing_key();
+        let (shared_secret, sign_gb_ga) = key_exchange.sign_and_agree(&msg1.g_a,
+                                                                      &sp_private_key,
+                                                                      &self.rng)?;
+        let (kdf_id, (smk, sk, mk, vk)) = match self.identity.key_derivation.as_ref() {
+            Some(kdf) => (kdf.kdf_id(), kdf.derive_secret_keys(&shared_secret)),
+            None => (DEFAULT_KDF_ID,
+                     derive_secret_keys(&Cmac::new(&derive_kdk(&shared_secret)))),
+        };
+        let smk = Locked::new(Cmac::new(&smk))?;
+        self.timings.key_derivation = key_derivation_started.elapsed();
+
+        // Challenge the enclave to prove the quote's freshness
+        let nonce = if self.identity.config.challenge_nonce {
+            let mut nonce: Nonce = [0u8; size_of::<Nonce>()];
+            self.rng.fill(&mut nonce[..]);
+            Some(nonce)
+        } else {
+            None
+        };
+
+        // Obtain SHA-256(g_a || g_b || vk [|| nonce])
         let mut verification_msg = Vec::new();
         verification_msg.write_all(&msg1.g_a).unwrap();
         verification_msg.write_all(&g_b[..]).unwrap();
         verification_msg.write_all(&vk).unwrap();
+        if let Some(nonce) = nonce.as_ref() {
+            verification_msg.write_all(&nonce[..]).unwrap();
+        }
         let verification_digest = sha256(&verification_msg[..]);
 
         // Set context
         self.smk = Some(smk);
-        self.sk_mk = Some((sk, mk));
+        self.sk_mk = Some(Locked::new((sk, mk))?);
         self.verification_digest = Some(verification_digest);
         self.g_a = Some(msg1.g_a.clone());
 
-        let spid: Spid = hex::decode(&self.config.spid).unwrap().as_slice()
-            .try_into().unwrap();
-        let quote_type = self.config.linkable as u16;
+        let spid = *tenant.spid.as_bytes();
+        let quote_type = self.identity.config.linkable as u16;
+
+        let sig_rl_started = Instant::now();
+        let sig_rl = self.sig_rl(msg1.gid, &tenant).await?;
+        self.timings.sig_rl_fetch = sig_rl_started.elapsed();
 
         Ok(RaMsg2::new(
             self.smk.as_ref().unwrap(),
             g_b,
             spid,
             quote_type, 
+            kdf_id,
             sign_gb_ga,
-            sig_rl.await?,
+            sig_rl,
+            nonce,
         ))
     }
 
+    /// `process_msg_1` for a client of Intel's sgx-ra-sample, see
+    /// `do_intel_attestation`.
+    #[cfg(feature = "intel-compat")]
+    pub async fn process_intel_msg_01(&mut self, msg01: IntelMsg01) -> SpRaResult<IntelMsg2> {
+        let session = self.session_id;
+        let msg1 = msg01.to_msg1();
+        self.run_hook(|h| h.on_msg1(session, &msg1))?;
+        let tenant = self.credentials().clone();
+        let sp_key = self.identity.intel_compat_key.clone()
+            .ok_or(SpRaError::IntelCompatNotConfigured)?;
+
+        // Sign (g_b, g_a) with the SP's ECDSA key and derive KDK from the
+        // little-endian shared secret, as the sample's SP does
+        let key_derivation_started = Instant::now();
+        let key_exchange = self.key_exchange.take().unwrap();
+        let g_b = compat::key_to_intel(key_exchange.get_public_key());
+        let sign_gb_ga = compat::sign_gb_ga(&g_b, &msg01.g_a, &sp_key, &self.rng)?;
+        let shared_secret = key_exchange.agree(&msg1.g_a)?;
+        let (smk, sk, mk, vk) =
+            derive_secret_keys(&Cmac::new(&compat::derive_intel_kdk(&shared_secret)));
+        let smk = Locked::new(Cmac::new(&smk))?;
+        self.timings.key_derivation = key_derivation_started.elapsed();
+
+        // Set context
+        self.smk = Some(smk);
+        self.sk_mk = Some(Locked::new((sk, mk))?);
+        self.verification_digest = Some(compat::verification_digest(&msg01.g_a, &g_b, &vk));
+        self.g_a = Some(msg1.g_a);
+
+        let spid = *tenant.spid.as_bytes();
+        let quote_type = self.identity.config.linkable as u16;
+
+        let sig_rl_started = Instant::now();
+        let sig_rl = self.sig_rl(msg1.gid, &tenant).await?;
+        self.timings.sig_rl_fetch = sig_rl_started.elapsed();
+
+        Ok(IntelMsg2::new(
+            self.smk.as_ref().unwrap(),
+            g_b,
+            spid,
+            quote_type,
+            DEFAULT_KDF_ID,
+            sign_gb_ga,
+            sig_rl.unwrap_or_default(),
+        ))
+    }
+
+    /// Get the SigRL of `gid` with the credentials of `tenant`, as the
+    /// revocation check asks.
+    fn sig_rl<'a>(&'a self, gid: Gid, tenant: &'a TenantConfig)
+        -> impl Future<Output = Result<Option<Vec<u8>>, IasError>> + 'a {
+            let session = self.session_id;
+            // A cache fetches with its own subscription keys
+            let sig_rl_cache = self.sig_rl_cache.as_ref()
+                .filter(|cache| cache.is_for_key(&tenant.primary_subscription_key));
+            let ias_client = &self.identity.ias_client;
+            let ias_slot = self.ias_slot.as_ref();
+            let primary_key = &tenant.primary_subscription_key;
+            let secondary_key = &tenant.secondary_subscription_key;
+            let hooks = self.hooks.as_ref();
+            let revocation_check = self.identity.config.revocation_check;
+            async move {
+                if revocation_check == RevocationCheck::Skip {
+                    return Ok(None);
+                }
+                let r = match sig_rl_cache {
+                    Some(cache) => cache.get_scheduled(&gid, ias_slot).await,
+                    None => {
+                        let _permit = ias_scheduler::acquire(ias_slot).await;
+                        ias_client.get_sig_rl_with_fallback(&gid,
+                                                            primary_key,
+                                                            secondary_key).await
+                    },
+                };
+                match r {
+                    Err(e) if revocation_check == RevocationCheck::Soft => {
+                        if cfg!(feature = "verbose") {
+                            eprintln!("SigRL unavailable, going on without: {:?}", e);
+                        }
+                        if let Some(hooks) = hooks {
+                            hooks.on_revocation_data_unavailable(session, &gid, &e);
+                        }
+                        Ok(sig_rl_cache.and_then(|cache| cache.get_stale(&gid)).flatten())
+                    },
+                    r => r,
+                }
+            }
+        }
+
     pub async fn process_msg_3(&mut self, msg3: RaMsg3) 
         -> SpRaResult<(RaMsg4, Option<String>)> {
             // Integrity check
@@ -183,19 +597,117 @@ impl SpRaContext {
             if !msg3.verify_mac(self.smk.as_ref().unwrap()).is_ok() {
                 return Err(SpRaError::IntegrityError);
             }
+            self.process_quote(&msg3.quote).await
+        }
 
-            let quote_digest: Sha256Digest = (&msg3.quote.as_ref()[368..400])
+    /// `process_msg_3` for a client of Intel's sgx-ra-sample, see
+    /// `do_intel_attestation`.
+    #[cfg(feature = "intel-compat")]
+    pub async fn process_intel_msg_3(&mut self, msg3: IntelMsg3)
+        -> SpRaResult<(RaMsg4, Option<String>)> {
+            // Integrity check
+            let g_a = compat::key_to_intel(self.g_a.as_ref().unwrap());
+            if &msg3.g_a[..] != &g_a[..] {
+                return Err(SpRaError::IntegrityError);
+            }
+            if !msg3.verify_mac(self.smk.as_ref().unwrap()).is_ok() {
+                return Err(SpRaError::IntegrityError);
+            }
+            // A quote with SigRL entries does not fit
+            if msg3.quote.len() != size_of::<Quote>() {
+                return Err(ra_verify::VerifyError::MalformedQuote.into());
+            }
+            let mut quote = [0u8; size_of::<Quote>()];
+            quote.copy_from_slice(&msg3.quote[..]);
+            self.process_quote(&quote).await
+        }
+
+    // Check that the quote of MSG3 is bound to the key exchange and decide
+    // on it. Returns MSG4 and the EPID pseudonym.
+    async fn process_quote(&mut self, quote: &Quote)
+        -> SpRaResult<(RaMsg4, Option<String>)> {
+            // Can unwrap since a Quote is always longer than its body
+            let quote_body = QuoteBody::parse(&quote[..]).unwrap();
+            let quote_digest: Sha256Digest = quote_body.report_data[..32]
                 .try_into().unwrap();
             if self.verification_digest.as_ref().unwrap() != &quote_digest {
                 return Err(SpRaError::IntegrityError);
             }
+            self.bound_data_digest = Some(quote_body.report_data[32..].try_into().unwrap());
 
-            // Verify attestation evidence
-            // TODO: use the secondary key as well
-            let attestation_result = self.ias_client
-                .verify_attestation_evidence(
-                    &msg3.quote, 
-                    &self.config.primary_subscription_key).await?;
+            let verification_started = Instant::now();
+            let (attestation_result, is_enclave_trusted) =
+                self.verify_quote(quote, &quote_body, true).await?;
+            self.timings.quote_verification = verification_started.elapsed();
+            let pse_manifest_status = attestation_result.pse_manifest_status.clone();
+            let is_pse_manifest_trusted = pse_manifest_status.map(
+                |status| (status == "OK") ||
+                self.identity.config.pse_trust_options.as_ref().unwrap().binary_search(&status)
+                .is_ok()); 
+
+            let msg4 = RaMsg4 {
+                is_enclave_trusted,
+                is_pse_manifest_trusted,
+                pib: attestation_result.platform_info_blob.clone(),
+            };
+            let epid_pseudonym = attestation_result.epid_pseudonym.clone();
+            self.quote_body = Some(quote_body);
+            self.report = Some(attestation_result);
+            Ok((msg4, epid_pseudonym))
+        }
+
+    /// Have IAS verify `quote` and decide whether to trust the enclave.
+    /// Returns the IAS report and the decision. With `use_cache`, the report
+    /// may come from the `VerdictCache` instead.
+    async fn verify_quote(&mut self, quote: &Quote, quote_body: &QuoteBody, use_cache: bool)
+        -> SpRaResult<(AttestationResponse, bool)> {
+            let session = self.session_id;
+            self.run_hook(|h| h.on_quote_received(session, quote))?;
+
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let max_skew = self.identity.config.report_max_skew_secs
+                .unwrap_or(DEFAULT_REPORT_MAX_SKEW_SECS);
+
+            // Verify attestation evidence, unless a trusted quote of the same
+            // enclave and platform was verified recently
+            let verdict_key = VerdictKey::from_quote_body(quote_body);
+            let verdict_cache = self.verdict_cache.clone().filter(|_| use_cache);
+            let cached = verdict_cache.as_ref().and_then(|cache| cache.get(&verdict_key));
+            // A cached report must be as fresh as one from IAS
+            let cached = match cached {
+                Some(report) if report.check_timestamp(now, max_skew).is_err() => {
+                    verdict_cache.as_ref().unwrap().remove(&verdict_key);
+                    None
+                },
+                cached => cached,
+            };
+            self.cached_verdict = cached.is_some();
+            let attestation_result = match cached {
+                Some(mut report) => {
+                    if cfg!(feature = "verbose") {
+                        eprintln!("Reusing cached IAS report {}", report.id);
+                    }
+                    // These identify the platform the report was issued for
+                    report.epid_pseudonym = None;
+                    report.platform_info_blob = None;
+                    report
+                },
+                None => {
+                    let tenant = self.credentials().clone();
+                    let attestation_result = {
+                        let _permit = ias_scheduler::acquire(self.ias_slot.as_ref()).await;
+                        self.identity.ias_client
+                            .verify_attestation_evidence_with_fallback(
+                                quote, 
+                                &tenant.primary_subscription_key,
+                                &tenant.secondary_subscription_key).await?
+                    };
+                    attestation_result.check_timestamp(now, max_skew)?;
+                    attestation_result
+                },
+            };
 
             if cfg!(feature = "verbose") {
                 eprintln!("==============Attestation Result==============");
@@ -203,41 +715,72 @@ impl SpRaContext {
                 eprintln!("==============================================");
             }
 
+            self.run_hook(|h| h.on_report_verified(session, &attestation_result))?;
+
             // Verify enclave identity
-            let mrenclave = &msg3.quote[112..144];
-            let mrsigner = &msg3.quote[176..208];
-            let isvprodid = (&msg3.quote[304..306]).read_u16::<LittleEndian>().unwrap();
-            let isvsvn = (&msg3.quote[306..308]).read_u16::<LittleEndian>().unwrap();
-            if mrenclave != &self.sigstruct.enclavehash[..] ||
-                mrsigner != &sha256(&self.sigstruct.modulus[..])[..] ||
-                    isvprodid != self.sigstruct.isvprodid ||
-                    isvsvn != self.sigstruct.isvsvn {
+            if quote_body.mr_enclave != self.identity.sigstruct.enclavehash ||
+                quote_body.mr_signer != sha256(&self.identity.sigstruct.modulus[..]) ||
+                    quote_body.isv_prod_id != self.identity.sigstruct.isvprodid ||
+                    quote_body.isv_svn != self.identity.sigstruct.isvsvn {
                         return Err(SpRaError::SigstructMismatched);
                     }
 
-            // Make sure the enclave is not in debug mode in production
-            let attribute_flags = &self.sigstruct.attributes.flags;
-            if cfg!(not(debug_assertions)) {
-                if (&sgx_isa::AttributesFlags::DEBUG).intersects(*attribute_flags) {
-                    return Err(SpRaError::EnclaveInDebugMode);
-                }
+            // The host can read the memory of a debug enclave
+            if quote_body.is_debug() && !self.identity.config.allow_debug_enclaves {
+                return Err(SpRaError::EnclaveInDebugMode);
             }
 
+            // ISVSVN does not reflect the platform's microcode
+            if self.identity.min_cpu_svn.iter()
+                .any(|r| r.applies_to(quote_body) && !r.is_met_by(quote_body)) {
+                    return Err(SpRaError::CpuSvnTooLow);
+                }
+
             // Decide whether to trust enclave
             let quote_status = attestation_result.isv_enclave_quote_status.clone();
-            let pse_manifest_status = attestation_result.pse_manifest_status.clone();
-            let is_enclave_trusted = (quote_status == "OK") || 
-                self.config.quote_trust_options.binary_search(&quote_status).is_ok();
-            let is_pse_manifest_trusted = pse_manifest_status.map(
-                |status| (status == "OK") ||
-                self.config.pse_trust_options.as_ref().unwrap().binary_search(&status)
-                .is_ok()); 
-
-            Ok((RaMsg4 {
-                is_enclave_trusted,
-                is_pse_manifest_trusted,
-                pib: attestation_result.platform_info_blob,
-            },
-            attestation_result.epid_pseudonym))
+            let mut is_enclave_trusted = (quote_status == "OK") || 
+                (self.identity.config.quote_trust_options
+                 .binary_search(&quote_status).is_ok() &&
+                 self.are_advisories_allowed(&attestation_result));
+            if is_enclave_trusted && !self.cached_verdict {
+                if let Some(quorum) = self.quorum.clone() {
+                    // The verifiers may block, e.g. on the network
+                    let quote = quote.to_vec();
+                    let body = quote_body.clone();
+                    let verdict = tokio::task::spawn_blocking(move || {
+                        quorum.decide(&quote[..], &body)
+                    }).await
+                        .map_err(|e| SpRaError::VerifierFailed(e.to_string()))?;
+                    if let Verdict::Reject(reason) = verdict {
+                        if cfg!(feature = "verbose") {
+                            eprintln!("Report rejected by verifier quorum: {}", reason);
+                        }
+                        is_enclave_trusted = false;
+                        self.rejection = Some(reason);
+                    }
+                }
+            }
+            if is_enclave_trusted && !self.cached_verdict {
+                if let Some(cache) = verdict_cache.as_ref() {
+                    cache.insert(verdict_key, attestation_result.clone());
+                }
+            }
+            if is_enclave_trusted {
+                if let Some(verifier) = self.verifier.as_ref() {
+                    if let Verdict::Reject(reason) = verifier.verify(quote_body,
+                                                                     &attestation_result) {
+                        if cfg!(feature = "verbose") {
+                            eprintln!("Report rejected by verifier: {}", reason);
+                        }
+                        is_enclave_trusted = false;
+                        self.rejection = Some(reason);
+                    }
+                }
+            }
+            Ok((attestation_result, is_enclave_trusted))
         }
 }
+
+fn since(instant: Option<Instant>) -> Duration {
+    instant.map_or(Duration::default(), |i| i.elapsed())
+}
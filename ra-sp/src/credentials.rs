@@ -0,0 +1,115 @@
+// The SP's IAS credentials as types of their own, so that a SPID or a
+// subscription key is parsed once, where it enters the SP, and never ends up
+// in logs through a Debug print of whatever holds it.
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error;
+
+/// The service provider ID that IAS assigned to the SP, sent to the client in
+/// MSG2 for its quote. Parsed from, and serialized to, 32 hex digits.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Spid([u8; 16]);
+
+impl Spid {
+    pub fn new(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    pub fn to_hex(self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl FromStr for Spid {
+    type Err = ParseCredentialError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !is_hex_32(s) {
+            return Err(ParseCredentialError);
+        }
+        let bytes = hex::decode(s).map_err(|_| ParseCredentialError)?;
+        Ok(Self(bytes.as_slice().try_into().unwrap()))
+    }
+}
+
+impl fmt::Debug for Spid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.write_str("Spid(..)")
+    }
+}
+
+impl Serialize for Spid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Spid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+/// A key of the SP's IAS subscription, sent to IAS with every request. IAS
+/// keys are 32 hex digits, which are kept as given.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SubscriptionKey(String);
+
+impl SubscriptionKey {
+    /// The key as IAS expects it in the `Ocp-Apim-Subscription-Key` header.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for SubscriptionKey {
+    type Err = ParseCredentialError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !is_hex_32(s) {
+            return Err(ParseCredentialError);
+        }
+        Ok(Self(s.to_owned()))
+    }
+}
+
+impl fmt::Debug for SubscriptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.write_str("SubscriptionKey(..)")
+    }
+}
+
+impl Serialize for SubscriptionKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for SubscriptionKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+/// A SPID or subscription key that is not 32 hex digits. Does not repeat the
+/// rejected value, which may be a key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseCredentialError;
+
+impl fmt::Display for ParseCredentialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.write_str("must be 32 hexadecimal digits")
+    }
+}
+
+impl std::error::Error for ParseCredentialError {}
+
+fn is_hex_32(s: &str) -> bool {
+    s.len() == 32 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
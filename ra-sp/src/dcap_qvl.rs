@@ -0,0 +1,324 @@
+// Verification of ECDSA (DCAP) quotes by Intel's Quote Verification Library,
+// libsgx_dcap_quoteverify, for SPs that must reach exactly Intel's verdicts.
+// Collateral (PCK CRLs, TCB Info, QE Identity) is fetched by the QVL through
+// the Quote Provider Library configured on the host, e.g. with
+// /etc/sgx_default_qcnl.conf.
+use std::{mem, ptr, slice};
+use std::time::{SystemTime, UNIX_EPOCH};
+use ra_common::quote::QuoteBody;
+use crate::quorum::QuoteVerifier;
+use crate::verifier::Verdict;
+use crate::error::SpRaError;
+use crate::SpRaResult;
+
+const SGX_QL_SUCCESS: u32 = 0;
+const ATT_KEY_TYPE_ECDSA_P256: u16 = 2;
+const TEE_TYPE_SGX: u32 = 0x00;
+const TEE_TYPE_TDX: u32 = 0x81;
+/// `SGX_QL_ERROR_UNEXPECTED`, for a QvE report that is missing.
+const SGX_QL_ERROR_UNEXPECTED: u32 = 0xe001;
+const QUOTE_NONCE_LEN: usize = 16;
+const TARGET_INFO_LEN: usize = 512;
+const REPORT_LEN: usize = 432;
+
+#[repr(C)]
+struct QeReportInfo {
+    nonce: [u8; QUOTE_NONCE_LEN],
+    app_enclave_target_info: [u8; TARGET_INFO_LEN],
+    qe_report: [u8; REPORT_LEN],
+}
+
+#[link(name = "sgx_dcap_quoteverify")]
+extern "C" {
+    fn sgx_qv_get_quote_supplemental_data_size(p_data_size: *mut u32) -> u32;
+
+    fn sgx_qv_verify_quote(p_quote: *const u8,
+                           quote_size: u32,
+                           p_quote_collateral: *const u8,
+                           expiration_check_date: i64,
+                           p_collateral_expiration_status: *mut u32,
+                           p_quote_verification_result: *mut u32,
+                           p_qve_report_info: *mut QeReportInfo,
+                           supplemental_data_size: u32,
+                           p_supplemental_data: *mut u8) -> u32;
+
+    fn tdx_qv_get_quote_supplemental_data_size(p_data_size: *mut u32) -> u32;
+
+    fn tdx_qv_verify_quote(p_quote: *const u8,
+                           quote_size: u32,
+                           p_quote_collateral: *const u8,
+                           expiration_check_date: i64,
+                           p_collateral_expiration_status: *mut u32,
+                           p_quote_verification_result: *mut u32,
+                           p_qve_report_info: *mut QeReportInfo,
+                           supplemental_data_size: u32,
+                           p_supplemental_data: *mut u8) -> u32;
+}
+
+/// A `quote3_error_t` other than success returned by the QVL, or by the
+/// check of the QvE's report, see `QveReportVerifier`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QvlError(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DcapQuoteType {
+    /// SGX ECDSA quote, version 3.
+    Sgx,
+    /// TD quote, version 4.
+    Tdx,
+}
+
+/// `sgx_ql_qv_result_t`: Intel's verdict on a quote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QvResult {
+    Ok,
+    ConfigNeeded,
+    OutOfDate,
+    OutOfDateConfigNeeded,
+    InvalidSignature,
+    Revoked,
+    Unspecified,
+    SwHardeningNeeded,
+    ConfigAndSwHardeningNeeded,
+    Other(u32),
+}
+
+impl QvResult {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            0x0000 => QvResult::Ok,
+            0xA001 => QvResult::ConfigNeeded,
+            0xA002 => QvResult::OutOfDate,
+            0xA003 => QvResult::OutOfDateConfigNeeded,
+            0xA004 => QvResult::InvalidSignature,
+            0xA005 => QvResult::Revoked,
+            0xA006 => QvResult::Unspecified,
+            0xA007 => QvResult::SwHardeningNeeded,
+            0xA008 => QvResult::ConfigAndSwHardeningNeeded,
+            raw => QvResult::Other(raw),
+        }
+    }
+
+    /// Whether the quote is authentic and the platform's TCB is up to date
+    /// or only needs configuration or software mitigations, which the
+    /// relying party may decide to accept.
+    pub fn is_authentic(&self) -> bool {
+        match self {
+            QvResult::Ok |
+                QvResult::ConfigNeeded |
+                QvResult::OutOfDate |
+                QvResult::OutOfDateConfigNeeded |
+                QvResult::SwHardeningNeeded |
+                QvResult::ConfigAndSwHardeningNeeded => true,
+            _ => false,
+        }
+    }
+}
+
+pub struct QvlVerification {
+    pub result: QvResult,
+    /// Whether some collateral had expired at the time of verification.
+    pub collateral_expired: bool,
+    /// `sgx_ql_qv_supplemental_t`, e.g. with the TCB date and advisory IDs.
+    pub supplemental_data: Vec<u8>,
+    /// `sgx_report_t` of the QvE, when verified with `verify_with_qve`.
+    pub qve_report: Option<[u8; REPORT_LEN]>,
+}
+
+/// Verify `quote` with the QVL in the SP's own process.
+pub fn verify_with_qvl(quote_type: DcapQuoteType, quote: &[u8])
+    -> Result<QvlVerification, QvlError> {
+        verify(quote_type, quote, now(), None).map(|(verification, _)| verification)
+    }
+
+/// Checks the report the QvE returns with its verdict. The report targets
+/// the application enclave, so only that enclave can check it: an
+/// implementation calls `sgx_tvl_verify_qve_report_and_identity` of Intel's
+/// libsgx_dcap_tvl there with these arguments, e.g. through an ecall, and
+/// returns its `quote3_error_t`. That checks the report's MAC, that its
+/// report data binds `nonce`, the quote, and every output of the QvE, e.g.
+/// the supplemental data, and that it comes from Intel's QvE with an ISVSVN
+/// of at least `qve_isvsvn_threshold`.
+pub trait QveReportVerifier {
+    /// `qve_report_info` is the `sgx_ql_qe_report_info_t` returned by the
+    /// QvE, and `quote_verification_result` its `sgx_ql_qv_result_t`.
+    fn verify_qve_report_and_identity(&self,
+                                      quote: &[u8],
+                                      qve_report_info: &[u8],
+                                      expiration_check_date: i64,
+                                      collateral_expiration_status: u32,
+                                      quote_verification_result: u32,
+                                      supplemental_data: &[u8],
+                                      qve_isvsvn_threshold: u16) -> u32;
+}
+
+/// Verify `quote` inside Intel's Quote Verification Enclave (QvE), which
+/// returns a report for the enclave described by `app_enclave_target_info`
+/// (`sgx_target_info_t`). The verdict is only returned once `report_verifier`
+/// accepted that report for `nonce` and a QvE with an ISVSVN of at least
+/// `qve_isvsvn_threshold`.
+pub fn verify_with_qve<V>(quote_type: DcapQuoteType,
+                          quote: &[u8],
+                          nonce: [u8; QUOTE_NONCE_LEN],
+                          app_enclave_target_info: &[u8; TARGET_INFO_LEN],
+                          report_verifier: &V,
+                          qve_isvsvn_threshold: u16)
+    -> Result<QvlVerification, QvlError> where V: QveReportVerifier + ?Sized {
+        let info = QeReportInfo {
+            nonce,
+            app_enclave_target_info: *app_enclave_target_info,
+            qe_report: [0u8; REPORT_LEN],
+        };
+        let now = now();
+        let (verification, raw) = verify(quote_type, quote, now, Some(info))?;
+        // Can unwrap since `verify` returns the info it was given
+        let info = raw.qve_report_info.unwrap();
+        // The info is plain bytes without padding
+        let info_bytes = unsafe {
+            slice::from_raw_parts(&info as *const QeReportInfo as *const u8,
+                                  mem::size_of::<QeReportInfo>())
+        };
+        let r = report_verifier.verify_qve_report_and_identity(
+            quote,
+            info_bytes,
+            now,
+            raw.collateral_expiration_status,
+            raw.result,
+            &verification.supplemental_data[..],
+            qve_isvsvn_threshold);
+        if r != SGX_QL_SUCCESS {
+            return Err(QvlError(r));
+        }
+        if verification.qve_report.is_none() {
+            return Err(QvlError(SGX_QL_ERROR_UNEXPECTED));
+        }
+        Ok(verification)
+    }
+
+// Outputs of the QVL as it returned them, which the QvE's report binds
+struct RawVerification {
+    collateral_expiration_status: u32,
+    result: u32,
+    qve_report_info: Option<QeReportInfo>,
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn verify(quote_type: DcapQuoteType, quote: &[u8], now: i64,
+          mut qve_report_info: Option<QeReportInfo>)
+    -> Result<(QvlVerification, RawVerification), QvlError> {
+        let mut supplemental_data_size = 0u32;
+        let r = unsafe {
+            match quote_type {
+                DcapQuoteType::Sgx =>
+                    sgx_qv_get_quote_supplemental_data_size(&mut supplemental_data_size),
+                DcapQuoteType::Tdx =>
+                    tdx_qv_get_quote_supplemental_data_size(&mut supplemental_data_size),
+            }
+        };
+        if r != SGX_QL_SUCCESS {
+            return Err(QvlError(r));
+        }
+        let mut supplemental_data = vec![0u8; supplemental_data_size as usize];
+        let mut collateral_expiration_status = 0u32;
+        let mut result = 0u32;
+        let info_ptr = qve_report_info.as_mut()
+            .map_or(ptr::null_mut(), |info| info as *mut QeReportInfo);
+        let verify_quote = match quote_type {
+            DcapQuoteType::Sgx => sgx_qv_verify_quote,
+            DcapQuoteType::Tdx => tdx_qv_verify_quote,
+        };
+        // Null collateral: the QVL fetches it through the QPL
+        let r = unsafe {
+            verify_quote(quote.as_ptr(),
+                         quote.len() as u32,
+                         ptr::null(),
+                         now,
+                         &mut collateral_expiration_status,
+                         &mut result,
+                         info_ptr,
+                         supplemental_data_size,
+                         supplemental_data.as_mut_ptr())
+        };
+        if r != SGX_QL_SUCCESS {
+            return Err(QvlError(r));
+        }
+        let verification = QvlVerification {
+            result: QvResult::from_raw(result),
+            collateral_expired: collateral_expiration_status != 0,
+            supplemental_data,
+            qve_report: qve_report_info.as_ref().map(|info| info.qe_report),
+        };
+        if cfg!(feature = "verbose") {
+            eprintln!("QVL verdict: {:?}", verification.result);
+        }
+        let raw = RawVerification {
+            collateral_expiration_status,
+            result,
+            qve_report_info,
+        };
+        Ok((verification, raw))
+    }
+
+/// The QVL as one backend of a `VerifierQuorum`, for quotes with an ECDSA
+/// attestation key of `quote_type`. It fails on any other quote, e.g. the
+/// EPID quotes of IAS attestations, which it cannot verify.
+pub struct QvlVerifier {
+    pub quote_type: DcapQuoteType,
+    /// Verdicts other than `Ok` to accept, e.g. `SwHardeningNeeded`.
+    pub accepted_results: Vec<QvResult>,
+    pub allow_expired_collateral: bool,
+}
+
+impl QvlVerifier {
+    /// Accepts only `QvResult::Ok` with current collateral.
+    pub fn new(quote_type: DcapQuoteType) -> Self {
+        Self {
+            quote_type,
+            accepted_results: Vec::new(),
+            allow_expired_collateral: false,
+        }
+    }
+}
+
+impl QuoteVerifier for QvlVerifier {
+    fn name(&self) -> &str {
+        "QVL"
+    }
+
+    fn verify(&self, quote: &[u8], _quote_body: &QuoteBody) -> SpRaResult<Verdict> {
+        if !is_ecdsa_quote(self.quote_type, quote) {
+            return Err(SpRaError::VerifierFailed(format!("not an ECDSA {:?} quote",
+                                                         self.quote_type)));
+        }
+        let verification = verify_with_qvl(self.quote_type, quote)
+            .map_err(|e| SpRaError::VerifierFailed(format!("QVL error {:#x}", e.0)))?;
+        if verification.collateral_expired && !self.allow_expired_collateral {
+            return Ok(Verdict::Reject("collateral expired".to_owned()));
+        }
+        if verification.result != QvResult::Ok &&
+            !self.accepted_results.contains(&verification.result) {
+                return Ok(Verdict::Reject(format!("{:?}", verification.result)));
+            }
+        Ok(Verdict::Accept)
+    }
+}
+
+// Whether the header of `quote` is that of a DCAP quote of `quote_type` with
+// an ECDSA P-256 attestation key
+fn is_ecdsa_quote(quote_type: DcapQuoteType, quote: &[u8]) -> bool {
+    if quote.len() < 8 {
+        return false;
+    }
+    let version = u16::from_le_bytes([quote[0], quote[1]]);
+    let att_key_type = u16::from_le_bytes([quote[2], quote[3]]);
+    let tee_type = u32::from_le_bytes([quote[4], quote[5], quote[6], quote[7]]);
+    att_key_type == ATT_KEY_TYPE_ECDSA_P256 && match quote_type {
+        DcapQuoteType::Sgx => version == 3 || (version == 4 && tee_type == TEE_TYPE_SGX),
+        DcapQuoteType::Tdx => version == 4 && tee_type == TEE_TYPE_TDX,
+    }
+}
@@ -1,4 +1,9 @@
 
+use ra_common::msg::{AbortReason, WireError};
+use ra_common::config_file::ConfigFileError;
+use crate::ra_tls::RaTlsError;
+use crate::config::ConfigProblem;
+
 #[derive(Debug)]
 pub enum SpRaError {
     IO(std::io::Error),
@@ -7,11 +12,78 @@ pub enum SpRaError {
     Certificate(sgx_crypto::certificate::CertError),
     IAS(IasError),
     Serialization(std::boxed::Box<bincode::ErrorKind>),
+    Config(serde_json::Error),
     IntegrityError,
     SigstructMismatched,
     EnclaveInDebugMode,
+    /// The platform's CPUSVN is below a floor of `SpConfig::min_cpu_svn` for
+    /// its EPID group, i.e. it lacks a required microcode update.
+    CpuSvnTooLow,
     EnclaveNotTrusted,
+    UnknownTenant(String),
+    Vetoed(String),
+    RejectedByVerifier(String),
+    Aborted(AbortReason),
+    RaTls(RaTlsError),
+    Verify(ra_verify::VerifyError),
+    /// A config field (or the environment variable it came from) could not
+    /// be parsed or decoded, or is inconsistent with the rest of the config.
+    InvalidConfigValue(String),
+    /// The config does not match the fields of `SpConfig`, with every field
+    /// that is missing, unknown, or of the wrong type.
+    InvalidConfig(Vec<ConfigProblem>),
+    /// The session's attestation is older than its validity period.
+    SessionExpired,
+    /// The enclave did not acknowledge these secrets.
+    SecretsNotAccepted(Vec<String>),
+    /// The session was revoked, e.g. after a missed heartbeat.
+    SessionRevoked,
+    /// The enclave sent no heartbeat within the monitor's timeout.
+    HeartbeatMissed,
+    /// The IAS report was made further from now than `report_max_skew_secs`,
+    /// with its timestamp, while IAS's clock agrees with the SP's. The report
+    /// may be replayed.
+    ReportOutOfDate(String),
+    /// The SP's clock is this many seconds ahead of IAS's (behind if
+    /// negative), more than `report_max_skew_secs`.
+    ClockSkew(i64),
+    /// A `QuoteVerifier` could not reach a verdict, e.g. because its service
+    /// is unreachable.
+    VerifierFailed(String),
+    /// No enclave of this ID was added to the `ChannelPool`.
+    UnknownEnclave(String),
+    /// A TD quote was received but no `TdxVerifier` is set on the
+    /// `SpIdentity`.
+    TdxNotConfigured,
+    /// An sgx-ra-sample client connected but no ECDSA key to sign its MSG2
+    /// is set on the `SpIdentity`.
+    IntelCompatNotConfigured,
+    /// The attestation panicked, with the panic's message, e.g. in a hook.
+    AttestationPanicked(String),
+}
 
+impl SpRaError {
+    /// The reason to report to the client, or None if the client already
+    /// knows the outcome (MSG4 was sent) or cannot be reached anymore.
+    pub fn abort_reason(&self) -> Option<AbortReason> {
+        match self {
+            SpRaError::Serialization(_) |
+                SpRaError::EnclaveNotTrusted |
+                SpRaError::RejectedByVerifier(_) |
+                SpRaError::Aborted(_) => None,
+            SpRaError::IAS(_) => Some(AbortReason::IasUnavailable),
+            SpRaError::IntegrityError |
+                SpRaError::KeyExchange(sgx_crypto::key_exchange::KeError::KeyDerivationError) =>
+                Some(AbortReason::IntegrityError),
+            SpRaError::SigstructMismatched |
+                SpRaError::EnclaveInDebugMode |
+                SpRaError::CpuSvnTooLow |
+                SpRaError::Vetoed(_) |
+                SpRaError::RaTls(_) => Some(AbortReason::QuoteRejected),
+            SpRaError::UnknownTenant(_) => Some(AbortReason::Unsupported),
+            _ => Some(AbortReason::Internal),
+        }
+    }
 }
 
 impl std::convert::From<std::io::Error> for SpRaError {
@@ -38,6 +110,39 @@ impl std::convert::From<std::boxed::Box<bincode::ErrorKind>> for SpRaError {
     fn from(e: std::boxed::Box<bincode::ErrorKind>) -> Self { Self::Serialization(e) }
 }
 
+impl std::convert::From<serde_json::Error> for SpRaError {
+    fn from(e: serde_json::Error) -> Self { Self::Config(e) }
+}
+
+impl std::convert::From<RaTlsError> for SpRaError {
+    fn from(e: RaTlsError) -> Self { Self::RaTls(e) }
+}
+
+impl std::convert::From<ra_verify::VerifyError> for SpRaError {
+    fn from(e: ra_verify::VerifyError) -> Self { Self::Verify(e) }
+}
+
+impl std::convert::From<ConfigFileError> for SpRaError {
+    fn from(e: ConfigFileError) -> Self {
+        match e {
+            ConfigFileError::IO(e) => Self::IO(e),
+            e => Self::InvalidConfig(vec![ConfigProblem {
+                field: "config".to_owned(),
+                message: e.to_string(),
+            }]),
+        }
+    }
+}
+
+impl std::convert::From<WireError> for SpRaError {
+    fn from(e: WireError) -> Self {
+        match e {
+            WireError::Serialization(e) => Self::Serialization(e),
+            WireError::Aborted(abort) => Self::Aborted(abort.reason),
+        }
+    }
+}
+
 impl std::fmt::Display for SpRaError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) 
         -> Result<(), std::fmt::Error> { 
@@ -53,6 +158,9 @@ pub enum AttestationError {
     Connection(http::StatusCode),
     MismatchedIASRootCertificate,
     InvalidIASCertificate,
+    /// The report signing certificate chains to the root CA but matches none
+    /// of `ias_signing_cert_pins`.
+    UnpinnedIASCertificate,
     BadSignature,
 }
 
@@ -62,6 +170,9 @@ pub enum IasError {
     Connection(hyper::error::Error),
     SigRLError(http::StatusCode),
     Attestation(AttestationError),
+    /// TLS could not be set up, e.g. an OpenSSL FIPS module that failed its
+    /// self-tests.
+    Tls(String),
 }
 
 impl std::convert::From<std::io::Error> for IasError {
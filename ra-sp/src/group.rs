@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::io;
+use sgx_crypto::key_wrap::Kek;
+use sgx_crypto::locked::Locked;
+use sgx_crypto::random::RandomState;
+use ra_common::group_key::{GroupKey, GroupKeyUpdate};
+use crate::session::Session;
+use crate::{AttestationResult, SpRaResult};
+
+/// A group key shared by enclaves the SP attested one by one. The key changes
+/// whenever a member joins or leaves, so that a new member cannot read what
+/// was encrypted before it joined and a former member cannot read what is
+/// encrypted after it left.
+///
+/// Every change returns one `GroupKeyUpdate` per member, keyed by session
+/// ID, for the application to send over that member's channel.
+pub struct KeyGroup {
+    epoch: u64,
+    key: Locked<GroupKey>,
+    members: HashMap<u64, (Session, Locked<Kek>)>,
+    rng: RandomState,
+}
+
+impl KeyGroup {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            epoch: 0,
+            key: Locked::new([0u8; 16])?,
+            members: HashMap::new(),
+            rng: RandomState::new(),
+        })
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Session IDs of the current members.
+    pub fn members(&self) -> Vec<u64> {
+        self.members.keys().cloned().collect()
+    }
+
+    /// Add the enclave attested in `session` and rotate the key. A session
+    /// that is already a member is only re-keyed, e.g. after it was
+    /// re-attested with new session keys. Fails with `SessionRevoked` or
+    /// `SessionExpired` if the session is no longer trusted, see
+    /// `Session::check`.
+    pub fn join(&mut self, session: &Session, result: &AttestationResult)
+        -> SpRaResult<Vec<(u64, GroupKeyUpdate)>> {
+            session.check()?;
+            let kek = Locked::new(result.keys.group_kek())?;
+            self.members.insert(session.id(), (session.clone(), kek));
+            Ok(self.rotate())
+        }
+
+    /// Remove a member and rotate the key if it was one.
+    pub fn leave(&mut self, session_id: u64) -> Vec<(u64, GroupKeyUpdate)> {
+        match self.members.remove(&session_id) {
+            Some(_) => self.rotate(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Remove the members whose sessions expired or were revoked, see
+    /// `Session::check`, and rotate the key if there were any.
+    pub fn remove_expired(&mut self) -> Vec<(u64, GroupKeyUpdate)> {
+        let before = self.members.len();
+        self.members.retain(|_, (session, _)| session.check().is_ok());
+        if self.members.len() == before {
+            return Vec::new();
+        }
+        self.rotate()
+    }
+
+    fn rotate(&mut self) -> Vec<(u64, GroupKeyUpdate)> {
+        self.epoch += 1;
+        self.rng.fill(&mut self.key[..]);
+        if cfg!(feature = "verbose") {
+            eprintln!("Group key rotated to epoch {} for {} members",
+                      self.epoch, self.members.len());
+        }
+        let (epoch, key) = (self.epoch, &*self.key);
+        self.members.iter()
+            .map(|(id, (_, kek))| (*id, GroupKeyUpdate::wrap(kek, epoch, key)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_member(group: &mut KeyGroup, id: u64, kek: Kek) -> Session {
+        let session = Session::new(id, None);
+        group.members.insert(id, (session.clone(), Locked::new(kek).unwrap()));
+        session
+    }
+
+    #[test]
+    fn revoked_members_get_no_new_key() {
+        let mut group = KeyGroup::new().unwrap();
+        let kept = add_member(&mut group, 1, [1u8; 16]);
+        let revoked = add_member(&mut group, 2, [2u8; 16]);
+        assert_eq!(group.rotate().len(), 2);
+
+        revoked.revoke();
+        let updates = group.remove_expired();
+        assert_eq!(group.members(), vec![kept.id()]);
+        assert_eq!(updates.len(), 1);
+        let (id, update) = &updates[0];
+        assert_eq!(*id, kept.id());
+        assert_eq!(update.epoch, group.epoch());
+        assert!(update.unwrap(&[2u8; 16]).is_err());
+        assert_eq!(&update.unwrap(&[1u8; 16]).unwrap()[..], &group.key[..]);
+
+        // nor on later changes
+        let updates = group.rotate();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].0, kept.id());
+    }
+}
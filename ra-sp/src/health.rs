@@ -0,0 +1,139 @@
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use serde_json::json;
+use sgx_crypto::random::RandomState;
+use ra_common::listener::ShutdownHandle;
+use crate::hooks::AttestationHooks;
+use crate::identity::SpIdentity;
+use crate::sig_rl_cache::SigRlCache;
+use crate::signing_keys::SpSigningKeys;
+use crate::SpRaResult;
+
+// State behind the probes. Holds no reference to the `SpIdentity`, whose
+// runtime the probes run on, so that the identity can still be dropped.
+struct Probes {
+    shutdown: ShutdownHandle,
+    sig_rl_cache: Option<SigRlCache>,
+    signing_keys: Arc<SpSigningKeys>,
+    hooks: Option<Arc<dyn AttestationHooks>>,
+    // Outcome of the last IAS check, with the error if it failed
+    ias: Mutex<Result<(), String>>,
+}
+
+impl Probes {
+    fn respond(&self, req: &Request<Body>) -> Response<Body> {
+        match req.uri().path() {
+            // Liveness: the SP answers at all
+            "/healthz" => response(StatusCode::OK, Body::from("{\"status\":\"ok\"}")),
+            "/readyz" => self.readiness(),
+            _ => response(StatusCode::NOT_FOUND, Body::empty()),
+        }
+    }
+
+    fn readiness(&self) -> Response<Body> {
+        let shutdown = if self.shutdown.is_shutdown() {
+            Err("shutting down".to_owned())
+        } else {
+            Ok(())
+        };
+        let ias = self.ias.lock().unwrap().clone();
+        let sig_rl_cache = match self.sig_rl_cache.as_ref() {
+            Some(cache) if !cache.is_warm() => Err("stale entries".to_owned()),
+            _ => Ok(()),
+        };
+        let hooks = self.hooks.as_ref().map_or(Ok(()), |h| h.check_ready());
+        // The key MSG2 is signed with now, e.g. after a rotation, can sign
+        let signing_key = self.signing_keys.signing_key()
+            .sign(b"readiness probe", &RandomState::new())
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e));
+        let checks = [("shutdown", shutdown),
+                      ("signing_key", signing_key),
+                      ("ias", ias),
+                      ("sig_rl_cache", sig_rl_cache),
+                      ("hooks", hooks)];
+        let ready = checks.iter().all(|(_, r)| r.is_ok());
+        let mut body = json!({ "ready": ready, "checks": {} });
+        for (name, r) in checks.iter() {
+            body["checks"][name] = match r {
+                Ok(()) => json!("ok"),
+                Err(e) => json!(e),
+            };
+        }
+        let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+        response(status, Body::from(body.to_string()))
+    }
+}
+
+fn response(status: StatusCode, body: Body) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap()
+}
+
+/// Answer `/healthz` and `/readyz` on `bind_addr:port` from the identity's
+/// runtime, and check that IAS accepts the subscription keys every
+/// `ias_probe_interval` until shutdown is requested.
+pub(crate) fn serve(identity: &SpIdentity,
+                    shutdown: ShutdownHandle,
+                    bind_addr: &str,
+                    port: u16,
+                    ias_probe_interval: Duration) -> SpRaResult<()> {
+    let listener = TcpListener::bind((bind_addr, port))?;
+    listener.set_nonblocking(true)?;
+    let probes = Arc::new(Probes {
+        shutdown,
+        sig_rl_cache: identity.sig_rl_cache.clone(),
+        signing_keys: identity.signing_keys.clone(),
+        hooks: identity.hooks.clone(),
+        ias: Mutex::new(Err("not checked yet".to_owned())),
+    });
+
+    // A client of its own, so that the probe task does not hold the identity
+    let ias_client = identity.config.ias_client()?;
+    let primary_key = identity.config.primary_subscription_key.clone();
+    let secondary_key = identity.config.secondary_subscription_key.clone();
+    let prober = probes.clone();
+    identity.runtime.spawn(async move {
+        while !prober.shutdown.is_shutdown() {
+            let r = ias_client.warm_up(&primary_key, &secondary_key).await
+                .map_err(|e| format!("{:?}", e));
+            if cfg!(feature = "verbose") {
+                if let Err(ref e) = r {
+                    eprintln!("IAS probe failed: {}", e);
+                }
+            }
+            *prober.ias.lock().unwrap() = r;
+            tokio::time::delay_for(ias_probe_interval).await;
+        }
+    });
+
+    let server = identity.runtime.handle().enter(|| Server::from_tcp(listener))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let make_service = make_service_fn(move |_| {
+        let probes = probes.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let resp = probes.respond(&req);
+                async move { Ok::<_, Infallible>(resp) }
+            }))
+        }
+    });
+    // Keeps answering while the SP drains, so that readiness fails rather
+    // than the connection
+    identity.runtime.spawn(async move {
+        let r = server.serve(make_service).await;
+        if cfg!(feature = "verbose") {
+            if let Err(e) = r {
+                eprintln!("Health endpoint failed: {:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use std::mem::size_of;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sgx_crypto::cmac::MacTag;
+use sgx_crypto::secure_channel::{SecureChannel, SecureTransport};
+use ra_common::heartbeat::{heartbeat_key, heartbeat_report_data, Heartbeat, HeartbeatAck};
+use ra_common::msg::{Quote, WireMessage};
+use ra_common::quote::QuoteBody;
+use crate::error::SpRaError;
+use crate::identity::SpIdentity;
+use crate::session::Session;
+use crate::{SpRaResult, AttestationResult};
+
+/// SP side of continuous attestation, see `ra_common::heartbeat`. Has IAS
+/// verify every heartbeat of an attested enclave against the identity's
+/// policy, as in the attestation, and revokes the session for good when a
+/// heartbeat is missing, late, or no longer trusted. A heartbeat that cannot
+/// be checked because IAS is unreachable does not count, so the session is
+/// revoked if IAS stays unreachable for the timeout.
+///
+/// The application serves heartbeats with `handle` whenever the enclave sends
+/// one, which its own protocol on the channel must allow for, and calls
+/// `check` before trusting the session, e.g. on every request.
+pub struct HeartbeatMonitor {
+    identity: Arc<SpIdentity>,
+    tenant: Option<String>,
+    key: MacTag,
+    timeout: Duration,
+    last_seq: u64,
+    last_beat: Instant,
+    last_quote_status: Option<String>,
+    session: Option<Session>,
+    revocation: Option<String>,
+}
+
+impl HeartbeatMonitor {
+    /// Monitor the enclave of `result`, which must send a heartbeat at least
+    /// every `timeout`, counting from now. The timeout should leave room for
+    /// IAS's response time on top of the enclave's interval.
+    pub fn new(identity: Arc<SpIdentity>, result: &AttestationResult, timeout: Duration) -> Self {
+        Self {
+            identity,
+            tenant: None,
+            key: heartbeat_key(&result.keys),
+            timeout,
+            last_seq: 0,
+            last_beat: Instant::now(),
+            last_quote_status: Some(result.quote_status.clone()),
+            session: None,
+            revocation: None,
+        }
+    }
+
+    /// Use the IAS credentials of this tenant, as in the attestation.
+    pub fn set_tenant(&mut self, name: Option<&str>) {
+        self.tenant = name.map(|n| n.to_owned());
+    }
+
+    /// Revoke `session` along with the monitor, and renew it on every
+    /// trusted heartbeat.
+    pub fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    /// The IAS quote status of the last verified heartbeat, or of the
+    /// attestation before the first one.
+    pub fn last_quote_status(&self) -> Option<&str> {
+        self.last_quote_status.as_ref().map(|s| s.as_str())
+    }
+
+    /// Why the session was revoked, if it was.
+    pub fn revocation(&self) -> Option<&str> {
+        self.revocation.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revocation.is_some()
+    }
+
+    fn revoke(&mut self, reason: String) {
+        if cfg!(feature = "verbose") {
+            eprintln!("Session revoked: {}", reason);
+        }
+        if let Some(session) = self.session.as_ref() {
+            session.revoke();
+        }
+        if self.revocation.is_none() {
+            self.revocation = Some(reason);
+        }
+    }
+
+    /// Fails with `SessionRevoked` if the session is revoked, revoking it
+    /// first with `HeartbeatMissed` if the last heartbeat is overdue.
+    pub fn check(&mut self) -> SpRaResult<()> {
+        if self.revocation.is_none() && self.last_beat.elapsed() > self.timeout {
+            self.revoke(format!("no heartbeat for {:?}", self.last_beat.elapsed()));
+            return Err(SpRaError::HeartbeatMissed);
+        }
+        if self.is_revoked() {
+            return Err(SpRaError::SessionRevoked);
+        }
+        Ok(())
+    }
+
+    /// Verify `heartbeat` and return the acknowledgement for the enclave,
+    /// which tells it whether the session is still trusted. Fails if the
+    /// heartbeat could not be verified or revoked the session.
+    pub fn process(&mut self, heartbeat: &Heartbeat) -> SpRaResult<HeartbeatAck> {
+        let mut ack = HeartbeatAck {
+            seq: heartbeat.seq,
+            is_enclave_trusted: false,
+            quote_status: None,
+        };
+        self.check()?;
+
+        // Bound to this session and newer than the last heartbeat
+        if heartbeat.seq <= self.last_seq || heartbeat.quote.len() != size_of::<Quote>() {
+            self.revoke("malformed or replayed heartbeat".to_owned());
+            return Err(SpRaError::IntegrityError);
+        }
+        let mut quote = [0u8; size_of::<Quote>()];
+        quote.copy_from_slice(&heartbeat.quote[..]);
+        // Can unwrap since a Quote is always longer than its body
+        let quote_body = QuoteBody::parse(&quote[..]).unwrap();
+        let report_data = heartbeat_report_data(&self.key, heartbeat.seq);
+        if quote_body.report_data[..] != report_data[..] {
+            self.revoke("heartbeat not bound to the session".to_owned());
+            return Err(SpRaError::IntegrityError);
+        }
+
+        let mut context = self.identity.new_session()?;
+        context.select_tenant(self.tenant.as_ref().map(|t| t.as_str()))?;
+        let (report, rejection) = context.verify_fresh_quote(&quote, &quote_body)
+            .map_err(|e| match e {
+                // A quote that contradicts the policy, e.g. of another enclave
+                e @ SpRaError::SigstructMismatched | e @ SpRaError::EnclaveInDebugMode |
+                    e @ SpRaError::CpuSvnTooLow | e @ SpRaError::Vetoed(_) => {
+                        self.revoke(format!("heartbeat rejected: {:?}", e));
+                        e
+                    },
+                // Not the enclave's fault, e.g. IAS unreachable
+                e => e,
+            })?;
+        ack.quote_status = Some(report.isv_enclave_quote_status.clone());
+        self.last_quote_status = ack.quote_status.clone();
+        self.last_seq = heartbeat.seq;
+        if let Some(e) = rejection {
+            self.revoke(format!("quote status {} no longer trusted: {:?}",
+                                report.isv_enclave_quote_status, e));
+            return Err(e);
+        }
+
+        self.last_beat = Instant::now();
+        if let Some(session) = self.session.as_ref() {
+            session.renew();
+        }
+        ack.is_enclave_trusted = true;
+        if cfg!(feature = "verbose") {
+            eprintln!("Heartbeat {} verified: {}", heartbeat.seq, report.isv_enclave_quote_status);
+        }
+        Ok(ack)
+    }
+
+    /// Read a heartbeat from `channel`, `process` it, and acknowledge it,
+    /// telling the enclave if the session was revoked.
+    pub fn handle<T: SecureTransport>(&mut self, channel: &mut SecureChannel<T>)
+        -> SpRaResult<HeartbeatAck> {
+            let heartbeat = Heartbeat::read_from(&mut *channel)?;
+            let r = self.process(&heartbeat);
+            let ack = match r.as_ref() {
+                Ok(ack) => ack.clone(),
+                Err(_) => HeartbeatAck {
+                    seq: heartbeat.seq,
+                    is_enclave_trusted: !self.is_revoked(),
+                    quote_status: self.last_quote_status.clone(),
+                },
+            };
+            ack.write_to(&mut *channel)?;
+            r
+        }
+}
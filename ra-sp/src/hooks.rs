@@ -0,0 +1,58 @@
+use ra_common::msg::{Gid, RaMsg1, Quote};
+use crate::attestation_response::AttestationResponse;
+use crate::error::{SpRaError, IasError};
+use crate::{AttestationResult, SessionId, SpRaResult};
+
+/// `Err(reason)` vetoes the attestation, which then fails with
+/// `SpRaError::Vetoed(reason)`.
+pub type HookResult = Result<(), String>;
+
+/// Callbacks invoked by `SpRaContext` at each stage of an attestation, e.g. to
+/// log, collect metrics, or apply additional checks. All methods default to
+/// doing nothing.
+///
+/// Calls for one attestation share its `SessionId`, which is also the
+/// `session_id` of its `AttestationResult`. Attestations may run on any
+/// thread, and one thread may interleave several of them, e.g. with
+/// `SpHandshake`.
+pub trait AttestationHooks: Send + Sync {
+    fn on_msg1(&self, _session: SessionId, _msg1: &RaMsg1) -> HookResult {
+        Ok(())
+    }
+
+    /// Called when the SigRL of `gid` could not be fetched and the
+    /// attestation goes on without it, see `RevocationCheck::Soft`.
+    fn on_revocation_data_unavailable(&self, _session: SessionId, _gid: &Gid, _error: &IasError) {}
+
+    /// Called once MSG3 passed the integrity checks, before contacting IAS.
+    fn on_quote_received(&self, _session: SessionId, _quote: &Quote) -> HookResult {
+        Ok(())
+    }
+
+    /// Called with the IAS report once its signature has been verified, before
+    /// the trust decision is made.
+    fn on_report_verified(&self, _session: SessionId, _report: &AttestationResponse) -> HookResult {
+        Ok(())
+    }
+
+    fn on_complete(&self, _result: &AttestationResult) {}
+
+    fn on_failure(&self, _session: SessionId, _error: &SpRaError) {}
+
+    /// Called by `SecretProvisioner` for each secret the enclave acknowledged.
+    fn on_secret_delivered(&self, _name: &str, _result: &AttestationResult) {}
+
+    /// Called by `SpServer` before it returns, e.g. to flush an audit log.
+    fn on_shutdown(&self) {}
+
+    /// Called by `SpServer` after reloading its config on SIGHUP, with
+    /// whether the new config was put in use, see `reload_on_hangup`.
+    fn on_reload(&self, _result: &SpRaResult<()>) {}
+
+    /// Called by the readiness probe of `SpServer::serve_health`. `Err(reason)`
+    /// reports the SP as not ready, e.g. because an audit log cannot be
+    /// written.
+    fn check_ready(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
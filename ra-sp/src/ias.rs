@@ -1,38 +1,60 @@
 use std::io::Write;
-use hyper::{Client, client::HttpConnector, Body, Request};
+use hyper::{Client, client::HttpConnector, Body, Request, StatusCode};
 use hyper::body::HttpBody as _;
-use hyper_tls::HttpsConnector;
 use sgx_crypto::certificate::X509Cert;
 use ra_common::msg::{Gid, Quote};
 use crate::error::{IasError, AttestationError};
 use crate::attestation_response::AttestationResponse;
+use crate::credentials::SubscriptionKey;
+use crate::ias_pin::IasCertPin;
 
-const BASE_URI: &str = "https://api.trustedservices.intel.com/sgx/dev";
+pub const DEFAULT_IAS_BASE_URI: &str = "https://api.trustedservices.intel.com/sgx/dev";
 const SIG_RL_PATH: &str = "/attestation/v3/sigrl/";
 const REPORT_PATH: &str = "/attestation/v3/report";
 
+#[cfg(not(feature = "openssl"))]
+type HttpsConnector = hyper_tls::HttpsConnector<HttpConnector>;
+#[cfg(feature = "openssl")]
+type HttpsConnector = hyper_openssl::HttpsConnector<HttpConnector>;
+
 pub struct IasClient {
-    https_client: Client<HttpsConnector<HttpConnector>>, 
+    https_client: Client<HttpsConnector>, 
     root_ca_cert: X509Cert,
+    base_uri: String,
+    signing_cert_pins: Vec<IasCertPin>,
 }
 
 
 impl IasClient {
-    pub fn new(root_ca_cert: X509Cert) -> Self {
-        Self {
+    pub fn new(root_ca_cert: X509Cert) -> Result<Self, IasError> {
+        Self::with_base_uri(root_ca_cert, DEFAULT_IAS_BASE_URI)
+    }
+
+    /// A client of the IAS API at `base_uri`, e.g. the production API or a
+    /// mock like `MockIas`. Plain HTTP is allowed.
+    pub fn with_base_uri(root_ca_cert: X509Cert, base_uri: &str) -> Result<Self, IasError> {
+        Ok(Self {
             https_client: Client::builder()
-                .build::<_, hyper::Body>(HttpsConnector::new()),
-                root_ca_cert
-        }
+                .build::<_, hyper::Body>(https_connector()?),
+                root_ca_cert,
+                base_uri: base_uri.trim_end_matches('/').to_owned(),
+                signing_cert_pins: Vec::new(),
+        })
+    }
+
+    /// Only trust reports whose signing certificate matches one of `pins`,
+    /// besides chaining to the root CA. No pinning if `pins` is empty.
+    pub fn set_signing_cert_pins(&mut self, pins: Vec<IasCertPin>) {
+        self.signing_cert_pins = pins;
     }
 
     pub async fn get_sig_rl(&self, gid: &Gid, 
-                            subscription_key: &str) 
+                            subscription_key: &SubscriptionKey) 
         -> Result<Option<Vec<u8>>, IasError> {
-            let uri = format!("{}{}{:02x}{:02x}{:02x}{:02x}", BASE_URI, SIG_RL_PATH, 
+            let uri = format!("{}{}{:02x}{:02x}{:02x}{:02x}", self.base_uri, SIG_RL_PATH, 
                               gid[0], gid[1], gid[2], gid[3]);
             let req = Request::get(uri)
-                .header("Ocp-Apim-Subscription-Key", subscription_key)
+                .header("Ocp-Apim-Subscription-Key", subscription_key.as_str())
                 .body(Body::empty()).unwrap();
             let mut resp = self.https_client.request(req).await?;
             if resp.status().as_u16() != 200 {
@@ -50,14 +72,14 @@ impl IasClient {
 
     pub async fn verify_attestation_evidence(&self,
                                     quote: &Quote, 
-                                    subscription_key: &str) 
+                                    subscription_key: &SubscriptionKey) 
         -> Result<AttestationResponse, IasError> {
-            let uri = format!("{}{}", BASE_URI, REPORT_PATH);
+            let uri = format!("{}{}", self.base_uri, REPORT_PATH);
             let quote_base64 = base64::encode(&quote[..]);
             let body = format!("{{\"isvEnclaveQuote\":\"{}\"}}", quote_base64);
             let req = Request::post(uri)
                 .header("Content-type","application/json")
-                .header("Ocp-Apim-Subscription-Key", subscription_key)
+                .header("Ocp-Apim-Subscription-Key", subscription_key.as_str())
                 .body(Body::from(body)).unwrap();
             let mut resp = self.https_client.request(req).await?;
             if resp.status().as_u16() != 200 {
@@ -69,8 +91,99 @@ impl IasClient {
                 body.write_all(&chunk.unwrap()).unwrap();
             }
 
-            AttestationResponse::from_response(
+            let response = AttestationResponse::from_response(
                 &self.root_ca_cert, resp.headers(), body)
-                .map_err(|e| IasError::Attestation(e))
+                .map_err(|e| IasError::Attestation(e))?;
+            self.check_signing_cert_pins(&response)?;
+            Ok(response)
         }
+
+    /// Open a connection to IAS and check the subscription keys with a SigRL
+    /// request for a dummy EPID group. Any answer other than 401 means IAS is
+    /// reachable and accepted a key; the connection then stays pooled for
+    /// subsequent requests made on the same runtime.
+    pub async fn warm_up(&self, primary_key: &SubscriptionKey,
+                         secondary_key: &SubscriptionKey)
+        -> Result<(), IasError> {
+            let gid: Gid = [0u8; 4];
+            match self.get_sig_rl_with_fallback(&gid, primary_key, secondary_key).await {
+                Err(IasError::SigRLError(status)) if status != StatusCode::UNAUTHORIZED =>
+                    Ok(()),
+                r => r.map(|_| ()),
+            }
+        }
+
+    /// Same as `get_sig_rl`, but retries with `secondary_key` if IAS rejects
+    /// `primary_key`, e.g. while keys are being rotated.
+    pub async fn get_sig_rl_with_fallback(&self, gid: &Gid,
+                                          primary_key: &SubscriptionKey,
+                                          secondary_key: &SubscriptionKey)
+        -> Result<Option<Vec<u8>>, IasError> {
+            match self.get_sig_rl(gid, primary_key).await {
+                Err(ref e) if is_unauthorized(e) => {
+                    if cfg!(feature = "verbose") {
+                        eprintln!("Primary subscription key rejected, retrying with secondary");
+                    }
+                    self.get_sig_rl(gid, secondary_key).await
+                },
+                r => r,
+            }
+        }
+
+    /// Same as `verify_attestation_evidence`, but retries with
+    /// `secondary_key` if IAS rejects `primary_key`.
+    pub async fn verify_attestation_evidence_with_fallback(&self,
+                                                           quote: &Quote,
+                                                           primary_key: &SubscriptionKey,
+                                                           secondary_key: &SubscriptionKey)
+        -> Result<AttestationResponse, IasError> {
+            match self.verify_attestation_evidence(quote, primary_key).await {
+                Err(ref e) if is_unauthorized(e) => {
+                    if cfg!(feature = "verbose") {
+                        eprintln!("Primary subscription key rejected, retrying with secondary");
+                    }
+                    self.verify_attestation_evidence(quote, secondary_key).await
+                },
+                r => r,
+            }
+        }
+
+    fn check_signing_cert_pins(&self, response: &AttestationResponse) -> Result<(), IasError> {
+        if self.signing_cert_pins.is_empty() {
+            return Ok(());
+        }
+        // The signing certificate comes first, and was parsed by from_response
+        let certificate = X509Cert::new_from_der(&response.certificates[0][..])
+            .map_err(|_| IasError::Attestation(AttestationError::InvalidIASCertificate))?;
+        if !self.signing_cert_pins.iter().any(|pin| pin.matches(&certificate)) {
+            if cfg!(feature = "verbose") {
+                eprintln!("IAS signing certificate matches no pin; its key is pinned by {}",
+                          IasCertPin::public_key_of(&certificate)
+                          .map_or("?".to_owned(), |pin| pin.to_string()));
+            }
+            return Err(IasError::Attestation(AttestationError::UnpinnedIASCertificate));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "openssl"))]
+fn https_connector() -> Result<HttpsConnector, IasError> {
+    Ok(HttpsConnector::new())
+}
+
+// Fails only if OpenSSL cannot be initialized, e.g. a FIPS module that does
+// not pass its self-tests
+#[cfg(feature = "openssl")]
+fn https_connector() -> Result<HttpsConnector, IasError> {
+    HttpsConnector::new().map_err(|e| IasError::Tls(e.to_string()))
+}
+
+fn is_unauthorized(e: &IasError) -> bool {
+    match e {
+        IasError::SigRLError(status) |
+            IasError::Attestation(AttestationError::Connection(status)) =>
+            *status == StatusCode::UNAUTHORIZED,
+        _ => false,
+    }
 }
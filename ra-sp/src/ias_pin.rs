@@ -0,0 +1,97 @@
+// Pins of the IAS report signing certificate, checked on top of its chain to
+// the configured root CA, so that a report signed under a compromised or
+// mis-issued certificate of that CA is not trusted. Any of several pins may
+// match, so that a planned rotation is done by adding the pin of the new
+// certificate before IAS switches to it, and removing the old one after.
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error;
+use sgx_crypto::certificate::X509Cert;
+use sgx_crypto::digest::sha256;
+
+const PUBLIC_KEY_PIN_PREFIX: &str = "sha256/";
+
+/// A pin of the IAS report signing certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IasCertPin {
+    /// SHA-256 of the certificate's SubjectPublicKeyInfo, which outlives a
+    /// reissue of the certificate for the same key. Written as
+    /// `sha256/<base64>`, as in HPKP and curl's `--pinnedpubkey`.
+    PublicKey([u8; 32]),
+    /// SHA-256 of the whole certificate. Written as 64 hex digits, with or
+    /// without the colons of `openssl x509 -noout -fingerprint -sha256`.
+    Certificate([u8; 32]),
+}
+
+impl IasCertPin {
+    /// The `PublicKey` pin of `cert`, e.g. to pin the certificate IAS is
+    /// using now.
+    pub fn public_key_of(cert: &X509Cert) -> Option<Self> {
+        cert.subject_public_key_info().map(|spki| IasCertPin::PublicKey(sha256(spki)))
+    }
+
+    pub fn matches(&self, cert: &X509Cert) -> bool {
+        match self {
+            IasCertPin::PublicKey(hash) => cert.subject_public_key_info()
+                .map_or(false, |spki| &sha256(spki) == hash),
+            IasCertPin::Certificate(hash) => &sha256(cert.as_ref()) == hash,
+        }
+    }
+}
+
+impl FromStr for IasCertPin {
+    type Err = ParseIasCertPinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(b64) = s.strip_prefix(PUBLIC_KEY_PIN_PREFIX) {
+            let hash = base64::decode(b64).map_err(|_| ParseIasCertPinError)?;
+            return hash.as_slice().try_into()
+                .map(IasCertPin::PublicKey)
+                .map_err(|_| ParseIasCertPinError);
+        }
+        let hex: String = s.chars().filter(|c| *c != ':').collect();
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParseIasCertPinError);
+        }
+        let mut hash = [0u8; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        Ok(IasCertPin::Certificate(hash))
+    }
+}
+
+impl fmt::Display for IasCertPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            IasCertPin::PublicKey(hash) =>
+                write!(f, "{}{}", PUBLIC_KEY_PIN_PREFIX, base64::encode(&hash[..])),
+            IasCertPin::Certificate(hash) => f.write_str(&hex::encode(hash)),
+        }
+    }
+}
+
+impl Serialize for IasCertPin {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for IasCertPin {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseIasCertPinError;
+
+impl fmt::Display for ParseIasCertPinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.write_str("must be sha256/<base64 of a SHA-256 hash> or 64 hexadecimal digits")
+    }
+}
+
+impl std::error::Error for ParseIasCertPinError {}
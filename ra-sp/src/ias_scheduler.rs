@@ -0,0 +1,202 @@
+// Bounds the IAS requests in flight, so that a burst of attestations, e.g. a
+// fleet coming up after a deploy, does not trip Intel's rate limits. Requests
+// beyond the limit wait in one queue per session, and the queues take turns,
+// so that a session with many requests cannot starve the others.
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::oneshot;
+
+struct State {
+    in_flight: usize,
+    // Sessions with waiting requests, in the order they take turns
+    waiting: VecDeque<(u64, VecDeque<oneshot::Sender<IasPermit>>)>,
+}
+
+pub(crate) struct IasScheduler {
+    max_in_flight: usize,
+    state: Mutex<State>,
+    next_session: AtomicU64,
+}
+
+impl IasScheduler {
+    pub(crate) fn new(max_in_flight: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max_in_flight: max_in_flight.max(1),
+            state: Mutex::new(State {
+                in_flight: 0,
+                waiting: VecDeque::new(),
+            }),
+            next_session: AtomicU64::new(0),
+        })
+    }
+
+    /// A queue for the requests of a new session.
+    pub(crate) fn new_slot(self: &Arc<Self>) -> IasSlot {
+        IasSlot {
+            scheduler: self.clone(),
+            session: self.next_session.fetch_add(1, Ordering::SeqCst),
+        }
+    }
+
+    // Hand the permit of a finished request to the next session in turn, or
+    // free it if none is waiting
+    fn release(self: &Arc<Self>) {
+        loop {
+            let next = {
+                let mut state = self.state.lock().unwrap();
+                let next = match state.waiting.pop_front() {
+                    Some((session, mut requests)) => {
+                        let next = requests.pop_front();
+                        if !requests.is_empty() {
+                            state.waiting.push_back((session, requests));
+                        }
+                        next
+                    },
+                    None => None,
+                };
+                match next {
+                    Some(next) => next,
+                    None => {
+                        state.in_flight -= 1;
+                        return;
+                    },
+                }
+            };
+            let permit = IasPermit { scheduler: Some(self.clone()) };
+            match next.send(permit) {
+                Ok(()) => return,
+                // The request was given up while it waited
+                Err(mut permit) => {
+                    permit.scheduler = None;
+                },
+            }
+        }
+    }
+}
+
+/// The place of one session in an `IasScheduler`.
+#[derive(Clone)]
+pub(crate) struct IasSlot {
+    scheduler: Arc<IasScheduler>,
+    session: u64,
+}
+
+impl IasSlot {
+    /// Wait until the session's request may be sent. The request is counted
+    /// as in flight until the permit is dropped.
+    pub(crate) async fn acquire(&self) -> IasPermit {
+        let receiver = {
+            let scheduler = &self.scheduler;
+            let mut state = scheduler.state.lock().unwrap();
+            if state.in_flight < scheduler.max_in_flight && state.waiting.is_empty() {
+                state.in_flight += 1;
+                return IasPermit { scheduler: Some(scheduler.clone()) };
+            }
+            let (sender, receiver) = oneshot::channel();
+            match state.waiting.iter_mut().find(|(session, _)| *session == self.session) {
+                Some((_, requests)) => requests.push_back(sender),
+                None => state.waiting.push_back((self.session, VecDeque::from(vec![sender]))),
+            }
+            receiver
+        };
+        // Can unwrap since the sender is only dropped after sending, see
+        // `release`
+        receiver.await.unwrap()
+    }
+}
+
+/// Acquire a permit of `slot`, if any.
+pub(crate) async fn acquire(slot: Option<&IasSlot>) -> Option<IasPermit> {
+    match slot {
+        Some(slot) => Some(slot.acquire().await),
+        None => None,
+    }
+}
+
+/// A request in flight, see `IasSlot::acquire`.
+pub(crate) struct IasPermit {
+    scheduler: Option<Arc<IasScheduler>>,
+}
+
+impl Drop for IasPermit {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use futures::FutureExt;
+    use super::*;
+
+    // Poll once: a request that has to wait joins its session's queue on its
+    // first poll, and is Ready once it was handed a permit
+    fn poll<F: Future + Unpin>(request: &mut F) -> Option<F::Output> {
+        FutureExt::now_or_never(request)
+    }
+
+    #[test]
+    fn bounds_requests_in_flight() {
+        let scheduler = IasScheduler::new(2);
+        let slot = scheduler.new_slot();
+        let _first = poll(&mut Box::pin(slot.acquire())).unwrap();
+        let second = poll(&mut Box::pin(slot.acquire())).unwrap();
+        let mut third = Box::pin(slot.acquire());
+        assert!(poll(&mut third).is_none());
+        drop(second);
+        assert!(poll(&mut third).is_some());
+    }
+
+    #[test]
+    fn sessions_take_turns() {
+        let scheduler = IasScheduler::new(1);
+        let (busy, quiet) = (scheduler.new_slot(), scheduler.new_slot());
+        let first = poll(&mut Box::pin(busy.acquire())).unwrap();
+        let mut busy_1 = Box::pin(busy.acquire());
+        let mut busy_2 = Box::pin(busy.acquire());
+        let mut busy_3 = Box::pin(busy.acquire());
+        let mut quiet_1 = Box::pin(quiet.acquire());
+        assert!(poll(&mut busy_1).is_none());
+        assert!(poll(&mut busy_2).is_none());
+        assert!(poll(&mut busy_3).is_none());
+        assert!(poll(&mut quiet_1).is_none());
+
+        // The quiet session goes second though it asked last
+        drop(first);
+        let permit = poll(&mut busy_1).unwrap();
+        assert!(poll(&mut quiet_1).is_none());
+        drop(permit);
+        let permit = poll(&mut quiet_1).unwrap();
+        assert!(poll(&mut busy_2).is_none());
+        drop(permit);
+        let permit = poll(&mut busy_2).unwrap();
+        assert!(poll(&mut busy_3).is_none());
+        drop(permit);
+        assert!(poll(&mut busy_3).is_some());
+    }
+
+    #[test]
+    fn skips_dropped_requests() {
+        let scheduler = IasScheduler::new(1);
+        let (gone, waiting) = (scheduler.new_slot(), scheduler.new_slot());
+        let first = poll(&mut Box::pin(gone.acquire())).unwrap();
+        let mut gone_1 = Box::pin(gone.acquire());
+        let mut waiting_1 = Box::pin(waiting.acquire());
+        assert!(poll(&mut gone_1).is_none());
+        assert!(poll(&mut waiting_1).is_none());
+
+        // The permit passes over the request given up to the next one
+        drop(gone_1);
+        drop(first);
+        let permit = poll(&mut waiting_1).unwrap();
+        drop(permit);
+
+        // and is freed once no request is waiting
+        assert_eq!(scheduler.state.lock().unwrap().in_flight, 0);
+        assert!(poll(&mut Box::pin(gone.acquire())).is_some());
+    }
+}
@@ -0,0 +1,240 @@
+use std::sync::Arc;
+use tokio::runtime::{Runtime, Builder};
+use ra_verify::sigstruct::Sigstruct;
+use ra_verify::policy::CpuSvnRequirement;
+use std::time::Duration;
+use sgx_crypto::signature::SigningKey;
+#[cfg(feature = "intel-compat")]
+use sgx_crypto::signature::EcdsaSigningKey;
+use ra_common::KeyDerivation;
+use crate::ias::IasClient;
+use crate::ias_scheduler::IasScheduler;
+use crate::sig_rl_cache::SigRlCache;
+use crate::verdict_cache::VerdictCache;
+use crate::signing_keys::SpSigningKeys;
+use crate::hooks::AttestationHooks;
+use crate::verifier::ReportVerifier;
+use crate::quorum::VerifierQuorum;
+#[cfg(feature = "ring-backend")]
+use crate::tdx::TdxVerifier;
+use crate::config::{SpConfig, zero_string};
+use crate::error::SpRaError;
+use crate::context::SpRaContext;
+use crate::SpRaResult;
+
+/// Everything an SP needs that does not change between attestations: its
+/// config, signing key, expected SIGSTRUCT, IAS client, and policy. It is
+/// loaded once and shared through an `Arc` by the sessions of all
+/// connections, which may run on different threads.
+///
+/// IAS requests of all sessions run on a runtime owned by the identity, so
+/// that HTTPS connections to IAS are kept alive between attestations.
+pub struct SpIdentity {
+    pub(crate) config: SpConfig,
+    pub(crate) sigstruct: Sigstruct,
+    pub(crate) min_cpu_svn: Vec<CpuSvnRequirement>,
+    pub(crate) ias_client: IasClient,
+    pub(crate) ias_scheduler: Option<Arc<IasScheduler>>,
+    // The parsed keys may keep parts of themselves on the crypto backend's
+    // heap, which is not locked. Shared with the identities reloaded from
+    // this one.
+    pub(crate) signing_keys: Arc<SpSigningKeys>,
+    pub(crate) sig_rl_cache: Option<SigRlCache>,
+    pub(crate) verdict_cache: Option<VerdictCache>,
+    pub(crate) hooks: Option<Arc<dyn AttestationHooks>>,
+    pub(crate) verifier: Option<Arc<dyn ReportVerifier>>,
+    pub(crate) quorum: Option<Arc<VerifierQuorum>>,
+    pub(crate) key_derivation: Option<Arc<dyn KeyDerivation>>,
+    #[cfg(feature = "ring-backend")]
+    pub(crate) tdx_verifier: Option<Arc<TdxVerifier>>,
+    #[cfg(feature = "intel-compat")]
+    pub(crate) intel_compat_key: Option<Arc<EcdsaSigningKey>>,
+    pub(crate) runtime: Runtime,
+}
+
+impl SpIdentity {
+    /// Fails with `InvalidConfig` if `config` asks for protocol options the
+    /// SP does not implement, see `SpConfig::problems`.
+    pub fn init(mut config: SpConfig) -> SpRaResult<Self> {
+        let problems = config.unsupported_options();
+        if !problems.is_empty() {
+            return Err(SpRaError::InvalidConfig(problems));
+        }
+
+        // Keep the key only in its parsed form, not as PEM in the config
+        let signing_keys = Arc::new(SpSigningKeys::new(config.sp_private_key()?));
+        if let Some(mut pem_base64) = config.sp_private_key_pem_base64.take() {
+            zero_string(&mut pem_base64);
+        }
+        if cfg!(feature = "verbose") {
+            eprintln!("==================SP Config==================");
+            eprintln!("{:#?}", config);
+            eprintln!("=============================================");
+        }
+
+        // Preparing for binary search
+        config.quote_trust_options.sort();
+        config.pse_trust_options.as_mut().map(|v| v.sort());
+        config.allowed_advisory_ids.as_mut().map(|v| v.sort());
+
+        let sigstruct = config.sigstruct()?;
+        let min_cpu_svn = config.min_cpu_svn.iter().flatten()
+            .map(|floor| floor.requirement()
+                 .ok_or_else(|| SpRaError::InvalidConfigValue("min_cpu_svn".to_owned())))
+            .collect::<SpRaResult<Vec<_>>>()?;
+
+        let mut runtime = Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()?;
+        let ias_client = config.ias_client()?;
+        if config.prewarm_ias_connection {
+            runtime.block_on(ias_client.warm_up(&config.primary_subscription_key,
+                                                &config.secondary_subscription_key))?;
+            if cfg!(feature = "verbose") {
+                eprintln!("IAS connection established");
+            }
+        }
+
+        let ias_scheduler = config.ias_max_in_flight
+            .map(|max| IasScheduler::new(max as usize));
+        let verdict_cache = config.verdict_cache_secs
+            .map(|secs| VerdictCache::new(Duration::from_secs(secs)));
+
+        Ok(Self {
+            config,
+            sigstruct,
+            min_cpu_svn,
+            ias_client,
+            ias_scheduler,
+            sig_rl_cache: None,
+            verdict_cache,
+            hooks: None,
+            verifier: None,
+            quorum: None,
+            key_derivation: None,
+            #[cfg(feature = "ring-backend")]
+            tdx_verifier: None,
+            #[cfg(feature = "intel-compat")]
+            intel_compat_key: None,
+            signing_keys,
+            runtime,
+        })
+    }
+
+    /// A new identity from `config` with this one's hooks, verifiers,
+    /// quorum, and key derivation, e.g. to reload the config of a running
+    /// `SpServer`. `config` is validated first. The SigRL cache is kept if it
+    /// has the new config's subscription keys; cached verdicts are not, since
+    /// the policy may have changed. The signing keys are shared with this
+    /// identity, with any rotation under way, so the new config's key must
+    /// be one of `sp_public_keys_pem`; replace the key with
+    /// `rotate_signing_key` instead.
+    pub fn with_config(&self, config: SpConfig) -> SpRaResult<Self> {
+        config.validate()?;
+        let mut identity = Self::init(config)?;
+        let config_key = identity.signing_keys.signing_key().public_key_pem();
+        if !self.signing_keys.public_keys_pem().contains(&config_key) {
+            return Err(SpRaError::InvalidConfigValue(
+                    "sp_private_key_pem_base64 is not a key in use".to_owned()));
+        }
+        identity.signing_keys = self.signing_keys.clone();
+        identity.sig_rl_cache = self.sig_rl_cache.clone()
+            .filter(|cache| cache.is_for_key(&identity.config.primary_subscription_key));
+        identity.hooks = self.hooks.clone();
+        identity.verifier = self.verifier.clone();
+        identity.quorum = self.quorum.clone();
+        identity.key_derivation = self.key_derivation.clone();
+        #[cfg(feature = "ring-backend")]
+        {
+            identity.tdx_verifier = self.tdx_verifier.clone();
+        }
+        #[cfg(feature = "intel-compat")]
+        {
+            identity.intel_compat_key = self.intel_compat_key.clone();
+        }
+        Ok(identity)
+    }
+
+    pub fn config(&self) -> &SpConfig {
+        &self.config
+    }
+
+    /// Take SigRLs from a cache shared with other connections instead of
+    /// fetching them from IAS for every attestation. Only connections of the
+    /// tenant with the cache's subscription keys use it, see `SigRlCache`.
+    pub fn set_sig_rl_cache(&mut self, cache: SigRlCache) {
+        self.sig_rl_cache = Some(cache);
+    }
+
+    /// Share `cache` with other identities, replacing the one set up from
+    /// `verdict_cache_secs`.
+    pub fn set_verdict_cache(&mut self, cache: VerdictCache) {
+        self.verdict_cache = Some(cache);
+    }
+
+    /// The cache of IAS verdicts, e.g. to `clear` it after an advisory.
+    pub fn verdict_cache(&self) -> Option<&VerdictCache> {
+        self.verdict_cache.as_ref()
+    }
+
+    pub fn set_hooks(&mut self, hooks: Arc<dyn AttestationHooks>) {
+        self.hooks = Some(hooks);
+    }
+
+    /// Register extra checks that run after the built-in trust decision.
+    pub fn set_report_verifier(&mut self, verifier: Arc<dyn ReportVerifier>) {
+        self.verifier = Some(verifier);
+    }
+
+    /// Also require `quorum` of independent quote verifiers to accept every
+    /// quote IAS vouches for, see `VerifierQuorum`. Fails if the quorum has
+    /// fewer verifiers than its threshold, since it could never be reached.
+    pub fn set_verifier_quorum(&mut self, quorum: VerifierQuorum) -> SpRaResult<()> {
+        if quorum.len() < quorum.threshold() {
+            return Err(SpRaError::InvalidConfigValue(format!(
+                        "Quorum of {} needs at least as many verifiers", quorum.threshold())));
+        }
+        self.quorum = Some(Arc::new(quorum));
+        Ok(())
+    }
+
+    /// Derive the session keys with `kdf` instead of the default KDF. The
+    /// enclave must have been set up with the same KDF.
+    pub fn set_key_derivation(&mut self, kdf: Arc<dyn KeyDerivation>) {
+        self.key_derivation = Some(kdf);
+    }
+
+    /// Verify the TD quotes of Intel TDX VMs with `verifier`, see
+    /// `SpRaContext::verify_td_quote`.
+    #[cfg(feature = "ring-backend")]
+    pub fn set_tdx_verifier(&mut self, verifier: TdxVerifier) {
+        self.tdx_verifier = Some(Arc::new(verifier));
+    }
+
+    /// Sign the MSG2s of sgx-ra-sample clients with `key`, the ECDSA P-256
+    /// key whose public key is compiled into their enclaves, see
+    /// `SpRaContext::do_intel_attestation`.
+    #[cfg(feature = "intel-compat")]
+    pub fn set_intel_compat_key(&mut self, key: EcdsaSigningKey) {
+        self.intel_compat_key = Some(Arc::new(key));
+    }
+
+    /// Replace the SP's signing key without a restart. Sessions keep being
+    /// signed with the old key for `grace`, so that enclaves have time to be
+    /// updated to trust the new one, see `SpSigningKeys`.
+    pub fn rotate_signing_key(&self, key: SigningKey, grace: Duration) {
+        self.signing_keys.rotate(key, grace)
+    }
+
+    /// The SP public keys enclaves should pin, in PEM.
+    pub fn sp_public_keys_pem(&self) -> Vec<String> {
+        self.signing_keys.public_keys_pem()
+    }
+
+    /// Start the session of a new connection. Only generates the session's
+    /// ephemeral key pair; nothing is read from disk.
+    pub fn new_session(self: &Arc<Self>) -> SpRaResult<SpRaContext> {
+        SpRaContext::new(self.clone())
+    }
+}
@@ -0,0 +1,80 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use sgx_crypto::signature::VerificationKey;
+use ra_common::quote::QuoteBody;
+use ra_verify::qe_identity::{split_signed, QeIdentity};
+use crate::attestation_response::AttestationResponse;
+use crate::verifier::{ReportVerifier, Verdict};
+use crate::SpRaResult;
+
+/// Expected measurements and ISVSVN baselines of the application enclave,
+/// from a file in the format of Intel's enclave identity collateral, so that
+/// they can be distributed and updated without changing the SP's config:
+///
+/// ```text
+/// {"enclaveIdentity":{"id":"my-enclave","version":2,"issueDate":"...",
+///  "nextUpdate":"2025-01-01T00:00:00Z","miscselect":"00000000",
+///  "miscselectMask":"FFFFFFFF","attributes":"04000000000000000000000000000000",
+///  "attributesMask":"06000000000000000000000000000000","mrsigner":"...",
+///  "mrenclave":"...","isvprodid":1,
+///  "tcbLevels":[{"tcb":{"isvsvn":3},"tcbStatus":"UpToDate"},
+///               {"tcb":{"isvsvn":2},"tcbStatus":"OutOfDate"},
+///               {"tcb":{"isvsvn":0},"tcbStatus":"Revoked"}]},
+///  "signature":"..."}
+/// ```
+///
+/// The signature is the operator's RSA PKCS#1 v1.5 SHA-256 signature over the
+/// bytes of the enclaveIdentity value, e.g. from `openssl dgst -sha256 -sign`,
+/// in hex. "mrenclave" is optional. Install with
+/// `SpIdentity::set_report_verifier`.
+pub struct EnclaveIdentityPolicy {
+    identity: QeIdentity,
+    tcb_trust_options: Vec<String>,
+}
+
+impl EnclaveIdentityPolicy {
+    pub fn from_file(path: &Path, operator_key: &VerificationKey) -> SpRaResult<Self> {
+        Self::from_json(&fs::read(path)?[..], operator_key)
+    }
+
+    pub fn from_json(json: &[u8], operator_key: &VerificationKey) -> SpRaResult<Self> {
+        let (body, signature) = split_signed(json)?;
+        operator_key.verify(body, &signature[..])?;
+        let identity = QeIdentity::parse(body, now())?;
+        if cfg!(feature = "verbose") {
+            eprintln!("Enclave identity {} loaded", identity.id);
+        }
+        Ok(Self { identity, tcb_trust_options: Vec::new() })
+    }
+
+    /// TCB statuses accepted besides "UpToDate", e.g. "OutOfDate" during a
+    /// migration to a new ISVSVN.
+    pub fn set_tcb_trust_options(&mut self, options: Vec<String>) {
+        self.tcb_trust_options = options;
+    }
+
+    pub fn identity(&self) -> &QeIdentity {
+        &self.identity
+    }
+}
+
+impl ReportVerifier for EnclaveIdentityPolicy {
+    fn verify(&self, quote: &QuoteBody, _report: &AttestationResponse) -> Verdict {
+        if now() >= self.identity.next_update {
+            return Verdict::Reject("Enclave identity expired".to_owned());
+        }
+        match self.identity.check_quote(quote) {
+            Ok(status) if status == "UpToDate" ||
+                self.tcb_trust_options.iter().any(|s| s == status) => Verdict::Accept,
+            Ok(status) => Verdict::Reject(format!("Enclave TCB status {} not trusted", status)),
+            Err(e) => Verdict::Reject(format!("{:?}", e)),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
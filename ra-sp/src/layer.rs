@@ -0,0 +1,76 @@
+// Tower middleware that lets only requests with a valid token from a
+// `TokenIssuer` through, e.g. in front of axum routes:
+//
+//     Router::new().route("/secret", get(handler))
+//         .layer(AttestationLayer::new(issuer))
+//
+// The token is expected as `Authorization: Bearer <token>`. Handlers find
+// the `TokenClaims` in the request extensions.
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use futures::future::{self, Either, Ready};
+use http::{Request, Response, StatusCode};
+use http::header::AUTHORIZATION;
+use tower_layer::Layer;
+use tower_service::Service;
+use crate::token::TokenIssuer;
+
+#[derive(Clone)]
+pub struct AttestationLayer {
+    issuer: Arc<TokenIssuer>,
+}
+
+impl AttestationLayer {
+    pub fn new(issuer: Arc<TokenIssuer>) -> Self {
+        Self { issuer }
+    }
+}
+
+impl<S> Layer<S> for AttestationLayer {
+    type Service = AttestationService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AttestationService {
+            inner,
+            issuer: self.issuer.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AttestationService<S> {
+    inner: S,
+    issuer: Arc<TokenIssuer>,
+}
+
+impl<S, B, R> Service<Request<B>> for AttestationService<S>
+    where S: Service<Request<B>, Response = Response<R>>, R: Default {
+    type Response = Response<R>;
+    type Error = S::Error;
+    type Future = Either<S::Future, Ready<Result<Response<R>, S::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let claims = request.headers().get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| self.issuer.verify(token.trim()).ok());
+        match claims {
+            Some(claims) => {
+                request.extensions_mut().insert(claims);
+                Either::Left(self.inner.call(request))
+            },
+            None => {
+                if cfg!(feature = "verbose") {
+                    eprintln!("Request to {} without a valid attestation token", request.uri());
+                }
+                let mut response = Response::new(R::default());
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                Either::Right(future::ready(Ok(response)))
+            },
+        }
+    }
+}
@@ -2,19 +2,213 @@ mod ias;
 mod attestation_response;
 mod error;
 mod context;
+mod identity;
 mod config;
+mod credentials;
+mod ias_pin;
+mod ias_scheduler;
+mod sig_rl_cache;
+mod verdict_cache;
+mod signing_keys;
+mod hooks;
+mod audit;
+#[cfg(feature = "otel")]
+mod telemetry;
+mod verifier;
+mod quorum;
+mod identity_policy;
+mod ra_tls;
+#[cfg(feature = "ring-backend")]
+mod tdx;
+#[cfg(feature = "dcap-qvl")]
+mod dcap_qvl;
+mod server;
+mod health;
+mod reattest;
+mod session;
+mod group;
+mod token;
+mod provisioner;
+mod pool;
+mod heartbeat;
+mod nonblocking;
+#[cfg(feature = "tower")]
+mod layer;
+#[cfg(feature = "loopback")]
+mod loopback;
+#[cfg(feature = "aws-kms")]
+mod aws_kms;
+
+// Intel builds libsgx_dcap_quoteverify for x86_64 only; everything else in
+// the SP runs on any host
+#[cfg(all(feature = "dcap-qvl", not(target_arch = "x86_64")))]
+compile_error!("`dcap-qvl` links Intel's QVL, which is only built for x86_64 hosts");
 
 pub use crate::error::*;
 pub use crate::context::*;
+pub use crate::identity::*;
 pub use crate::config::*;
+pub use crate::credentials::*;
+pub use crate::ias_pin::*;
+pub use crate::sig_rl_cache::*;
+pub use crate::verdict_cache::*;
+pub use crate::signing_keys::*;
+pub use crate::hooks::*;
+pub use crate::audit::*;
+#[cfg(feature = "otel")]
+pub use crate::telemetry::*;
+pub use crate::verifier::*;
+pub use crate::quorum::*;
+pub use crate::identity_policy::*;
+pub use crate::ra_tls::*;
+#[cfg(feature = "ring-backend")]
+pub use crate::tdx::*;
+#[cfg(feature = "dcap-qvl")]
+pub use crate::dcap_qvl::*;
+pub use crate::server::*;
+pub use crate::reattest::*;
+pub use crate::session::*;
+pub use crate::group::*;
+pub use crate::token::*;
+pub use crate::provisioner::*;
+pub use crate::pool::*;
+pub use crate::heartbeat::*;
+pub use crate::nonblocking::*;
+#[cfg(feature = "tower")]
+pub use crate::layer::*;
+#[cfg(feature = "loopback")]
+pub use crate::loopback::*;
+#[cfg(feature = "aws-kms")]
+pub use crate::aws_kms::*;
+pub use crate::attestation_response::AttestationResponse;
 
 pub type SpRaResult<T> = Result<T, crate::error::SpRaError>;
 
+use std::time::Duration;
+use serde::{Serialize, Serializer};
 use sgx_crypto::cmac::MacTag;
+use sgx_crypto::digest::{sha256, Sha256Digest};
+use sgx_crypto::locked::Locked;
+use ra_common::quote::QuoteBody;
+use ra_common::session_keys::SessionKeys;
+use ra_common::tls_psk::ExternalPsk;
 
+/// Outcome of a successful attestation. Serializes, e.g. with `to_json`, to
+/// everything but the session keys, with byte strings in hex.
+#[derive(Serialize)]
 pub struct AttestationResult {
+    /// The `SpRaContext::session_id` of the attestation, as passed to the
+    /// hooks.
+    pub session_id: SessionId,
     pub epid_pseudonym: Option<String>,
-    pub signing_key: MacTag,
-    pub master_key: MacTag,
+    /// Session keys, in locked memory that is left out of core dumps.
+    #[serde(skip)]
+    pub signing_key: Locked<MacTag>,
+    /// Keys derived from MK for each use of the session, e.g.
+    /// `keys.channel_key()` for the `SecureChannel` to the enclave.
+    #[serde(skip)]
+    pub keys: Locked<SessionKeys>,
+    /// Second half of the quote's REPORTDATA. All zeros unless the enclave
+    /// bound data to the quote with `EnclaveRaContext::bind_data`.
+    #[serde(serialize_with = "to_hex")]
+    pub bound_data_digest: Sha256Digest,
+    pub enclave: EnclaveIdentity,
+    /// The IAS quote status, e.g. "OK" or "GROUP_OUT_OF_DATE", which tells
+    /// whether the platform's TCB is up to date.
+    pub quote_status: String,
+    pub advisory_ids: Vec<String>,
+    pub advisory_url: Option<String>,
+    /// ID and timestamp of the IAS report, as IAS formats it (UTC).
+    pub report_id: String,
+    pub report_timestamp: String,
+    /// When the attestation completed, in seconds since the Unix epoch.
+    pub attested_at: u64,
+    /// Whether the IAS report is that of an earlier quote of the same enclave
+    /// and platform, taken from the `VerdictCache` instead of asking IAS. The
+    /// EPID pseudonym is then unknown.
+    pub cached_verdict: bool,
+    /// How long each phase of the attestation took.
+    pub timings: AttestationTimings,
+}
+
+impl AttestationResult {
+    /// Check that `data`, e.g. a public key presented by the enclave after the
+    /// handshake, is the data the enclave bound to its quote.
+    pub fn verify_bound_data(&self, data: &[u8]) -> bool {
+        sha256(data) == self.bound_data_digest
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// A TLS 1.3 external PSK for `identity`, keyed by the session. The
+    /// enclave derives the same PSK with `ExternalPsk::derive`.
+    pub fn tls_psk(&self, identity: &[u8]) -> std::io::Result<ExternalPsk> {
+        self.keys.tls_psk(identity)
+    }
+}
+
+/// Measurements and attributes of an attested enclave, from its quote.
+#[derive(Serialize, Debug, Clone)]
+pub struct EnclaveIdentity {
+    #[serde(serialize_with = "to_hex")]
+    pub mr_enclave: [u8; 32],
+    #[serde(serialize_with = "to_hex")]
+    pub mr_signer: [u8; 32],
+    pub isv_prod_id: u16,
+    pub isv_svn: u16,
+    /// The platform's CPUSVN, which reflects its microcode-level TCB.
+    #[serde(serialize_with = "to_hex")]
+    pub cpu_svn: [u8; 16],
+    pub debug: bool,
 }
 
+impl EnclaveIdentity {
+    pub fn from_quote_body(quote_body: &QuoteBody) -> Self {
+        Self {
+            mr_enclave: quote_body.mr_enclave,
+            mr_signer: quote_body.mr_signer,
+            isv_prod_id: quote_body.isv_prod_id,
+            isv_svn: quote_body.isv_svn,
+            cpu_svn: quote_body.cpu_svn,
+            debug: quote_body.is_debug(),
+        }
+    }
+}
+
+/// Durations of the phases of an attestation, to tell where a slow one
+/// spends its time. Serializes to milliseconds.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct AttestationTimings {
+    /// From the start of the attestation until MSG1 arrived, i.e. the client
+    /// connecting and getting g_a from the enclave.
+    #[serde(serialize_with = "to_millis")]
+    pub msg1_wait: Duration,
+    /// Signing the keys of the key exchange and deriving the session keys.
+    #[serde(serialize_with = "to_millis")]
+    pub key_derivation: Duration,
+    /// Getting the SigRL from IAS or the `SigRlCache`. Zero if revocation
+    /// checks are skipped.
+    #[serde(serialize_with = "to_millis")]
+    pub sig_rl_fetch: Duration,
+    /// From MSG2 sent until MSG3 arrived, i.e. the client getting a quote of
+    /// the enclave from AESM.
+    #[serde(serialize_with = "to_millis")]
+    pub msg3_wait: Duration,
+    /// IAS verifying the quote and the checks of the report, including any
+    /// verifiers.
+    #[serde(serialize_with = "to_millis")]
+    pub quote_verification: Duration,
+    /// The whole attestation, until MSG4 was sent.
+    #[serde(serialize_with = "to_millis")]
+    pub total: Duration,
+}
+
+fn to_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+fn to_hex<T: AsRef<[u8]>, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
@@ -0,0 +1,492 @@
+// End-to-end attestation in one process, for tests of SP policies and hooks
+// in plain `cargo test`, without SGX hardware, AESM, or access to IAS. The SP
+// runs unchanged against `MockIas`, a local stand-in for IAS that signs its
+// reports with a fixed test certificate chain, and `SimulatedEnclave` plays
+// the client and the enclave over a `MemoryStream`, with a quote made up from
+// the SIGSTRUCT rather than produced by the quoting enclave.
+//
+// `loopback_config` sets up an SP for the SIGSTRUCT in data/loopback, of a
+// made-up enclave, generated in the format `sgxs-sign` writes.
+//
+// Nothing here is secure: the mock's private key is public, in
+// data/loopback, and the quotes are not signed. Never trust the mock's root
+// certificate outside of tests.
+use std::convert::{Infallible, TryInto};
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::net::{SocketAddr, TcpListener};
+use std::panic;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::json;
+use tokio::runtime::Builder;
+use tokio::sync::oneshot;
+use sgx_crypto::cmac::Cmac;
+use sgx_crypto::digest::sha256;
+use sgx_crypto::key_exchange::OneWayAuthenticatedDHKE;
+use sgx_crypto::random::RandomState;
+use sgx_crypto::signature::{SigningKey, VerificationKey};
+use ra_verify::sigstruct::Sigstruct;
+use ra_common::heartbeat::{heartbeat_key, heartbeat_report_data, Heartbeat};
+use ra_common::memory::MemoryStream;
+use ra_common::msg::{Quote, RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4, WireMessage};
+use ra_common::quote::{AttributeFlags, QUOTE_BODY_LEN};
+use ra_common::session_keys::SessionKeys;
+use ra_common::{derive_secret_keys, DEFAULT_KDF_ID};
+use crate::config::SpConfig;
+use crate::error::SpRaError;
+use crate::identity::SpIdentity;
+use crate::{SpRaResult, AttestationResult};
+
+/// Root certificate of `MockIas`, for `SpConfig::ias_root_cert_pem_base64`.
+pub const MOCK_IAS_ROOT_CA_PEM: &str = include_str!("../data/loopback/ias_root_ca.pem");
+const MOCK_IAS_SIGNING_CERT_PEM: &str = include_str!("../data/loopback/ias_signing_cert.pem");
+const MOCK_IAS_SIGNING_KEY_PEM: &str = include_str!("../data/loopback/ias_signing_key.pem");
+const LOOPBACK_SIGSTRUCT: &[u8] = include_bytes!("../data/loopback/enclave.sig");
+const LOOPBACK_SP_PRIVATE_KEY_PEM: &str =
+    include_str!("../../sample-sp/data/sp-keys/private_key.pem");
+
+const SIG_RL_PATH: &str = "/attestation/v3/sigrl/";
+const REPORT_PATH: &str = "/attestation/v3/report";
+
+/// A config that `Loopback::start` takes as is: the sample SP's signing key,
+/// the SIGSTRUCT in data/loopback, and placeholder SPID and subscription
+/// keys, which `MockIas` does not check. Only quote status OK is trusted.
+pub fn loopback_config() -> SpConfig {
+    // Can unwrap since the config is fixed and complete
+    SpConfig::from_json(json!({
+        "linkable": true,
+        "random_nonce": false,
+        "use_platform_service": false,
+        "spid": "00112233445566778899aabbccddeeff",
+        "primary_subscription_key": "00000000000000000000000000000001",
+        "secondary_subscription_key": "00000000000000000000000000000002",
+        "quote_trust_options": [],
+        "sp_private_key_pem_base64": base64::encode(LOOPBACK_SP_PRIVATE_KEY_PEM),
+        "sigstruct_base64": base64::encode(LOOPBACK_SIGSTRUCT),
+    })).unwrap()
+}
+
+/// What `MockIas` reports for the quotes it is sent.
+#[derive(Debug, Clone)]
+pub struct MockVerdict {
+    /// e.g. "OK", "GROUP_OUT_OF_DATE", or "SIGNATURE_INVALID".
+    pub quote_status: String,
+    pub advisory_ids: Vec<String>,
+    pub advisory_url: Option<String>,
+    /// Served for every EPID group; none if empty.
+    pub sig_rl: Vec<u8>,
+    /// Answer every request with this status instead, e.g. 401 to test
+    /// subscription key fallback or 503 for an IAS outage.
+    pub http_status: Option<u16>,
+}
+
+impl Default for MockVerdict {
+    fn default() -> Self {
+        Self {
+            quote_status: "OK".to_owned(),
+            advisory_ids: Vec::new(),
+            advisory_url: None,
+            sig_rl: Vec::new(),
+            http_status: None,
+        }
+    }
+}
+
+struct MockState {
+    verdict: Mutex<MockVerdict>,
+    signing_key: SigningKey,
+    rng: RandomState,
+    reports: AtomicU64,
+}
+
+impl MockState {
+    fn respond(&self, method: &Method, path: &str, body: &[u8]) -> Response<Body> {
+        let verdict = self.verdict.lock().unwrap().clone();
+        if let Some(status) = verdict.http_status {
+            return empty_response(StatusCode::from_u16(status)
+                                  .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+        if method == Method::GET && path.starts_with(SIG_RL_PATH) {
+            return Response::builder()
+                .header("Content-Length", verdict.sig_rl.len())
+                .body(Body::from(verdict.sig_rl))
+                .unwrap();
+        }
+        if method == Method::POST && path == REPORT_PATH {
+            return match self.report(&verdict, body) {
+                Some(resp) => resp,
+                None => empty_response(StatusCode::BAD_REQUEST),
+            };
+        }
+        empty_response(StatusCode::NOT_FOUND)
+    }
+
+    fn report(&self, verdict: &MockVerdict, body: &[u8]) -> Option<Response<Body>> {
+        let request: serde_json::Value = serde_json::from_slice(body).ok()?;
+        let quote = base64::decode(request["isvEnclaveQuote"].as_str()?).ok()?;
+        if quote.len() < QUOTE_BODY_LEN {
+            return None;
+        }
+        let id = self.reports.fetch_add(1, Ordering::SeqCst);
+        let report = json!({
+            "id": format!("{}", id),
+            "timestamp": ias_timestamp(),
+            "version": 3,
+            "isvEnclaveQuoteStatus": verdict.quote_status,
+            "isvEnclaveQuoteBody": base64::encode(&quote[..QUOTE_BODY_LEN]),
+            // Linkable quotes are pseudonymous per platform and SP
+            "epidPseudonym": base64::encode(&sha256(&quote[4..8])[..]),
+        }).to_string();
+        let signature = self.signing_key.sign(report.as_bytes(), &self.rng).ok()?;
+        let chain = format!("{}{}", MOCK_IAS_SIGNING_CERT_PEM, MOCK_IAS_ROOT_CA_PEM);
+
+        let mut resp = Response::builder()
+            .header("Content-Type", "application/json")
+            .header("Request-ID", format!("{:032x}", id))
+            .header("X-IASReport-Signature", base64::encode(&signature[..]))
+            .header("X-IASReport-Signing-Certificate",
+                    utf8_percent_encode(&chain, NON_ALPHANUMERIC).to_string());
+        if !verdict.advisory_ids.is_empty() {
+            resp = resp.header("Advisory-IDs", verdict.advisory_ids.join(","));
+        }
+        if let Some(url) = verdict.advisory_url.as_ref() {
+            resp = resp.header("Advisory-URL", url.as_str());
+        }
+        resp.body(Body::from(report)).ok()
+    }
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Length", 0)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// The current time as IAS formats it, e.g. "2020-01-31T12:34:56.000000".
+fn ias_timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((now / 86400) as i64);
+    let secs = now % 86400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.000000",
+            year, month, day, secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+// Howard Hinnant's days-to-civil algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// A stand-in for IAS on 127.0.0.1, serving SigRLs and attestation reports
+/// signed by a chain under `MOCK_IAS_ROOT_CA_PEM`. Every quote gets the
+/// current `MockVerdict`, and the subscription keys are not checked. Runs on
+/// a thread of its own until dropped.
+pub struct MockIas {
+    addr: SocketAddr,
+    state: Arc<MockState>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockIas {
+    pub fn start() -> SpRaResult<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(MockState {
+            verdict: Mutex::new(MockVerdict::default()),
+            signing_key: SigningKey::new_from_pem(MOCK_IAS_SIGNING_KEY_PEM)?,
+            rng: RandomState::new(),
+            reports: AtomicU64::new(0),
+        });
+
+        let mut runtime = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()?;
+        let server = runtime.enter(|| Server::from_tcp(listener))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let service_state = state.clone();
+        let thread = thread::spawn(move || {
+            let make_service = make_service_fn(move |_| {
+                let state = service_state.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let state = state.clone();
+                        async move {
+                            let (parts, body) = req.into_parts();
+                            let body = hyper::body::to_bytes(body).await
+                                .unwrap_or_default();
+                            Ok::<_, Infallible>(
+                                state.respond(&parts.method, parts.uri.path(), &body[..]))
+                        }
+                    }))
+                }
+            });
+            let server = server.serve(make_service)
+                .with_graceful_shutdown(async { let _r = shutdown_rx.await; });
+            let r = runtime.block_on(server);
+            if cfg!(feature = "verbose") {
+                if let Err(e) = r {
+                    eprintln!("Mock IAS failed: {:?}", e);
+                }
+            }
+        });
+        if cfg!(feature = "verbose") {
+            eprintln!("Mock IAS listening on {}", addr);
+        }
+        Ok(Self {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    /// For `SpConfig::ias_base_uri`.
+    pub fn base_uri(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Point `config` at the mock: its base URI and root certificate.
+    pub fn configure(&self, config: &mut SpConfig) {
+        config.ias_base_uri = Some(self.base_uri());
+        config.ias_root_cert_pem_base64 = Some(base64::encode(MOCK_IAS_ROOT_CA_PEM));
+    }
+
+    pub fn verdict(&self) -> MockVerdict {
+        self.state.verdict.lock().unwrap().clone()
+    }
+
+    /// Answer subsequent requests with `verdict`.
+    pub fn set_verdict(&self, verdict: MockVerdict) {
+        *self.state.verdict.lock().unwrap() = verdict;
+    }
+
+    /// Shorthand for a verdict with the quote status `status` and the given
+    /// advisory IDs.
+    pub fn set_quote_status(&self, status: &str, advisory_ids: &[&str]) {
+        let mut verdict = self.state.verdict.lock().unwrap();
+        verdict.quote_status = status.to_owned();
+        verdict.advisory_ids = advisory_ids.iter().map(|id| id.to_string()).collect();
+    }
+
+    /// Number of attestation reports issued so far.
+    pub fn reports_issued(&self) -> u64 {
+        self.state.reports.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockIas {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _r = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _r = thread.join();
+        }
+    }
+}
+
+/// The client and the enclave of an attestation, in software. Answers MSG2
+/// the way ra-enclave does and presents a quote of an enclave with the
+/// measurements of `sigstruct`, which the SP checks as usual. Fields can be
+/// changed to test what the SP rejects, e.g. a wrong `mr_enclave`.
+pub struct SimulatedEnclave {
+    /// Verification key of the SP, as pinned by a real enclave.
+    pub sp_vkey_pem: String,
+    pub tenant: Option<String>,
+    pub gid: [u8; 4],
+    pub mr_enclave: [u8; 32],
+    pub mr_signer: [u8; 32],
+    pub isv_prod_id: u16,
+    pub isv_svn: u16,
+    pub cpu_svn: [u8; 16],
+    pub attributes: [u8; 16],
+    /// Data bound to the quote, as with `EnclaveRaContext::bind_data`.
+    pub bound_data: Option<Vec<u8>>,
+}
+
+impl SimulatedEnclave {
+    /// An enclave signed with `sigstruct` that trusts the SP key
+    /// `sp_vkey_pem`. Not a debug enclave.
+    pub fn from_sigstruct(sigstruct: &Sigstruct, sp_vkey_pem: &str) -> Self {
+        let flags = (sigstruct.attributes.flags | AttributeFlags::INIT) - AttributeFlags::DEBUG;
+        let mut attributes = [0u8; 16];
+        attributes[..8].copy_from_slice(&flags.bits().to_le_bytes());
+        attributes[8..].copy_from_slice(&sigstruct.attributes.xfrm.to_le_bytes());
+        Self {
+            sp_vkey_pem: sp_vkey_pem.to_owned(),
+            tenant: None,
+            gid: [0u8; 4],
+            mr_enclave: sigstruct.enclavehash,
+            mr_signer: sha256(&sigstruct.modulus[..]),
+            isv_prod_id: sigstruct.isvprodid,
+            isv_svn: sigstruct.isvsvn,
+            cpu_svn: [0u8; 16],
+            attributes,
+            bound_data: None,
+        }
+    }
+
+    /// The enclave `identity` expects, trusting its current signing key.
+    pub fn for_identity(identity: &SpIdentity) -> Self {
+        // An identity always has a signing key
+        let sp_vkey_pem = identity.sp_public_keys_pem().remove(0);
+        Self::from_sigstruct(&identity.sigstruct, &sp_vkey_pem)
+    }
+
+    pub fn set_debug(&mut self, debug: bool) {
+        let mut flags = AttributeFlags::from_bits_truncate(
+            u64::from_le_bytes(self.attributes[..8].try_into().unwrap()));
+        flags.set(AttributeFlags::DEBUG, debug);
+        self.attributes[..8].copy_from_slice(&flags.bits().to_le_bytes());
+    }
+
+    /// A version 2 EPID quote with this enclave's measurements and
+    /// `report_data`, and an empty signature.
+    fn quote(&self, quote_type: u16, report_data: &[u8; 64]) -> Quote {
+        let mut quote = [0u8; size_of::<Quote>()];
+        quote[0..2].copy_from_slice(&2u16.to_le_bytes());
+        quote[2..4].copy_from_slice(&quote_type.to_le_bytes());
+        quote[4..8].copy_from_slice(&self.gid[..]);
+        quote[48..64].copy_from_slice(&self.cpu_svn[..]);
+        quote[96..112].copy_from_slice(&self.attributes[..]);
+        quote[112..144].copy_from_slice(&self.mr_enclave[..]);
+        quote[176..208].copy_from_slice(&self.mr_signer[..]);
+        quote[304..306].copy_from_slice(&self.isv_prod_id.to_le_bytes());
+        quote[306..308].copy_from_slice(&self.isv_svn.to_le_bytes());
+        quote[368..432].copy_from_slice(&report_data[..]);
+        quote
+    }
+
+    /// Heartbeat `seq` of the session of `keys`, for a `HeartbeatMonitor`.
+    pub fn heartbeat(&self, keys: &SessionKeys, seq: u64) -> Heartbeat {
+        let report_data = heartbeat_report_data(&heartbeat_key(keys), seq);
+        // Linkable, the only quote type the SP supports
+        Heartbeat { seq, quote: self.quote(1, &report_data).to_vec() }
+    }
+
+    /// Attest to the SP at the other end of `sp_stream`. Returns the session
+    /// keys the enclave ends up with, which match those of the SP's
+    /// `AttestationResult`.
+    pub fn do_attestation(&self, mut sp_stream: &mut (impl Read+Write))
+        -> SpRaResult<SessionKeys> {
+            let rng = RandomState::new();
+            let key_exchange = OneWayAuthenticatedDHKE::generate_keypair(&rng)?;
+            let g_a = key_exchange.get_public_key().to_owned();
+            let sp_vkey = VerificationKey::new_from_pem(&self.sp_vkey_pem)?;
+
+            RaMsg0 { exgid: 0, tenant: self.tenant.clone() }.write_to(&mut sp_stream)?;
+            RaMsg1 { gid: self.gid, g_a }.write_to(&mut sp_stream)?;
+
+            let msg2 = RaMsg2::read_from(&mut sp_stream)?;
+            if msg2.kdf_id != DEFAULT_KDF_ID {
+                return Err(SpRaError::IntegrityError);
+            }
+            let kdk = key_exchange.verify_and_derive(&msg2.g_b, &msg2.sign_gb_ga, &sp_vkey)?;
+            let (smk, _sk, mk, vk) = derive_secret_keys(&Cmac::new(&kdk));
+            let smk = Cmac::new(&smk);
+            msg2.verify_mac(&smk).map_err(|_| SpRaError::IntegrityError)?;
+
+            // REPORTDATA = SHA-256(g_a || g_b || vk [|| nonce]) || SHA-256(bound data)
+            let mut verification_msg = Vec::new();
+            verification_msg.extend_from_slice(&g_a[..]);
+            verification_msg.extend_from_slice(&msg2.g_b[..]);
+            verification_msg.extend_from_slice(&vk[..]);
+            if let Some(nonce) = msg2.nonce.as_ref() {
+                verification_msg.extend_from_slice(&nonce[..]);
+            }
+            let mut report_data = [0u8; 64];
+            report_data[..32].copy_from_slice(&sha256(&verification_msg[..])[..]);
+            if let Some(data) = self.bound_data.as_ref() {
+                report_data[32..].copy_from_slice(&sha256(&data[..])[..]);
+            }
+
+            let quote = self.quote(msg2.quote_type, &report_data);
+            RaMsg3::new(&smk, g_a, None, quote).write_to(&mut sp_stream)?;
+
+            let msg4 = RaMsg4::read_from(&mut sp_stream)?;
+            if !msg4.is_enclave_trusted || msg4.is_pse_manifest_trusted == Some(false) {
+                return Err(SpRaError::EnclaveNotTrusted);
+            }
+            Ok(SessionKeys::new(&mk))
+        }
+}
+
+/// An SP identity attesting simulated enclaves against a `MockIas`. Hooks,
+/// report verifiers, and the like are set on `identity` as usual, before the
+/// first attestation, e.g.
+///
+/// ```ignore
+/// let mut loopback = Loopback::start(config)?;
+/// loopback.identity_mut().unwrap().set_hooks(hooks);
+/// let (sp, enclave) = loopback.attest(loopback.enclave());
+/// ```
+pub struct Loopback {
+    pub ias: MockIas,
+    pub identity: Arc<SpIdentity>,
+}
+
+impl Loopback {
+    /// Start a `MockIas` and load `config` with IAS pointed at it. The rest of
+    /// the config is used as is; the SIGSTRUCT and the SP's private key must
+    /// load, and `spid` must be 32 hex digits.
+    pub fn start(mut config: SpConfig) -> SpRaResult<Self> {
+        let ias = MockIas::start()?;
+        ias.configure(&mut config);
+        let identity = Arc::new(SpIdentity::init(config)?);
+        Ok(Self { ias, identity })
+    }
+
+    /// The identity, to change its settings before it is shared, i.e. while
+    /// no attestation runs.
+    pub fn identity_mut(&mut self) -> Option<&mut SpIdentity> {
+        Arc::get_mut(&mut self.identity)
+    }
+
+    /// An enclave that the identity's policy accepts as far as its
+    /// measurements go.
+    pub fn enclave(&self) -> SimulatedEnclave {
+        SimulatedEnclave::for_identity(&self.identity)
+    }
+
+    /// Attest `enclave` to a new session of the identity, each on a thread of
+    /// its own, and return both outcomes: the SP's, and the session keys of
+    /// the enclave.
+    pub fn attest(&self, enclave: SimulatedEnclave)
+        -> (SpRaResult<AttestationResult>, SpRaResult<SessionKeys>) {
+            let (mut sp_stream, mut enclave_stream) = MemoryStream::pair();
+            let enclave_thread = thread::spawn(move || {
+                let r = enclave.do_attestation(&mut enclave_stream);
+                // Unblocks the SP if the enclave gave up
+                drop(enclave_stream);
+                r
+            });
+            let sp = self.identity.new_session()
+                .and_then(|context| context.do_attestation(&mut sp_stream));
+            drop(sp_stream);
+            let enclave = enclave_thread.join()
+                .unwrap_or_else(|e| panic::resume_unwind(e));
+            (sp, enclave)
+        }
+}
@@ -0,0 +1,202 @@
+// The SP's side of the attestation as a state machine, for event loops that
+// serve many clients on one thread. IAS requests still run on the identity's
+// runtime; the handshake reports `Pending` meanwhile and wakes the event loop
+// when IAS answers.
+use std::future::Future;
+use std::io::{Read, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use ra_common::msg::{RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4};
+use ra_common::nonblocking::{HandshakeStatus, NonBlockingIo};
+use crate::context::SpRaContext;
+use crate::identity::SpIdentity;
+use crate::{SpRaResult, AttestationResult};
+
+// A step that needs IAS. It owns the context while it runs.
+type Step<T> = Pin<Box<dyn Future<Output = (SpRaContext, SpRaResult<T>)>>>;
+
+enum State {
+    ReadMsg0,
+    ReadMsg1,
+    ProcessMsg1(Step<RaMsg2>),
+    SendMsg2,
+    ReadMsg3,
+    ProcessMsg3(Step<(RaMsg4, Option<String>)>),
+    SendMsg4(RaMsg4, Option<String>),
+    Done,
+}
+
+/// `SpRaContext::do_attestation` on a non-blocking stream, e.g. a
+/// `mio::net::TcpStream`. Call `advance` whenever the stream is ready or the
+/// waker is woken, until it returns `Done` or fails.
+pub struct SpHandshake {
+    identity: Arc<SpIdentity>,
+    context: Option<SpRaContext>,
+    state: State,
+    io: NonBlockingIo,
+}
+
+impl SpHandshake {
+    pub fn new(context: SpRaContext) -> Self {
+        Self {
+            identity: context.identity.clone(),
+            context: Some(context),
+            state: State::ReadMsg0,
+            io: NonBlockingIo::new(),
+        }
+    }
+
+    /// Read and write what `stream` allows without blocking. `waker` is
+    /// woken when a pending IAS request completes, e.g. through a
+    /// `mio::Waker`. On failure, an abort is sent to the client if the
+    /// stream takes it right away. Panics if called after the handshake
+    /// finished.
+    pub fn advance(&mut self, stream: &mut (impl Read+Write), waker: &Waker)
+        -> SpRaResult<HandshakeStatus<AttestationResult>> {
+            let result = match self.step(stream, waker) {
+                Ok(HandshakeStatus::Done(result)) => Ok(result),
+                Ok(status) => return Ok(status),
+                Err(e) => Err(e),
+            };
+            self.state = State::Done;
+            // Steps hand the context back when they finish, failed or not
+            let context = self.context.as_ref().unwrap();
+            if let Some(abort) = result.as_ref().err().and_then(|e| context.abort_for(e)) {
+                if self.io.queue_msg(&abort).is_ok() {
+                    let _r = self.io.flush(stream);
+                }
+            }
+            context.report_outcome(&result);
+            result.map(HandshakeStatus::Done)
+        }
+
+    fn step(&mut self, stream: &mut (impl Read+Write), waker: &Waker)
+        -> SpRaResult<HandshakeStatus<AttestationResult>> {
+            loop {
+                match std::mem::replace(&mut self.state, State::Done) {
+                    State::ReadMsg0 => {
+                        self.context.as_mut().unwrap().start_timing();
+                        let msg0: Option<RaMsg0> = self.io.read_msg(stream)?;
+                        let msg0 = match msg0 {
+                            Some(msg0) => msg0,
+                            None => {
+                                self.state = State::ReadMsg0;
+                                return Ok(HandshakeStatus::NeedsRead);
+                            },
+                        };
+                        if cfg!(feature = "verbose") {
+                            eprintln!("MSG0 received ");
+                        }
+                        self.context.as_mut().unwrap()
+                            .select_tenant(msg0.tenant.as_ref().map(|t| t.as_str()))?;
+                        self.state = State::ReadMsg1;
+                    },
+                    State::ReadMsg1 => {
+                        let msg1: Option<RaMsg1> = self.io.read_msg(stream)?;
+                        let msg1 = match msg1 {
+                            Some(msg1) => msg1,
+                            None => {
+                                self.state = State::ReadMsg1;
+                                return Ok(HandshakeStatus::NeedsRead);
+                            },
+                        };
+                        if cfg!(feature = "verbose") {
+                            eprintln!("MSG1 received");
+                        }
+                        let mut context = self.context.take().unwrap();
+                        context.msg1_received();
+                        self.state = State::ProcessMsg1(Box::pin(async move {
+                            let r = context.process_msg_1(msg1).await;
+                            (context, r)
+                        }));
+                    },
+                    State::ProcessMsg1(mut step) => {
+                        let msg2 = match self.poll(&mut step, waker) {
+                            Some(msg2) => msg2?,
+                            None => {
+                                self.state = State::ProcessMsg1(step);
+                                return Ok(HandshakeStatus::Pending);
+                            },
+                        };
+                        if cfg!(feature = "verbose") {
+                            eprintln!("MSG1 processed");
+                        }
+                        self.io.queue_msg(&msg2)?;
+                        self.state = State::SendMsg2;
+                    },
+                    State::SendMsg2 => {
+                        if !self.io.flush(stream)? {
+                            self.state = State::SendMsg2;
+                            return Ok(HandshakeStatus::NeedsWrite);
+                        }
+                        self.context.as_mut().unwrap().msg2_sent();
+                        if cfg!(feature = "verbose") {
+                            eprintln!("MSG2 sent");
+                        }
+                        self.state = State::ReadMsg3;
+                    },
+                    State::ReadMsg3 => {
+                        let msg3: Option<RaMsg3> = self.io.read_msg(stream)?;
+                        let msg3 = match msg3 {
+                            Some(msg3) => msg3,
+                            None => {
+                                self.state = State::ReadMsg3;
+                                return Ok(HandshakeStatus::NeedsRead);
+                            },
+                        };
+                        if cfg!(feature = "verbose") {
+                            eprintln!("MSG3 received");
+                        }
+                        let mut context = self.context.take().unwrap();
+                        context.msg3_received();
+                        self.state = State::ProcessMsg3(Box::pin(async move {
+                            let r = context.process_msg_3(msg3).await;
+                            (context, r)
+                        }));
+                    },
+                    State::ProcessMsg3(mut step) => {
+                        let (msg4, epid_pseudonym) = match self.poll(&mut step, waker) {
+                            Some(r) => r?,
+                            None => {
+                                self.state = State::ProcessMsg3(step);
+                                return Ok(HandshakeStatus::Pending);
+                            },
+                        };
+                        if cfg!(feature = "verbose") {
+                            eprintln!("MSG4 generated");
+                        }
+                        self.io.queue_msg(&msg4)?;
+                        self.state = State::SendMsg4(msg4, epid_pseudonym);
+                    },
+                    State::SendMsg4(msg4, epid_pseudonym) => {
+                        if !self.io.flush(stream)? {
+                            self.state = State::SendMsg4(msg4, epid_pseudonym);
+                            return Ok(HandshakeStatus::NeedsWrite);
+                        }
+                        if cfg!(feature = "verbose") {
+                            eprintln!("MSG4 sent");
+                        }
+                        let result = self.context.as_mut().unwrap()
+                            .finish(&msg4, epid_pseudonym)?;
+                        return Ok(HandshakeStatus::Done(result));
+                    },
+                    State::Done => panic!("SpHandshake advanced after it finished"),
+                }
+            }
+        }
+
+    // Poll an IAS step on the identity's runtime, which its requests need,
+    // and take the context back once it is done
+    fn poll<T>(&mut self, step: &mut Step<T>, waker: &Waker) -> Option<SpRaResult<T>> {
+        let mut cx = Context::from_waker(waker);
+        let poll = self.identity.runtime.handle().enter(|| step.as_mut().poll(&mut cx));
+        match poll {
+            Poll::Ready((context, r)) => {
+                self.context = Some(context);
+                Some(r)
+            },
+            Poll::Pending => None,
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write, ErrorKind};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use sgx_crypto::secure_channel::SecureChannel;
+use ra_common::tcp::tcp_connect;
+use crate::error::SpRaError;
+use crate::identity::SpIdentity;
+use crate::session::Session;
+use crate::{SpRaResult, AttestationResult};
+
+// How often a health check looks for the pong while it waits
+const PONG_POLL_MILLIS: u64 = 50;
+
+/// Where a `ChannelPool` reaches an enclave. On every new connection, the
+/// enclave must speak the client side of the attestation protocol, then use
+/// the channel key of the attestation for its `SecureChannel`.
+#[derive(Debug, Clone)]
+pub struct EnclaveAddr {
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PoolSettings {
+    pub connect_timeout: Duration,
+    /// How often channels that sat idle that long are pinged. A channel whose
+    /// peer does not answer within `health_check_timeout` is dropped and the
+    /// enclave attested again. None turns health checks off.
+    pub health_check_interval: Option<Duration>,
+    pub health_check_timeout: Duration,
+    /// How long an attestation is trusted for, see `Session`. Channels whose
+    /// session expired are replaced with freshly attested ones. None keeps
+    /// channels until they fail.
+    pub session_validity: Option<Duration>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(5),
+            health_check_interval: Some(Duration::from_secs(30)),
+            health_check_timeout: Duration::from_secs(5),
+            session_validity: None,
+        }
+    }
+}
+
+/// An enclave the SP attested, with the `SecureChannel` keyed with the
+/// attestation's channel key.
+pub struct AttestedChannel {
+    pub channel: SecureChannel<TcpStream>,
+    pub result: AttestationResult,
+    pub session: Session,
+}
+
+impl AttestedChannel {
+    fn is_usable(&self) -> bool {
+        !self.channel.is_closed() && self.session.check().is_ok()
+    }
+}
+
+struct Idle {
+    attested: AttestedChannel,
+    since: Instant,
+}
+
+struct PoolShared {
+    identity: Arc<SpIdentity>,
+    settings: PoolSettings,
+    enclaves: Mutex<HashMap<String, EnclaveAddr>>,
+    idle: Mutex<HashMap<String, Vec<Idle>>>,
+}
+
+/// Established `SecureChannel`s to known enclaves, so that request handlers
+/// can `get` a channel to an enclave by its ID rather than attest it
+/// themselves. A channel is used by one handler at a time and goes back to
+/// the pool when its `PooledChannel` is dropped; when every channel to the
+/// enclave is in use, or none is left, `get` connects and attests the
+/// enclave again. Channels that failed, expired, or were revoked are never
+/// handed out. Clones share the same channels.
+#[derive(Clone)]
+pub struct ChannelPool {
+    shared: Arc<PoolShared>,
+}
+
+impl ChannelPool {
+    /// Health checks, if enabled, run on a thread of their own until the
+    /// last clone of the pool is dropped.
+    pub fn new(identity: Arc<SpIdentity>, settings: PoolSettings) -> Self {
+        let shared = Arc::new(PoolShared {
+            identity,
+            settings,
+            enclaves: Mutex::new(HashMap::new()),
+            idle: Mutex::new(HashMap::new()),
+        });
+        if let Some(interval) = settings.health_check_interval {
+            spawn_health_checks(Arc::downgrade(&shared), interval);
+        }
+        Self { shared }
+    }
+
+    /// Register `enclave_id`, or change its address. Channels to the old
+    /// address stay in use until they fail.
+    pub fn add_enclave(&self, enclave_id: &str, addr: EnclaveAddr) {
+        self.shared.enclaves.lock().unwrap().insert(enclave_id.to_owned(), addr);
+    }
+
+    /// Forget `enclave_id` and close its idle channels. Channels in use are
+    /// closed when dropped.
+    pub fn remove_enclave(&self, enclave_id: &str) {
+        self.shared.enclaves.lock().unwrap().remove(enclave_id);
+        self.shared.idle.lock().unwrap().remove(enclave_id);
+    }
+
+    /// Attest `enclave_id` ahead of its first `get`, e.g. right after
+    /// registering it.
+    pub fn warm_up(&self, enclave_id: &str) -> SpRaResult<()> {
+        let attested = self.shared.connect(enclave_id)?;
+        self.shared.check_in(enclave_id, attested);
+        Ok(())
+    }
+
+    /// A channel to `enclave_id`, attesting the enclave first if no idle
+    /// channel to it is left. Fails with `UnknownEnclave` if it was not
+    /// added.
+    pub fn get(&self, enclave_id: &str) -> SpRaResult<PooledChannel> {
+        let attested = loop {
+            let next = self.shared.idle.lock().unwrap()
+                .get_mut(enclave_id)
+                .and_then(|channels| channels.pop());
+            match next {
+                Some(idle) if idle.attested.is_usable() => break idle.attested,
+                Some(_) => {},
+                None => break self.shared.connect(enclave_id)?,
+            }
+        };
+        Ok(PooledChannel {
+            enclave_id: enclave_id.to_owned(),
+            attested: Some(attested),
+            failed: false,
+            pool: Arc::downgrade(&self.shared),
+        })
+    }
+
+    /// Number of idle channels to `enclave_id`.
+    pub fn idle_count(&self, enclave_id: &str) -> usize {
+        self.shared.idle.lock().unwrap().get(enclave_id).map_or(0, |c| c.len())
+    }
+}
+
+impl PoolShared {
+    fn connect(&self, enclave_id: &str) -> SpRaResult<AttestedChannel> {
+        let addr = self.enclaves.lock().unwrap().get(enclave_id).cloned()
+            .ok_or_else(|| SpRaError::UnknownEnclave(enclave_id.to_owned()))?;
+        let mut stream = tcp_connect(&addr.host, addr.port, self.settings.connect_timeout)?;
+        let result = self.identity.new_session()?.do_attestation(&mut stream)?;
+        let channel = SecureChannel::new(stream, &result.keys.channel_key());
+        let id = result.session_id;
+        if cfg!(feature = "verbose") {
+            eprintln!("Enclave {} attested for the pool", enclave_id);
+        }
+        Ok(AttestedChannel {
+            channel,
+            result,
+            session: Session::new(id, self.settings.session_validity),
+        })
+    }
+
+    fn check_in(&self, enclave_id: &str, attested: AttestedChannel) {
+        if !self.enclaves.lock().unwrap().contains_key(enclave_id) {
+            return;
+        }
+        self.idle.lock().unwrap()
+            .entry(enclave_id.to_owned())
+            .or_insert_with(Vec::new)
+            .push(Idle { attested, since: Instant::now() });
+    }
+
+    // Ping the channels that sat idle for `interval`, outside the lock so
+    // that `get` is not held up, and replace the dead ones
+    fn check_idle(&self, interval: Duration) {
+        let due: Vec<(String, Vec<Idle>)> = {
+            let mut idle = self.idle.lock().unwrap();
+            idle.iter_mut()
+                .map(|(enclave_id, channels)| {
+                    let (due, recent) = channels.drain(..)
+                        .partition(|c| c.since.elapsed() >= interval);
+                    *channels = recent;
+                    (enclave_id.clone(), due)
+                })
+                .filter(|(_, due)| !due.is_empty())
+                .collect()
+        };
+        for (enclave_id, channels) in due {
+            let mut dead = 0;
+            for mut idle in channels {
+                if idle.attested.is_usable() && self.ping(&mut idle.attested.channel) {
+                    self.check_in(&enclave_id, idle.attested);
+                } else {
+                    dead += 1;
+                }
+            }
+            if dead == 0 {
+                continue;
+            }
+            if cfg!(feature = "verbose") {
+                eprintln!("{} channels to enclave {} failed the health check",
+                          dead, enclave_id);
+            }
+            // Attest again now, so that the next `get` does not wait for it
+            match self.connect(&enclave_id) {
+                Ok(attested) => self.check_in(&enclave_id, attested),
+                Err(e) => if cfg!(feature = "verbose") {
+                    eprintln!("Re-attestation of enclave {} failed: {:?}", enclave_id, e);
+                },
+            }
+        }
+    }
+
+    // Whether the peer of an idle channel answers a ping in time. Any data
+    // instead means the channel is out of step with the peer.
+    fn ping(&self, channel: &mut SecureChannel<TcpStream>) -> bool {
+        let sent = Instant::now();
+        if channel.ping().is_err() {
+            return false;
+        }
+        channel.set_read_timeout(Some(Duration::from_millis(PONG_POLL_MILLIS)));
+        let alive = loop {
+            match channel.fill_buf() {
+                Err(ref e) if e.kind() == ErrorKind::TimedOut ||
+                    e.kind() == ErrorKind::WouldBlock => {
+                        if channel.last_pong().map_or(false, |t| t >= sent) {
+                            break true;
+                        }
+                        if sent.elapsed() >= self.settings.health_check_timeout {
+                            break false;
+                        }
+                    },
+                _ => break false,
+            }
+        };
+        channel.set_read_timeout(None);
+        alive
+    }
+}
+
+fn spawn_health_checks(pool: Weak<PoolShared>, interval: Duration) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            match pool.upgrade() {
+                Some(pool) => pool.check_idle(interval),
+                None => break,
+            }
+        }
+    });
+}
+
+/// A channel of a `ChannelPool`, see `ChannelPool::get`. Goes back to the
+/// pool when dropped, unless a read or write on it failed, e.g. timed out,
+/// since the peer may then be out of step with the next user.
+pub struct PooledChannel {
+    enclave_id: String,
+    attested: Option<AttestedChannel>,
+    failed: bool,
+    pool: Weak<PoolShared>,
+}
+
+impl PooledChannel {
+    pub fn enclave_id(&self) -> &str {
+        &self.enclave_id
+    }
+
+    /// The attestation that keyed the channel.
+    pub fn result(&self) -> &AttestationResult {
+        &self.attested().result
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.attested().session
+    }
+
+    /// See `SecureChannel::set_read_timeout`. Reset when the channel goes
+    /// back to the pool.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.channel().set_read_timeout(timeout);
+    }
+
+    /// See `SecureChannel::set_write_timeout`. Reset when the channel goes
+    /// back to the pool.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.channel().set_write_timeout(timeout);
+    }
+
+    /// Close the channel instead of returning it to the pool, e.g. after the
+    /// enclave's response could not be parsed.
+    pub fn discard(mut self) {
+        self.failed = true;
+    }
+
+    // Can unwrap since `attested` is only taken on drop
+    fn attested(&self) -> &AttestedChannel {
+        self.attested.as_ref().unwrap()
+    }
+
+    fn channel(&mut self) -> &mut SecureChannel<TcpStream> {
+        &mut self.attested.as_mut().unwrap().channel
+    }
+
+    fn track<R>(&mut self, r: io::Result<R>) -> io::Result<R> {
+        if r.is_err() {
+            self.failed = true;
+        }
+        r
+    }
+}
+
+impl Read for PooledChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let r = self.channel().read(buf);
+        self.track(r)
+    }
+}
+
+impl BufRead for PooledChannel {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let channel = &mut self.attested.as_mut().unwrap().channel;
+        match channel.fill_buf() {
+            Ok(buf) => Ok(buf),
+            Err(e) => {
+                self.failed = true;
+                Err(e)
+            },
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.channel().consume(amt)
+    }
+}
+
+impl Write for PooledChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let r = self.channel().write(buf);
+        self.track(r)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let r = self.channel().flush();
+        self.track(r)
+    }
+}
+
+impl Drop for PooledChannel {
+    fn drop(&mut self) {
+        let pool = match self.pool.upgrade() {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut attested = match self.attested.take() {
+            Some(attested) if !self.failed && attested.is_usable() => attested,
+            _ => return,
+        };
+        if attested.channel.flush().is_err() {
+            return;
+        }
+        attested.channel.set_read_timeout(None);
+        attested.channel.set_write_timeout(None);
+        pool.check_in(&self.enclave_id, attested);
+    }
+}
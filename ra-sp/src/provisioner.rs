@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use sgx_crypto::secure_channel::{SecureChannel, SecureTransport};
+use ra_common::msg::WireMessage;
+use ra_common::secrets::{Secret, SecretBundle, SecretAck};
+use crate::error::SpRaError;
+use crate::hooks::AttestationHooks;
+use crate::{SpRaResult, AttestationResult};
+
+type SecretPredicate = Box<dyn Fn(&AttestationResult) -> bool + Send + Sync>;
+
+struct Registered {
+    secret: Secret,
+    // Which attested enclaves get the secret; all of them if None
+    predicate: Option<SecretPredicate>,
+}
+
+/// Secrets the SP hands to enclaves once they are attested, e.g. a database
+/// key or an API token. `provision` sends an enclave the secrets it is
+/// entitled to over its `SecureChannel` and waits for the enclave's
+/// acknowledgement, which it receives with
+/// `ra_enclave::secrets::receive_secrets`. Every delivery is reported to the
+/// hooks' `on_secret_delivered`, e.g. to an `AuditLog`.
+#[derive(Default)]
+pub struct SecretProvisioner {
+    secrets: Vec<Registered>,
+    hooks: Option<Arc<dyn AttestationHooks>>,
+}
+
+impl SecretProvisioner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver `value` to every attested enclave.
+    pub fn register(&mut self, name: &str, value: Vec<u8>) {
+        self.secrets.push(Registered {
+            secret: Secret { name: name.to_owned(), value },
+            predicate: None,
+        });
+    }
+
+    /// Deliver `value` only to enclaves for which `predicate` holds, e.g. one
+    /// MRENCLAVE.
+    pub fn register_for<F>(&mut self, name: &str, value: Vec<u8>, predicate: F)
+        where F: Fn(&AttestationResult) -> bool + Send + Sync + 'static {
+            self.secrets.push(Registered {
+                secret: Secret { name: name.to_owned(), value },
+                predicate: Some(Box::new(predicate)),
+            });
+        }
+
+    pub fn set_hooks(&mut self, hooks: Arc<dyn AttestationHooks>) {
+        self.hooks = Some(hooks);
+    }
+
+    /// Send the enclave of `result` its secrets over `channel`, which must be
+    /// keyed with the result's channel key, and return the names of the
+    /// secrets it acknowledged. Fails with `SecretsNotAccepted` if the
+    /// enclave did not accept all of them; the accepted ones are still
+    /// reported as delivered.
+    pub fn provision<T: SecureTransport>(&self, result: &AttestationResult,
+                                         channel: &mut SecureChannel<T>)
+        -> SpRaResult<Vec<String>> {
+            let bundle = SecretBundle {
+                secrets: self.secrets.iter()
+                    .filter(|r| r.predicate.as_ref().map_or(true, |p| p(result)))
+                    .map(|r| Secret {
+                        name: r.secret.name.clone(),
+                        value: r.secret.value.clone(),
+                    })
+                    .collect(),
+            };
+            bundle.write_to(&mut *channel)?;
+            let ack = SecretAck::read_from(&mut *channel)?;
+
+            let mut not_accepted = Vec::new();
+            for secret in bundle.secrets.iter() {
+                if !ack.accepted.contains(&secret.name) {
+                    not_accepted.push(secret.name.clone());
+                    continue;
+                }
+                if let Some(hooks) = self.hooks.as_ref() {
+                    hooks.on_secret_delivered(&secret.name, result);
+                }
+            }
+            if cfg!(feature = "verbose") {
+                eprintln!("{} of {} secrets delivered",
+                          bundle.secrets.len() - not_accepted.len(), bundle.secrets.len());
+            }
+            if !not_accepted.is_empty() {
+                return Err(SpRaError::SecretsNotAccepted(not_accepted));
+            }
+            Ok(ack.accepted)
+        }
+}
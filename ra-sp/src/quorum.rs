@@ -0,0 +1,96 @@
+use std::sync::Arc;
+use ra_common::quote::QuoteBody;
+use crate::verifier::Verdict;
+use crate::SpRaResult;
+
+/// An independent verification backend, e.g. Intel's QVL on the SP's host or
+/// a cloud attestation service, that appraises the quote itself rather than
+/// IAS's report of it. `Err` means the backend could not reach a verdict,
+/// e.g. because it is unreachable; it then counts as neither accepting nor
+/// rejecting the quote.
+pub trait QuoteVerifier: Send + Sync {
+    /// Name of the backend in logs and rejection reasons.
+    fn name(&self) -> &str;
+
+    /// Called on a blocking thread, so it may block, e.g. on the network.
+    fn verify(&self, quote: &[u8], quote_body: &QuoteBody) -> SpRaResult<Verdict>;
+}
+
+/// N-of-M agreement of quote verifiers, for deployments that do not want a
+/// single verification root of trust. IAS stays mandatory: the quorum is
+/// only consulted for enclaves the built-in policy already trusts, and on
+/// top of it at least `threshold` of the verifiers must accept the quote.
+pub struct VerifierQuorum {
+    verifiers: Vec<Arc<dyn QuoteVerifier>>,
+    threshold: usize,
+}
+
+impl VerifierQuorum {
+    pub fn new(threshold: usize) -> Self {
+        assert!(threshold > 0, "A quorum needs at least one vote");
+        Self {
+            verifiers: Vec::new(),
+            threshold,
+        }
+    }
+
+    pub fn add(&mut self, verifier: Arc<dyn QuoteVerifier>) {
+        self.verifiers.push(verifier);
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn len(&self) -> usize {
+        self.verifiers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.verifiers.is_empty()
+    }
+
+    /// Ask the verifiers in turn until `threshold` of them accepted the
+    /// quote, or so many rejected it or failed that the rest cannot make up
+    /// the quorum.
+    pub fn decide(&self, quote: &[u8], quote_body: &QuoteBody) -> Verdict {
+        let mut accepted = 0;
+        let mut dissents = Vec::new();
+        for (i, verifier) in self.verifiers.iter().enumerate() {
+            if accepted >= self.threshold ||
+                accepted + self.verifiers.len() - i < self.threshold {
+                    break;
+                }
+            match verifier.verify(quote, quote_body) {
+                Ok(Verdict::Accept) => accepted += 1,
+                Ok(Verdict::Reject(reason)) => {
+                    dissents.push(format!("{} rejected: {}", verifier.name(), reason));
+                },
+                Err(e) => {
+                    dissents.push(format!("{} failed: {:?}", verifier.name(), e));
+                },
+            }
+            if cfg!(feature = "verbose") {
+                eprintln!("Quorum: {} of {} accepted after {}",
+                          accepted, self.threshold, verifier.name());
+            }
+        }
+        if accepted >= self.threshold {
+            return Verdict::Accept;
+        }
+        Verdict::Reject(format!("quorum of {} of {} not reached ({} accepted): {}",
+                                self.threshold, self.verifiers.len(), accepted,
+                                dissents.join("; ")))
+    }
+}
+
+impl<F> QuoteVerifier for (String, F)
+    where F: Fn(&[u8], &QuoteBody) -> SpRaResult<Verdict> + Send + Sync {
+        fn name(&self) -> &str {
+            &self.0
+        }
+
+        fn verify(&self, quote: &[u8], quote_body: &QuoteBody) -> SpRaResult<Verdict> {
+            (self.1)(quote, quote_body)
+        }
+    }
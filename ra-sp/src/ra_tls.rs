@@ -0,0 +1,119 @@
+// Verification of RA-TLS certificates as issued by ra-enclave's `ra_tls` and
+// by Gramine's ra_tls_attest library (EPID flavor). Both put the raw quote in
+// an extension with OID 1.2.840.113741.1337.6 and bind the certificate's key
+// by setting the first half of REPORTDATA to SHA-256 of the DER-encoded
+// SubjectPublicKeyInfo. Gramine encodes that OID with a redundant inner DER
+// header (06 09), which is accepted as well. The TCG DICE tagged-evidence
+// extension that newer Gramine versions add next to it is ignored.
+use std::mem::size_of;
+use sgx_crypto::digest::sha256;
+use ra_common::msg::Quote;
+use ra_common::quote::QuoteBody;
+use ra_verify::asn1::{self, TAG_INTEGER, TAG_OCTET_STRING, TAG_SEQUENCE};
+use crate::attestation_response::AttestationResponse;
+
+/// DER encoding of 1.2.840.113741.1337.6 without tag and length.
+const QUOTE_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf8, 0x4d, 0x8a, 0x39, 0x06];
+const GRAMINE_QUOTE_OID_PREFIX: &[u8] = &[0x06, 0x09];
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_OID: u8 = 0x06;
+const TAG_EXPLICIT_0: u8 = 0xa0;
+const TAG_EXPLICIT_3: u8 = 0xa3;
+
+#[derive(Debug)]
+pub enum RaTlsError {
+    Malformed,
+    MissingQuote,
+    InvalidQuoteLength(usize),
+    /// REPORTDATA does not commit to the certificate's public key.
+    PublicKeyMismatch,
+}
+
+/// What an RA-TLS certificate claims. Only the binding between quote and key
+/// has been checked; the quote itself still has to be verified with IAS, see
+/// `SpRaContext::verify_ra_tls_certificate`.
+pub struct RaTlsEvidence {
+    pub quote: Quote,
+    pub quote_body: QuoteBody,
+    /// DER-encoded SubjectPublicKeyInfo of the certificate.
+    pub public_key_info: Vec<u8>,
+}
+
+/// A verified RA-TLS certificate.
+pub struct RaTlsAttestation {
+    pub evidence: RaTlsEvidence,
+    pub report: AttestationResponse,
+}
+
+impl RaTlsEvidence {
+    pub fn from_certificate(cert_der: &[u8]) -> Result<Self, RaTlsError> {
+        let mut cert = expect(&mut &cert_der[..], TAG_SEQUENCE)?;
+        let mut tbs = expect(&mut cert, TAG_SEQUENCE)?;
+
+        if tbs.first() == Some(&TAG_EXPLICIT_0) {
+            next(&mut tbs)?; // version
+        }
+        expect(&mut tbs, TAG_INTEGER)?; // serialNumber
+        for _ in 0..4 {
+            expect(&mut tbs, TAG_SEQUENCE)?; // signature, issuer, validity, subject
+        }
+        let (tag, _, public_key_info) = next(&mut tbs)?;
+        if tag != TAG_SEQUENCE {
+            return Err(RaTlsError::Malformed);
+        }
+
+        let mut quote = None;
+        while !tbs.is_empty() {
+            let (tag, content, _) = next(&mut tbs)?;
+            if tag != TAG_EXPLICIT_3 {
+                continue; // issuerUniqueID, subjectUniqueID
+            }
+            let mut extensions = expect(&mut &content[..], TAG_SEQUENCE)?;
+            while !extensions.is_empty() {
+                let mut extension = expect(&mut extensions, TAG_SEQUENCE)?;
+                let oid = expect(&mut extension, TAG_OID)?;
+                if extension.first() == Some(&TAG_BOOLEAN) {
+                    next(&mut extension)?; // critical
+                }
+                let value = expect(&mut extension, TAG_OCTET_STRING)?;
+                if is_quote_oid(oid) {
+                    quote = Some(value);
+                }
+            }
+        }
+
+        let value = quote.ok_or(RaTlsError::MissingQuote)?;
+        let mut quote = [0u8; size_of::<Quote>()];
+        if value.len() != quote.len() {
+            return Err(RaTlsError::InvalidQuoteLength(value.len()));
+        }
+        quote.copy_from_slice(value);
+        // Can unwrap since a Quote is always longer than its body
+        let quote_body = QuoteBody::parse(&quote[..]).unwrap();
+        if sha256(public_key_info)[..] != quote_body.report_data[..32] {
+            return Err(RaTlsError::PublicKeyMismatch);
+        }
+
+        Ok(Self {
+            quote,
+            quote_body,
+            public_key_info: public_key_info.to_owned(),
+        })
+    }
+}
+
+fn is_quote_oid(oid: &[u8]) -> bool {
+    oid == QUOTE_OID ||
+        (oid.starts_with(GRAMINE_QUOTE_OID_PREFIX) &&
+         &oid[GRAMINE_QUOTE_OID_PREFIX.len()..] == QUOTE_OID)
+}
+
+// ra_verify's DER reader, failing with `Malformed`
+fn expect<'a>(data: &mut &'a [u8], tag: u8) -> Result<&'a [u8], RaTlsError> {
+    asn1::expect(data, tag).ok_or(RaTlsError::Malformed)
+}
+
+fn next<'a>(data: &mut &'a [u8]) -> Result<(u8, &'a [u8], &'a [u8]), RaTlsError> {
+    asn1::next(data).ok_or(RaTlsError::Malformed)
+}
@@ -0,0 +1,58 @@
+use std::time::{Duration, Instant};
+use sgx_crypto::secure_channel::{SecureChannel, SecureTransport};
+use crate::context::SpRaContext;
+use crate::{SpRaResult, AttestationResult};
+
+/// Runs the attestation again over a live `SecureChannel` every `interval`,
+/// so that a long session reflects the enclave's current TCB status rather
+/// than its status when the session started. On success, the channel
+/// switches to the new channel key.
+///
+/// The enclave must answer with `ra_enclave::reattest::respond` when the
+/// application asks it to, e.g. right after a request of its own protocol,
+/// and its client with `ClientRaContext::do_reattestation`. No application
+/// data may be in flight meanwhile.
+pub struct Reattestation {
+    interval: Duration,
+    last_attested: Instant,
+}
+
+impl Reattestation {
+    /// Counts from now, i.e. from the attestation that keyed the channel.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_attested: Instant::now(),
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last_attested.elapsed() >= self.interval
+    }
+
+    /// Attest with `context` over `channel`, then rekey the channel. If the
+    /// attestation fails, the channel keeps its keys; the session should
+    /// then be closed, since the enclave was not confirmed to be trusted.
+    pub fn run<T: SecureTransport>(&mut self, context: SpRaContext,
+                                   channel: &mut SecureChannel<T>)
+        -> SpRaResult<AttestationResult> {
+            let result = context.do_attestation(channel)?;
+            channel.rekey(&result.keys.channel_key())?;
+            self.last_attested = Instant::now();
+            if cfg!(feature = "verbose") {
+                eprintln!("Re-attestation succeeded, channel rekeyed");
+            }
+            Ok(result)
+        }
+
+    /// `run` if the interval has elapsed, creating the context with
+    /// `new_context` only then.
+    pub fn run_if_due<T, F>(&mut self, new_context: F, channel: &mut SecureChannel<T>)
+        -> SpRaResult<Option<AttestationResult>>
+        where T: SecureTransport, F: FnOnce() -> SpRaResult<SpRaContext> {
+            if !self.is_due() {
+                return Ok(None);
+            }
+            self.run(new_context()?, channel).map(Some)
+        }
+}
@@ -0,0 +1,504 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use ra_common::listener::{GracefulListener, ShutdownHandle, Connection};
+use ra_common::msg::{RaAbort, AbortReason, WireMessage};
+use crate::identity::SpIdentity;
+use crate::config::SpConfig;
+use crate::error::SpRaError;
+use crate::session::Session;
+use crate::sig_rl_cache::SigRlCache;
+use crate::health;
+use crate::context::{self, SessionId};
+use crate::{SpRaResult, AttestationResult};
+
+const WATCHDOG_PERIOD_MILLIS: u64 = 100;
+// How long the SigRL cache of an `attest_many` batch keeps SigRLs, if the
+// identity has no cache of its own
+const BATCH_SIG_RL_TTL_SECS: u64 = 300;
+
+// Keeps the abort sent at shutdown from landing inside a message of the SP:
+// the handshake writes each message whole while holding it, and the abort is
+// only sent when it is free, after which it is set and no message follows
+type FrameLock = Arc<Mutex<bool>>;
+type Handshakes = Arc<Mutex<HashMap<SessionId, (Instant, TcpStream, FrameLock)>>>;
+type Sessions = Arc<Mutex<HashMap<SessionId, (Session, TcpStream)>>>;
+type CurrentIdentity = Arc<RwLock<Arc<SpIdentity>>>;
+
+/// Bounds on what one connection can take from an `SpServer`, so that
+/// half-open or looping clients cannot pin threads.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionLimits {
+    /// Handshakes a client may make on one connection. After a failed one,
+    /// the client can start over with a new MSG0 until this many were made.
+    pub max_attempts: u32,
+    /// Time from accepting a connection to the end of its handshakes, after
+    /// which the connection is dropped.
+    pub handshake_deadline: Duration,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            handshake_deadline: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Serves attestations to many clients, one thread per connection, until
+/// shutdown is requested through a `ShutdownHandle` or, after
+/// `shutdown_on_signals`, by SIGTERM or SIGINT. Its config can be replaced
+/// while it runs with `reload` or, after `reload_on_hangup`, by SIGHUP.
+pub struct SpServer {
+    // The identity new connections are attested with, replaced by `reload`
+    identity: CurrentIdentity,
+    // The identity the server was bound with, whose runtime runs the signal
+    // handlers and health probes, so it is kept for the server's whole life
+    bound_identity: Arc<SpIdentity>,
+    listener: GracefulListener,
+    limits: ConnectionLimits,
+    session_validity: Option<Duration>,
+    // Clones of the streams of connections that are still in the handshake,
+    // with when they were accepted and the locks of their messages
+    handshakes: Handshakes,
+    // Clones of the streams of attested connections that `on_attested` is
+    // still serving, with their sessions
+    sessions: Sessions,
+}
+
+/// A connection of an `SpServer::attest_many` batch, with the outcome of its
+/// attestation.
+pub struct BatchAttestation {
+    pub connection: Connection,
+    pub result: SpRaResult<(AttestationResult, Session)>,
+}
+
+impl SpServer {
+    pub fn bind(identity: Arc<SpIdentity>, bind_addr: &str, port: u16) -> SpRaResult<Self> {
+        Ok(Self {
+            identity: Arc::new(RwLock::new(identity.clone())),
+            bound_identity: identity,
+            listener: GracefulListener::bind(bind_addr, port)?,
+            limits: ConnectionLimits::default(),
+            session_validity: None,
+            handshakes: Arc::new(Mutex::new(HashMap::new())),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn set_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = limits;
+    }
+
+    /// How long an attestation is trusted for. Sessions never expire unless
+    /// this is set. `run` disconnects clients whose sessions expired.
+    pub fn set_session_validity(&mut self, validity: Duration) {
+        self.session_validity = Some(validity);
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.listener.shutdown_handle()
+    }
+
+    /// The identity new connections are attested with.
+    pub fn identity(&self) -> Arc<SpIdentity> {
+        self.identity.read().unwrap().clone()
+    }
+
+    /// Attest new connections with an identity built from `config` by
+    /// `SpIdentity::with_config`, which keeps the current identity's hooks,
+    /// verifiers, quorum, and signing keys. The config is validated and the
+    /// identity built before it is swapped in, so the current one stays in
+    /// use if either fails. Connections accepted before, including attested
+    /// ones and their secure channels, keep the identity they were accepted
+    /// with. Blocks while the identity is built.
+    pub fn reload(&self, config: SpConfig) -> SpRaResult<()> {
+        reload(&self.identity, config)
+    }
+
+    /// Attest new connections with `identity`, e.g. one with other verifiers
+    /// or another quorum. Connections accepted before keep theirs.
+    pub fn set_identity(&self, identity: Arc<SpIdentity>) {
+        *self.identity.write().unwrap() = identity;
+    }
+
+    /// Serve liveness (`/healthz`) and readiness (`/readyz`) probes over HTTP
+    /// on `bind_addr:port`. The SP is ready while it is not shutting down, the
+    /// last IAS check, made every `ias_probe_interval`, succeeded, and no
+    /// SigRL in its cache is stale. `/readyz` answers 200 or 503 with the
+    /// outcome of each check as JSON. The probes keep checking IAS with the
+    /// subscription keys of the identity the server was bound with.
+    pub fn serve_health(&self, bind_addr: &str, port: u16, ias_probe_interval: Duration)
+        -> SpRaResult<()> {
+            health::serve(&self.bound_identity, self.shutdown_handle(),
+                          bind_addr, port, ias_probe_interval)
+        }
+
+    /// Request shutdown on SIGTERM or SIGINT instead of being killed.
+    #[cfg(unix)]
+    pub fn shutdown_on_signals(&self) -> SpRaResult<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+        let handle = self.shutdown_handle();
+        let (mut term, mut int) = self.bound_identity.runtime.handle().enter(|| {
+            Ok::<_, std::io::Error>((signal(SignalKind::terminate())?,
+                                     signal(SignalKind::interrupt())?))
+        })?;
+        self.bound_identity.runtime.spawn(async move {
+            futures::future::select(Box::pin(term.recv()), Box::pin(int.recv())).await;
+            if cfg!(feature = "verbose") {
+                eprintln!("Shutdown requested");
+            }
+            handle.shutdown();
+        });
+        Ok(())
+    }
+
+    /// `reload` the config from `path`, e.g. the server's settings file, on
+    /// SIGHUP. A config that fails to load or validate is not put in use;
+    /// the outcome of each reload is passed to `AttestationHooks::on_reload`
+    /// of the identity in use afterwards.
+    #[cfg(unix)]
+    pub fn reload_on_hangup(&self, path: std::path::PathBuf) -> SpRaResult<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = self.bound_identity.runtime.handle()
+            .enter(|| signal(SignalKind::hangup()))?;
+        let (sender, receiver) = mpsc::channel();
+        self.bound_identity.runtime.spawn(async move {
+            while hangup.recv().await.is_some() {
+                if sender.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+        // Identities are built and dropped off the runtime, since each one
+        // starts and owns a runtime of its own
+        let identity = self.identity.clone();
+        thread::spawn(move || {
+            for () in receiver {
+                let result = SpConfig::from_file(&path)
+                    .and_then(|config| reload(&identity, config));
+                if cfg!(feature = "verbose") {
+                    match result.as_ref() {
+                        Ok(()) => eprintln!("Reloaded {}", path.display()),
+                        Err(e) => eprintln!("Reload of {} failed: {:?}", path.display(), e),
+                    }
+                }
+                let current = identity.read().unwrap().clone();
+                if let Some(hooks) = current.hooks.as_ref() {
+                    hooks.on_reload(&result);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Accept connections and attest each client on its own thread, passing
+    /// successful results, their connection, and their `Session` to
+    /// `on_attested`. Connections are held to the server's
+    /// `ConnectionLimits`, and attested ones are disconnected once their
+    /// session fails `Session::check`, i.e. expired without being renewed
+    /// or was revoked, so no request is served on them. Once shutdown is
+    /// requested, stops accepting and waits up to `deadline` for open
+    /// connections to finish. Clients still in the handshake then are sent an
+    /// unauthenticated `ShuttingDown` abort between two messages of the SP
+    /// and disconnected, so they can retry elsewhere; one the SP is in the
+    /// middle of sending a message to is disconnected without it. Returns
+    /// the number of connections that were still open at the deadline.
+    pub fn run<F>(self, on_attested: F, deadline: Duration) -> SpRaResult<usize>
+        where F: Fn(AttestationResult, Connection, Session) + Send + Sync + 'static {
+            let on_attested = Arc::new(on_attested);
+            self.spawn_watchdog(Arc::new(AtomicBool::new(false)));
+            while let Some(mut connection) = self.listener.accept()? {
+                let (id, frame_lock) = self.start_handshake(&connection);
+                let identity = self.identity();
+                let max_attempts = self.limits.max_attempts;
+                let session_validity = self.session_validity;
+                let handshakes = self.handshakes.clone();
+                let sessions = self.sessions.clone();
+                let on_attested = on_attested.clone();
+                thread::spawn(move || {
+                    let result = attest_connection(&identity, None, &mut connection,
+                                                   id, &frame_lock, max_attempts,
+                                                   &handshakes);
+                    match result {
+                        Ok(result) => {
+                            let session = Session::new(id, session_validity);
+                            if let Ok(stream) = connection.stream().try_clone() {
+                                sessions.lock().unwrap().insert(id, (session.clone(), stream));
+                            }
+                            on_attested(result, connection, session);
+                            sessions.lock().unwrap().remove(&id);
+                        },
+                        Err(e) => if cfg!(feature = "verbose") {
+                            eprintln!("Attestation with {} failed: {:?}",
+                                      connection.peer_addr(), e);
+                        },
+                    }
+                });
+            }
+
+            let open = self.listener.close(deadline);
+            let handshakes: Vec<_> = self.handshakes.lock().unwrap().drain().collect();
+            for (_, (_, mut stream, frame_lock)) in handshakes {
+                // A client the SP is sending a message to is only disconnected
+                if let Ok(mut aborted) = frame_lock.try_lock() {
+                    let _r = RaAbort::new(AbortReason::ShuttingDown, None).write_to(&mut stream);
+                    *aborted = true;
+                }
+                let _r = stream.shutdown(Shutdown::Both);
+            }
+            if let Some(hooks) = self.identity().hooks.as_ref() {
+                hooks.on_shutdown();
+            }
+            Ok(open)
+        }
+
+    /// Accept the connections already waiting on the server's address, up to
+    /// `max`, without blocking, e.g. to pass them to `attest_many`.
+    pub fn accept_pending(&self, max: usize) -> SpRaResult<Vec<Connection>> {
+        Ok(self.listener.accept_pending(max)?)
+    }
+
+    /// Attest a batch of connections, e.g. from enclaves brought up together
+    /// after a deploy, on up to `max_parallel` threads, which bounds the IAS
+    /// requests in flight. The attestations share SigRL lookups: each EPID
+    /// group's SigRL is fetched once, through the identity's `SigRlCache` or,
+    /// without one, a cache for the batch. Connections of tenants without the
+    /// cache's subscription keys fetch theirs for every attestation.
+    /// Connections are held to the server's `ConnectionLimits`. Blocks until
+    /// every attestation is done and returns the connections with their
+    /// outcomes, in the order given; an attestation that panicked fails with
+    /// `SpRaError::AttestationPanicked`.
+    pub fn attest_many(&self, connections: Vec<Connection>, max_parallel: usize)
+        -> SpRaResult<Vec<BatchAttestation>> {
+            // The whole batch is attested with the identity in use now
+            let identity = self.identity();
+            let config = &identity.config;
+            // Connections of other tenants than the top-level one fetch
+            // their SigRLs themselves, see `SigRlCache`
+            let sig_rl_cache = match identity.sig_rl_cache.as_ref() {
+                Some(cache) => cache.clone(),
+                None => SigRlCache::with_ias_client(config.ias_client()?,
+                                                    &config.primary_subscription_key,
+                                                    &config.secondary_subscription_key,
+                                                    Duration::from_secs(BATCH_SIG_RL_TTL_SECS)),
+            };
+            let total = connections.len();
+            let queue: VecDeque<_> = connections.into_iter()
+                .enumerate()
+                .map(|(i, connection)| {
+                    let (id, frame_lock) = self.start_handshake(&connection);
+                    (i, id, frame_lock, connection)
+                })
+                .collect();
+            let queue = Arc::new(Mutex::new(queue));
+            let done = Arc::new(AtomicBool::new(false));
+            self.spawn_watchdog(done.clone());
+
+            let (sender, receiver) = mpsc::channel();
+            let workers: Vec<_> = (0..max_parallel.max(1).min(total)).map(|_| {
+                let queue = queue.clone();
+                let sender = sender.clone();
+                let identity = identity.clone();
+                let sig_rl_cache = sig_rl_cache.clone();
+                let max_attempts = self.limits.max_attempts;
+                let session_validity = self.session_validity;
+                let handshakes = self.handshakes.clone();
+                thread::spawn(move || {
+                    loop {
+                        let next = queue.lock().unwrap().pop_front();
+                        let (i, id, frame_lock, mut connection) = match next {
+                            Some(next) => next,
+                            None => break,
+                        };
+                        // Keep the connection and its outcome if a hook or
+                        // verifier panics
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            attest_connection(&identity, Some(&sig_rl_cache),
+                                              &mut connection, id, &frame_lock,
+                                              max_attempts, &handshakes)
+                        })).unwrap_or_else(|panic| {
+                            handshakes.lock().unwrap().remove(&id);
+                            Err(SpRaError::AttestationPanicked(panic_message(&*panic)))
+                        })
+                            .map(|result| (result, Session::new(id, session_validity)));
+                        let _r = sender.send((i, BatchAttestation { connection, result }));
+                    }
+                })
+            }).collect();
+            drop(sender);
+
+            let mut outcomes: Vec<Option<BatchAttestation>> = (0..total).map(|_| None).collect();
+            for (i, outcome) in receiver {
+                outcomes[i] = Some(outcome);
+            }
+            for worker in workers {
+                let _r = worker.join();
+            }
+            done.store(true, Ordering::SeqCst);
+            // Can unwrap since workers send an outcome for every connection
+            // they take, even if its attestation panicked
+            Ok(outcomes.into_iter().map(Option::unwrap).collect())
+        }
+
+    // Register `connection` with the watchdog and return its session id, which
+    // its attempts pass to the hooks, and the lock of its messages
+    fn start_handshake(&self, connection: &Connection) -> (SessionId, FrameLock) {
+        let id = context::next_session_id();
+        let frame_lock = Arc::new(Mutex::new(false));
+        if let Ok(stream) = connection.stream().try_clone() {
+            self.handshakes.lock().unwrap()
+                .insert(id, (Instant::now(), stream, frame_lock.clone()));
+        }
+        (id, frame_lock)
+    }
+
+    // Disconnect clients whose handshakes outlast the deadline or whose
+    // sessions fail their check, until shutdown is requested or `done` is set
+    fn spawn_watchdog(&self, done: Arc<AtomicBool>) {
+        let handshakes = self.handshakes.clone();
+        let sessions = self.sessions.clone();
+        let shutdown = self.shutdown_handle();
+        let deadline = self.limits.handshake_deadline;
+        thread::spawn(move || {
+            while !shutdown.is_shutdown() && !done.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(WATCHDOG_PERIOD_MILLIS));
+                handshakes.lock().unwrap().retain(|_, (accepted, stream, _)| {
+                    if accepted.elapsed() < deadline {
+                        return true;
+                    }
+                    if cfg!(feature = "verbose") {
+                        if let Ok(peer_addr) = stream.peer_addr() {
+                            eprintln!("Handshake with {} timed out", peer_addr);
+                        }
+                    }
+                    let _r = stream.shutdown(Shutdown::Both);
+                    false
+                });
+                sessions.lock().unwrap().retain(|_, (session, stream)| {
+                    let e = match session.check() {
+                        Ok(()) => return true,
+                        Err(e) => e,
+                    };
+                    if cfg!(feature = "verbose") {
+                        if let Ok(peer_addr) = stream.peer_addr() {
+                            eprintln!("Session {} with {} ended: {:?}",
+                                      session.id(), peer_addr, e);
+                        }
+                    }
+                    let _r = stream.shutdown(Shutdown::Both);
+                    false
+                });
+            }
+        });
+    }
+}
+
+// Build the identity from `config` like the current one and swap it in for
+// new connections
+fn reload(identity: &RwLock<Arc<SpIdentity>>, config: SpConfig) -> SpRaResult<()> {
+    let current = identity.read().unwrap().clone();
+    let reloaded = Arc::new(current.with_config(config)?);
+    *identity.write().unwrap() = reloaded;
+    Ok(())
+}
+
+// Attest the client on `connection`, letting it start over after a failed
+// handshake while it has attempts left and the watchdog did not drop it, then
+// unregister the connection from the watchdog
+fn attest_connection(identity: &Arc<SpIdentity>,
+                     sig_rl_cache: Option<&SigRlCache>,
+                     connection: &mut Connection,
+                     id: SessionId,
+                     frame_lock: &FrameLock,
+                     max_attempts: u32,
+                     handshakes: &Handshakes) -> SpRaResult<AttestationResult> {
+    let mut stream = FramedConnection {
+        connection: &mut *connection,
+        frame_lock,
+        buffer: Vec::new(),
+    };
+    let mut attempt = 1;
+    let result = loop {
+        let result = identity.new_session()
+            .and_then(|mut s| {
+                s.set_session_id(id);
+                if let Some(cache) = sig_rl_cache {
+                    s.set_sig_rl_cache(cache.clone());
+                }
+                s.do_attestation(&mut stream)
+            });
+        match result {
+            Err(ref e) if attempt < max_attempts && can_restart(e) &&
+                handshakes.lock().unwrap().contains_key(&id) => {
+                    if cfg!(feature = "verbose") {
+                        eprintln!("Attempt {} with {} failed: {:?}",
+                                  attempt, stream.connection.peer_addr(), e);
+                    }
+                    attempt += 1;
+                },
+            r => break r,
+        }
+    };
+    handshakes.lock().unwrap().remove(&id);
+    result
+}
+
+// A connection in the handshake, holding back what the SP writes until the
+// message is flushed and then writing it whole under its `FrameLock`
+struct FramedConnection<'a> {
+    connection: &'a mut Connection,
+    frame_lock: &'a FrameLock,
+    buffer: Vec<u8>,
+}
+
+impl Read for FramedConnection<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.connection.read(buf)
+    }
+}
+
+impl Write for FramedConnection<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let aborted = self.frame_lock.lock().unwrap();
+        if *aborted {
+            self.buffer.clear();
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                      "the server is shutting down"));
+        }
+        let r = self.connection.write_all(&self.buffer[..]);
+        self.buffer.clear();
+        r?;
+        self.connection.flush()
+    }
+}
+
+// The message a panic was started with, e.g. by `panic!` or `expect`
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => (*message).to_owned(),
+        None => panic.downcast_ref::<String>().cloned()
+            .unwrap_or_else(|| "unknown panic".to_owned()),
+    }
+}
+
+// Whether the client may still be following the protocol after `e`, i.e. the
+// connection did not break and the SP did not receive garbage
+fn can_restart(e: &SpRaError) -> bool {
+    match e {
+        SpRaError::IO(_) | SpRaError::Serialization(_) => false,
+        _ => e.abort_reason().is_some(),
+    }
+}
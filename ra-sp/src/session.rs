@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::error::SpRaError;
+use crate::{SessionId, SpRaResult};
+
+/// Standing of an attested connection of an `SpServer`. An attestation is
+/// only trusted for the server's session validity period; after that,
+/// `SpServer::run` disconnects the client, and handlers should refuse
+/// requests on the session, see `check`, unless the enclave was attested
+/// again and the session renewed in time with `renew`. A session can also be
+/// revoked for good, e.g. by a `HeartbeatMonitor`. Clones share the same
+/// state.
+#[derive(Clone, Debug)]
+pub struct Session {
+    id: SessionId,
+    validity: Option<Duration>,
+    attested_at: Arc<Mutex<Instant>>,
+    revoked: Arc<AtomicBool>,
+}
+
+impl Session {
+    pub(crate) fn new(id: SessionId, validity: Option<Duration>) -> Self {
+        Self {
+            id,
+            validity,
+            attested_at: Arc::new(Mutex::new(Instant::now())),
+            revoked: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The `SpRaContext::session_id` of the attestation, as passed to the
+    /// hooks and audit events.
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    /// Time until the session expires, zero once it has. None if sessions
+    /// do not expire.
+    pub fn remaining_validity(&self) -> Option<Duration> {
+        let elapsed = self.attested_at.lock().unwrap().elapsed();
+        self.validity.map(|v| v.checked_sub(elapsed).unwrap_or_default())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining_validity() == Some(Duration::from_secs(0))
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `SessionRevoked` once the session is revoked and with
+    /// `SessionExpired` once it must be re-attested, e.g. at the start of
+    /// every request handler.
+    pub fn check(&self) -> SpRaResult<()> {
+        if self.is_revoked() {
+            return Err(SpRaError::SessionRevoked);
+        }
+        if self.is_expired() {
+            return Err(SpRaError::SessionExpired);
+        }
+        Ok(())
+    }
+
+    /// Start a new validity period, after the enclave was attested again,
+    /// e.g. with `Reattestation::run`.
+    pub fn renew(&self) {
+        *self.attested_at.lock().unwrap() = Instant::now();
+    }
+
+    /// Stop trusting the session, e.g. because the enclave's platform is no
+    /// longer trusted. Unlike expiry, renewing does not undo this.
+    pub fn revoke(&self) {
+        self.revoked.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sgx_crypto::certificate::X509Cert;
+use ra_common::msg::Gid;
+use crate::ias::IasClient;
+use crate::ias_scheduler::{self, IasSlot};
+use crate::error::IasError;
+use crate::credentials::SubscriptionKey;
+
+struct Entry {
+    sig_rl: Option<Vec<u8>>,
+    fetched_at: Instant,
+}
+
+/// SigRLs fetched from IAS, cached per EPID group for `ttl`. In server mode,
+/// `spawn_refresh` keeps the entries fresh in the background so that an
+/// attestation never waits for a SigRL download. Concurrent misses for the
+/// same group wait for a single download.
+///
+/// SigRLs are fetched with the cache's subscription keys, so a session only
+/// uses a cache with the same keys as its tenant. Sessions of other tenants
+/// fetch their SigRLs from IAS directly, unless they are given a cache of
+/// their tenant's with `SpRaContext::set_sig_rl_cache`.
+#[derive(Clone)]
+pub struct SigRlCache {
+    ias_client: Arc<IasClient>,
+    primary_subscription_key: SubscriptionKey,
+    secondary_subscription_key: SubscriptionKey,
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<Gid, Entry>>>,
+    // Held while a SigRL of the group is being fetched by `get`
+    fetching: Arc<Mutex<HashMap<Gid, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl SigRlCache {
+    pub fn new(root_ca_cert: X509Cert,
+               primary_subscription_key: &SubscriptionKey,
+               secondary_subscription_key: &SubscriptionKey,
+               ttl: Duration) -> Result<Self, IasError> {
+        Ok(Self::with_ias_client(IasClient::new(root_ca_cert)?,
+                                 primary_subscription_key,
+                                 secondary_subscription_key,
+                                 ttl))
+    }
+
+    pub(crate) fn with_ias_client(ias_client: IasClient,
+                                  primary_subscription_key: &SubscriptionKey,
+                                  secondary_subscription_key: &SubscriptionKey,
+                                  ttl: Duration) -> Self {
+        Self {
+            ias_client: Arc::new(ias_client),
+            primary_subscription_key: primary_subscription_key.clone(),
+            secondary_subscription_key: secondary_subscription_key.clone(),
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            fetching: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Whether SigRLs are fetched with the subscription key `primary`, i.e.
+    /// for the tenant that has it.
+    pub fn is_for_key(&self, primary: &SubscriptionKey) -> bool {
+        self.primary_subscription_key == *primary
+    }
+
+    /// Return the cached SigRL of `gid`, fetching it first if it is missing
+    /// or expired.
+    pub async fn get(&self, gid: &Gid) -> Result<Option<Vec<u8>>, IasError> {
+        self.get_scheduled(gid, None).await
+    }
+
+    /// `get`, sending the request in turn with the other requests of
+    /// `ias_slot`'s scheduler.
+    pub(crate) async fn get_scheduled(&self, gid: &Gid, ias_slot: Option<&IasSlot>)
+        -> Result<Option<Vec<u8>>, IasError> {
+            if let Some(sig_rl) = self.get_fresh(gid) {
+                return Ok(sig_rl);
+            }
+            let fetching = self.fetching.lock().unwrap()
+                .entry(*gid)
+                .or_insert_with(|| Arc::new(tokio::sync::Mutex::new(())))
+                .clone();
+            let _fetching = fetching.lock().await;
+            // Another get may have fetched it while this one waited
+            if let Some(sig_rl) = self.get_fresh(gid) {
+                return Ok(sig_rl);
+            }
+            let _permit = ias_scheduler::acquire(ias_slot).await;
+            self.fetch(gid).await
+        }
+
+    fn get_fresh(&self, gid: &Gid) -> Option<Option<Vec<u8>>> {
+        self.entries.lock().unwrap().get(gid)
+            .filter(|e| e.fetched_at.elapsed() < self.ttl)
+            .map(|e| e.sig_rl.clone())
+    }
+
+    /// The last SigRL fetched for `gid`, however old, or None if there is
+    /// none.
+    pub fn get_stale(&self, gid: &Gid) -> Option<Option<Vec<u8>>> {
+        self.entries.lock().unwrap().get(gid).map(|e| e.sig_rl.clone())
+    }
+
+    /// Whether no cached SigRL has expired, i.e. refreshes keep up. An empty
+    /// cache is warm, as there is nothing it should have refreshed.
+    pub fn is_warm(&self) -> bool {
+        self.entries.lock().unwrap().values()
+            .all(|e| e.fetched_at.elapsed() < self.ttl)
+    }
+
+    async fn fetch(&self, gid: &Gid) -> Result<Option<Vec<u8>>, IasError> {
+        let sig_rl = self.ias_client
+            .get_sig_rl_with_fallback(gid,
+                                      &self.primary_subscription_key,
+                                      &self.secondary_subscription_key).await?;
+        self.entries.lock().unwrap().insert(*gid, Entry {
+            sig_rl: sig_rl.clone(),
+            fetched_at: Instant::now(),
+        });
+        Ok(sig_rl)
+    }
+
+    /// Refresh every group whose entry expires within `refresh_before`,
+    /// checking every `interval`. Must be called from within a Tokio runtime;
+    /// the task runs until aborted or the runtime shuts down. Failed refreshes
+    /// are retried on the next tick, and the stale entry is kept until then.
+    pub fn spawn_refresh(&self, interval: Duration, refresh_before: Duration)
+        -> tokio::task::JoinHandle<()> {
+            let cache = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::delay_for(interval).await;
+                    let expiring: Vec<Gid> = {
+                        let threshold = cache.ttl.checked_sub(refresh_before)
+                            .unwrap_or_default();
+                        let entries = cache.entries.lock().unwrap();
+                        entries.iter()
+                            .filter(|(_, e)| e.fetched_at.elapsed() >= threshold)
+                            .map(|(gid, _)| *gid)
+                            .collect()
+                    };
+                    for gid in expiring.iter() {
+                        let r = cache.fetch(gid).await;
+                        if cfg!(feature = "verbose") {
+                            if let Err(e) = r {
+                                eprintln!("SigRL refresh failed: {:?}", e);
+                            }
+                        }
+                    }
+                }
+            })
+        }
+}
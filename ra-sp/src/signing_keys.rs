@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use sgx_crypto::signature::SigningKey;
+
+struct Keys {
+    // The key signing MSG2, unless one of `upcoming` is due
+    signing: Arc<SigningKey>,
+    // Keys of rotations, in order, with when each starts signing
+    upcoming: VecDeque<(Arc<SigningKey>, Instant)>,
+}
+
+impl Keys {
+    // Drop the keys replaced by the last one that is due at `now`
+    fn advance(&mut self, now: Instant) {
+        while self.upcoming.front().map_or(false, |(_, from)| now >= *from) {
+            self.signing = self.upcoming.pop_front().unwrap().0;
+        }
+    }
+
+    // The key signing at `now`, then the keys of the rotations to come
+    fn trusted(&self, now: Instant) -> impl Iterator<Item = &Arc<SigningKey>> {
+        let due = self.upcoming.iter().take_while(|(_, from)| now >= *from).count();
+        std::iter::once(&self.signing)
+            .chain(self.upcoming.iter().map(|(key, _)| key))
+            .skip(due)
+    }
+}
+
+/// The SP's signing key for MSG2, which can be replaced while the SP serves.
+///
+/// Enclaves pin the SP's public key, so a new key cannot sign MSG2 before the
+/// enclaves were updated to trust it. After `rotate`, MSG2 is therefore still
+/// signed with the old key until the grace window ends, while both public keys
+/// are advertised by `public_keys_pem` for the enclaves to pin, e.g. with
+/// `EnclaveRaContext::trust_sp_key`. Once the window ends, the new key signs
+/// and the old one is dropped. A rotation during the grace window of another
+/// waits for it: its own grace window starts when the other's ends, so that
+/// no key signs before it was advertised for its full grace window.
+pub struct SpSigningKeys {
+    keys: RwLock<Keys>,
+}
+
+impl SpSigningKeys {
+    pub fn new(key: SigningKey) -> Self {
+        Self {
+            keys: RwLock::new(Keys {
+                signing: Arc::new(key),
+                upcoming: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Replace the key, keeping the current one in use for `grace`, or, if
+    /// a grace window is open, for `grace` after it ends.
+    pub fn rotate(&self, key: SigningKey, grace: Duration) {
+        let now = Instant::now();
+        let mut keys = self.keys.write().unwrap();
+        keys.advance(now);
+        let from = keys.upcoming.back().map_or(now, |(_, from)| *from) + grace;
+        keys.upcoming.push_back((Arc::new(key), from));
+        if cfg!(feature = "verbose") {
+            eprintln!("SP signing key rotated, signing in {:?}", from - now);
+        }
+    }
+
+    /// The key to sign MSG2 with now.
+    pub fn signing_key(&self) -> Arc<SigningKey> {
+        let mut keys = self.keys.write().unwrap();
+        keys.advance(Instant::now());
+        keys.signing.clone()
+    }
+
+    /// Public keys enclaves should trust: the current key and, during a grace
+    /// window, the keys that replace it.
+    pub fn public_keys_pem(&self) -> Vec<String> {
+        self.keys.read().unwrap()
+            .trusted(Instant::now())
+            .map(|key| key.public_key_pem())
+            .collect()
+    }
+
+    /// Whether a rotation's grace window is still open, i.e. the key
+    /// returned by `signing_key` is about to be replaced.
+    pub fn in_grace_window(&self) -> bool {
+        match self.keys.read().unwrap().upcoming.back() {
+            Some((_, from)) => Instant::now() < *from,
+            None => false,
+        }
+    }
+}
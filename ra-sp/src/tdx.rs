@@ -0,0 +1,47 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use sgx_crypto::certificate::X509Cert;
+use ra_verify::policy::TdPolicy;
+use ra_verify::tdx::{verify_td_quote, TdReportBody};
+use ra_verify::report::trust_anchor_from_der;
+use crate::SpRaResult;
+
+/// Verifies TD quotes of Intel TDX VMs next to the SGX attestations of an
+/// `SpRaContext`, see `SpIdentity::set_tdx_verifier`. TD quotes are DCAP
+/// quotes and are checked locally against Intel's SGX root CA and the QE
+/// Identity, TCB Info, and CRLs of the policy instead of through IAS.
+pub struct TdxVerifier {
+    root_ca_cert: X509Cert,
+    policy: TdPolicy,
+}
+
+impl TdxVerifier {
+    /// `root_ca_cert_pem_path` is Intel's SGX root CA, which issues the PCK
+    /// certificates embedded in TD quotes.
+    pub fn new(root_ca_cert_pem_path: &Path, policy: TdPolicy) -> SpRaResult<Self> {
+        Ok(Self {
+            root_ca_cert: X509Cert::new_from_pem_file(root_ca_cert_pem_path)?,
+            policy,
+        })
+    }
+
+    pub fn policy(&self) -> &TdPolicy {
+        &self.policy
+    }
+
+    /// Returns the TD report of `quote` if it is authentic and trusted by the
+    /// policy. The caller must check that its REPORTDATA binds the quote to
+    /// the session, e.g. a nonce or a TLS key.
+    pub fn verify(&self, quote: &[u8]) -> SpRaResult<TdReportBody> {
+        // A clock before 1970 fails the certificate validity checks
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let anchor = trust_anchor_from_der(self.root_ca_cert.as_ref())?;
+        let report = verify_td_quote(quote, &[anchor], now, &self.policy)?;
+        if cfg!(feature = "verbose") {
+            eprintln!("TD quote verified, MRTD: {}", hex::encode(&report.mr_td[..]));
+        }
+        Ok(report)
+    }
+}
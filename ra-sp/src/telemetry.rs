@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use opentelemetry::{global, KeyValue};
+use opentelemetry::metrics::{Counter, ValueRecorder};
+use opentelemetry::trace::{Span, Tracer, TraceError};
+use ra_common::msg::{RaMsg1, Quote};
+use crate::attestation_response::AttestationResponse;
+use crate::error::SpRaError;
+use crate::hooks::{AttestationHooks, HookResult};
+use crate::{AttestationResult, SessionId, SpRaResult};
+
+const INSTRUMENTATION_NAME: &str = "ra-sp";
+
+/// Send the spans of the global tracer to the OTLP collector at `endpoint`,
+/// e.g. `http://localhost:4317`, until the returned guard is dropped. Metrics
+/// go to the global meter provider, which the application sets up along with
+/// its other metrics, e.g. with opentelemetry-prometheus.
+pub fn install_otlp_pipeline(endpoint: &str) -> Result<opentelemetry_otlp::Uninstall, TraceError> {
+    let (_tracer, uninstall) = opentelemetry_otlp::new_pipeline()
+        .with_endpoint(endpoint)
+        .install()?;
+    Ok(uninstall)
+}
+
+/// Records each attestation as an `attestation` span, with an event per
+/// stage, and the counters and histogram below on the global OpenTelemetry
+/// providers:
+///
+/// - `ra_sp.attestations`: finished attestations, by `outcome` (`trusted`,
+///   `rejected`, or `error`)
+/// - `ra_sp.attestation.duration`: seconds from MSG1 to the outcome
+///
+/// Install with `SpIdentity::set_hooks`; other hooks, e.g. an `AuditLog`, are
+/// still called through `with_hooks`.
+pub struct Telemetry {
+    inner: Option<Arc<dyn AttestationHooks>>,
+    // Spans of the attestations under way, by session
+    in_flight: Mutex<HashMap<SessionId, (Instant, global::BoxedSpan)>>,
+    attestations: Counter<u64>,
+    duration: ValueRecorder<f64>,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        let meter = global::meter(INSTRUMENTATION_NAME);
+        Self {
+            inner: None,
+            in_flight: Mutex::new(HashMap::new()),
+            attestations: meter.u64_counter("ra_sp.attestations")
+                .with_description("Finished attestations by outcome")
+                .init(),
+            duration: meter.f64_value_recorder("ra_sp.attestation.duration")
+                .with_description("Seconds from MSG1 to the outcome of an attestation")
+                .init(),
+        }
+    }
+
+    pub fn with_hooks(mut self, hooks: Arc<dyn AttestationHooks>) -> Self {
+        self.inner = Some(hooks);
+        self
+    }
+
+    fn add_event(&self, session: SessionId, name: &str, attributes: Vec<KeyValue>) {
+        let in_flight = self.in_flight.lock().unwrap();
+        if let Some((_, span)) = in_flight.get(&session) {
+            span.add_event(name.to_owned(), attributes);
+        }
+    }
+
+    fn finish(&self, session: SessionId, outcome: &'static str, attributes: Vec<KeyValue>) {
+        let labels = [KeyValue::new("outcome", outcome)];
+        self.attestations.add(1, &labels);
+        let started = self.in_flight.lock().unwrap().remove(&session);
+        if let Some((started, span)) = started {
+            self.duration.record(started.elapsed().as_secs_f64(), &labels);
+            span.set_attribute(KeyValue::new("outcome", outcome));
+            for attribute in attributes {
+                span.set_attribute(attribute);
+            }
+            span.end();
+        }
+    }
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AttestationHooks for Telemetry {
+    fn on_msg1(&self, session: SessionId, msg1: &RaMsg1) -> HookResult {
+        let span = global::tracer(INSTRUMENTATION_NAME).start("attestation");
+        span.set_attribute(KeyValue::new("sgx.gid", hex::encode(&msg1.gid[..])));
+        self.in_flight.lock().unwrap()
+            .insert(session, (Instant::now(), span));
+        self.inner.as_ref().map_or(Ok(()), |h| h.on_msg1(session, msg1))
+    }
+
+    fn on_quote_received(&self, session: SessionId, quote: &Quote) -> HookResult {
+        self.add_event(session, "quote_received", Vec::new());
+        self.inner.as_ref().map_or(Ok(()), |h| h.on_quote_received(session, quote))
+    }
+
+    fn on_report_verified(&self, session: SessionId, report: &AttestationResponse) -> HookResult {
+        self.add_event(session, "report_verified", vec![
+            KeyValue::new("sgx.quote_status", report.isv_enclave_quote_status.clone()),
+        ]);
+        self.inner.as_ref().map_or(Ok(()), |h| h.on_report_verified(session, report))
+    }
+
+    fn on_complete(&self, result: &AttestationResult) {
+        self.finish(result.session_id, "trusted", vec![
+            KeyValue::new("sgx.mr_enclave", hex::encode(&result.enclave.mr_enclave[..])),
+            KeyValue::new("sgx.mr_signer", hex::encode(&result.enclave.mr_signer[..])),
+        ]);
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_complete(result);
+        }
+    }
+
+    fn on_failure(&self, session: SessionId, error: &SpRaError) {
+        let outcome = match error {
+            SpRaError::EnclaveNotTrusted |
+                SpRaError::RejectedByVerifier(_) |
+                SpRaError::SigstructMismatched |
+                SpRaError::EnclaveInDebugMode |
+                SpRaError::CpuSvnTooLow |
+                SpRaError::Vetoed(_) => "rejected",
+            _ => "error",
+        };
+        self.finish(session, outcome, vec![KeyValue::new("error", format!("{:?}", error))]);
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_failure(session, error);
+        }
+    }
+
+    fn on_secret_delivered(&self, name: &str, result: &AttestationResult) {
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_secret_delivered(name, result);
+        }
+    }
+
+    fn on_shutdown(&self) {
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_shutdown();
+        }
+    }
+
+    fn on_reload(&self, result: &SpRaResult<()>) {
+        if let Some(hooks) = self.inner.as_ref() {
+            hooks.on_reload(result);
+        }
+    }
+
+    fn check_ready(&self) -> Result<(), String> {
+        self.inner.as_ref().map_or(Ok(()), |h| h.check_ready())
+    }
+}
@@ -0,0 +1,93 @@
+use std::convert::TryInto;
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sgx_crypto::cmac::{Cmac, MacTag};
+use sgx_crypto::locked::Locked;
+use sgx_crypto::random::RandomState;
+use ra_common::session_keys::SessionKeys;
+use crate::AttestationResult;
+
+const TOKEN_VERSION: u8 = 1;
+const TOKEN_LEN: usize = 1 + 32 + 32 + 8 + 16;
+
+#[derive(Debug, PartialEq)]
+pub enum TokenError {
+    Malformed,
+    /// The token was not issued with this issuer's key.
+    BadMac,
+    Expired,
+}
+
+/// What a valid token says about the enclave it was issued to.
+#[derive(Debug, Clone)]
+pub struct TokenClaims {
+    pub mr_enclave: [u8; 32],
+    pub mr_signer: [u8; 32],
+    /// In seconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+/// Issues bearer tokens to attested enclaves, so that ordinary web services
+/// can authorize their requests by attestation, e.g. with
+/// `AttestationLayer`. A token is MACed with the issuer's key and carries
+/// the enclave's measurements and an expiry.
+pub struct TokenIssuer {
+    key: Locked<MacTag>,
+}
+
+impl TokenIssuer {
+    /// With a random key, for services in the same process.
+    pub fn new() -> io::Result<Self> {
+        let mut key = [0u8; 16];
+        RandomState::new().fill(&mut key);
+        Self::with_key(&key)
+    }
+
+    /// With a key shared by every service that checks the tokens.
+    pub fn with_key(key: &MacTag) -> io::Result<Self> {
+        Ok(Self { key: Locked::new(*key)? })
+    }
+
+    /// With the token-signing key of an attested session, so that the enclave
+    /// can check the tokens too.
+    pub fn for_session(keys: &SessionKeys) -> io::Result<Self> {
+        Self::with_key(&keys.token_signing_key())
+    }
+
+    /// A token for the enclave of `result`, valid for `valid_for`, e.g. the
+    /// remaining validity of its `Session`.
+    pub fn issue(&self, result: &AttestationResult, valid_for: Duration) -> String {
+        let expires_at = (SystemTime::now() + valid_for).duration_since(UNIX_EPOCH)
+            .unwrap().as_secs();
+        let mut token = Vec::with_capacity(TOKEN_LEN);
+        token.push(TOKEN_VERSION);
+        token.extend_from_slice(&result.enclave.mr_enclave[..]);
+        token.extend_from_slice(&result.enclave.mr_signer[..]);
+        token.extend_from_slice(&expires_at.to_be_bytes());
+        let mac = Cmac::new(&self.key).sign(&token[..]);
+        token.extend_from_slice(&mac[..]);
+        base64::encode_config(&token[..], base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<TokenClaims, TokenError> {
+        let token = base64::decode_config(token, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| TokenError::Malformed)?;
+        if token.len() != TOKEN_LEN || token[0] != TOKEN_VERSION {
+            return Err(TokenError::Malformed);
+        }
+        let (data, mac) = token.split_at(TOKEN_LEN - 16);
+        Cmac::new(&self.key).verify(data, mac.try_into().unwrap())
+            .map_err(|_| TokenError::BadMac)?;
+
+        let expires_at = u64::from_be_bytes(data[65..73].try_into().unwrap());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        if now >= expires_at {
+            return Err(TokenError::Expired);
+        }
+        Ok(TokenClaims {
+            mr_enclave: data[1..33].try_into().unwrap(),
+            mr_signer: data[33..65].try_into().unwrap(),
+            expires_at,
+        })
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use ra_common::msg::Gid;
+use ra_common::quote::QuoteBody;
+use crate::attestation_response::AttestationResponse;
+
+/// What a cached verdict applies to: the enclave, its EPID group, and the
+/// platform's TCB as the quote reports it. A platform that updated its
+/// microcode or quoting enclave gets a key of its own.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VerdictKey {
+    pub mr_enclave: [u8; 32],
+    pub mr_signer: [u8; 32],
+    pub gid: Gid,
+    pub cpu_svn: [u8; 16],
+    pub qe_svn: u16,
+    pub pce_svn: u16,
+}
+
+impl VerdictKey {
+    pub fn from_quote_body(quote_body: &QuoteBody) -> Self {
+        Self {
+            mr_enclave: quote_body.mr_enclave,
+            mr_signer: quote_body.mr_signer,
+            gid: quote_body.epid_group_id,
+            cpu_svn: quote_body.cpu_svn,
+            qe_svn: quote_body.qe_svn,
+            pce_svn: quote_body.pce_svn,
+        }
+    }
+}
+
+struct Entry {
+    report: AttestationResponse,
+    verified_at: Instant,
+}
+
+/// IAS reports of trusted quotes, reused for `window` by later quotes with the
+/// same `VerdictKey`, so that a fleet of identical enclaves reconnecting at
+/// once does not send IAS a request per enclave. The key exchange still runs
+/// for every session, and the SP's own checks of the quote, e.g. of its
+/// SIGSTRUCT and CPUSVN, still run on every quote.
+///
+/// A cache hit skips IAS, which is the only check of an EPID quote's
+/// signature: within the window, a quote is trusted on what its body claims,
+/// and a platform whose TCB IAS stopped trusting is trusted until its entry
+/// expires. Keep the window short, and leave the cache off where clients
+/// cannot be trusted to send genuine quotes. A cached report is also only
+/// reused while its timestamp is within `report_max_skew_secs`, and it is the
+/// report of the earlier quote: its quote body and nonce are not those of the
+/// quote it is reused for.
+#[derive(Clone)]
+pub struct VerdictCache {
+    window: Duration,
+    entries: Arc<Mutex<HashMap<VerdictKey, Entry>>>,
+}
+
+impl VerdictCache {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// The report IAS issued for an earlier quote with the same key, if it
+    /// was trusted less than `window` ago.
+    pub fn get(&self, key: &VerdictKey) -> Option<AttestationResponse> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(key)
+            .filter(|e| e.verified_at.elapsed() < self.window)
+            .map(|e| e.report.clone())
+    }
+
+    /// Remember the report of a quote with `key` that was trusted.
+    pub fn insert(&self, key: VerdictKey, report: AttestationResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        let window = self.window;
+        entries.retain(|_, e| e.verified_at.elapsed() < window);
+        entries.insert(key, Entry {
+            report,
+            verified_at: Instant::now(),
+        });
+    }
+
+    /// Forget the verdict of `key`, e.g. because its report went stale.
+    pub fn remove(&self, key: &VerdictKey) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    /// Forget every verdict, e.g. after an advisory, so that the next quotes
+    /// go to IAS again.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,22 @@
+use ra_common::quote::QuoteBody;
+use crate::attestation_response::AttestationResponse;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Accept,
+    Reject(String),
+}
+
+/// Application-specific checks on top of the built-in policy. A verifier is
+/// only consulted for enclaves the built-in policy already trusts, and can
+/// only turn that decision into a rejection.
+pub trait ReportVerifier: Send + Sync {
+    fn verify(&self, quote: &QuoteBody, report: &AttestationResponse) -> Verdict;
+}
+
+impl<F> ReportVerifier for F
+    where F: Fn(&QuoteBody, &AttestationResponse) -> Verdict + Send + Sync {
+        fn verify(&self, quote: &QuoteBody, report: &AttestationResponse) -> Verdict {
+            self(quote, report)
+        }
+    }
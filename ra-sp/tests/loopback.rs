@@ -0,0 +1,56 @@
+// End-to-end attestations in one process: the SP against `MockIas`, with a
+// `SimulatedEnclave` as the client and the enclave. Run with
+// `cargo test --features loopback`.
+#![cfg(feature = "loopback")]
+use ra_sp::{loopback_config, Loopback, SpRaError};
+
+#[test]
+fn attests_enclave_of_sigstruct() {
+    let loopback = Loopback::start(loopback_config()).unwrap();
+    let enclave = loopback.enclave();
+    let mr_enclave = enclave.mr_enclave;
+    let (sp, enclave) = loopback.attest(enclave);
+    let sp = sp.unwrap();
+    let enclave = enclave.unwrap();
+    assert_eq!(sp.quote_status, "OK");
+    assert_eq!(sp.enclave.mr_enclave, mr_enclave);
+    // Both sides end up with the same keys
+    assert_eq!(sp.keys.channel_key(), enclave.channel_key());
+    assert_eq!(loopback.ias.reports_issued(), 1);
+}
+
+#[test]
+fn rejects_untrusted_quote_status() {
+    let loopback = Loopback::start(loopback_config()).unwrap();
+    loopback.ias.set_quote_status("GROUP_OUT_OF_DATE", &["INTEL-SA-00334"]);
+    let (sp, enclave) = loopback.attest(loopback.enclave());
+    match sp {
+        Err(SpRaError::EnclaveNotTrusted) => {},
+        r => panic!("expected EnclaveNotTrusted, got {:?}", r.map(|r| r.quote_status)),
+    }
+    assert!(enclave.is_err());
+}
+
+#[test]
+fn rejects_other_enclave() {
+    let loopback = Loopback::start(loopback_config()).unwrap();
+    let mut enclave = loopback.enclave();
+    enclave.mr_enclave[0] ^= 1;
+    let (sp, _) = loopback.attest(enclave);
+    match sp {
+        Err(SpRaError::SigstructMismatched) => {},
+        r => panic!("expected SigstructMismatched, got {:?}", r.map(|r| r.quote_status)),
+    }
+}
+
+#[test]
+fn rejects_debug_enclave() {
+    let loopback = Loopback::start(loopback_config()).unwrap();
+    let mut enclave = loopback.enclave();
+    enclave.set_debug(true);
+    let (sp, _) = loopback.attest(enclave);
+    match sp {
+        Err(SpRaError::EnclaveInDebugMode) => {},
+        r => panic!("expected EnclaveInDebugMode, got {:?}", r.map(|r| r.quote_status)),
+    }
+}
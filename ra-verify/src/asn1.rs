@@ -0,0 +1,82 @@
+// DER encoding and decoding of the few ASN.1 structures attestation needs,
+// without an ASN.1 crate; shared with sgx-crypto.
+use alloc::vec::Vec;
+
+/// Turn a raw r || s ECDSA signature (big-endian) into an ASN.1
+/// Ecdsa-Sig-Value, as webpki expects.
+pub fn ecdsa_sig_to_der(sig: &[u8]) -> Vec<u8> {
+    let mut content = Vec::new();
+    for half in sig.chunks(sig.len() / 2) {
+        let skip = half.iter().take_while(|b| **b == 0).count();
+        let int = &half[skip..];
+        let pad = int.first().is_none_or(|b| b & 0x80 != 0);
+        content.push(0x02);
+        content.push((int.len() + pad as usize) as u8);
+        if pad {
+            content.push(0);
+        }
+        content.extend_from_slice(int);
+    }
+    let mut der = Vec::with_capacity(content.len() + 2);
+    der.push(0x30);
+    der.push(content.len() as u8);
+    der.extend_from_slice(&content[..]);
+    der
+}
+
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_SEQUENCE: u8 = 0x30;
+
+/// Encode one TLV with a definite length.
+pub fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len() + 6);
+    out.push(tag);
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = (len as u32).to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Split the next TLV off `data`. Returns (tag, content, whole TLV), or None
+/// if `data` does not start with a well-formed TLV.
+pub fn next<'a>(data: &mut &'a [u8]) -> Option<(u8, &'a [u8], &'a [u8])> {
+    let input: &'a [u8] = data;
+    if input.len() < 2 {
+        return None;
+    }
+    let tag = input[0];
+    let (len, header_len) = match input[1] {
+        l if l < 0x80 => (l as usize, 2),
+        l => {
+            let n = (l & 0x7f) as usize;
+            if n == 0 || n > 4 || input.len() < 2 + n {
+                return None;
+            }
+            let len = input[2..(2 + n)].iter()
+                .fold(0usize, |len, b| (len << 8) | *b as usize);
+            (len, 2 + n)
+        },
+    };
+    if input.len() - header_len < len {
+        return None;
+    }
+    let (tlv, rest) = input.split_at(header_len + len);
+    *data = rest;
+    Some((tag, &tlv[header_len..], tlv))
+}
+
+/// Like `next`, but only returns the content and fails on any other tag.
+pub fn expect<'a>(data: &mut &'a [u8], tag: u8) -> Option<&'a [u8]> {
+    match next(data)? {
+        (t, content, _) if t == tag => Some(content),
+        _ => None,
+    }
+}
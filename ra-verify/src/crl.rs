@@ -0,0 +1,284 @@
+// X.509 CRLs of Intel's SGX PKI, which TD quotes need next to their QE
+// Identity and TCB Info: the PCK CRL, issued by the PCK Platform or Processor
+// CA and fetched from the PCS, e.g.
+// /sgx/certification/v4/pckcrl?ca=platform&encoding=der, revokes the PCK
+// certificates of compromised platforms, and the root CA CRL, e.g.
+// https://certificates.trustedservices.intel.com/IntelSGXRootCA.der, revokes
+// the PCK CAs. Without them, a quote signed with a leaked PCK key is trusted.
+//
+// A CRL is parsed without its signature being checked, which needs its
+// issuer; `tdx::verify_td_quote` checks it against the quote's PCK CA or the
+// trust anchor.
+use alloc::vec::Vec;
+#[cfg(feature = "ring-backend")]
+use ring::signature;
+#[cfg(feature = "ring-backend")]
+use untrusted::Input;
+use crate::asn1::{self, TAG_INTEGER, TAG_SEQUENCE};
+use crate::report::days_from_civil;
+use crate::VerifyError;
+
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_VERSION: u8 = 0xa0;
+// AlgorithmIdentifier of ecdsa-with-SHA256 (1.2.840.10045.4.3.2), which has
+// no parameters
+const ECDSA_WITH_SHA256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+
+/// A certificate revocation list signed with ECDSA-SHA256, as Intel's are.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "ring-backend"), allow(dead_code))]
+pub struct Crl {
+    tbs: Vec<u8>,
+    signature: Vec<u8>,
+    // Content of the issuer's Name
+    issuer: Vec<u8>,
+    // Serial numbers of the revoked certificates, as INTEGER contents
+    revoked: Vec<Vec<u8>>,
+    /// When the CRL was issued, in seconds since the Unix epoch.
+    pub this_update: u64,
+    /// When the issuer publishes the next CRL, in seconds since the Unix
+    /// epoch. The CRL is not accepted after that.
+    pub next_update: u64,
+}
+
+impl Crl {
+    /// Parse a DER CRL. Fails with `MalformedEvidence` if it is not signed
+    /// with ECDSA-SHA256 or does not say when the next CRL is due.
+    pub fn parse(der: &[u8]) -> Result<Self, VerifyError> {
+        Self::parse_der(der).ok_or(VerifyError::MalformedEvidence)
+    }
+
+    fn parse_der(der: &[u8]) -> Option<Self> {
+        let mut d = der;
+        let mut crl = asn1::expect(&mut d, TAG_SEQUENCE)?;
+        let (tag, mut tbs, tbs_der) = asn1::next(&mut crl)?;
+        if tag != TAG_SEQUENCE || !d.is_empty() {
+            return None;
+        }
+        if asn1::expect(&mut crl, TAG_SEQUENCE)? != ECDSA_WITH_SHA256 {
+            return None;
+        }
+        let signature = bit_string(asn1::expect(&mut crl, TAG_BIT_STRING)?)?;
+        if !crl.is_empty() {
+            return None;
+        }
+
+        // Version 2, if present
+        if tbs.first() == Some(&TAG_INTEGER) {
+            asn1::next(&mut tbs)?;
+        }
+        if asn1::expect(&mut tbs, TAG_SEQUENCE)? != ECDSA_WITH_SHA256 {
+            return None;
+        }
+        let issuer = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        let this_update = time(&mut tbs)?;
+        let next_update = time(&mut tbs)?;
+        let mut revoked = Vec::new();
+        if tbs.first() == Some(&TAG_SEQUENCE) {
+            let mut entries = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+            while !entries.is_empty() {
+                let mut entry = asn1::expect(&mut entries, TAG_SEQUENCE)?;
+                revoked.push(asn1::expect(&mut entry, TAG_INTEGER)?.to_vec());
+            }
+        }
+        Some(Self {
+            tbs: tbs_der.to_vec(),
+            signature: signature.to_vec(),
+            issuer: issuer.to_vec(),
+            revoked,
+            this_update,
+            next_update,
+        })
+    }
+
+    /// Check that the CRL is current at `time`, in seconds since the Unix
+    /// epoch.
+    pub fn check_current(&self, time: u64) -> Result<(), VerifyError> {
+        if time < self.this_update || time >= self.next_update {
+            return Err(VerifyError::Rejected("CRL expired"));
+        }
+        Ok(())
+    }
+
+    /// Whether the certificate `cert_der` is revoked. Fails with
+    /// `InvalidCertificate` if the certificate cannot be parsed and with
+    /// `Rejected` if it was not issued by the CRL's issuer.
+    pub fn revokes(&self, cert_der: &[u8]) -> Result<bool, VerifyError> {
+        let cert = Certificate::parse(cert_der).ok_or(VerifyError::InvalidCertificate)?;
+        if cert.issuer != &self.issuer[..] {
+            return Err(VerifyError::Rejected("CRL not of the certificate's issuer"));
+        }
+        Ok(self.revoked.iter().any(|serial| &serial[..] == cert.serial))
+    }
+
+    /// Check the CRL's signature with the key of its issuer, whose Name and
+    /// SubjectPublicKeyInfo are given without their SEQUENCE header, like
+    /// those of `webpki::TrustAnchor`.
+    #[cfg(feature = "ring-backend")]
+    pub fn verify_signature(&self, issuer: &[u8], spki: &[u8]) -> Result<(), VerifyError> {
+        if issuer != &self.issuer[..] {
+            return Err(VerifyError::BadSignature);
+        }
+        verify_ecdsa(spki, &self.tbs[..], &self.signature[..])
+    }
+}
+
+// The fields of a DER certificate that revocation checks need, with the Names
+// and the SubjectPublicKeyInfo without their SEQUENCE header
+#[cfg_attr(not(feature = "ring-backend"), allow(dead_code))]
+pub(crate) struct Certificate<'a> {
+    pub(crate) der: &'a [u8],
+    pub(crate) tbs: &'a [u8],
+    pub(crate) signature: &'a [u8],
+    pub(crate) serial: &'a [u8],
+    pub(crate) issuer: &'a [u8],
+    pub(crate) subject: &'a [u8],
+    pub(crate) spki: &'a [u8],
+}
+
+impl<'a> Certificate<'a> {
+    pub(crate) fn parse(der: &'a [u8]) -> Option<Self> {
+        let mut d = der;
+        let mut cert = asn1::expect(&mut d, TAG_SEQUENCE)?;
+        let (tag, mut tbs, tbs_der) = asn1::next(&mut cert)?;
+        if tag != TAG_SEQUENCE {
+            return None;
+        }
+        asn1::expect(&mut cert, TAG_SEQUENCE)?;
+        let signature = bit_string(asn1::expect(&mut cert, TAG_BIT_STRING)?)?;
+        if tbs.first() == Some(&TAG_VERSION) {
+            asn1::next(&mut tbs)?;
+        }
+        let serial = asn1::expect(&mut tbs, TAG_INTEGER)?;
+        asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        let issuer = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        // Validity
+        asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        let subject = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        let spki = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        Some(Self { der, tbs: tbs_der, signature, serial, issuer, subject, spki })
+    }
+}
+
+// Check an ECDSA P-256 signature (an Ecdsa-Sig-Value) with the key of `spki`
+#[cfg(feature = "ring-backend")]
+pub(crate) fn verify_ecdsa(spki: &[u8], msg: &[u8], sig: &[u8]) -> Result<(), VerifyError> {
+    let mut spki = spki;
+    asn1::expect(&mut spki, TAG_SEQUENCE).ok_or(VerifyError::InvalidCertificate)?;
+    let key = asn1::expect(&mut spki, TAG_BIT_STRING)
+        .and_then(bit_string)
+        .ok_or(VerifyError::InvalidCertificate)?;
+    signature::verify(&signature::ECDSA_P256_SHA256_ASN1,
+                      Input::from(key),
+                      Input::from(msg),
+                      Input::from(sig))
+        .map_err(|_| VerifyError::BadSignature)
+}
+
+// The bytes of a BIT STRING without unused bits
+fn bit_string(content: &[u8]) -> Option<&[u8]> {
+    match content.split_first()? {
+        (0, bits) => Some(bits),
+        _ => None,
+    }
+}
+
+// Seconds since the Unix epoch of a UTCTime or GeneralizedTime, which DER
+// requires in UTC and with seconds, e.g. "250501120000Z"
+fn time(data: &mut &[u8]) -> Option<u64> {
+    let (tag, value, _) = asn1::next(data)?;
+    let (z, digits) = value.split_last()?;
+    if *z != b'Z' || !digits.iter().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let number = |s: &[u8]| s.iter().fold(0u64, |n, d| n * 10 + (d - b'0') as u64);
+    let (year, rest) = match (tag, digits.len()) {
+        (TAG_UTC_TIME, 12) => match number(&digits[..2]) {
+            year if year < 50 => (2000 + year, &digits[2..]),
+            year => (1900 + year, &digits[2..]),
+        },
+        (TAG_GENERALIZED_TIME, 14) => (number(&digits[..4]), &digits[4..]),
+        _ => return None,
+    };
+    let (month, day) = (number(&rest[0..2]), number(&rest[2..4]));
+    let (hour, minute, second) = (number(&rest[4..6]), number(&rest[6..8]), number(&rest[8..10]));
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) ||
+        hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asn1::der;
+
+    const ISSUER: &[u8] = b"\x31\x0b\x30\x09\x06\x03\x55\x04\x03\x0c\x02CA";
+
+    fn utc_time(s: &str) -> Vec<u8> {
+        der(TAG_UTC_TIME, s.as_bytes())
+    }
+
+    fn crl(next_update: &[u8], serials: &[&[u8]]) -> Vec<u8> {
+        let entries: Vec<u8> = serials.iter()
+            .flat_map(|s| der(TAG_SEQUENCE, &[der(TAG_INTEGER, s), utc_time("250101000000Z")].concat()))
+            .collect();
+        let tbs = der(TAG_SEQUENCE, &[
+            der(TAG_INTEGER, &[1]),
+            der(TAG_SEQUENCE, ECDSA_WITH_SHA256),
+            der(TAG_SEQUENCE, ISSUER),
+            utc_time("250501120000Z"),
+            next_update.to_vec(),
+            der(TAG_SEQUENCE, &entries[..]),
+        ].concat());
+        der(TAG_SEQUENCE, &[
+            tbs,
+            der(TAG_SEQUENCE, ECDSA_WITH_SHA256),
+            der(TAG_BIT_STRING, &[0, 0x30, 0]),
+        ].concat())
+    }
+
+    fn certificate(serial: &[u8], issuer: &[u8]) -> Vec<u8> {
+        let tbs = der(TAG_SEQUENCE, &[
+            der(TAG_VERSION, &der(TAG_INTEGER, &[2])),
+            der(TAG_INTEGER, serial),
+            der(TAG_SEQUENCE, ECDSA_WITH_SHA256),
+            der(TAG_SEQUENCE, issuer),
+            der(TAG_SEQUENCE, &[utc_time("250101000000Z"), utc_time("350101000000Z")].concat()),
+            der(TAG_SEQUENCE, b""),
+            der(TAG_SEQUENCE, b""),
+        ].concat());
+        der(TAG_SEQUENCE, &[
+            tbs,
+            der(TAG_SEQUENCE, ECDSA_WITH_SHA256),
+            der(TAG_BIT_STRING, &[0, 0x30, 0]),
+        ].concat())
+    }
+
+    #[test]
+    fn finds_revoked_serials() {
+        let crl = Crl::parse(&crl(&utc_time("250601120000Z"), &[&[0x01, 0x02], &[0x7f]])[..]).unwrap();
+        assert_eq!(crl.this_update, 1746100800);
+        assert_eq!(crl.next_update, 1748779200);
+        assert_eq!(crl.revokes(&certificate(&[0x7f], ISSUER)[..]), Ok(true));
+        assert_eq!(crl.revokes(&certificate(&[0x01], ISSUER)[..]), Ok(false));
+        assert_eq!(crl.revokes(&certificate(&[0x7f], b"\x31\x00")[..]),
+                   Err(VerifyError::Rejected("CRL not of the certificate's issuer")));
+    }
+
+    #[test]
+    fn rejects_crls_out_of_date() {
+        let crl = Crl::parse(&crl(&der(TAG_GENERALIZED_TIME, b"20250601120000Z"), &[])[..]).unwrap();
+        assert_eq!(crl.check_current(1746100800), Ok(()));
+        assert_eq!(crl.check_current(1746100799), Err(VerifyError::Rejected("CRL expired")));
+        assert_eq!(crl.check_current(1748779200), Err(VerifyError::Rejected("CRL expired")));
+    }
+
+    #[test]
+    fn requires_next_update() {
+        assert_eq!(Crl::parse(&crl(&[], &[&[0x01]])[..]).err(), Some(VerifyError::MalformedEvidence));
+    }
+}
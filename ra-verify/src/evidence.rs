@@ -0,0 +1,115 @@
+// DER encoding of SGX attestation evidence, for relying parties that expect
+// it inside an X.509 extension or a CMS structure rather than on the
+// attestation protocol's wire. The extension OID or CMS content type is left
+// to the application; the value is always:
+//
+//   SgxEvidence ::= SEQUENCE {
+//       version          INTEGER { v1(1) },
+//       quote            OCTET STRING,            -- sgx_quote_t
+//       report           OCTET STRING,            -- IAS report body (JSON)
+//       reportSignature  OCTET STRING,            -- X-IASReport-Signature, decoded
+//       certificates     SEQUENCE OF Certificate, -- report signing certificate first
+//       channelBinding   [0] IMPLICIT OCTET STRING OPTIONAL
+//   }
+use alloc::vec::Vec;
+#[cfg(feature = "ring-backend")]
+use ring::digest;
+use crate::asn1::{der, next, expect, TAG_INTEGER, TAG_OCTET_STRING, TAG_SEQUENCE};
+#[cfg(feature = "ring-backend")]
+use crate::quote::QUOTE_BODY_LEN;
+#[cfg(feature = "ring-backend")]
+use crate::policy::Policy;
+#[cfg(feature = "ring-backend")]
+use crate::{verify_evidence, VerifiedEvidence};
+use crate::VerifyError;
+
+const VERSION: u8 = 1;
+const TAG_CHANNEL_BINDING: u8 = 0x80;
+
+/// A quote together with everything needed to verify it offline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evidence {
+    pub quote: Vec<u8>,
+    pub report: Vec<u8>,
+    pub report_signature: Vec<u8>,
+    /// DER certificates, starting with the one that signed the report.
+    pub certificates: Vec<Vec<u8>>,
+    /// Data whose SHA-256 the enclave put in the second half of REPORTDATA,
+    /// e.g. a TLS exporter value or a public key.
+    pub channel_binding: Option<Vec<u8>>,
+}
+
+impl Evidence {
+    pub fn to_der(&self) -> Vec<u8> {
+        let certificates: Vec<u8> = self.certificates.concat();
+        let mut content = [
+            der(TAG_INTEGER, &[VERSION]),
+            der(TAG_OCTET_STRING, &self.quote[..]),
+            der(TAG_OCTET_STRING, &self.report[..]),
+            der(TAG_OCTET_STRING, &self.report_signature[..]),
+            der(TAG_SEQUENCE, &certificates[..]),
+        ].concat();
+        if let Some(binding) = self.channel_binding.as_ref() {
+            content.extend_from_slice(&der(TAG_CHANNEL_BINDING, &binding[..]));
+        }
+        der(TAG_SEQUENCE, &content[..])
+    }
+
+    pub fn from_der(input: &[u8]) -> Result<Self, VerifyError> {
+        Self::parse(input).ok_or(VerifyError::MalformedEvidence)
+    }
+
+    fn parse(mut input: &[u8]) -> Option<Self> {
+        let mut content = expect(&mut input, TAG_SEQUENCE)?;
+        if !input.is_empty() || expect(&mut content, TAG_INTEGER)? != &[VERSION][..] {
+            return None;
+        }
+        let quote = expect(&mut content, TAG_OCTET_STRING)?.to_vec();
+        let report = expect(&mut content, TAG_OCTET_STRING)?.to_vec();
+        let report_signature = expect(&mut content, TAG_OCTET_STRING)?.to_vec();
+        let mut chain = expect(&mut content, TAG_SEQUENCE)?;
+        let mut certificates = Vec::new();
+        while !chain.is_empty() {
+            match next(&mut chain)? {
+                (TAG_SEQUENCE, _, cert) => certificates.push(cert.to_vec()),
+                _ => return None,
+            }
+        }
+        let channel_binding = match next(&mut content) {
+            Some((TAG_CHANNEL_BINDING, binding, _)) => Some(binding.to_vec()),
+            Some(_) => return None,
+            None if content.is_empty() => None,
+            None => return None,
+        };
+        if !content.is_empty() {
+            return None;
+        }
+        Some(Self { quote, report, report_signature, certificates, channel_binding })
+    }
+
+    /// Verify the report with `verify_evidence`, then check that it covers
+    /// `quote` and, if present, that `channel_binding` matches REPORTDATA.
+    #[cfg(feature = "ring-backend")]
+    pub fn verify(&self,
+                  trust_anchors: &[webpki::TrustAnchor],
+                  time: u64,
+                  policy: &Policy) -> Result<VerifiedEvidence, VerifyError> {
+        let signing_cert = self.certificates.first()
+            .ok_or(VerifyError::MissingEndorsement)?;
+        let verified = verify_evidence(&self.report[..], &self.report_signature[..],
+                                       &signing_cert[..], trust_anchors, None, time, policy)?;
+
+        let body = base64::decode(&verified.report.isv_enclave_quote_body)
+            .map_err(|_| VerifyError::MalformedReport)?;
+        if self.quote.len() < QUOTE_BODY_LEN || body[..] != self.quote[..QUOTE_BODY_LEN] {
+            return Err(VerifyError::Rejected("Quote does not match the report"));
+        }
+        if let Some(binding) = self.channel_binding.as_ref() {
+            let expected = digest::digest(&digest::SHA256, &binding[..]);
+            if expected.as_ref() != &verified.quote.report_data[32..] {
+                return Err(VerifyError::Rejected("Channel binding does not match REPORTDATA"));
+            }
+        }
+        Ok(verified)
+    }
+}
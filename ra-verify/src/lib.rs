@@ -0,0 +1,70 @@
+// Verification of SGX, TDX, and SEV-SNP evidence for relying parties that
+// cannot run the full SP, e.g. gateways or HSM firmware. Only needs `alloc`.
+#![no_std]
+extern crate alloc;
+#[macro_use]
+extern crate bitflags;
+
+pub mod quote;
+pub mod sigstruct;
+pub mod report;
+pub mod policy;
+pub mod tdx;
+pub mod qe_identity;
+pub mod tcb_info;
+pub mod pck;
+pub mod crl;
+pub mod sev_snp;
+pub mod rats;
+pub mod evidence;
+pub mod asn1;
+
+use crate::quote::QuoteBody;
+use crate::report::IasReport;
+#[cfg(feature = "ring-backend")]
+use crate::policy::Policy;
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    /// The signing certificate does not chain to a trust anchor.
+    InvalidCertificate,
+    BadSignature,
+    MalformedReport,
+    MalformedQuote,
+    /// An `evidence::Evidence` that is not valid DER or not the expected
+    /// structure.
+    MalformedEvidence,
+    /// Not a quote type this crate can verify, e.g. a TD quote that is not
+    /// version 4 or not signed with an ECDSA P-256 attestation key.
+    UnsupportedQuote,
+    /// A certificate or signature the evidence must come with is missing.
+    MissingEndorsement,
+    /// The evidence is authentic but rejected by the policy.
+    Rejected(&'static str),
+}
+
+/// Evidence that passed `verify_evidence`.
+pub struct VerifiedEvidence {
+    pub quote: QuoteBody,
+    pub report: IasReport,
+}
+
+/// Check an IAS attestation report end to end: its signature, the quote body
+/// it contains, and `policy`. `advisory_ids` is the comma-separated
+/// `Advisory-IDs` header, if any, and `time` the current time in seconds since
+/// the Unix epoch.
+#[cfg(feature = "ring-backend")]
+pub fn verify_evidence(body: &[u8],
+                       signature: &[u8],
+                       signing_cert_der: &[u8],
+                       trust_anchors: &[webpki::TrustAnchor],
+                       advisory_ids: Option<&str>,
+                       time: u64,
+                       policy: &Policy) -> Result<VerifiedEvidence, VerifyError> {
+    report::verify_signature(body, signature, signing_cert_der, trust_anchors, time)?;
+    let report = IasReport::from_json(body)?;
+    let quote = report.quote_body()?;
+    policy.check(&quote, &report, advisory_ids)?;
+    policy.check_report_age(&report, time)?;
+    Ok(VerifiedEvidence { quote, report })
+}
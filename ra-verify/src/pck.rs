@@ -0,0 +1,244 @@
+// The PCK certificate of ECDSA (DCAP) quotes identifies the platform to
+// Intel's Provisioning Certification Service (PCS). Its SGX extensions carry
+// the FMSPC and CA type that select the TCB Info and PCK CRL to fetch as
+// collateral, and the PPID, PCE ID, and TCB level the platform was registered
+// with. Nothing here is verified: extract, then verify the quote, e.g. with
+// `tdx::verify_td_quote` or Intel's QVL.
+use core::convert::TryInto;
+use alloc::vec::Vec;
+use crate::asn1::{self, TAG_INTEGER, TAG_OCTET_STRING, TAG_SEQUENCE};
+use crate::tdx::{self, Reader};
+use crate::VerifyError;
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_OID: u8 = 0x06;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+const TEE_TYPE_SGX: u32 = 0x00;
+const SGX_REPORT_BODY_LEN: usize = 384;
+// 1.2.840.113741.1.13.1, with the extensions below it numbered by their last
+// arc
+const SGX_EXTENSIONS_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf8, 0x4d, 0x01, 0x0d, 0x01];
+const PPID: u8 = 1;
+const TCB: u8 = 2;
+const PCE_ID: u8 = 3;
+const FMSPC: u8 = 4;
+const SGX_TYPE: u8 = 5;
+// Under TCB: 1 to 16 are the component SVNs
+const PCE_SVN: u8 = 17;
+const CPU_SVN: u8 = 18;
+
+/// The PCK certificate chain embedded in an SGX (version 3 or 4) or TD
+/// (version 4) quote with an ECDSA P-256 attestation key, as DER
+/// certificates with the PCK certificate first. Fails with
+/// `UnsupportedQuote` if the quote carries other certification data, e.g.
+/// an encrypted PPID for platforms whose PCK certificate is fetched by the
+/// verifier.
+pub fn pck_cert_chain(quote: &[u8]) -> Result<Vec<Vec<u8>>, VerifyError> {
+    let mut r = Reader(quote);
+    let header = r.take(tdx::HEADER_LEN)?;
+    let version = u16::from_le_bytes(header[0..2].try_into().unwrap());
+    let att_key_type = u16::from_le_bytes(header[2..4].try_into().unwrap());
+    let tee_type = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let body_len = match (version, tee_type) {
+        (3, _) | (4, TEE_TYPE_SGX) => SGX_REPORT_BODY_LEN,
+        (4, tdx::TEE_TYPE_TDX) => tdx::TD_REPORT_BODY_LEN,
+        _ => return Err(VerifyError::UnsupportedQuote),
+    };
+    if att_key_type != tdx::ATT_KEY_TYPE_ECDSA_P256 {
+        return Err(VerifyError::UnsupportedQuote);
+    }
+    r.take(body_len)?;
+
+    let sig_data_len = r.u32()? as usize;
+    let mut r = Reader(r.take(sig_data_len)?);
+    // Quote signature and attestation key
+    r.take(64 + 64)?;
+    // Version 4 wraps the QE report in certification data of its own
+    if version == 4 {
+        if r.u16()? != tdx::CERT_DATA_QE_REPORT {
+            return Err(VerifyError::UnsupportedQuote);
+        }
+        let cert_data_len = r.u32()? as usize;
+        r = Reader(r.take(cert_data_len)?);
+    }
+    // QE report and its signature
+    r.take(tdx::QE_REPORT_LEN + 64)?;
+    let qe_auth_data_len = r.u16()? as usize;
+    r.take(qe_auth_data_len)?;
+    if r.u16()? != tdx::CERT_DATA_PCK_CHAIN {
+        return Err(VerifyError::UnsupportedQuote);
+    }
+    let pck_chain_len = r.u32()? as usize;
+    let pck_chain = tdx::pem_certificates(r.take(pck_chain_len)?)?;
+    if pck_chain.is_empty() {
+        return Err(VerifyError::InvalidCertificate);
+    }
+    Ok(pck_chain)
+}
+
+/// The intermediate CA that issued a PCK certificate, which PCS's PCK CRL
+/// requests name with `ca=processor` or `ca=platform`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PckCa {
+    Processor,
+    Platform,
+}
+
+impl PckCa {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PckCa::Processor => "processor",
+            PckCa::Platform => "platform",
+        }
+    }
+}
+
+/// SGX extensions of a PCK certificate.
+#[derive(Debug, Clone)]
+pub struct PckExtensions {
+    pub ca: PckCa,
+    pub ppid: [u8; 16],
+    /// Family-model-stepping-platform-custom SKU, which selects the TCB Info
+    /// to fetch from PCS.
+    pub fmspc: [u8; 6],
+    pub pce_id: [u8; 2],
+    /// TCB level the certificate was issued for.
+    pub tcb_comp_svns: [u8; 16],
+    pub pce_svn: u16,
+    pub cpu_svn: [u8; 16],
+    /// 0 for Standard, 1 for Scalable.
+    pub sgx_type: u8,
+}
+
+impl PckExtensions {
+    /// Parse the PCK certificate, the first of `pck_cert_chain`.
+    pub fn from_certificate(cert_der: &[u8]) -> Result<Self, VerifyError> {
+        Self::parse(cert_der).ok_or(VerifyError::InvalidCertificate)
+    }
+
+    /// Parse the PCK certificate of `quote`.
+    pub fn from_quote(quote: &[u8]) -> Result<Self, VerifyError> {
+        Self::from_certificate(&pck_cert_chain(quote)?[0][..])
+    }
+
+    fn parse(cert_der: &[u8]) -> Option<Self> {
+        let mut d = cert_der;
+        let mut cert = asn1::expect(&mut d, TAG_SEQUENCE)?;
+        let mut tbs = asn1::expect(&mut cert, TAG_SEQUENCE)?;
+        if tbs.first() == Some(&TAG_VERSION) {
+            asn1::next(&mut tbs)?;
+        }
+        asn1::expect(&mut tbs, TAG_INTEGER)?;
+        asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        let issuer = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        // The CN is "Intel SGX PCK Processor CA" or "Intel SGX PCK Platform CA"
+        let ca = if contains(issuer, b"Processor") {
+            PckCa::Processor
+        } else if contains(issuer, b"Platform") {
+            PckCa::Platform
+        } else {
+            return None;
+        };
+        let sgx_extensions = loop {
+            match asn1::next(&mut tbs)? {
+                (TAG_EXTENSIONS, content, _) => {
+                    let mut content = content;
+                    break find_extension(asn1::expect(&mut content, TAG_SEQUENCE)?)?;
+                },
+                _ => continue,
+            }
+        };
+
+        let mut ext = Self {
+            ca,
+            ppid: [0u8; 16],
+            fmspc: [0u8; 6],
+            pce_id: [0u8; 2],
+            tcb_comp_svns: [0u8; 16],
+            pce_svn: 0,
+            cpu_svn: [0u8; 16],
+            sgx_type: 0,
+        };
+        let mut found = 0;
+        for (arc, tag, value) in sgx_entries(sgx_extensions)? {
+            match (arc, tag) {
+                (PPID, TAG_OCTET_STRING) => ext.ppid = value.try_into().ok()?,
+                (PCE_ID, TAG_OCTET_STRING) => ext.pce_id = value.try_into().ok()?,
+                (FMSPC, TAG_OCTET_STRING) => ext.fmspc = value.try_into().ok()?,
+                (SGX_TYPE, TAG_ENUMERATED) => ext.sgx_type = integer(value)? as u8,
+                (TCB, TAG_SEQUENCE) => {
+                    for (arc, tag, value) in sgx_entries(value)? {
+                        match (arc, tag) {
+                            (1..=16, TAG_INTEGER) =>
+                                ext.tcb_comp_svns[(arc - 1) as usize] = integer(value)? as u8,
+                            (PCE_SVN, TAG_INTEGER) => ext.pce_svn = integer(value)?,
+                            (CPU_SVN, TAG_OCTET_STRING) => ext.cpu_svn = value.try_into().ok()?,
+                            _ => continue,
+                        }
+                    }
+                },
+                _ => continue,
+            }
+            found += 1;
+        }
+        // PPID, TCB, PCE ID, FMSPC, and SGX type are mandatory
+        if found < 5 {
+            return None;
+        }
+        Some(ext)
+    }
+}
+
+/// Content of the SGX extensions' extnValue among `extensions`.
+fn find_extension(mut extensions: &[u8]) -> Option<&[u8]> {
+    while !extensions.is_empty() {
+        let mut extension = asn1::expect(&mut extensions, TAG_SEQUENCE)?;
+        let oid = asn1::expect(&mut extension, TAG_OID)?;
+        if extension.first() == Some(&TAG_BOOLEAN) {
+            asn1::next(&mut extension)?;
+        }
+        let mut value = asn1::expect(&mut extension, TAG_OCTET_STRING)?;
+        if oid == SGX_EXTENSIONS_OID {
+            return asn1::expect(&mut value, TAG_SEQUENCE);
+        }
+    }
+    None
+}
+
+/// (last OID arc, value tag, value content) of each `SEQUENCE { OID, value }`
+/// in `entries` whose OID is right below the SGX extensions', or right below
+/// their TCB for the TCB's entries.
+fn sgx_entries(mut entries: &[u8]) -> Option<Vec<(u8, u8, &[u8])>> {
+    let mut out = Vec::new();
+    while !entries.is_empty() {
+        let mut entry = asn1::expect(&mut entries, TAG_SEQUENCE)?;
+        let oid = asn1::expect(&mut entry, TAG_OID)?;
+        let (tag, value, _) = asn1::next(&mut entry)?;
+        if !oid.starts_with(SGX_EXTENSIONS_OID) {
+            continue;
+        }
+        let arcs = &oid[SGX_EXTENSIONS_OID.len()..];
+        let arc = match arcs {
+            [arc] => *arc,
+            [TCB, arc] => *arc,
+            _ => continue,
+        };
+        out.push((arc, tag, value));
+    }
+    Some(out)
+}
+
+/// A small non-negative DER INTEGER or ENUMERATED.
+fn integer(content: &[u8]) -> Option<u16> {
+    let skip = content.iter().take_while(|b| **b == 0).count();
+    if content.is_empty() || content[0] & 0x80 != 0 || content.len() - skip > 2 {
+        return None;
+    }
+    Some(content[skip..].iter().fold(0u16, |n, b| (n << 8) | *b as u16))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
@@ -0,0 +1,336 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::quote::{QuoteBody, AttributeFlags};
+use crate::qe_identity::QeIdentity;
+use crate::tcb_info::TcbInfo;
+use crate::crl::Crl;
+use crate::report::IasReport;
+use crate::tdx::TdReportBody;
+use crate::sev_snp::SnpReport;
+use crate::VerifyError;
+
+/// Which enclaves a relying party trusts. Unset fields are not checked.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    pub mr_enclave: Option<[u8; 32]>,
+    pub mr_signer: Option<[u8; 32]>,
+    pub isv_prod_id: Option<u16>,
+    pub min_isv_svn: Option<u16>,
+    pub allow_debug: bool,
+    /// CPUSVN baselines the platform must meet, e.g. to require a microcode
+    /// update that ISVSVN cannot reflect. Every requirement that applies to
+    /// the quote's platform family must be met.
+    pub min_cpu_svn: Vec<CpuSvnRequirement>,
+    /// Attributes the enclave must not have, e.g. `PROVISIONKEY`. DEBUG is
+    /// governed by `allow_debug` instead.
+    pub denied_attributes: AttributeFlags,
+    /// Quote statuses accepted besides "OK", e.g. "GROUP_OUT_OF_DATE".
+    pub quote_trust_options: Vec<String>,
+    /// If set, a status accepted through `quote_trust_options` is only
+    /// trusted when every advisory ID reported by IAS is in this list, and
+    /// never when the advisory IDs are not known, e.g. because the evidence
+    /// does not carry IAS's `Advisory-IDs` header.
+    pub allowed_advisory_ids: Option<Vec<String>>,
+    /// Reject reports made more than this many seconds before the time of
+    /// verification, so that archived or cached evidence cannot be used
+    /// indefinitely. Reports of any age are accepted if unset.
+    pub max_report_age_secs: Option<u64>,
+}
+
+impl Policy {
+    pub fn check(&self, quote: &QuoteBody, report: &IasReport,
+                 advisory_ids: Option<&str>) -> Result<(), VerifyError> {
+        let status = report.isv_enclave_quote_status.as_str();
+        if status != "OK" {
+            if !self.quote_trust_options.iter().any(|s| s == status) {
+                return Err(VerifyError::Rejected("Quote status not trusted"));
+            }
+            if let Some(allowed) = self.allowed_advisory_ids.as_ref() {
+                let advisory_ids = advisory_ids
+                    .ok_or(VerifyError::Rejected("Advisory IDs unknown"))?;
+                let all_allowed = advisory_ids
+                    .split(',')
+                    .map(|id| id.trim())
+                    .filter(|id| !id.is_empty())
+                    .all(|id| allowed.iter().any(|a| a == id));
+                if !all_allowed {
+                    return Err(VerifyError::Rejected("Advisory not allowed"));
+                }
+            }
+        }
+
+        if self.mr_enclave.is_some_and(|m| m != quote.mr_enclave) {
+            return Err(VerifyError::Rejected("MRENCLAVE mismatch"));
+        }
+        if self.mr_signer.is_some_and(|m| m != quote.mr_signer) {
+            return Err(VerifyError::Rejected("MRSIGNER mismatch"));
+        }
+        if self.isv_prod_id.is_some_and(|id| id != quote.isv_prod_id) {
+            return Err(VerifyError::Rejected("ISVPRODID mismatch"));
+        }
+        if self.min_isv_svn.is_some_and(|svn| quote.isv_svn < svn) {
+            return Err(VerifyError::Rejected("ISVSVN too low"));
+        }
+        if self.min_cpu_svn.iter().any(|r| r.applies_to(quote) && !r.is_met_by(quote)) {
+            return Err(VerifyError::Rejected("CPUSVN too low"));
+        }
+        if !self.allow_debug && quote.is_debug() {
+            return Err(VerifyError::Rejected("Enclave in debug mode"));
+        }
+        if quote.attribute_flags().intersects(self.denied_attributes - AttributeFlags::DEBUG) {
+            return Err(VerifyError::Rejected("Enclave has a denied attribute"));
+        }
+        Ok(())
+    }
+
+    /// Check `report` against `max_report_age_secs` at `time`, in seconds
+    /// since the Unix epoch.
+    pub fn check_report_age(&self, report: &IasReport, time: u64) -> Result<(), VerifyError> {
+        let max_age = match self.max_report_age_secs {
+            Some(max_age) => max_age,
+            None => return Ok(()),
+        };
+        let timestamp = report.timestamp_secs().ok_or(VerifyError::MalformedReport)?;
+        if time.saturating_sub(timestamp) > max_age {
+            return Err(VerifyError::Rejected("Report too old"));
+        }
+        Ok(())
+    }
+}
+
+/// A minimum CPUSVN for one platform family. CPUSVN is only comparable
+/// between platforms of the same family, so the baseline is compared
+/// component by component, like a TD's TEE TCB SVN, and only against quotes
+/// from the EPID groups of that family.
+#[derive(Debug, Clone, Default)]
+pub struct CpuSvnRequirement {
+    /// EPID groups of the platform family. Empty to apply to every quote.
+    pub epid_group_ids: Vec<[u8; 4]>,
+    pub min_cpu_svn: [u8; 16],
+}
+
+impl CpuSvnRequirement {
+    pub fn applies_to(&self, quote: &QuoteBody) -> bool {
+        self.epid_group_ids.is_empty() ||
+            self.epid_group_ids.contains(&quote.epid_group_id)
+    }
+
+    pub fn is_met_by(&self, quote: &QuoteBody) -> bool {
+        self.min_cpu_svn.iter().zip(quote.cpu_svn.iter()).all(|(min, svn)| svn >= min)
+    }
+}
+
+/// Which TDs a relying party trusts. Unset fields are not checked.
+#[derive(Debug, Clone, Default)]
+pub struct TdPolicy {
+    pub mr_td: Option<[u8; 48]>,
+    pub mr_config_id: Option<[u8; 48]>,
+    pub mr_owner: Option<[u8; 48]>,
+    pub rtmrs: [Option<[u8; 48]>; 4],
+    /// Minimum TEE TCB SVN, compared component by component.
+    pub min_tee_tcb_svn: Option<[u8; 16]>,
+    pub allow_debug: bool,
+    /// Intel's QE Identity for the TD quoting enclave, which the QE that
+    /// signed the quote's attestation key must match. Required: quotes are
+    /// rejected without it.
+    pub qe_identity: Option<QeIdentity>,
+    /// QE TCB statuses accepted besides "UpToDate", e.g. "OutOfDate".
+    pub qe_tcb_trust_options: Vec<String>,
+    /// Intel's TDX TCB Info for the FMSPC of the platforms, which gives the
+    /// platform's TCB status. Required: quotes are rejected without it.
+    pub tcb_info: Option<TcbInfo>,
+    /// Platform TCB statuses accepted besides "UpToDate", e.g.
+    /// "SWHardeningNeeded".
+    pub tcb_trust_options: Vec<String>,
+    /// Intel's PCK CRL of the PCK CA that issues the platforms' PCK
+    /// certificates. Required: quotes are rejected without it.
+    pub pck_crl: Option<Crl>,
+    /// Intel's SGX root CA CRL, which revokes PCK CAs. Required: quotes are
+    /// rejected without it.
+    pub root_ca_crl: Option<Crl>,
+    /// Reject a `qe_identity`, `tcb_info`, or CRL issued more than this many
+    /// seconds before the time of verification, even if Intel's next update
+    /// is not due yet. Collateral of any age is accepted if unset.
+    pub max_collateral_age_secs: Option<u64>,
+}
+
+impl TdPolicy {
+    /// Check collateral issued at `issue_date`, e.g. `QeIdentity::issue_date`,
+    /// against `max_collateral_age_secs` at `time`, in seconds since the Unix
+    /// epoch. Collateral without an issue date is rejected if there is a
+    /// maximum age.
+    pub fn check_collateral_age(&self, issue_date: Option<u64>, time: u64)
+        -> Result<(), VerifyError> {
+            let max_age = match self.max_collateral_age_secs {
+                Some(max_age) => max_age,
+                None => return Ok(()),
+            };
+            let issue_date = issue_date
+                .ok_or(VerifyError::Rejected("Collateral has no issue date"))?;
+            if time.saturating_sub(issue_date) > max_age {
+                return Err(VerifyError::Rejected("Collateral too old"));
+            }
+            Ok(())
+        }
+
+    pub fn check(&self, report: &TdReportBody) -> Result<(), VerifyError> {
+        if self.mr_td.is_some_and(|m| m[..] != report.mr_td[..]) {
+            return Err(VerifyError::Rejected("MRTD mismatch"));
+        }
+        if self.mr_config_id.is_some_and(|m| m[..] != report.mr_config_id[..]) {
+            return Err(VerifyError::Rejected("MRCONFIGID mismatch"));
+        }
+        if self.mr_owner.is_some_and(|m| m[..] != report.mr_owner[..]) {
+            return Err(VerifyError::Rejected("MROWNER mismatch"));
+        }
+        for (expected, rtmr) in self.rtmrs.iter().zip(report.rtmrs.iter()) {
+            if expected.is_some_and(|m| m[..] != rtmr[..]) {
+                return Err(VerifyError::Rejected("RTMR mismatch"));
+            }
+        }
+        if let Some(min) = self.min_tee_tcb_svn.as_ref() {
+            if min.iter().zip(report.tee_tcb_svn.iter()).any(|(min, svn)| svn < min) {
+                return Err(VerifyError::Rejected("TEE TCB SVN too low"));
+            }
+        }
+        if !self.allow_debug && report.is_debug() {
+            return Err(VerifyError::Rejected("TD in debug mode"));
+        }
+        Ok(())
+    }
+}
+
+/// Which SEV-SNP guests a relying party trusts. Unset fields are not checked.
+#[derive(Debug, Clone, Default)]
+pub struct SnpPolicy {
+    pub measurement: Option<[u8; 48]>,
+    pub host_data: Option<[u8; 32]>,
+    pub id_key_digest: Option<[u8; 48]>,
+    pub min_guest_svn: Option<u32>,
+    /// Minimum TCB the VCEK was derived from, compared component by component
+    /// in the layout of `SnpReport::reported_tcb`.
+    pub min_reported_tcb: Option<[u8; 8]>,
+    /// Highest VMPL the report may have been requested from, e.g. 0 to only
+    /// accept reports requested by the most privileged guest software.
+    pub max_vmpl: Option<u32>,
+    pub allow_debug: bool,
+}
+
+impl SnpPolicy {
+    pub fn check(&self, report: &SnpReport) -> Result<(), VerifyError> {
+        if self.measurement.is_some_and(|m| m[..] != report.measurement[..]) {
+            return Err(VerifyError::Rejected("MEASUREMENT mismatch"));
+        }
+        if self.host_data.is_some_and(|h| h != report.host_data) {
+            return Err(VerifyError::Rejected("HOST_DATA mismatch"));
+        }
+        if self.id_key_digest.is_some_and(|d| d[..] != report.id_key_digest[..]) {
+            return Err(VerifyError::Rejected("ID key mismatch"));
+        }
+        if self.min_guest_svn.is_some_and(|svn| report.guest_svn < svn) {
+            return Err(VerifyError::Rejected("Guest SVN too low"));
+        }
+        if let Some(min) = self.min_reported_tcb.as_ref() {
+            if min.iter().zip(report.reported_tcb.iter()).any(|(min, svn)| svn < min) {
+                return Err(VerifyError::Rejected("Reported TCB too low"));
+            }
+        }
+        if self.max_vmpl.is_some_and(|vmpl| report.vmpl > vmpl) {
+            return Err(VerifyError::Rejected("VMPL not allowed"));
+        }
+        if !self.allow_debug && report.is_debug() {
+            return Err(VerifyError::Rejected("Guest debugging allowed"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quote::QUOTE_BODY_LEN;
+
+    fn report(status: &str) -> IasReport {
+        let json = alloc::format!(r#"{{"id": "1", "timestamp": "2020-01-31T12:34:56.000000",
+            "version": 3, "isvEnclaveQuoteStatus": "{}", "isvEnclaveQuoteBody": ""}}"#, status);
+        IasReport::from_json(json.as_bytes()).unwrap()
+    }
+
+    fn policy() -> Policy {
+        Policy {
+            quote_trust_options: alloc::vec![String::from("GROUP_OUT_OF_DATE")],
+            allowed_advisory_ids: Some(alloc::vec![String::from("INTEL-SA-00334")]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accepts_allowed_advisories() {
+        let quote = QuoteBody::parse(&[0u8; QUOTE_BODY_LEN][..]).unwrap();
+        let report = report("GROUP_OUT_OF_DATE");
+        assert_eq!(policy().check(&quote, &report, Some("INTEL-SA-00334")), Ok(()));
+        assert_eq!(policy().check(&quote, &report, Some("INTEL-SA-00334, INTEL-SA-00219")),
+                   Err(VerifyError::Rejected("Advisory not allowed")));
+    }
+
+    #[test]
+    fn rejects_unknown_advisories() {
+        let quote = QuoteBody::parse(&[0u8; QUOTE_BODY_LEN][..]).unwrap();
+        assert_eq!(policy().check(&quote, &report("GROUP_OUT_OF_DATE"), None),
+                   Err(VerifyError::Rejected("Advisory IDs unknown")));
+        // Only statuses besides OK come with advisories
+        assert_eq!(policy().check(&quote, &report("OK"), None), Ok(()));
+        let any_advisory = Policy { allowed_advisory_ids: None, ..policy() };
+        assert_eq!(any_advisory.check(&quote, &report("GROUP_OUT_OF_DATE"), None), Ok(()));
+    }
+
+    fn quote_with_cpu_svn(gid: [u8; 4], cpu_svn: [u8; 16]) -> QuoteBody {
+        let mut quote = QuoteBody::parse(&[0u8; QUOTE_BODY_LEN][..]).unwrap();
+        quote.epid_group_id = gid;
+        quote.cpu_svn = cpu_svn;
+        quote
+    }
+
+    #[test]
+    fn requires_every_cpu_svn_component() {
+        let requirement = CpuSvnRequirement {
+            epid_group_ids: alloc::vec![[0x6a, 0x0b, 0, 0]],
+            min_cpu_svn: [4; 16],
+        };
+        let mut cpu_svn = [5u8; 16];
+        assert!(requirement.is_met_by(&quote_with_cpu_svn([0x6a, 0x0b, 0, 0], cpu_svn)));
+        // Higher components elsewhere do not make up for one below the floor
+        cpu_svn[7] = 3;
+        let quote = quote_with_cpu_svn([0x6a, 0x0b, 0, 0], cpu_svn);
+        assert!(requirement.applies_to(&quote));
+        assert!(!requirement.is_met_by(&quote));
+        let policy = Policy { min_cpu_svn: alloc::vec![requirement], ..Default::default() };
+        assert_eq!(policy.check(&quote, &report("OK"), None),
+                   Err(VerifyError::Rejected("CPUSVN too low")));
+    }
+
+    #[test]
+    fn applies_cpu_svn_floors_to_their_epid_groups() {
+        let requirement = CpuSvnRequirement {
+            epid_group_ids: alloc::vec![[0x6a, 0x0b, 0, 0], [0x6b, 0x0b, 0, 0]],
+            min_cpu_svn: [4; 16],
+        };
+        let quote = quote_with_cpu_svn([0x6c, 0x0b, 0, 0], [0; 16]);
+        assert!(!requirement.applies_to(&quote));
+        let policy = Policy { min_cpu_svn: alloc::vec![requirement], ..Default::default() };
+        assert_eq!(policy.check(&quote, &report("OK"), None), Ok(()));
+    }
+
+    #[test]
+    fn applies_cpu_svn_floors_without_epid_groups_to_every_quote() {
+        let requirement = CpuSvnRequirement {
+            epid_group_ids: Vec::new(),
+            min_cpu_svn: [4; 16],
+        };
+        for gid in &[[0u8; 4], [0x6a, 0x0b, 0, 0], [0xff; 4]] {
+            assert!(requirement.applies_to(&quote_with_cpu_svn(*gid, [0; 16])));
+        }
+        assert!(!requirement.is_met_by(&quote_with_cpu_svn([0; 4], [3; 16])));
+        assert!(requirement.is_met_by(&quote_with_cpu_svn([0; 4], [4; 16])));
+    }
+}
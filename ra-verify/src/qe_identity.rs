@@ -0,0 +1,321 @@
+// Intel's QE Identity collateral, which says which Quoting Enclave may sign
+// the attestation keys of ECDSA (DCAP) quotes. Without it, any enclave that
+// holds a PCK key could certify an attestation key. Fetched from the PCS,
+// e.g. /tdx/certification/v4/qe/identity:
+//
+//   {"enclaveIdentity":{"id":"TD_QE","version":2,"issueDate":"...",
+//    "nextUpdate":"...","miscselect":"...","miscselectMask":"...",
+//    "attributes":"...","attributesMask":"...","mrsigner":"...",
+//    "isvprodid":2,"tcbLevels":[{"tcb":{"isvsvn":4},"tcbStatus":"UpToDate"}]},
+//   "signature":"..."}
+//
+// The signature is over the bytes of the enclaveIdentity value as served, and
+// is made by the TCB signing certificate of the SGX-Enclave-Identity-Issuer-Chain
+// response header.
+//
+// The same format describes application enclaves in policy files signed by
+// their operator, see `split_signed` and `QeIdentity::parse`. Such files may
+// also pin "mrenclave", which Intel's QE Identity does not have.
+use core::convert::TryInto;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use serde::Deserialize;
+#[cfg(feature = "ring-backend")]
+use untrusted::Input;
+#[cfg(feature = "ring-backend")]
+use crate::asn1::ecdsa_sig_to_der;
+use crate::quote::QuoteBody;
+use crate::report::parse_timestamp;
+#[cfg(feature = "ring-backend")]
+use crate::tdx::pem_certificates;
+use crate::VerifyError;
+
+#[cfg(feature = "ring-backend")]
+static SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+];
+
+#[derive(Deserialize)]
+struct Signed<'a> {
+    signature: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Body {
+    id: String,
+    #[serde(default)]
+    issue_date: Option<String>,
+    next_update: String,
+    miscselect: String,
+    miscselect_mask: String,
+    attributes: String,
+    attributes_mask: String,
+    mrsigner: String,
+    #[serde(default)]
+    mrenclave: Option<String>,
+    isvprodid: u16,
+    tcb_levels: Vec<TcbLevel>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TcbLevel {
+    tcb: Tcb,
+    tcb_status: String,
+}
+
+#[derive(Deserialize)]
+struct Tcb {
+    isvsvn: u16,
+}
+
+/// Verified QE Identity: the expected identity of the Quoting Enclave and the
+/// TCB status of each of its ISVSVNs.
+#[derive(Debug, Clone)]
+pub struct QeIdentity {
+    /// E.g. "QE" for SGX or "TD_QE" for TDX.
+    pub id: String,
+    pub misc_select: u32,
+    pub misc_select_mask: u32,
+    pub attributes: [u8; 16],
+    pub attributes_mask: [u8; 16],
+    pub mr_signer: [u8; 32],
+    pub mr_enclave: Option<[u8; 32]>,
+    pub isv_prod_id: u16,
+    /// (minimum ISVSVN, status), highest ISVSVN first, as ordered by Intel.
+    pub tcb_levels: Vec<(u16, String)>,
+    /// When the collateral was issued, in seconds since the Unix epoch, if it
+    /// says, e.g. for `TdPolicy::max_collateral_age_secs`.
+    pub issue_date: Option<u64>,
+    /// When Intel publishes the next version, in seconds since the Unix
+    /// epoch. The collateral is not accepted after that.
+    pub next_update: u64,
+}
+
+impl QeIdentity {
+    /// Check the signature of `json` with the first certificate of
+    /// `issuer_chain_pem`, which must chain to `trust_anchors` (Intel's SGX
+    /// root CA) at `time`, and that the collateral has not expired.
+    #[cfg(feature = "ring-backend")]
+    pub fn verify(json: &[u8],
+                  issuer_chain_pem: &[u8],
+                  trust_anchors: &[webpki::TrustAnchor],
+                  time: u64) -> Result<Self, VerifyError> {
+        let body = verify_signed(json, "enclaveIdentity", issuer_chain_pem, trust_anchors, time)?;
+        Self::parse(body, time)
+    }
+
+    /// Parse the enclaveIdentity value of a signed identity, whose signature
+    /// the caller has checked, and check that it has not expired at `time`.
+    pub fn parse(body: &[u8], time: u64) -> Result<Self, VerifyError> {
+        let body: Body = serde_json::from_slice(body)
+            .map_err(|_| VerifyError::MalformedEvidence)?;
+        let next_update = parse_time(&body.next_update).ok_or(VerifyError::MalformedEvidence)?;
+        if time >= next_update {
+            return Err(VerifyError::Rejected("Enclave identity expired"));
+        }
+        let issue_date = match body.issue_date.as_ref() {
+            Some(date) => Some(parse_time(date).ok_or(VerifyError::MalformedEvidence)?),
+            None => None,
+        };
+        let mut misc_select = [0u8; 4];
+        let mut misc_select_mask = [0u8; 4];
+        let mut attributes = [0u8; 16];
+        let mut attributes_mask = [0u8; 16];
+        let mut mr_signer = [0u8; 32];
+        hex(&body.miscselect, &mut misc_select[..])?;
+        hex(&body.miscselect_mask, &mut misc_select_mask[..])?;
+        hex(&body.attributes, &mut attributes[..])?;
+        hex(&body.attributes_mask, &mut attributes_mask[..])?;
+        hex(&body.mrsigner, &mut mr_signer[..])?;
+        let mr_enclave = match body.mrenclave.as_ref() {
+            Some(m) => {
+                let mut mr_enclave = [0u8; 32];
+                hex(m, &mut mr_enclave[..])?;
+                Some(mr_enclave)
+            },
+            None => None,
+        };
+        Ok(Self {
+            id: body.id,
+            misc_select: u32::from_be_bytes(misc_select),
+            misc_select_mask: u32::from_be_bytes(misc_select_mask),
+            attributes,
+            attributes_mask,
+            mr_signer,
+            mr_enclave,
+            isv_prod_id: body.isvprodid,
+            tcb_levels: body.tcb_levels.into_iter()
+                .map(|l| (l.tcb.isvsvn, l.tcb_status))
+                .collect(),
+            issue_date,
+            next_update,
+        })
+    }
+
+    /// Check the QE's report body (`sgx_report_body_t`) against the identity
+    /// and return the TCB status of its ISVSVN, e.g. "UpToDate" or
+    /// "OutOfDate". Revoked QEs and ISVSVNs below every TCB level are
+    /// rejected.
+    pub fn check<'a>(&'a self, qe_report: &[u8]) -> Result<&'a str, VerifyError> {
+        if qe_report.len() < 384 {
+            return Err(VerifyError::MalformedQuote);
+        }
+        self.check_fields(u32::from_le_bytes(qe_report[16..20].try_into().unwrap()),
+                          &qe_report[48..64],
+                          &qe_report[64..96],
+                          &qe_report[128..160],
+                          u16::from_le_bytes(qe_report[256..258].try_into().unwrap()),
+                          u16::from_le_bytes(qe_report[258..260].try_into().unwrap()))
+    }
+
+    /// Same as `check`, for the enclave that produced `quote`.
+    pub fn check_quote<'a>(&'a self, quote: &QuoteBody) -> Result<&'a str, VerifyError> {
+        self.check_fields(quote.misc_select, &quote.attributes[..], &quote.mr_enclave[..],
+                          &quote.mr_signer[..], quote.isv_prod_id, quote.isv_svn)
+    }
+
+    fn check_fields<'a>(&'a self,
+                        misc_select: u32,
+                        attributes: &[u8],
+                        mr_enclave: &[u8],
+                        mr_signer: &[u8],
+                        isv_prod_id: u16,
+                        isv_svn: u16) -> Result<&'a str, VerifyError> {
+        if misc_select & self.misc_select_mask != self.misc_select & self.misc_select_mask {
+            return Err(VerifyError::Rejected("MISCSELECT mismatch"));
+        }
+        let attributes_match = attributes.iter()
+            .zip(self.attributes.iter().zip(self.attributes_mask.iter()))
+            .all(|(a, (expected, mask))| a & mask == expected & mask);
+        if !attributes_match {
+            return Err(VerifyError::Rejected("ATTRIBUTES mismatch"));
+        }
+        if self.mr_enclave.is_some_and(|m| m[..] != *mr_enclave) {
+            return Err(VerifyError::Rejected("MRENCLAVE mismatch"));
+        }
+        if mr_signer != &self.mr_signer[..] {
+            return Err(VerifyError::Rejected("MRSIGNER mismatch"));
+        }
+        if isv_prod_id != self.isv_prod_id {
+            return Err(VerifyError::Rejected("ISVPRODID mismatch"));
+        }
+        match self.tcb_levels.iter().find(|(min_svn, _)| isv_svn >= *min_svn) {
+            Some((_, status)) if status != "Revoked" => Ok(status.as_str()),
+            Some(_) => Err(VerifyError::Rejected("Enclave revoked")),
+            None => Err(VerifyError::Rejected("ISVSVN too low")),
+        }
+    }
+}
+
+/// Split a signed identity document into the bytes of its enclaveIdentity
+/// value, as signed, and its hex-decoded signature.
+pub fn split_signed(json: &[u8]) -> Result<(&[u8], Vec<u8>), VerifyError> {
+    split_signed_value(json, "enclaveIdentity")
+}
+
+// Same as `split_signed`, for Intel collateral whose signed value is `key`,
+// e.g. "tcbInfo"
+pub(crate) fn split_signed_value<'a>(json: &'a [u8], key: &str)
+    -> Result<(&'a [u8], Vec<u8>), VerifyError> {
+        let signed: Signed = serde_json::from_slice(json)
+            .map_err(|_| VerifyError::MalformedEvidence)?;
+        if !signed.signature.len().is_multiple_of(2) {
+            return Err(VerifyError::MalformedEvidence);
+        }
+        let mut signature = vec![0u8; signed.signature.len() / 2];
+        hex(signed.signature, &mut signature[..])?;
+        let body = raw_value(json, key).ok_or(VerifyError::MalformedEvidence)?;
+        Ok((body, signature))
+    }
+
+// The signed `key` value of Intel collateral, once its signature is checked
+// with the first certificate of `issuer_chain_pem`, which must chain to
+// `trust_anchors` at `time`
+#[cfg(feature = "ring-backend")]
+pub(crate) fn verify_signed<'a>(json: &'a [u8],
+                                key: &str,
+                                issuer_chain_pem: &[u8],
+                                trust_anchors: &[webpki::TrustAnchor],
+                                time: u64) -> Result<&'a [u8], VerifyError> {
+    let chain = pem_certificates(issuer_chain_pem)?;
+    let signing_cert = chain.first().ok_or(VerifyError::MissingEndorsement)?;
+    let intermediates: Vec<&[u8]> = chain[1..].iter().map(|c| &c[..]).collect();
+    let cert = webpki::EndEntityCert::from(Input::from(&signing_cert[..]))
+        .map_err(|_| VerifyError::InvalidCertificate)?;
+    cert.verify_is_valid_tls_server_cert(SIG_ALGS,
+                                         &webpki::TLSServerTrustAnchors(trust_anchors),
+                                         &intermediates[..],
+                                         webpki::Time::from_seconds_since_unix_epoch(time))
+        .map_err(|_| VerifyError::InvalidCertificate)?;
+
+    let (body, signature) = split_signed_value(json, key)?;
+    if signature.len() != 64 {
+        return Err(VerifyError::MalformedEvidence);
+    }
+    cert.verify_signature(&webpki::ECDSA_P256_SHA256,
+                          Input::from(body),
+                          Input::from(&ecdsa_sig_to_der(&signature[..])[..]))
+        .map_err(|_| VerifyError::BadSignature)?;
+    Ok(body)
+}
+
+pub(crate) fn hex(s: &str, out: &mut [u8]) -> Result<(), VerifyError> {
+    let bytes = s.as_bytes();
+    if bytes.len() != out.len() * 2 {
+        return Err(VerifyError::MalformedEvidence);
+    }
+    let digit = |b: u8| (b as char).to_digit(16).ok_or(VerifyError::MalformedEvidence);
+    for (o, pair) in out.iter_mut().zip(bytes.chunks(2)) {
+        *o = (digit(pair[0])? * 16 + digit(pair[1])?) as u8;
+    }
+    Ok(())
+}
+
+// The bytes of the object value of top-level `key` in `json`, as they were
+// signed
+fn raw_value<'a>(json: &'a [u8], key: &str) -> Option<&'a [u8]> {
+    let mut pattern = Vec::with_capacity(key.len() + 2);
+    pattern.push(b'"');
+    pattern.extend_from_slice(key.as_bytes());
+    pattern.push(b'"');
+    let key_at = json.windows(pattern.len()).position(|w| w == &pattern[..])?;
+    let rest = &json[(key_at + pattern.len())..];
+    let start = rest.iter().position(|b| *b == b'{')?;
+    if rest[..start].iter().any(|b| *b != b':' && !b.is_ascii_whitespace()) {
+        return None;
+    }
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, b) in rest[start..].iter().enumerate() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {},
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&rest[start..(start + i + 1)]);
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+// Seconds since the Unix epoch of a UTC time like "2024-05-01T12:00:00Z"
+pub(crate) fn parse_time(s: &str) -> Option<u64> {
+    parse_timestamp(s.get(..19)?)
+}
@@ -0,0 +1,109 @@
+use core::convert::TryInto;
+
+/// Length of the quote up to and including the report body. This is also what
+/// IAS returns as `isvEnclaveQuoteBody`.
+pub const QUOTE_BODY_LEN: usize = 432;
+
+/// Fields of a quote (`sgx_quote_t`) header and the enclave report body it
+/// contains. The signature that follows is not parsed.
+#[derive(Clone)]
+pub struct QuoteBody {
+    pub version: u16,
+    pub sign_type: u16,
+    pub epid_group_id: [u8; 4],
+    pub qe_svn: u16,
+    pub pce_svn: u16,
+    pub xeid: u32,
+    pub basename: [u8; 32],
+    pub cpu_svn: [u8; 16],
+    pub misc_select: u32,
+    pub attributes: [u8; 16],
+    pub mr_enclave: [u8; 32],
+    pub mr_signer: [u8; 32],
+    pub isv_prod_id: u16,
+    pub isv_svn: u16,
+    pub report_data: [u8; 64],
+}
+
+impl QuoteBody {
+    /// Returns None if `quote` is shorter than `QUOTE_BODY_LEN`.
+    pub fn parse(quote: &[u8]) -> Option<Self> {
+        if quote.len() < QUOTE_BODY_LEN {
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes(quote[i..(i + 2)].try_into().unwrap());
+        let u32_at = |i: usize| u32::from_le_bytes(quote[i..(i + 4)].try_into().unwrap());
+        let mut report_data = [0u8; 64];
+        report_data.copy_from_slice(&quote[368..432]);
+        Some(Self {
+            version: u16_at(0),
+            sign_type: u16_at(2),
+            epid_group_id: quote[4..8].try_into().unwrap(),
+            qe_svn: u16_at(8),
+            pce_svn: u16_at(10),
+            xeid: u32_at(12),
+            basename: quote[16..48].try_into().unwrap(),
+            cpu_svn: quote[48..64].try_into().unwrap(),
+            misc_select: u32_at(64),
+            attributes: quote[96..112].try_into().unwrap(),
+            mr_enclave: quote[112..144].try_into().unwrap(),
+            mr_signer: quote[176..208].try_into().unwrap(),
+            isv_prod_id: u16_at(304),
+            isv_svn: u16_at(306),
+            report_data,
+        })
+    }
+
+    /// Whether the enclave was launched in debug mode, in which its memory is
+    /// readable by the host.
+    pub fn is_debug(&self) -> bool {
+        self.attribute_flags().contains(AttributeFlags::DEBUG)
+    }
+
+    /// The flags half of ATTRIBUTES. Bits this crate has no name for are
+    /// dropped; they are reserved and must be zero.
+    pub fn attribute_flags(&self) -> AttributeFlags {
+        AttributeFlags::from_bits_truncate(u64::from_le_bytes(
+                self.attributes[..8].try_into().unwrap()))
+    }
+
+    /// The XFRM half of ATTRIBUTES: the XSAVE features (XCR0) enabled for
+    /// the enclave.
+    pub fn xfrm(&self) -> u64 {
+        u64::from_le_bytes(self.attributes[8..].try_into().unwrap())
+    }
+
+    pub fn misc_select_flags(&self) -> MiscSelect {
+        MiscSelect::from_bits_truncate(self.misc_select)
+    }
+}
+
+bitflags! {
+    /// Flags of an enclave's ATTRIBUTES, as set in its SIGSTRUCT.
+    #[derive(Default)]
+    pub struct AttributeFlags: u64 {
+        const INIT = 0x01;
+        /// The host can read and write the enclave's memory.
+        const DEBUG = 0x02;
+        const MODE64BIT = 0x04;
+        /// The enclave can get the provisioning key, e.g. it is the PvE or
+        /// the QE.
+        const PROVISIONKEY = 0x10;
+        /// The enclave can get the launch key, i.e. it is a launch enclave.
+        const EINITTOKENKEY = 0x20;
+        const CET = 0x40;
+        /// Key separation and sharing: KSS fields of the report are in use.
+        const KSS = 0x80;
+    }
+}
+
+bitflags! {
+    /// Extended features reported in an enclave's SSA frames on an AEX.
+    #[derive(Default)]
+    pub struct MiscSelect: u32 {
+        /// Page fault and general protection exception info.
+        const EXINFO = 0x01;
+        /// Control protection exception info, with CET.
+        const CPINFO = 0x02;
+    }
+}
@@ -0,0 +1,205 @@
+// Roles of the RATS architecture (RFC 9334) over the TEEs this crate knows. An
+// `Attester` produces evidence bound to caller-chosen report data, a
+// `Verifier` checks evidence of one TEE type against its endorsements and
+// turns it into `Claims`, and an `AppraisalPolicy` decides on the claims the
+// same way for every TEE type.
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+#[cfg(feature = "ring-backend")]
+use crate::policy::{Policy, TdPolicy, SnpPolicy};
+#[cfg(feature = "ring-backend")]
+use crate::report::{self, IasReport};
+#[cfg(feature = "ring-backend")]
+use crate::{tdx, sev_snp};
+use crate::VerifyError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeeType {
+    Sgx,
+    Tdx,
+    SevSnp,
+}
+
+/// What verified evidence says about the attester.
+#[derive(Debug, Clone)]
+pub struct Claims {
+    pub tee: TeeType,
+    /// MRENCLAVE, MRTD, or the SEV-SNP launch measurement.
+    pub measurement: Vec<u8>,
+    pub report_data: [u8; 64],
+    pub debug: bool,
+}
+
+/// Produces evidence inside a TEE.
+pub trait Attester {
+    type Error;
+
+    fn tee_type(&self) -> TeeType;
+
+    /// Evidence whose report data is `report_data`, e.g. a hash of a nonce
+    /// and a public key.
+    fn evidence(&mut self, report_data: &[u8; 64]) -> Result<Vec<u8>, Self::Error>;
+}
+
+/// Appraises evidence of one TEE type. `endorsements` are the certificates
+/// or signatures that vouch for the evidence and do not travel inside it;
+/// see each implementation. `time` is in seconds since the Unix epoch.
+pub trait Verifier {
+    fn tee_type(&self) -> TeeType;
+
+    fn verify(&self, evidence: &[u8], endorsements: &[&[u8]], time: u64)
+        -> Result<Claims, VerifyError>;
+}
+
+/// SGX evidence as IAS reports: `evidence` is the report body and
+/// `endorsements` are the report signature, the signing certificate (DER),
+/// and optionally IAS's `Advisory-IDs` header. Without the header, a policy
+/// with `allowed_advisory_ids` only trusts the quote status "OK".
+#[cfg(feature = "ring-backend")]
+pub struct IasReportVerifier<'a> {
+    pub trust_anchors: &'a [webpki::TrustAnchor<'a>],
+    pub policy: Policy,
+}
+
+#[cfg(feature = "ring-backend")]
+impl<'a> Verifier for IasReportVerifier<'a> {
+    fn tee_type(&self) -> TeeType {
+        TeeType::Sgx
+    }
+
+    fn verify(&self, evidence: &[u8], endorsements: &[&[u8]], time: u64)
+        -> Result<Claims, VerifyError> {
+        let (signature, cert_der, advisory_ids) = match endorsements {
+            [signature, cert_der] => (signature, cert_der, None),
+            [signature, cert_der, advisory_ids] => {
+                let advisory_ids = core::str::from_utf8(advisory_ids)
+                    .map_err(|_| VerifyError::MalformedReport)?;
+                (signature, cert_der, Some(advisory_ids))
+            },
+            _ => return Err(VerifyError::MissingEndorsement),
+        };
+        report::verify_signature(evidence, signature, cert_der, self.trust_anchors, time)?;
+        let report = IasReport::from_json(evidence)?;
+        let quote = report.quote_body()?;
+        self.policy.check(&quote, &report, advisory_ids)?;
+        self.policy.check_report_age(&report, time)?;
+        Ok(Claims {
+            tee: TeeType::Sgx,
+            measurement: quote.mr_enclave.to_vec(),
+            report_data: quote.report_data,
+            debug: quote.is_debug(),
+        })
+    }
+}
+
+/// TD quotes. They embed their PCK certificate chain, and the QE Identity,
+/// TCB Info, and CRLs come with the policy, so no endorsements are needed.
+#[cfg(feature = "ring-backend")]
+pub struct TdQuoteVerifier<'a> {
+    pub trust_anchors: &'a [webpki::TrustAnchor<'a>],
+    pub policy: TdPolicy,
+}
+
+#[cfg(feature = "ring-backend")]
+impl<'a> Verifier for TdQuoteVerifier<'a> {
+    fn tee_type(&self) -> TeeType {
+        TeeType::Tdx
+    }
+
+    fn verify(&self, evidence: &[u8], _endorsements: &[&[u8]], time: u64)
+        -> Result<Claims, VerifyError> {
+        let report = tdx::verify_td_quote(evidence, self.trust_anchors, time, &self.policy)?;
+        Ok(Claims {
+            tee: TeeType::Tdx,
+            measurement: report.mr_td.to_vec(),
+            report_data: report.report_data,
+            debug: report.is_debug(),
+        })
+    }
+}
+
+/// SEV-SNP attestation reports: `endorsements` are the VCEK and the ASK (DER).
+#[cfg(feature = "ring-backend")]
+pub struct SnpReportVerifier<'a> {
+    pub trust_anchors: &'a [webpki::TrustAnchor<'a>],
+    pub policy: SnpPolicy,
+}
+
+#[cfg(feature = "ring-backend")]
+impl<'a> Verifier for SnpReportVerifier<'a> {
+    fn tee_type(&self) -> TeeType {
+        TeeType::SevSnp
+    }
+
+    fn verify(&self, evidence: &[u8], endorsements: &[&[u8]], time: u64)
+        -> Result<Claims, VerifyError> {
+        let (vcek_der, ask_der) = match endorsements {
+            [vcek_der, ask_der] => (vcek_der, ask_der),
+            _ => return Err(VerifyError::MissingEndorsement),
+        };
+        let report = sev_snp::verify_snp_report(evidence, vcek_der, ask_der,
+                                                self.trust_anchors, time, &self.policy)?;
+        Ok(Claims {
+            tee: TeeType::SevSnp,
+            measurement: report.measurement.to_vec(),
+            report_data: report.report_data,
+            debug: report.is_debug(),
+        })
+    }
+}
+
+/// Decision on claims that applies to every TEE type, on top of the
+/// TEE-specific policy of each verifier.
+#[derive(Debug, Clone, Default)]
+pub struct AppraisalPolicy {
+    /// Accepted (TEE type, measurement) pairs. A TEE type without any entry
+    /// accepts every measurement its verifier's policy accepts.
+    pub reference_values: Vec<(TeeType, Vec<u8>)>,
+    pub allow_debug: bool,
+}
+
+impl AppraisalPolicy {
+    pub fn appraise(&self, claims: &Claims) -> Result<(), VerifyError> {
+        let mut references = self.reference_values.iter()
+            .filter(|(tee, _)| *tee == claims.tee)
+            .peekable();
+        if references.peek().is_some() &&
+            !references.any(|(_, measurement)| *measurement == claims.measurement) {
+                return Err(VerifyError::Rejected("Measurement not in reference values"));
+            }
+        if !self.allow_debug && claims.debug {
+            return Err(VerifyError::Rejected("Attester in debug mode"));
+        }
+        Ok(())
+    }
+}
+
+/// One entry point for a mixed fleet: dispatches evidence to the verifier of
+/// its TEE type and appraises the resulting claims.
+pub struct VerifierSet<'a> {
+    verifiers: Vec<Box<dyn Verifier + 'a>>,
+    pub policy: AppraisalPolicy,
+}
+
+impl<'a> VerifierSet<'a> {
+    pub fn new(policy: AppraisalPolicy) -> Self {
+        Self { verifiers: Vec::new(), policy }
+    }
+
+    /// Replaces the verifier registered for the same TEE type, if any.
+    pub fn add(&mut self, verifier: Box<dyn Verifier + 'a>) {
+        let tee = verifier.tee_type();
+        self.verifiers.retain(|v| v.tee_type() != tee);
+        self.verifiers.push(verifier);
+    }
+
+    pub fn verify(&self, tee: TeeType, evidence: &[u8], endorsements: &[&[u8]], time: u64)
+        -> Result<Claims, VerifyError> {
+        let verifier = self.verifiers.iter()
+            .find(|v| v.tee_type() == tee)
+            .ok_or(VerifyError::UnsupportedQuote)?;
+        let claims = verifier.verify(evidence, endorsements, time)?;
+        self.policy.appraise(&claims)?;
+        Ok(claims)
+    }
+}
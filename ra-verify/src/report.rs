@@ -0,0 +1,152 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::Deserialize;
+#[cfg(feature = "ring-backend")]
+use untrusted::Input;
+use crate::quote::{QuoteBody, QUOTE_BODY_LEN};
+use crate::VerifyError;
+
+#[cfg(feature = "ring-backend")]
+static SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+];
+
+/// Body of an IAS attestation verification report (API version 3).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IasReport {
+    pub id: String,
+    pub timestamp: String,
+    pub version: u16,
+    pub isv_enclave_quote_status: String,
+    pub isv_enclave_quote_body: String,
+    pub revocation_reason: Option<u32>,
+    pub pse_manifest_status: Option<String>,
+    pub pse_manifest_hash: Option<String>,
+    pub platform_info_blob: Option<String>,
+    pub nonce: Option<String>,
+    pub epid_pseudonym: Option<String>,
+}
+
+impl IasReport {
+    pub fn from_json(body: &[u8]) -> Result<Self, VerifyError> {
+        serde_json::from_slice(body).map_err(|_| VerifyError::MalformedReport)
+    }
+
+    pub fn quote_body(&self) -> Result<QuoteBody, VerifyError> {
+        let quote: Vec<u8> = base64::decode(&self.isv_enclave_quote_body)
+            .map_err(|_| VerifyError::MalformedReport)?;
+        if quote.len() != QUOTE_BODY_LEN {
+            return Err(VerifyError::MalformedQuote);
+        }
+        QuoteBody::parse(&quote[..]).ok_or(VerifyError::MalformedQuote)
+    }
+
+    /// `timestamp` in seconds since the Unix epoch, see `parse_timestamp`.
+    pub fn timestamp_secs(&self) -> Option<u64> {
+        parse_timestamp(&self.timestamp)
+    }
+}
+
+/// Seconds since the Unix epoch of a report timestamp, which IAS writes in
+/// UTC without a zone, e.g. "2020-01-31T12:34:56.000000". The fraction of a
+/// second is dropped. None if `timestamp` is not of that form.
+pub fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let field = |s: &str, start: usize, len: usize| -> Option<u64> {
+        let digits = s.get(start..(start + len))?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    let ts = timestamp;
+    let separators = [(4, b'-'), (7, b'-'), (10, b'T'), (13, b':'), (16, b':')];
+    if ts.len() < 19 || separators.iter().any(|(i, c)| ts.as_bytes()[*i] != *c) {
+        return None;
+    }
+    let (year, month, day) = (field(ts, 0, 4)?, field(ts, 5, 2)?, field(ts, 8, 2)?);
+    let (hour, minute, second) = (field(ts, 11, 2)?, field(ts, 14, 2)?, field(ts, 17, 2)?);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) ||
+        hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+    match ts.get(19..)? {
+        "" => {},
+        fraction if fraction.starts_with('.') && fraction.len() > 1 &&
+            fraction[1..].bytes().all(|b| b.is_ascii_digit()) => {},
+        _ => return None,
+    }
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+// Howard Hinnant's days-from-civil algorithm, for dates since 1970
+pub(crate) fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Check that `signature` (the decoded `X-IASReport-Signature` header) is a
+/// signature over `body` by the certificate `signing_cert_der`, and that the
+/// certificate was issued by one of `trust_anchors` and is valid at `time`.
+#[cfg(feature = "ring-backend")]
+pub fn verify_signature(body: &[u8],
+                        signature: &[u8],
+                        signing_cert_der: &[u8],
+                        trust_anchors: &[webpki::TrustAnchor],
+                        time: u64) -> Result<(), VerifyError> {
+    let cert = webpki::EndEntityCert::from(Input::from(signing_cert_der))
+        .map_err(|_| VerifyError::InvalidCertificate)?;
+    cert.verify_is_valid_tls_server_cert(SIG_ALGS,
+                                         &webpki::TLSServerTrustAnchors(trust_anchors),
+                                         &[],
+                                         webpki::Time::from_seconds_since_unix_epoch(time))
+        .map_err(|_| VerifyError::InvalidCertificate)?;
+    cert.verify_signature(&webpki::RSA_PKCS1_2048_8192_SHA256,
+                          Input::from(body), Input::from(signature))
+        .map_err(|_| VerifyError::BadSignature)
+}
+
+/// Use a DER certificate, e.g. Intel's attestation report signing CA, as a
+/// trust anchor.
+#[cfg(feature = "std")]
+pub fn trust_anchor_from_der(cert_der: &[u8]) -> Result<webpki::TrustAnchor, VerifyError> {
+    webpki::trust_anchor_util::cert_der_as_trust_anchor(Input::from(cert_der))
+        .map_err(|_| VerifyError::InvalidCertificate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ias_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00"), Some(0));
+        assert_eq!(parse_timestamp("2020-01-31T12:34:56.000000"), Some(1_580_474_096));
+        // The fraction is dropped, not rounded
+        assert_eq!(parse_timestamp("2020-01-31T12:34:56.999999"), Some(1_580_474_096));
+    }
+
+    #[test]
+    fn counts_leap_days() {
+        assert_eq!(parse_timestamp("2020-02-29T00:00:00"), Some(1_582_934_400));
+        assert_eq!(parse_timestamp("2020-03-01T00:00:00"), Some(1_582_934_400 + 86400));
+        // 2000 is a leap year, 2100 is not
+        assert_eq!(parse_timestamp("2000-02-29T00:00:00"), Some(951_782_400));
+        assert_eq!(parse_timestamp("2100-02-28T00:00:00"), Some(4_107_456_000));
+        assert_eq!(parse_timestamp("2100-03-01T00:00:00"), Some(4_107_456_000 + 86400));
+    }
+
+    #[test]
+    fn rejects_malformed_timestamps() {
+        for timestamp in &["", "2020-01-31", "2020-01-31 12:34:56", "2020-01-31T12:34:56Z",
+                           "2020-01-31T12:34:56.", "2020-01-31T12:34:5x", "2020-13-01T00:00:00",
+                           "2020-01-32T00:00:00", "2020-01-31T24:00:00", "1969-12-31T23:59:59",
+                           "+020-01-31T12:34:56"] {
+            assert_eq!(parse_timestamp(timestamp), None, "{}", timestamp);
+        }
+    }
+}
@@ -0,0 +1,408 @@
+// Verification of AMD SEV-SNP attestation reports (ABI version 2 and later).
+// The report is signed with the chip's VCEK (ECDSA P-384), which the verifier
+// fetches from AMD's key distribution service together with the ASK. The VCEK
+// is checked up to the ARK, AMD's root for the processor family, which is
+// passed as a trust anchor.
+//
+// AMD signs the ASK and the VCEK with RSA-PSS (SHA-384, 48-byte salt), and the
+// ARK and ASK keys themselves are rsassaPss keys, which webpki does not accept
+// as RSA keys. The two links of the chain are therefore checked here with ring
+// directly; the certificates are only parsed as far as that needs.
+use core::convert::TryInto;
+#[cfg(feature = "ring-backend")]
+use alloc::vec::Vec;
+#[cfg(feature = "ring-backend")]
+use ring::signature;
+#[cfg(feature = "ring-backend")]
+use untrusted::Input;
+#[cfg(feature = "ring-backend")]
+use crate::asn1::{self, TAG_INTEGER, TAG_SEQUENCE};
+#[cfg(feature = "ring-backend")]
+use crate::policy::SnpPolicy;
+#[cfg(feature = "ring-backend")]
+use crate::VerifyError;
+#[cfg(feature = "ring-backend")]
+use crate::asn1::ecdsa_sig_to_der;
+#[cfg(feature = "ring-backend")]
+use crate::report;
+
+pub const SNP_REPORT_LEN: usize = 0x4a0;
+#[cfg(feature = "ring-backend")]
+const SIGNED_LEN: usize = 0x2a0;
+#[cfg(feature = "ring-backend")]
+const SIG_ALGO_ECDSA_P384_SHA384: u32 = 1;
+// Guest policy bit allowing the hypervisor to debug the guest
+const POLICY_DEBUG: u64 = 1 << 19;
+
+#[cfg(feature = "ring-backend")]
+const TAG_BIT_STRING: u8 = 0x03;
+#[cfg(feature = "ring-backend")]
+const TAG_NULL: u8 = 0x05;
+#[cfg(feature = "ring-backend")]
+const TAG_OID: u8 = 0x06;
+#[cfg(feature = "ring-backend")]
+const TAG_UTC_TIME: u8 = 0x17;
+#[cfg(feature = "ring-backend")]
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+#[cfg(feature = "ring-backend")]
+const TAG_VERSION: u8 = 0xa0;
+#[cfg(feature = "ring-backend")]
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+#[cfg(feature = "ring-backend")]
+const OID_MGF1: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x08];
+#[cfg(feature = "ring-backend")]
+const OID_RSASSA_PSS: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a];
+#[cfg(feature = "ring-backend")]
+const OID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+#[cfg(feature = "ring-backend")]
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+#[cfg(feature = "ring-backend")]
+const OID_SECP384R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+
+/// Fields of an SEV-SNP `ATTESTATION_REPORT` that are covered by its signature.
+#[derive(Clone)]
+pub struct SnpReport {
+    pub version: u32,
+    pub guest_svn: u32,
+    pub policy: u64,
+    pub family_id: [u8; 16],
+    pub image_id: [u8; 16],
+    pub vmpl: u32,
+    pub signature_algo: u32,
+    /// Platform TCB version the report was made at: boot loader, TEE,
+    /// reserved (4 bytes), SNP firmware, and microcode SVNs.
+    pub current_tcb: [u8; 8],
+    pub report_data: [u8; 64],
+    pub measurement: [u8; 48],
+    pub host_data: [u8; 32],
+    pub id_key_digest: [u8; 48],
+    pub author_key_digest: [u8; 48],
+    pub report_id: [u8; 32],
+    /// TCB version the VCEK was derived from.
+    pub reported_tcb: [u8; 8],
+    pub chip_id: [u8; 64],
+}
+
+impl SnpReport {
+    /// Returns None if `report` is shorter than `SNP_REPORT_LEN`.
+    pub fn parse(report: &[u8]) -> Option<Self> {
+        if report.len() < SNP_REPORT_LEN {
+            return None;
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(report[i..(i + 4)].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(report[i..(i + 8)].try_into().unwrap());
+        let a48 = |i: usize| -> [u8; 48] {
+            let mut a = [0u8; 48];
+            a.copy_from_slice(&report[i..(i + 48)]);
+            a
+        };
+        let a64 = |i: usize| -> [u8; 64] {
+            let mut a = [0u8; 64];
+            a.copy_from_slice(&report[i..(i + 64)]);
+            a
+        };
+        Some(Self {
+            version: u32_at(0x00),
+            guest_svn: u32_at(0x04),
+            policy: u64_at(0x08),
+            family_id: report[0x10..0x20].try_into().unwrap(),
+            image_id: report[0x20..0x30].try_into().unwrap(),
+            vmpl: u32_at(0x30),
+            signature_algo: u32_at(0x34),
+            current_tcb: report[0x38..0x40].try_into().unwrap(),
+            report_data: a64(0x50),
+            measurement: a48(0x90),
+            host_data: report[0xc0..0xe0].try_into().unwrap(),
+            id_key_digest: a48(0xe0),
+            author_key_digest: a48(0x110),
+            report_id: report[0x140..0x160].try_into().unwrap(),
+            reported_tcb: report[0x180..0x188].try_into().unwrap(),
+            chip_id: a64(0x1a0),
+        })
+    }
+
+    /// Whether the guest policy lets the hypervisor debug the guest, in which
+    /// case the host can read its memory.
+    pub fn is_debug(&self) -> bool {
+        self.policy & POLICY_DEBUG != 0
+    }
+}
+
+/// Check an SEV-SNP report end to end: the VCEK (`vcek_der`) issued through
+/// `ask_der` by one of `trust_anchors` (the ARK) and valid at `time`, the
+/// report signature, and `policy`.
+#[cfg(feature = "ring-backend")]
+pub fn verify_snp_report(report: &[u8],
+                         vcek_der: &[u8],
+                         ask_der: &[u8],
+                         trust_anchors: &[webpki::TrustAnchor],
+                         time: u64,
+                         policy: &SnpPolicy) -> Result<SnpReport, VerifyError> {
+    let parsed = SnpReport::parse(report).ok_or(VerifyError::MalformedQuote)?;
+    if parsed.signature_algo != SIG_ALGO_ECDSA_P384_SHA384 {
+        return Err(VerifyError::UnsupportedQuote);
+    }
+
+    let ask = AmdCert::parse(ask_der).ok_or(VerifyError::InvalidCertificate)?;
+    let vcek = AmdCert::parse(vcek_der).ok_or(VerifyError::InvalidCertificate)?;
+    let ark = trust_anchors.iter()
+        .find(|anchor| anchor.subject == ask.issuer)
+        .ok_or(VerifyError::InvalidCertificate)?;
+    let ark_key = rsa_public_key(ark.spki).ok_or(VerifyError::InvalidCertificate)?;
+    ask.check_issued_by(ark.subject, ark_key, time)?;
+    let ask_key = rsa_public_key(ask.spki).ok_or(VerifyError::InvalidCertificate)?;
+    vcek.check_issued_by(ask.subject, ask_key, time)?;
+    let vcek_key = p384_public_key(vcek.spki).ok_or(VerifyError::InvalidCertificate)?;
+
+    // r and s are stored as 72-byte little-endian integers
+    let mut sig = Vec::with_capacity(96);
+    sig.extend(report[SIGNED_LEN..(SIGNED_LEN + 48)].iter().rev());
+    sig.extend(report[(SIGNED_LEN + 72)..(SIGNED_LEN + 120)].iter().rev());
+    signature::verify(&signature::ECDSA_P384_SHA384_ASN1,
+                      Input::from(vcek_key),
+                      Input::from(&report[..SIGNED_LEN]),
+                      Input::from(&ecdsa_sig_to_der(&sig[..])[..]))
+        .map_err(|_| VerifyError::BadSignature)?;
+
+    policy.check(&parsed)?;
+    Ok(parsed)
+}
+
+// The parts of an ASK or VCEK that the chain check needs
+#[cfg(feature = "ring-backend")]
+struct AmdCert<'a> {
+    // The whole TBSCertificate, which the signature covers
+    tbs: &'a [u8],
+    // Content of the issuer and subject Names and of the
+    // SubjectPublicKeyInfo, as in `webpki::TrustAnchor`
+    issuer: &'a [u8],
+    subject: &'a [u8],
+    spki: &'a [u8],
+    not_before: u64,
+    not_after: u64,
+    signature_alg: &'a [u8],
+    signature: &'a [u8],
+}
+
+#[cfg(feature = "ring-backend")]
+impl<'a> AmdCert<'a> {
+    fn parse(der: &'a [u8]) -> Option<Self> {
+        let mut d = der;
+        let mut cert = asn1::expect(&mut d, TAG_SEQUENCE)?;
+        let (mut tbs, tbs_tlv) = match asn1::next(&mut cert)? {
+            (TAG_SEQUENCE, content, tlv) => (content, tlv),
+            _ => return None,
+        };
+        let signature_alg = asn1::expect(&mut cert, TAG_SEQUENCE)?;
+        let signature = bit_string(asn1::expect(&mut cert, TAG_BIT_STRING)?)?;
+        if !d.is_empty() || !cert.is_empty() {
+            return None;
+        }
+
+        if tbs.first() == Some(&TAG_VERSION) {
+            asn1::next(&mut tbs)?;
+        }
+        asn1::expect(&mut tbs, TAG_INTEGER)?;
+        // The signed copy of the signature algorithm must match
+        if asn1::expect(&mut tbs, TAG_SEQUENCE)? != signature_alg {
+            return None;
+        }
+        let issuer = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        let mut validity = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        let not_before = cert_time(&mut validity)?;
+        let not_after = cert_time(&mut validity)?;
+        let subject = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        let spki = asn1::expect(&mut tbs, TAG_SEQUENCE)?;
+        Some(Self {
+            tbs: tbs_tlv,
+            issuer,
+            subject,
+            spki,
+            not_before,
+            not_after,
+            signature_alg,
+            signature,
+        })
+    }
+
+    /// Check that the certificate was issued by `issuer_subject`, whose
+    /// RSAPublicKey is `issuer_key`, and is valid at `time`.
+    fn check_issued_by(&self, issuer_subject: &[u8], issuer_key: &[u8], time: u64)
+        -> Result<(), VerifyError> {
+            if self.issuer != issuer_subject || !is_rsa_pss_sha384(self.signature_alg) ||
+                time < self.not_before || time > self.not_after {
+                    return Err(VerifyError::InvalidCertificate);
+                }
+            signature::verify(&signature::RSA_PSS_2048_8192_SHA384,
+                              Input::from(issuer_key),
+                              Input::from(self.tbs),
+                              Input::from(self.signature))
+                .map_err(|_| VerifyError::InvalidCertificate)
+        }
+}
+
+// Content of a BIT STRING without unused bits
+#[cfg(feature = "ring-backend")]
+fn bit_string(content: &[u8]) -> Option<&[u8]> {
+    match content.split_first()? {
+        (0, bits) => Some(bits),
+        _ => None,
+    }
+}
+
+// Seconds since the Unix epoch of the next UTCTime or GeneralizedTime, which
+// certificates write in UTC to the second
+#[cfg(feature = "ring-backend")]
+fn cert_time(data: &mut &[u8]) -> Option<u64> {
+    let number = |digits: &[u8]| -> Option<u64> {
+        if !digits.iter().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(digits.iter().fold(0, |n, b| n * 10 + (b - b'0') as u64))
+    };
+    let (year, time) = match asn1::next(data)? {
+        (TAG_UTC_TIME, time, _) if time.len() == 13 => match number(&time[..2])? {
+            year if year < 50 => (2000 + year, &time[2..]),
+            year => (1900 + year, &time[2..]),
+        },
+        (TAG_GENERALIZED_TIME, time, _) if time.len() == 15 => (number(&time[..4])?, &time[4..]),
+        _ => return None,
+    };
+    if time[10] != b'Z' {
+        return None;
+    }
+    let (month, day) = (number(&time[0..2])?, number(&time[2..4])?);
+    let (hour, minute, second) = (number(&time[4..6])?, number(&time[6..8])?, number(&time[8..10])?);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) ||
+        hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+    Some(report::days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+// Whether `alg` (an AlgorithmIdentifier's content) is RSASSA-PSS with SHA-384,
+// MGF1 with SHA-384, and a 48-byte salt, which is how AMD signs certificates
+#[cfg(feature = "ring-backend")]
+fn is_rsa_pss_sha384(alg: &[u8]) -> bool {
+    let parse = |mut alg: &[u8]| -> Option<()> {
+        if asn1::expect(&mut alg, TAG_OID)? != OID_RSASSA_PSS {
+            return None;
+        }
+        let mut params = asn1::expect(&mut alg, TAG_SEQUENCE)?;
+        let mut hash = asn1::expect(&mut params, 0xa0)?;
+        if hash_algorithm(asn1::expect(&mut hash, TAG_SEQUENCE)?)? != OID_SHA384 {
+            return None;
+        }
+        let mut mgf = asn1::expect(&mut params, 0xa1)?;
+        let mut mgf = asn1::expect(&mut mgf, TAG_SEQUENCE)?;
+        if asn1::expect(&mut mgf, TAG_OID)? != OID_MGF1 ||
+            hash_algorithm(asn1::expect(&mut mgf, TAG_SEQUENCE)?)? != OID_SHA384 {
+                return None;
+            }
+        let mut salt = asn1::expect(&mut params, 0xa2)?;
+        if asn1::expect(&mut salt, TAG_INTEGER)? != [48] {
+            return None;
+        }
+        // The trailer field may only be the default, 1
+        if !params.is_empty() {
+            let mut trailer = asn1::expect(&mut params, 0xa3)?;
+            if asn1::expect(&mut trailer, TAG_INTEGER)? != [1] {
+                return None;
+            }
+        }
+        if !params.is_empty() || !alg.is_empty() {
+            return None;
+        }
+        Some(())
+    };
+    parse(alg).is_some()
+}
+
+// OID of a hash AlgorithmIdentifier, whose parameters are absent or NULL
+#[cfg(feature = "ring-backend")]
+fn hash_algorithm(mut alg: &[u8]) -> Option<&[u8]> {
+    let oid = asn1::expect(&mut alg, TAG_OID)?;
+    match alg {
+        [] | [TAG_NULL, 0] => Some(oid),
+        _ => None,
+    }
+}
+
+// RSAPublicKey of the content of a SubjectPublicKeyInfo, whether its
+// algorithm is rsaEncryption or rsassaPss as on AMD's ARK and ASK
+#[cfg(feature = "ring-backend")]
+fn rsa_public_key(mut spki: &[u8]) -> Option<&[u8]> {
+    let mut alg = asn1::expect(&mut spki, TAG_SEQUENCE)?;
+    let oid = asn1::expect(&mut alg, TAG_OID)?;
+    if oid != OID_RSA_ENCRYPTION && oid != OID_RSASSA_PSS {
+        return None;
+    }
+    bit_string(asn1::expect(&mut spki, TAG_BIT_STRING)?)
+}
+
+// Uncompressed point of the content of a SubjectPublicKeyInfo with a P-384
+// key, which the VCEK has
+#[cfg(feature = "ring-backend")]
+fn p384_public_key(mut spki: &[u8]) -> Option<&[u8]> {
+    let mut alg = asn1::expect(&mut spki, TAG_SEQUENCE)?;
+    if asn1::expect(&mut alg, TAG_OID)? != OID_EC_PUBLIC_KEY ||
+        asn1::expect(&mut alg, TAG_OID)? != OID_SECP384R1 {
+            return None;
+        }
+    bit_string(asn1::expect(&mut spki, TAG_BIT_STRING)?)
+}
+
+// The fixtures mirror AMD's Milan chain, which could not be fetched from the
+// KDS here: RSA-4096 ARK and ASK with rsassaPss keys, certificates signed with
+// RSA-PSS SHA-384 and a 48-byte salt, a P-384 VCEK, and a version 2 report
+// signed by it. They were generated with pyca/cryptography and check out with
+// `openssl verify`.
+#[cfg(all(test, feature = "ring-backend"))]
+mod tests {
+    use super::*;
+
+    const ARK: &[u8] = include_bytes!("../data/snp/ark.der");
+    const ASK: &[u8] = include_bytes!("../data/snp/ask.der");
+    const VCEK: &[u8] = include_bytes!("../data/snp/vcek.der");
+    const REPORT: &[u8] = include_bytes!("../data/snp/report.bin");
+    // 2025-10-09, within the VCEK's validity (2023 to 2030)
+    const TIME: u64 = 1_760_000_000;
+
+    fn verify(report: &[u8], vcek: &[u8], ask: &[u8], time: u64) -> Result<SnpReport, VerifyError> {
+        let ark = AmdCert::parse(ARK).unwrap();
+        let anchors = [webpki::TrustAnchor {
+            subject: ark.subject,
+            spki: ark.spki,
+            name_constraints: None,
+        }];
+        verify_snp_report(report, vcek, ask, &anchors[..], time, &SnpPolicy::default())
+    }
+
+    #[test]
+    fn verifies_rsa_pss_chain() {
+        let report = verify(REPORT, VCEK, ASK, TIME).unwrap();
+        assert_eq!(report.version, 2);
+        assert!(!report.is_debug());
+    }
+
+    #[test]
+    fn rejects_vcek_outside_validity() {
+        // 2022-12-31T23:59:59Z and 2030-01-01T00:00:01Z
+        assert_eq!(verify(REPORT, VCEK, ASK, 1_672_531_199).err(),
+                   Some(VerifyError::InvalidCertificate));
+        assert_eq!(verify(REPORT, VCEK, ASK, 1_893_456_001).err(),
+                   Some(VerifyError::InvalidCertificate));
+    }
+
+    #[test]
+    fn rejects_swapped_certificates() {
+        assert_eq!(verify(REPORT, ASK, VCEK, TIME).err(), Some(VerifyError::InvalidCertificate));
+    }
+
+    #[test]
+    fn rejects_tampered_report() {
+        let mut report = REPORT.to_vec();
+        report[0x50] ^= 1;
+        assert_eq!(verify(&report[..], VCEK, ASK, TIME).err(), Some(VerifyError::BadSignature));
+    }
+}
@@ -0,0 +1,111 @@
+// The fields of an enclave's SIGSTRUCT that its quotes are checked against,
+// so that a verifier can read the SIGSTRUCT of the enclave it expects on any
+// host, without the SGX crates. The signature is not verified: the SIGSTRUCT
+// is configured by the verifier itself, and the CPU checks it at EINIT.
+use core::convert::TryInto;
+use crate::quote::AttributeFlags;
+
+/// Length of a SIGSTRUCT, as written by `sgx_sign` or `sgxs-sign`.
+pub const SIGSTRUCT_LEN: usize = 1808;
+
+/// ATTRIBUTES as in a SIGSTRUCT, the flags and XFRM halves apart.
+#[derive(Clone, Copy, Default)]
+pub struct SigstructAttributes {
+    pub flags: AttributeFlags,
+    pub xfrm: u64,
+}
+
+#[derive(Clone)]
+pub struct Sigstruct {
+    pub vendor: u32,
+    /// Date of signing, as BCD yyyymmdd.
+    pub date: u32,
+    /// Public key of the signer, little-endian. MRSIGNER is its SHA-256.
+    pub modulus: [u8; 384],
+    pub exponent: u32,
+    pub miscselect: u32,
+    pub miscmask: u32,
+    pub attributes: SigstructAttributes,
+    pub attributemask: SigstructAttributes,
+    /// MRENCLAVE of the enclave.
+    pub enclavehash: [u8; 32],
+    pub isvprodid: u16,
+    pub isvsvn: u16,
+}
+
+impl Sigstruct {
+    /// Returns None if `sigstruct` is not `SIGSTRUCT_LEN` long.
+    pub fn parse(sigstruct: &[u8]) -> Option<Self> {
+        if sigstruct.len() != SIGSTRUCT_LEN {
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes(sigstruct[i..(i + 2)].try_into().unwrap());
+        let u32_at = |i: usize| u32::from_le_bytes(sigstruct[i..(i + 4)].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(sigstruct[i..(i + 8)].try_into().unwrap());
+        let attributes_at = |i: usize| SigstructAttributes {
+            flags: AttributeFlags::from_bits_truncate(u64_at(i)),
+            xfrm: u64_at(i + 8),
+        };
+        let mut modulus = [0u8; 384];
+        modulus.copy_from_slice(&sigstruct[128..512]);
+        Some(Self {
+            vendor: u32_at(16),
+            date: u32_at(20),
+            modulus,
+            exponent: u32_at(512),
+            miscselect: u32_at(900),
+            miscmask: u32_at(904),
+            attributes: attributes_at(928),
+            attributemask: attributes_at(944),
+            enclavehash: sigstruct[960..992].try_into().unwrap(),
+            isvprodid: u16_at(1024),
+            isvsvn: u16_at(1026),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The loopback enclave's SIGSTRUCT, signed in the layout of sgxs-sign
+    const SIGSTRUCT: &[u8] = include_bytes!("../../ra-sp/data/loopback/enclave.sig");
+
+    #[test]
+    fn parses_fields() {
+        let sigstruct = Sigstruct::parse(SIGSTRUCT).unwrap();
+        assert_eq!(sigstruct.vendor, 0);
+        assert_eq!(sigstruct.date, 0x2020_1016);
+        assert_eq!(&sigstruct.modulus[..8], &[0x97, 0x79, 0x22, 0xb8, 0x81, 0xeb, 0xb5, 0xa3]);
+        assert_eq!(sigstruct.exponent, 3);
+        assert_eq!(sigstruct.miscselect, 0);
+        assert_eq!(sigstruct.miscmask, 0xffff_ffff);
+        assert_eq!(sigstruct.enclavehash, [
+            0xba, 0x6d, 0xd3, 0x79, 0x1b, 0x05, 0x45, 0x4c, 0xd6, 0x24, 0x74, 0x12, 0x2b, 0x85, 0xdb,
+            0xa7, 0x8f, 0xe9, 0x83, 0x5a, 0x0c, 0x57, 0x89, 0xe5, 0x2c, 0x43, 0xeb, 0x26, 0x81, 0x4a,
+            0x7a, 0x64,
+        ]);
+        assert_eq!(sigstruct.isvprodid, 1);
+        assert_eq!(sigstruct.isvsvn, 2);
+    }
+
+    #[test]
+    fn splits_attributes() {
+        let sigstruct = Sigstruct::parse(SIGSTRUCT).unwrap();
+        assert_eq!(sigstruct.attributes.flags, AttributeFlags::MODE64BIT);
+        assert_eq!(sigstruct.attributes.xfrm, 0x3);
+        // Every flag but DEBUG must match, i.e. the enclave is not a debug one
+        let mask = sigstruct.attributemask.flags;
+        assert!(mask.contains(AttributeFlags::INIT | AttributeFlags::MODE64BIT));
+        assert!(!mask.contains(AttributeFlags::DEBUG));
+        assert_eq!(sigstruct.attributemask.xfrm, 0xffff_ffff_fff9_ff1b);
+    }
+
+    #[test]
+    fn rejects_wrong_length() {
+        assert!(Sigstruct::parse(&SIGSTRUCT[..(SIGSTRUCT_LEN - 1)]).is_none());
+        let mut longer = [0u8; SIGSTRUCT_LEN + 1];
+        longer[..SIGSTRUCT_LEN].copy_from_slice(SIGSTRUCT);
+        assert!(Sigstruct::parse(&longer[..]).is_none());
+    }
+}
@@ -0,0 +1,191 @@
+// Intel's TCB Info collateral, which says how up to date the TCB of each
+// platform model (FMSPC) is at each level of its component SVNs. The PCK
+// certificate of a quote names the platform's FMSPC and the SGX TCB level it
+// was issued for, and a TD quote carries the TDX TCB level it was made at;
+// the first TCB level both reach gives the platform's status. Fetched from
+// the PCS, e.g. /tdx/certification/v4/tcb?fmspc=...:
+//
+//   {"tcbInfo":{"id":"TDX","version":3,"issueDate":"...","nextUpdate":"...",
+//    "fmspc":"...","pceId":"0000","tcbLevels":[{"tcb":{"sgxtcbcomponents":
+//    [{"svn":2},...],"pcesvn":11,"tdxtcbcomponents":[{"svn":5},...]},
+//    "tcbStatus":"UpToDate","advisoryIDs":["INTEL-SA-00837"]},...]},
+//   "signature":"..."}
+//
+// Only version 3, the format of the v4 PCS API, is supported. As with the QE
+// Identity, the signature is over the bytes of the tcbInfo value as served,
+// made by the certificate of the TCB-Info-Issuer-Chain response header. The
+// TDX module identities of version 3 are not evaluated: all 16 TDX
+// components are compared, which can only give a lower level than Intel's
+// evaluation.
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::Deserialize;
+use crate::pck::PckExtensions;
+use crate::qe_identity::{hex, parse_time};
+#[cfg(feature = "ring-backend")]
+use crate::qe_identity::verify_signed;
+use crate::VerifyError;
+
+const VERSION: u32 = 3;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Body {
+    id: String,
+    version: u32,
+    #[serde(default)]
+    issue_date: Option<String>,
+    next_update: String,
+    fmspc: String,
+    pce_id: String,
+    tcb_levels: Vec<Level>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Level {
+    tcb: Tcb,
+    tcb_status: String,
+    #[serde(default, rename = "advisoryIDs")]
+    advisory_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Tcb {
+    sgxtcbcomponents: Vec<Component>,
+    pcesvn: u16,
+    #[serde(default)]
+    tdxtcbcomponents: Option<Vec<Component>>,
+}
+
+#[derive(Deserialize)]
+struct Component {
+    svn: u8,
+}
+
+/// One TCB level of a `TcbInfo`.
+#[derive(Debug, Clone)]
+pub struct TcbLevel {
+    pub sgx_tcb_comp_svns: [u8; 16],
+    pub pce_svn: u16,
+    /// TDX component SVNs, for TDX TCB Info only.
+    pub tdx_tcb_comp_svns: Option<[u8; 16]>,
+    /// E.g. "UpToDate", "SWHardeningNeeded", "OutOfDate", or "Revoked".
+    pub status: String,
+    pub advisory_ids: Vec<String>,
+}
+
+/// Verified TCB Info for one FMSPC.
+#[derive(Debug, Clone)]
+pub struct TcbInfo {
+    /// "SGX" or "TDX".
+    pub id: String,
+    pub fmspc: [u8; 6],
+    pub pce_id: [u8; 2],
+    /// Highest TCB level first, as ordered by Intel.
+    pub tcb_levels: Vec<TcbLevel>,
+    /// When the collateral was issued, in seconds since the Unix epoch, if it
+    /// says.
+    pub issue_date: Option<u64>,
+    /// When Intel publishes the next version, in seconds since the Unix
+    /// epoch. The collateral is not accepted after that.
+    pub next_update: u64,
+}
+
+impl TcbInfo {
+    /// Check the signature of `json` with the first certificate of
+    /// `issuer_chain_pem`, which must chain to `trust_anchors` (Intel's SGX
+    /// root CA) at `time`, and that the collateral has not expired.
+    #[cfg(feature = "ring-backend")]
+    pub fn verify(json: &[u8],
+                  issuer_chain_pem: &[u8],
+                  trust_anchors: &[webpki::TrustAnchor],
+                  time: u64) -> Result<Self, VerifyError> {
+        let body = verify_signed(json, "tcbInfo", issuer_chain_pem, trust_anchors, time)?;
+        Self::parse(body, time)
+    }
+
+    /// Parse the tcbInfo value of signed TCB Info, whose signature the caller
+    /// has checked, and check that it has not expired at `time`.
+    pub fn parse(body: &[u8], time: u64) -> Result<Self, VerifyError> {
+        let body: Body = serde_json::from_slice(body)
+            .map_err(|_| VerifyError::MalformedEvidence)?;
+        if body.version != VERSION {
+            return Err(VerifyError::MalformedEvidence);
+        }
+        let next_update = parse_time(&body.next_update).ok_or(VerifyError::MalformedEvidence)?;
+        if time >= next_update {
+            return Err(VerifyError::Rejected("TCB Info expired"));
+        }
+        let issue_date = match body.issue_date.as_ref() {
+            Some(date) => Some(parse_time(date).ok_or(VerifyError::MalformedEvidence)?),
+            None => None,
+        };
+        let mut fmspc = [0u8; 6];
+        let mut pce_id = [0u8; 2];
+        hex(&body.fmspc, &mut fmspc[..])?;
+        hex(&body.pce_id, &mut pce_id[..])?;
+        let mut tcb_levels = Vec::with_capacity(body.tcb_levels.len());
+        for level in body.tcb_levels {
+            let tdx_tcb_comp_svns = match level.tcb.tdxtcbcomponents.as_ref() {
+                Some(components) => Some(svns(components)?),
+                None => None,
+            };
+            tcb_levels.push(TcbLevel {
+                sgx_tcb_comp_svns: svns(&level.tcb.sgxtcbcomponents)?,
+                pce_svn: level.tcb.pcesvn,
+                tdx_tcb_comp_svns,
+                status: level.tcb_status,
+                advisory_ids: level.advisory_ids,
+            });
+        }
+        Ok(Self {
+            id: body.id,
+            fmspc,
+            pce_id,
+            tcb_levels,
+            issue_date,
+            next_update,
+        })
+    }
+
+    /// The TCB level of the platform with the PCK certificate extensions
+    /// `pck`, and for TDX the TEE TCB SVN of its TD quote. Fails if the
+    /// collateral is for another platform model, if no level is reached, or
+    /// if the level is revoked.
+    pub fn tcb_level(&self, pck: &PckExtensions, tee_tcb_svn: Option<&[u8; 16]>)
+        -> Result<&TcbLevel, VerifyError> {
+            if pck.fmspc != self.fmspc || pck.pce_id != self.pce_id {
+                return Err(VerifyError::Rejected("TCB Info for another platform"));
+            }
+            let level = self.tcb_levels.iter().find(|level| {
+                at_least(&pck.tcb_comp_svns, &level.sgx_tcb_comp_svns) &&
+                    pck.pce_svn >= level.pce_svn &&
+                    match (tee_tcb_svn, level.tdx_tcb_comp_svns.as_ref()) {
+                        (Some(svn), Some(min)) => at_least(svn, min),
+                        (None, _) => true,
+                        (Some(_), None) => false,
+                    }
+            }).ok_or(VerifyError::Rejected("TCB level not supported"))?;
+            if level.status == "Revoked" {
+                return Err(VerifyError::Rejected("Platform TCB revoked"));
+            }
+            Ok(level)
+        }
+}
+
+fn svns(components: &[Component]) -> Result<[u8; 16], VerifyError> {
+    if components.len() != 16 {
+        return Err(VerifyError::MalformedEvidence);
+    }
+    let mut svns = [0u8; 16];
+    for (svn, component) in svns.iter_mut().zip(components.iter()) {
+        *svn = component.svn;
+    }
+    Ok(svns)
+}
+
+// Whether every component of `svns` is at least that of `min`
+fn at_least(svns: &[u8; 16], min: &[u8; 16]) -> bool {
+    svns.iter().zip(min.iter()).all(|(svn, min)| svn >= min)
+}
@@ -0,0 +1,262 @@
+// Verification of Intel TDX TD quotes (version 4, ECDSA-256 attestation key).
+// The quote is checked up to Intel's root CA: the PCK certificate chain
+// embedded in the quote, the QE report signed by the PCK key, the attestation
+// key the QE report commits to, and the quote signature made with that key.
+// The QE is checked against Intel's QE Identity collateral, the TCB level of
+// the platform against Intel's TCB Info, and the PCK certificate and the PCK
+// CA that issued it against Intel's CRLs, all of which the policy must have.
+use core::convert::TryInto;
+use alloc::vec::Vec;
+#[cfg(feature = "ring-backend")]
+use ring::digest;
+#[cfg(feature = "ring-backend")]
+use ring::signature;
+#[cfg(feature = "ring-backend")]
+use untrusted::Input;
+#[cfg(feature = "ring-backend")]
+use crate::policy::TdPolicy;
+#[cfg(feature = "ring-backend")]
+use crate::pck::PckExtensions;
+use crate::VerifyError;
+#[cfg(feature = "ring-backend")]
+use crate::asn1::ecdsa_sig_to_der;
+#[cfg(feature = "ring-backend")]
+use crate::crl::{self, Certificate};
+
+#[cfg(feature = "ring-backend")]
+const QUOTE_VERSION: u16 = 4;
+pub(crate) const ATT_KEY_TYPE_ECDSA_P256: u16 = 2;
+pub(crate) const TEE_TYPE_TDX: u32 = 0x81;
+pub(crate) const HEADER_LEN: usize = 48;
+pub(crate) const TD_REPORT_BODY_LEN: usize = 584;
+pub(crate) const QE_REPORT_LEN: usize = 384;
+pub(crate) const CERT_DATA_QE_REPORT: u16 = 6;
+pub(crate) const CERT_DATA_PCK_CHAIN: u16 = 5;
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+#[cfg(feature = "ring-backend")]
+static SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+];
+
+/// TD report body (`sgx_report2_body_t`) of a TD quote.
+#[derive(Clone)]
+pub struct TdReportBody {
+    pub tee_tcb_svn: [u8; 16],
+    pub mr_seam: [u8; 48],
+    pub mr_signer_seam: [u8; 48],
+    pub seam_attributes: [u8; 8],
+    pub td_attributes: [u8; 8],
+    pub xfam: [u8; 8],
+    pub mr_td: [u8; 48],
+    pub mr_config_id: [u8; 48],
+    pub mr_owner: [u8; 48],
+    pub mr_owner_config: [u8; 48],
+    pub rtmrs: [[u8; 48]; 4],
+    pub report_data: [u8; 64],
+}
+
+impl TdReportBody {
+    /// Returns None if `body` is shorter than a TD report body.
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        if body.len() < TD_REPORT_BODY_LEN {
+            return None;
+        }
+        let a48 = |i: usize| -> [u8; 48] {
+            let mut a = [0u8; 48];
+            a.copy_from_slice(&body[i..(i + 48)]);
+            a
+        };
+        let mut report_data = [0u8; 64];
+        report_data.copy_from_slice(&body[520..584]);
+        Some(Self {
+            tee_tcb_svn: body[0..16].try_into().unwrap(),
+            mr_seam: a48(16),
+            mr_signer_seam: a48(64),
+            seam_attributes: body[112..120].try_into().unwrap(),
+            td_attributes: body[120..128].try_into().unwrap(),
+            xfam: body[128..136].try_into().unwrap(),
+            mr_td: a48(136),
+            mr_config_id: a48(184),
+            mr_owner: a48(232),
+            mr_owner_config: a48(280),
+            rtmrs: [a48(328), a48(376), a48(424), a48(472)],
+            report_data,
+        })
+    }
+
+    /// Whether the TD runs in debug mode (TUD.DEBUG), in which the host can
+    /// read its memory.
+    pub fn is_debug(&self) -> bool {
+        self.td_attributes[0] & 0x01 != 0
+    }
+}
+
+/// Check a TD quote end to end: its signature chain up to `trust_anchors`
+/// (Intel's SGX root CA) at `time`, in seconds since the Unix epoch, the QE
+/// and platform TCB against the collateral of `policy`, the revocation of the
+/// PCK certificate and its CA against the CRLs of `policy`, and `policy`.
+/// Fails with `MissingEndorsement` if the policy has no QE Identity, TCB Info,
+/// PCK CRL, or root CA CRL.
+#[cfg(feature = "ring-backend")]
+pub fn verify_td_quote(quote: &[u8],
+                       trust_anchors: &[webpki::TrustAnchor],
+                       time: u64,
+                       policy: &TdPolicy) -> Result<TdReportBody, VerifyError> {
+    let qe_identity = policy.qe_identity.as_ref().ok_or(VerifyError::MissingEndorsement)?;
+    let tcb_info = policy.tcb_info.as_ref().ok_or(VerifyError::MissingEndorsement)?;
+    let pck_crl = policy.pck_crl.as_ref().ok_or(VerifyError::MissingEndorsement)?;
+    let root_ca_crl = policy.root_ca_crl.as_ref().ok_or(VerifyError::MissingEndorsement)?;
+    if qe_identity.id != "TD_QE" || tcb_info.id != "TDX" {
+        return Err(VerifyError::Rejected("Collateral not for TDX"));
+    }
+    let mut r = Reader(quote);
+    let header = r.take(HEADER_LEN)?;
+    if u16::from_le_bytes(header[0..2].try_into().unwrap()) != QUOTE_VERSION ||
+        u16::from_le_bytes(header[2..4].try_into().unwrap()) != ATT_KEY_TYPE_ECDSA_P256 ||
+            u32::from_le_bytes(header[4..8].try_into().unwrap()) != TEE_TYPE_TDX {
+                return Err(VerifyError::UnsupportedQuote);
+            }
+    let body = r.take(TD_REPORT_BODY_LEN)?;
+    let signed = &quote[..(HEADER_LEN + TD_REPORT_BODY_LEN)];
+
+    let sig_data_len = r.u32()? as usize;
+    let mut r = Reader(r.take(sig_data_len)?);
+    let quote_sig = r.take(64)?;
+    let att_key = r.take(64)?;
+    if r.u16()? != CERT_DATA_QE_REPORT {
+        return Err(VerifyError::UnsupportedQuote);
+    }
+    let cert_data_len = r.u32()? as usize;
+    let mut r = Reader(r.take(cert_data_len)?);
+    let qe_report = r.take(QE_REPORT_LEN)?;
+    let qe_report_sig = r.take(64)?;
+    let qe_auth_data_len = r.u16()? as usize;
+    let qe_auth_data = r.take(qe_auth_data_len)?;
+    if r.u16()? != CERT_DATA_PCK_CHAIN {
+        return Err(VerifyError::UnsupportedQuote);
+    }
+    let pck_chain_len = r.u32()? as usize;
+    let pck_chain = pem_certificates(r.take(pck_chain_len)?)?;
+    if pck_chain.is_empty() {
+        return Err(VerifyError::InvalidCertificate);
+    }
+
+    // PCK certificate, issued by Intel through the PCK platform or processor CA
+    let intermediates: Vec<&[u8]> = pck_chain[1..].iter().map(|c| &c[..]).collect();
+    let pck_cert = webpki::EndEntityCert::from(Input::from(&pck_chain[0][..]))
+        .map_err(|_| VerifyError::InvalidCertificate)?;
+    pck_cert.verify_is_valid_tls_server_cert(SIG_ALGS,
+                                             &webpki::TLSServerTrustAnchors(trust_anchors),
+                                             &intermediates[..],
+                                             webpki::Time::from_seconds_since_unix_epoch(time))
+        .map_err(|_| VerifyError::InvalidCertificate)?;
+
+    // Neither the PCK certificate nor the PCK CA that issued it is revoked
+    let pck = Certificate::parse(&pck_chain[0][..]).ok_or(VerifyError::InvalidCertificate)?;
+    let pck_ca = pck_chain[1..].iter()
+        .filter_map(|c| Certificate::parse(&c[..]))
+        .find(|c| c.subject == pck.issuer)
+        .ok_or(VerifyError::InvalidCertificate)?;
+    let root_ca = trust_anchors.iter()
+        .find(|a| a.subject == pck_ca.issuer)
+        .ok_or(VerifyError::InvalidCertificate)?;
+    // The path webpki found may not go through this PCK CA, e.g. if the chain
+    // has two of the same name, so check that the root CA issued it
+    crl::verify_ecdsa(root_ca.spki, pck_ca.tbs, pck_ca.signature)
+        .map_err(|_| VerifyError::InvalidCertificate)?;
+    for (crl, cert, issuer, issuer_spki) in &[(pck_crl, &pck_chain[0][..], pck_ca.subject, pck_ca.spki),
+                                              (root_ca_crl, pck_ca.der, root_ca.subject, root_ca.spki)] {
+        crl.verify_signature(issuer, issuer_spki)?;
+        crl.check_current(time)?;
+        policy.check_collateral_age(Some(crl.this_update), time)?;
+        if crl.revokes(cert)? {
+            return Err(VerifyError::Rejected("Certificate revoked"));
+        }
+    }
+
+    // QE report, signed with the PCK key
+    pck_cert.verify_signature(&webpki::ECDSA_P256_SHA256,
+                              Input::from(qe_report),
+                              Input::from(&ecdsa_sig_to_der(qe_report_sig)[..]))
+        .map_err(|_| VerifyError::BadSignature)?;
+
+    // The QE is one Intel vouches for
+    policy.check_collateral_age(qe_identity.issue_date, time)?;
+    let status = qe_identity.check(qe_report)?;
+    if status != "UpToDate" && !policy.qe_tcb_trust_options.iter().any(|s| s == status) {
+        return Err(VerifyError::Rejected("QE TCB status not trusted"));
+    }
+
+    // The QE report data commits to the attestation key
+    let mut key_and_auth = Vec::with_capacity(att_key.len() + qe_auth_data.len());
+    key_and_auth.extend_from_slice(att_key);
+    key_and_auth.extend_from_slice(qe_auth_data);
+    let expected = digest::digest(&digest::SHA256, &key_and_auth[..]);
+    let qe_report_data = &qe_report[320..384];
+    if &qe_report_data[..32] != expected.as_ref() || qe_report_data[32..].iter().any(|b| *b != 0) {
+        return Err(VerifyError::BadSignature);
+    }
+
+    // Quote signature, made with the attestation key
+    let mut att_key_point = Vec::with_capacity(65);
+    att_key_point.push(0x04);
+    att_key_point.extend_from_slice(att_key);
+    signature::verify(&signature::ECDSA_P256_SHA256_FIXED,
+                      Input::from(&att_key_point[..]),
+                      Input::from(signed),
+                      Input::from(quote_sig))
+        .map_err(|_| VerifyError::BadSignature)?;
+
+    // Can unwrap since the length is checked above
+    let body = TdReportBody::parse(body).unwrap();
+
+    // The platform's TCB, from its PCK certificate and the TD's TEE TCB SVN
+    policy.check_collateral_age(tcb_info.issue_date, time)?;
+    let pck = PckExtensions::from_certificate(&pck_chain[0][..])?;
+    let level = tcb_info.tcb_level(&pck, Some(&body.tee_tcb_svn))?;
+    if level.status != "UpToDate" && !policy.tcb_trust_options.iter().any(|s| *s == level.status) {
+        return Err(VerifyError::Rejected("Platform TCB status not trusted"));
+    }
+    policy.check(&body)?;
+    Ok(body)
+}
+
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], VerifyError> {
+        if self.0.len() < len {
+            return Err(VerifyError::MalformedQuote);
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, VerifyError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, VerifyError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+/// DER certificates of a PEM chain, in order.
+pub(crate) fn pem_certificates(pem: &[u8]) -> Result<Vec<Vec<u8>>, VerifyError> {
+    let pem = core::str::from_utf8(pem).map_err(|_| VerifyError::InvalidCertificate)?;
+    let mut certs = Vec::new();
+    let mut rest = pem;
+    while let Some(begin) = rest.find(PEM_BEGIN) {
+        rest = &rest[(begin + PEM_BEGIN.len())..];
+        let end = rest.find(PEM_END).ok_or(VerifyError::InvalidCertificate)?;
+        let b64: Vec<u8> = rest[..end].bytes()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        certs.push(base64::decode(&b64[..]).map_err(|_| VerifyError::InvalidCertificate)?);
+        rest = &rest[(end + PEM_END.len())..];
+    }
+    Ok(certs)
+}
@@ -1,13 +1,46 @@
+use std::process::exit;
 use std::time::Duration;
 use ra_common::tcp::tcp_connect;
 use ra_client::ClientRaContext;
+use ra_client::platform::{PlatformInfo, AesmInfo, EpidStatus};
+
+// Print what AESM can do, e.g. to attach to a report of failing quote
+// generation, and exit non-zero if it cannot make quotes
+fn aesm_status() -> ! {
+    let info = AesmInfo::query();
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    println!("running:        {}", info.running);
+    println!("version:        {}", info.version.as_ref().map_or("unknown", |v| v.as_str()));
+    for (plugin, version) in info.plugins.iter() {
+        println!("plugin:         {} {}", plugin, version);
+    }
+    println!("installed keys: {:?}", info.installed_key_types);
+    match info.epid {
+        EpidStatus::Ready { gid } => println!("epid:           ready, group {}", hex(&gid[..])),
+        EpidStatus::Unprovisioned => println!("epid:           not provisioned"),
+        EpidStatus::Unavailable(ref e) => println!("epid:           unavailable: {}", e),
+    }
+    exit(if info.installed_key_types.is_empty() { 1 } else { 0 });
+}
 
 fn main() {
+    if std::env::args().nth(1).as_ref().map(|a| a.as_str()) == Some("--aesm-status") {
+        aesm_status();
+    }
+
     let enclave_port = 7777;
     let sp_port = 1234;
     let localhost = "localhost";
     let timeout = Duration::from_secs(5);
 
+    let problems = PlatformInfo::detect().problems();
+    for problem in problems.iter() {
+        eprintln!("Client: {}", problem);
+    }
+    if !problems.is_empty() {
+        exit(1);
+    }
+
     let mut enclave_stream = tcp_connect(localhost, enclave_port, timeout)
         .expect("Client: Enclave connection failed");
     eprintln!("Client: connected to enclave.");
@@ -13,7 +13,7 @@ fn main() {
         .expect("Enclave: Client connection failed");
     eprintln!("Enclave: connected to client.");
     let context = EnclaveRaContext::init(SP_VKEY_PEM).unwrap();
-    let (_signing_key, master_key) = 
+    let (_signing_key, keys) = 
         context.do_attestation(&mut client_stream).unwrap();
 
     // talk to SP directly from now on
@@ -22,7 +22,7 @@ fn main() {
         .expect("Enclave: SP connection failed");
 
     // establish secure channel with enclave
-    let mut secure_channel = SecureChannel::new(sp_stream, &master_key);
+    let mut secure_channel = SecureChannel::new(sp_stream, &keys.channel_key());
     let msg = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. Pellentesque non placerat risus, et lobortis quam. Mauris velit lorem, elementum id neque a, aliquet tempus turpis. Nam eu congue urna, in semper quam. Ut tristique gravida nunc nec feugiat. Proin tincidunt massa a arcu volutpat, sagittis dignissim velit convallis. Cras ac finibus lorem, nec congue felis. Pellentesque fermentum vitae ipsum sed gravida. Nulla consectetur sit amet erat a pellentesque. Donec non velit sem. Sed eu metus felis. Nullam efficitur consequat ante, ut commodo nisi pharetra consequat. Ut accumsan eget ligula laoreet dictum. Maecenas tristique porta convallis. Suspendisse tempor sodales velit, ac luctus urna varius eu. Ut ultrices urna vestibulum vestibulum euismod. Vivamus eu sapien urna.";
     secure_channel.write_u32::<NetworkEndian>(msg.len() as u32).unwrap();
     write!(&mut secure_channel, "{}", msg).unwrap();
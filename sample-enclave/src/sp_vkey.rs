@@ -1,6 +1,6 @@
 // 2048-bit RSASSA-PKCS1-v1_5 public key in PEM format
 pub const SP_VKEY_PEM: &str = "\
------BEGIN RSA PUBLIC KEY-----\n
+-----BEGIN RSA PUBLIC KEY-----\n\
 MIIBCgKCAQEAvtc94gzwX0KeL1HJVh6XdHPXXA4PYE+ClqWUvxp5ts1/nLQzJVcy\
 1SHMGaPUCr+IZJBeWapkFpgnJnw7YzdQ2kA8k6GiN/k8hlQMWXA2nE0LDeOHX8i7\
 fc31lWy5nHdAXj7SfC/YV5RC/yhkJ2cYNMB15VPRHGQRukdVmvHUFunxwfkHq5mM\
@@ -0,0 +1,53 @@
+// SP side of the sample as a library: accept the client, attest the enclave
+// through it, then open a secure channel to the enclave directly. Applications
+// can call `attest_and_connect` instead of copying main().
+use std::path::Path;
+use std::net::TcpStream;
+use std::time::Duration;
+use ra_sp::{SpRaContext, SpConfig, SpRaResult, AttestationResult};
+use ra_common::tcp::{tcp_accept, tcp_connect};
+use sgx_crypto::secure_channel::SecureChannel;
+
+/// Where the SP waits for the client and where it reaches the enclave once the
+/// attestation succeeded.
+#[derive(Debug, Clone)]
+pub struct SpEndpoints {
+    pub client_port: u16,
+    pub enclave_host: String,
+    pub enclave_port: u16,
+    pub connect_timeout: Duration,
+}
+
+impl Default for SpEndpoints {
+    fn default() -> Self {
+        Self {
+            client_port: 1234,
+            enclave_host: "localhost".to_owned(),
+            enclave_port: 1235,
+            connect_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+pub struct SpSession {
+    pub result: AttestationResult,
+    /// Keyed with the channel key of the attestation.
+    pub channel: SecureChannel<TcpStream>,
+}
+
+pub fn attest_and_connect(config: SpConfig, endpoints: &SpEndpoints) -> SpRaResult<SpSession> {
+    let mut client_stream = tcp_accept(endpoints.client_port)?;
+    let context = SpRaContext::init(config)?;
+    let result = context.do_attestation(&mut client_stream)?;
+
+    // talk to enclave directly from now on
+    let enclave_stream = tcp_connect(&endpoints.enclave_host, endpoints.enclave_port,
+                                     endpoints.connect_timeout)?;
+    let channel = SecureChannel::new(enclave_stream, &result.keys.channel_key());
+    Ok(SpSession { result, channel })
+}
+
+pub fn attest_and_connect_with_config_file(config_path: &Path, endpoints: &SpEndpoints)
+    -> SpRaResult<SpSession> {
+        attest_and_connect(SpConfig::from_file(config_path)?, endpoints)
+    }
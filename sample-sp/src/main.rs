@@ -1,32 +1,146 @@
+use std::fs;
 use std::io::Read;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::process::exit;
 use byteorder::{ReadBytesExt, NetworkEndian};
-use ra_sp::{SpRaContext, SpConfig};
-use ra_common::tcp::{tcp_accept, tcp_connect};
-use sgx_crypto::secure_channel::SecureChannel;
+use ra_sp::{SpConfig, SpRaError};
+use ra_common::encrypted_config::{encrypt_config, ConfigKeySource, EnvConfigKey};
+use ra_verify::pck::{pck_cert_chain, PckExtensions};
+use sample_sp::{attest_and_connect, SpEndpoints};
 
-fn parse_config_file(path: &str) -> SpConfig {
-    serde_json::from_reader(std::fs::File::open(path).unwrap()).unwrap()
+const CONFIG_PATH: &str = "data/settings.json";
+
+// The config, or its encrypted version once the cleartext one is deleted
+fn default_config_path() -> PathBuf {
+    let encrypted = PathBuf::from(format!("{}.enc", CONFIG_PATH));
+    if !Path::new(CONFIG_PATH).exists() && encrypted.exists() {
+        return encrypted;
+    }
+    PathBuf::from(CONFIG_PATH)
+}
+
+// Load the config, or report what is wrong with it and exit
+fn load_config(path: &Path) -> SpConfig {
+    match SpConfig::from_file(path) {
+        Ok(config) => config,
+        Err(SpRaError::InvalidConfig(problems)) => {
+            for problem in problems.iter() {
+                eprintln!("{}: {}", path.display(), problem);
+            }
+            exit(2);
+        },
+        Err(e) => {
+            eprintln!("{}: cannot be parsed: {:?}", path.display(), e);
+            exit(2);
+        },
+    }
+}
+
+// Report every problem of the config, including subscription keys that IAS
+// rejects, and exit non-zero if there is any
+fn check_config(path: &Path) -> ! {
+    let config = load_config(path);
+    let mut problems = config.problems();
+    if problems.is_empty() {
+        match config.ias_problems() {
+            Ok(p) => problems = p,
+            Err(e) => {
+                eprintln!("{}: cannot check IAS credentials: {:?}", path.display(), e);
+                exit(1);
+            },
+        }
+    }
+    for problem in problems.iter() {
+        eprintln!("{}: {}", path.display(), problem);
+    }
+    if !problems.is_empty() {
+        exit(1);
+    }
+    eprintln!("{}: OK", path.display());
+    exit(0);
+}
+
+// Encrypt the config at `path` to `<path>.enc` under the key in
+// RA_CONFIG_KEY, which SpConfig::from_file decrypts it with
+fn encrypt_config_file(path: &Path) -> ! {
+    let encrypted = fs::read(path)
+        .map_err(|e| format!("cannot be read: {:?}", e))
+        .and_then(|config| {
+            let key = EnvConfigKey::default().config_key(&[])?;
+            encrypt_config(&config[..], &key, &[]).map_err(|e| e.to_string())
+        });
+    let encrypted_path = format!("{}.enc", path.display());
+    let r = encrypted.and_then(|encrypted| fs::write(&encrypted_path, encrypted)
+                               .map_err(|e| format!("cannot be written: {:?}", e)));
+    match r {
+        Ok(()) => {
+            eprintln!("{}: encrypted to {}", path.display(), encrypted_path);
+            exit(0);
+        },
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            exit(2);
+        },
+    }
+}
+
+// Print what PCS knows the platform of an ECDSA quote by, e.g. to fetch its
+// collateral or to debug its registration
+fn pck_info(path: &Path) -> ! {
+    let quote = match std::fs::read(path) {
+        Ok(quote) => quote,
+        Err(e) => {
+            eprintln!("{}: cannot be read: {:?}", path.display(), e);
+            exit(2);
+        },
+    };
+    let r = pck_cert_chain(&quote[..]).and_then(|chain| {
+        let ext = PckExtensions::from_certificate(&chain[0][..])?;
+        Ok((chain, ext))
+    });
+    let (chain, ext) = match r {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}: no PCK certificate: {:?}", path.display(), e);
+            exit(1);
+        },
+    };
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    println!("fmspc:          {}", hex(&ext.fmspc[..]));
+    println!("ca:             {}", ext.ca.as_str());
+    println!("pce_id:         {}", hex(&ext.pce_id[..]));
+    println!("ppid:           {}", hex(&ext.ppid[..]));
+    println!("tcb_comp_svns:  {}", hex(&ext.tcb_comp_svns[..]));
+    println!("pce_svn:        {}", ext.pce_svn);
+    println!("cpu_svn:        {}", hex(&ext.cpu_svn[..]));
+    println!("sgx_type:       {}", ext.sgx_type);
+    println!("chain:          {} certificates", chain.len());
+    exit(0);
 }
 
 fn main() {
-    let client_port = 1234;
-    let mut client_stream = tcp_accept(client_port)
-        .expect("SP: Client connection failed");
-    eprintln!("SP: connected to client.");
-    let config = parse_config_file("data/settings.json");
-    let context = SpRaContext::init(config).unwrap();
-    let result = context.do_attestation(&mut client_stream).unwrap();
-
-    // talk to enclave directly from now on
-    let enclave_port = 1235;
-    let localhost = "localhost";
-    let timeout = Duration::from_secs(5);
-    let enclave_stream = tcp_connect(localhost, enclave_port, timeout)
-        .expect("SP: Enclave connection failed");
-
-    // establish secure channel with enclave
-    let mut secure_channel = SecureChannel::new(enclave_stream, &result.master_key);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|a| a.as_str()) == Some("--check-config") {
+        check_config(&args.get(1).map(PathBuf::from).unwrap_or_else(default_config_path));
+    }
+    if args.first().map(|a| a.as_str()) == Some("--encrypt-config") {
+        encrypt_config_file(Path::new(args.get(1).map(|a| a.as_str()).unwrap_or(CONFIG_PATH)));
+    }
+    if args.first().map(|a| a.as_str()) == Some("--pck-info") {
+        match args.get(1) {
+            Some(path) => pck_info(Path::new(path)),
+            None => {
+                eprintln!("usage: sample-sp --pck-info <quote file>");
+                exit(2);
+            },
+        }
+    }
+
+    let endpoints = SpEndpoints::default();
+    let config = load_config(&default_config_path());
+    let session = attest_and_connect(config, &endpoints)
+        .expect("SP: Attestation failed");
+    let mut secure_channel = session.channel;
     let len = secure_channel.read_u32::<NetworkEndian>().unwrap() as usize;
     let mut msg = vec![0u8; len];
     secure_channel.read_exact(&mut msg[..]).unwrap();
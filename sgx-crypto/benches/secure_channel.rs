@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write, Result};
+use std::sync::{Arc, Mutex};
+use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId, Throughput};
+use sgx_crypto::secure_channel::SecureChannel;
+use sgx_crypto::secure_channel::compression::Compression;
+
+const KEY: [u8; 16] = [7u8; 16];
+const RECORD_SIZES: &[usize] = &[64, 1024, 16 * 1024, 256 * 1024];
+
+// In-memory transport: whatever one channel writes, the other reads.
+#[derive(Clone)]
+struct Pipe(Arc<Mutex<VecDeque<u8>>>);
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.lock().unwrap().extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn compressions() -> Vec<(&'static str, Compression)> {
+    let mut compressions = vec![("none", Compression::None)];
+    #[cfg(feature = "lz4")]
+    compressions.push(("lz4", Compression::Lz4));
+    #[cfg(feature = "zstd")]
+    compressions.push(("zstd", Compression::Zstd));
+    compressions
+}
+
+fn round_trip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("secure_channel_round_trip");
+    for (name, compression) in compressions() {
+        for &size in RECORD_SIZES {
+            // Half-random data, so that compression has something to do
+            let data: Vec<u8> = (0..size).map(|i| if i % 2 == 0 { (i % 251) as u8 } else { 0 })
+                .collect();
+            let pipe = Pipe(Arc::new(Mutex::new(VecDeque::new())));
+            let mut sender = SecureChannel::new(pipe.clone(), &KEY);
+            sender.set_compression(compression);
+            let mut receiver = SecureChannel::new(pipe, &KEY);
+            let mut buf = vec![0u8; size];
+
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new(name, size), &data, |b, data| {
+                b.iter(|| {
+                    sender.write_all(&data[..]).unwrap();
+                    sender.flush().unwrap();
+                    receiver.read_exact(&mut buf[..]).unwrap();
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, round_trip);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+// AES-128-GCM over a single message, e.g. for data sealed to disk
+use crate::backend::{Backend, CryptoBackend, GCM_NONCE_LEN, GCM_TAG_LEN};
+use crate::random::RandomState;
+
+pub type AeadKey = [u8; 16];
+
+#[derive(Debug)]
+pub enum AeadError {
+    EncryptionError,
+    /// The ciphertext, tag, or additional data was modified, or the key is
+    /// wrong.
+    IntegrityError,
+}
+
+/// Encrypt `plaintext` and authenticate it along with `aad`. Returns
+/// nonce || ciphertext || tag. Nonces are random, so a key should seal no more
+/// than 2^32 messages.
+pub fn seal(key: &AeadKey, aad: &[u8], plaintext: &[u8], rng: &RandomState)
+    -> Result<Vec<u8>, AeadError> {
+        let key = Backend::gcm_key(key).map_err(|_| AeadError::EncryptionError)?;
+        let mut nonce = [0u8; GCM_NONCE_LEN];
+        rng.fill(&mut nonce[..]);
+
+        let mut sealed = Vec::with_capacity(GCM_NONCE_LEN + plaintext.len() + GCM_TAG_LEN);
+        sealed.extend_from_slice(&nonce[..]);
+        sealed.extend_from_slice(plaintext);
+        sealed.resize(GCM_NONCE_LEN + plaintext.len() + GCM_TAG_LEN, 0);
+        Backend::gcm_seal_in_place(&key, &nonce, aad, &mut sealed[GCM_NONCE_LEN..])
+            .map_err(|_| AeadError::EncryptionError)?;
+        Ok(sealed)
+    }
+
+/// Reverse `seal`.
+pub fn open(key: &AeadKey, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, AeadError> {
+    if sealed.len() < GCM_NONCE_LEN + GCM_TAG_LEN {
+        return Err(AeadError::IntegrityError);
+    }
+    let key = Backend::gcm_key(key).map_err(|_| AeadError::IntegrityError)?;
+    let mut nonce = [0u8; GCM_NONCE_LEN];
+    nonce.copy_from_slice(&sealed[..GCM_NONCE_LEN]);
+
+    let mut in_out = sealed[GCM_NONCE_LEN..].to_vec();
+    let len = Backend::gcm_open_in_place(&key, &nonce, aad, &mut in_out[..])
+        .map_err(|_| AeadError::IntegrityError)?;
+    in_out.truncate(len);
+    Ok(in_out)
+}
@@ -0,0 +1,199 @@
+use std::sync::Mutex;
+use mbedtls::cipher::{Cipher, Decryption, Encryption, Authenticated, Fresh};
+use mbedtls::cipher::raw::{self, CipherId, CipherMode};
+use mbedtls::ecp::{EcGroup, EcPoint};
+use mbedtls::hash::{Md, Type as MdType};
+use mbedtls::pk::{Pk, EcGroupId};
+use mbedtls::rng::{Random, Rdrand};
+use super::{CryptoBackend, BackendError, GCM_NONCE_LEN, GCM_TAG_LEN, ECDH_PUBKEY_LEN,
+            p256_pkcs8};
+
+// Modulus sizes accepted for RSA signatures, in bits, as with ring's
+// RSA_PKCS1_2048_8192_SHA256
+const RSA_MIN_BITS: usize = 2048;
+const RSA_MAX_BITS: usize = 8192;
+// Large enough for an RSA-8192 or ECDSA P-256 signature
+const MAX_SIG_LEN: usize = RSA_MAX_BITS / 8;
+
+/// Randomness comes from RDRAND, which is available inside enclaves where
+/// there is no OS entropy source.
+pub struct MbedtlsBackend;
+
+impl CryptoBackend for MbedtlsBackend {
+    type Rng = Rdrand;
+    // Ciphers are consumed by each operation, so only the key is kept
+    type GcmKey = [u8; 16];
+    type EcdhPrivateKey = Pk;
+    // mbedtls needs exclusive access to a key to sign with it
+    type RsaKeyPair = Mutex<Pk>;
+    type EcdsaKeyPair = Mutex<Pk>;
+
+    fn new_rng() -> Self::Rng {
+        Rdrand
+    }
+
+    fn fill_random(_rng: &Self::Rng, dest: &mut [u8]) -> Result<(), BackendError> {
+        Rdrand.random(dest).map_err(|_| BackendError)
+    }
+
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        Md::hash(MdType::Sha256, data, &mut out[..]).unwrap();
+        out
+    }
+
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+
+    fn aes128_cmac(key: &[u8; 16], data: &[u8]) -> [u8; 16] {
+        let mut out = [0u8; 16];
+        let mut cipher = raw::Cipher::setup(CipherId::Aes, CipherMode::ECB, 128).unwrap();
+        cipher.cmac(&key[..], data, &mut out[..]).unwrap();
+        out
+    }
+
+    fn gcm_key(key: &[u8; 16]) -> Result<Self::GcmKey, BackendError> {
+        Ok(*key)
+    }
+
+    fn gcm_seal_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<(), BackendError> {
+        if in_out.len() < GCM_TAG_LEN {
+            return Err(BackendError);
+        }
+        let len = in_out.len() - GCM_TAG_LEN;
+        let plaintext = in_out[..len].to_vec();
+        let (ciphertext, tag) = in_out.split_at_mut(len);
+        Cipher::<Encryption, Authenticated, Fresh>::new(CipherId::Aes, CipherMode::GCM, 128)
+            .and_then(|c| c.set_key_iv(&key[..], &nonce[..]))
+            .and_then(|c| c.encrypt_auth(aad, &plaintext[..], ciphertext, tag))
+            .map(|_| ())
+            .map_err(|_| BackendError)
+    }
+
+    fn gcm_open_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<usize, BackendError> {
+        if in_out.len() < GCM_TAG_LEN {
+            return Err(BackendError);
+        }
+        let len = in_out.len() - GCM_TAG_LEN;
+        let ciphertext = in_out[..len].to_vec();
+        let (plaintext, tag) = in_out.split_at_mut(len);
+        Cipher::<Decryption, Authenticated, Fresh>::new(CipherId::Aes, CipherMode::GCM, 128)
+            .and_then(|c| c.set_key_iv(&key[..], &nonce[..]))
+            .and_then(|c| c.decrypt_auth(aad, &ciphertext[..], plaintext, tag))
+            .map_err(|_| BackendError)?;
+        Ok(len)
+    }
+
+    fn ecdh_generate(_rng: &Self::Rng)
+        -> Result<(Self::EcdhPrivateKey, [u8; ECDH_PUBKEY_LEN]), BackendError> {
+            let private_key = Pk::generate_ec(&mut Rdrand, EcGroupId::SecP256R1)
+                .map_err(|_| BackendError)?;
+            let public_key = ec_public_key(&private_key)?;
+            let mut out = [0u8; ECDH_PUBKEY_LEN];
+            out.copy_from_slice(&public_key[..]);
+            Ok((private_key, out))
+        }
+
+    fn ecdh_agree(mut private_key: Self::EcdhPrivateKey, peer_public_key: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            let group = EcGroup::new(EcGroupId::SecP256R1).map_err(|_| BackendError)?;
+            let point = EcPoint::from_binary(&group, peer_public_key)
+                .map_err(|_| BackendError)?;
+            let peer = Pk::public_from_ec_components(group, point)
+                .map_err(|_| BackendError)?;
+            let mut shared_secret = vec![0u8; 32];
+            let len = private_key.agree(&peer, &mut shared_secret[..], &mut Rdrand)
+                .map_err(|_| BackendError)?;
+            shared_secret.truncate(len);
+            Ok(shared_secret)
+        }
+
+    fn rsa_key_pair(private_key_der: &[u8]) -> Result<Self::RsaKeyPair, BackendError> {
+        let key_pair = Pk::from_private_key(private_key_der, None)
+            .map_err(|_| BackendError)?;
+        check_rsa_len(&key_pair)?;
+        Ok(Mutex::new(key_pair))
+    }
+
+    fn rsa_sign(key_pair: &Self::RsaKeyPair, _rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            sign(&mut key_pair.lock().unwrap(), msg)
+        }
+
+    fn rsa_verify(public_key_der: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError> {
+            let mut public_key = Pk::from_public_key(public_key_der)
+                .map_err(|_| BackendError)?;
+            check_rsa_len(&public_key)?;
+            public_key.verify(MdType::Sha256, &Self::sha256(msg)[..], signature)
+                .map_err(|_| BackendError)
+        }
+
+    fn ecdsa_generate(_rng: &Self::Rng) -> Result<(Self::EcdsaKeyPair, Vec<u8>), BackendError> {
+        let key_pair = Pk::generate_ec(&mut Rdrand, EcGroupId::SecP256R1)
+            .map_err(|_| BackendError)?;
+        let private_key = key_pair.ec_private()
+            .and_then(|d| d.to_binary_padded(32))
+            .map_err(|_| BackendError)?;
+        let public_key = ec_public_key(&key_pair)?;
+        let pkcs8 = p256_pkcs8(&private_key[..], &public_key[..]);
+        Ok((Mutex::new(key_pair), pkcs8))
+    }
+
+    fn ecdsa_key_pair(pkcs8: &[u8]) -> Result<Self::EcdsaKeyPair, BackendError> {
+        let key_pair = Pk::from_private_key(pkcs8, None).map_err(|_| BackendError)?;
+        match key_pair.curve() {
+            Ok(EcGroupId::SecP256R1) => Ok(Mutex::new(key_pair)),
+            _ => Err(BackendError),
+        }
+    }
+
+    fn ecdsa_public_key(key_pair: &Self::EcdsaKeyPair) -> Vec<u8> {
+        // Can unwrap since the key pair is on P-256
+        ec_public_key(&key_pair.lock().unwrap()).unwrap()
+    }
+
+    fn ecdsa_sign(key_pair: &Self::EcdsaKeyPair, _rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            sign(&mut key_pair.lock().unwrap(), msg)
+        }
+
+    fn ecdsa_verify(public_key: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError> {
+            let group = EcGroup::new(EcGroupId::SecP256R1).map_err(|_| BackendError)?;
+            let point = EcPoint::from_binary(&group, public_key)
+                .map_err(|_| BackendError)?;
+            let mut public_key = Pk::public_from_ec_components(group, point)
+                .map_err(|_| BackendError)?;
+            public_key.verify(MdType::Sha256, &Self::sha256(msg)[..], signature)
+                .map_err(|_| BackendError)
+        }
+}
+
+// Uncompressed SEC1 point of a P-256 key
+fn ec_public_key(pk: &Pk) -> Result<Vec<u8>, BackendError> {
+    let group = EcGroup::new(EcGroupId::SecP256R1).map_err(|_| BackendError)?;
+    pk.ec_public()
+        .and_then(|point| point.to_binary(&group, false))
+        .map_err(|_| BackendError)
+}
+
+fn check_rsa_len(pk: &Pk) -> Result<(), BackendError> {
+    if pk.len() < RSA_MIN_BITS || pk.len() > RSA_MAX_BITS {
+        return Err(BackendError);
+    }
+    Ok(())
+}
+
+// PKCS#1 v1.5 for RSA keys, ASN.1 DER ECDSA for EC keys, over SHA-256
+fn sign(pk: &mut Pk, msg: &[u8]) -> Result<Vec<u8>, BackendError> {
+    let mut signature = vec![0u8; MAX_SIG_LEN];
+    let len = pk.sign(MdType::Sha256, &MbedtlsBackend::sha256(msg)[..], &mut signature[..],
+                      &mut Rdrand)
+        .map_err(|_| BackendError)?;
+    signature.truncate(len);
+    Ok(signature)
+}
@@ -0,0 +1,170 @@
+// Primitives sgx-crypto is built on, behind a trait so that the
+// implementation can be chosen at build time:
+// - ring (feature `ring-backend`, the default);
+// - the pure-Rust RustCrypto crates (feature `rustcrypto`), which build for
+//   SGX targets without ring's C and assembly and without pinning a ring
+//   version;
+// - mbedtls (feature `mbedtls-backend`), for enclaves that already link it;
+// - OpenSSL (feature `openssl-backend`), for SPs that must use a
+//   FIPS-validated module.
+// Exactly one backend must be enabled, so crates depending on sgx-crypto
+// disable its default features and forward the backend feature of their own.
+//
+// Certificate verification (`certificate::X509Cert::verify_cert`) goes
+// through webpki under `ring-backend` only. The other backends check the
+// certificate's signature with their own RSA, so that they do not link ring.
+#[cfg(feature = "ring-backend")]
+mod ring_backend;
+#[cfg(feature = "rustcrypto")]
+mod rust_crypto;
+#[cfg(feature = "mbedtls-backend")]
+mod mbedtls_backend;
+#[cfg(feature = "openssl-backend")]
+mod openssl_backend;
+
+#[cfg(any(all(feature = "ring-backend", feature = "rustcrypto"),
+          all(feature = "ring-backend", feature = "mbedtls-backend"),
+          all(feature = "ring-backend", feature = "openssl-backend"),
+          all(feature = "rustcrypto", feature = "mbedtls-backend"),
+          all(feature = "rustcrypto", feature = "openssl-backend"),
+          all(feature = "mbedtls-backend", feature = "openssl-backend")))]
+compile_error!("features `ring-backend`, `rustcrypto`, `mbedtls-backend` and `openssl-backend` are mutually exclusive");
+#[cfg(not(any(feature = "ring-backend", feature = "rustcrypto",
+              feature = "mbedtls-backend", feature = "openssl-backend")))]
+compile_error!("enable one crypto backend: `ring-backend`, `rustcrypto`, `mbedtls-backend` or `openssl-backend`");
+
+#[cfg(feature = "ring-backend")]
+pub use self::ring_backend::RingBackend as Backend;
+#[cfg(feature = "rustcrypto")]
+pub use self::rust_crypto::RustCryptoBackend as Backend;
+#[cfg(feature = "mbedtls-backend")]
+pub use self::mbedtls_backend::MbedtlsBackend as Backend;
+#[cfg(feature = "openssl-backend")]
+pub use self::openssl_backend::OpensslBackend as Backend;
+
+pub const GCM_NONCE_LEN: usize = 12;
+pub const GCM_TAG_LEN: usize = 16;
+pub const ECDH_PUBKEY_LEN: usize = 65;
+
+/// Failure of a primitive. Callers map it to their own error type; backends
+/// do not say more about what went wrong.
+#[derive(Debug)]
+pub struct BackendError;
+
+pub trait CryptoBackend {
+    /// Cryptographically secure random number generator.
+    type Rng;
+    /// AES-128-GCM key, expanded once and reused for many messages.
+    type GcmKey;
+    /// Ephemeral ECDH P-256 private key, consumed by the key agreement.
+    type EcdhPrivateKey;
+    /// RSA private key for PKCS#1 v1.5 SHA-256 signatures.
+    type RsaKeyPair;
+    /// ECDSA P-256 private key.
+    type EcdsaKeyPair;
+
+    fn new_rng() -> Self::Rng;
+
+    fn fill_random(rng: &Self::Rng, dest: &mut [u8]) -> Result<(), BackendError>;
+
+    fn sha256(data: &[u8]) -> [u8; 32];
+
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool;
+
+    fn aes128_cmac(key: &[u8; 16], data: &[u8]) -> [u8; 16];
+
+    fn gcm_key(key: &[u8; 16]) -> Result<Self::GcmKey, BackendError>;
+
+    /// Encrypt `in_out` in place except for its last `GCM_TAG_LEN` bytes,
+    /// which receive the tag.
+    fn gcm_seal_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<(), BackendError>;
+
+    /// Reverse `gcm_seal_in_place`. Returns the plaintext length; the
+    /// plaintext is at the start of `in_out`.
+    fn gcm_open_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<usize, BackendError>;
+
+    /// Returns the private key and the public key as an uncompressed SEC1
+    /// point.
+    fn ecdh_generate(rng: &Self::Rng)
+        -> Result<(Self::EcdhPrivateKey, [u8; ECDH_PUBKEY_LEN]), BackendError>;
+
+    /// Shared secret (the x-coordinate) with `peer_public_key`, an
+    /// uncompressed SEC1 point.
+    fn ecdh_agree(private_key: Self::EcdhPrivateKey, peer_public_key: &[u8])
+        -> Result<Vec<u8>, BackendError>;
+
+    /// `private_key_der` is a PKCS#1 `RSAPrivateKey`.
+    fn rsa_key_pair(private_key_der: &[u8]) -> Result<Self::RsaKeyPair, BackendError>;
+
+    fn rsa_sign(key_pair: &Self::RsaKeyPair, rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError>;
+
+    /// `public_key_der` is a PKCS#1 `RSAPublicKey`.
+    fn rsa_verify(public_key_der: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError>;
+
+    /// Returns the key pair and its private key as a PKCS#8 document.
+    fn ecdsa_generate(rng: &Self::Rng) -> Result<(Self::EcdsaKeyPair, Vec<u8>), BackendError>;
+
+    /// `pkcs8` is a PKCS#8 document of a P-256 key, as `ecdsa_generate`
+    /// returns.
+    fn ecdsa_key_pair(pkcs8: &[u8]) -> Result<Self::EcdsaKeyPair, BackendError>;
+
+    /// Uncompressed SEC1 point.
+    fn ecdsa_public_key(key_pair: &Self::EcdsaKeyPair) -> Vec<u8>;
+
+    /// ASN.1 DER encoded signature.
+    fn ecdsa_sign(key_pair: &Self::EcdsaKeyPair, rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError>;
+
+    /// `public_key` is an uncompressed SEC1 point and `signature` ASN.1 DER
+    /// encoded.
+    fn ecdsa_verify(public_key: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError>;
+}
+
+// AES-CMAC of the `cmac` crate, for backends without one of their own
+#[cfg(any(feature = "ring-backend", feature = "rustcrypto"))]
+fn soft_aes128_cmac(key: &[u8; 16], data: &[u8]) -> [u8; 16] {
+    use crypto_mac::Mac;
+    let mut mac = cmac::Cmac::<aes::Aes128>::new_varkey(&key[..]).unwrap();
+    mac.input(data);
+    mac.result().code().into()
+}
+
+// PKCS#8 v1 document of a P-256 key with the public key included, the same
+// encoding ring generates, for backends that cannot write PKCS#8
+#[cfg(any(feature = "rustcrypto", feature = "mbedtls-backend", feature = "openssl-backend"))]
+fn p256_pkcs8(private_key: &[u8], public_key: &[u8]) -> Vec<u8> {
+    const PREFIX: &[u8] = &[
+        0x30, 0x81, 0x87, 0x02, 0x01, 0x00, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86,
+        0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d,
+        0x03, 0x01, 0x07, 0x04, 0x6d, 0x30, 0x6b, 0x02, 0x01, 0x01, 0x04, 0x20,
+    ];
+    const MIDDLE: &[u8] = &[0xa1, 0x44, 0x03, 0x42, 0x00];
+    let mut pkcs8 = Vec::with_capacity(PREFIX.len() + private_key.len() +
+                                       MIDDLE.len() + public_key.len());
+    pkcs8.extend_from_slice(PREFIX);
+    pkcs8.extend_from_slice(private_key);
+    pkcs8.extend_from_slice(MIDDLE);
+    pkcs8.extend_from_slice(public_key);
+    pkcs8
+}
+
+// The private key of a PKCS#8 document of an EC key: PrivateKeyInfo ::=
+// SEQUENCE { version, algorithm, OCTET STRING { ECPrivateKey ::= SEQUENCE {
+// version, OCTET STRING privateKey, ... } } }
+#[cfg(feature = "rustcrypto")]
+fn ec_pkcs8_private_key(pkcs8: &[u8]) -> Option<&[u8]> {
+    use ra_verify::asn1;
+    let mut pkcs8 = pkcs8;
+    let mut info = asn1::expect(&mut pkcs8, asn1::TAG_SEQUENCE)?;
+    asn1::expect(&mut info, asn1::TAG_INTEGER)?;
+    asn1::expect(&mut info, asn1::TAG_SEQUENCE)?;
+    let mut ec_private_key = asn1::expect(&mut info, asn1::TAG_OCTET_STRING)?;
+    let mut ec_private_key = asn1::expect(&mut ec_private_key, asn1::TAG_SEQUENCE)?;
+    asn1::expect(&mut ec_private_key, asn1::TAG_INTEGER)?;
+    asn1::expect(&mut ec_private_key, asn1::TAG_OCTET_STRING)
+}
@@ -0,0 +1,210 @@
+use openssl::bn::BigNumContext;
+use openssl::derive::Deriver;
+use openssl::ec::{EcGroup, EcKey, EcPoint, PointConversionForm};
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private, Public};
+use openssl::rsa::Rsa;
+use openssl::sign::{Signer, Verifier};
+use openssl::symm::{self, Cipher};
+use super::{CryptoBackend, BackendError, GCM_NONCE_LEN, GCM_TAG_LEN, ECDH_PUBKEY_LEN,
+            p256_pkcs8};
+
+// Modulus sizes accepted for RSA signatures, in bytes, as with ring's
+// RSA_PKCS1_2048_8192_SHA256
+const RSA_MIN_LEN: u32 = 2048 / 8;
+const RSA_MAX_LEN: u32 = 8192 / 8;
+
+/// Whether primitives run in a FIPS-validated module depends on how the
+/// linked OpenSSL is built and configured, not on this crate.
+pub struct OpensslBackend;
+
+/// OpenSSL's own generator; it needs no state on this side.
+pub struct OpensslRng;
+
+impl CryptoBackend for OpensslBackend {
+    type Rng = OpensslRng;
+    // `symm` takes the key on every call
+    type GcmKey = [u8; 16];
+    type EcdhPrivateKey = PKey<Private>;
+    type RsaKeyPair = PKey<Private>;
+    type EcdsaKeyPair = PKey<Private>;
+
+    fn new_rng() -> Self::Rng {
+        OpensslRng
+    }
+
+    fn fill_random(_rng: &Self::Rng, dest: &mut [u8]) -> Result<(), BackendError> {
+        openssl::rand::rand_bytes(dest).map_err(|_| BackendError)
+    }
+
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        openssl::sha::sha256(data)
+    }
+
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && memcmp::eq(a, b)
+    }
+
+    fn aes128_cmac(key: &[u8; 16], data: &[u8]) -> [u8; 16] {
+        let mut out = [0u8; 16];
+        let pkey = PKey::cmac(&Cipher::aes_128_cbc(), &key[..]).unwrap();
+        let mut signer = Signer::new_without_digest(&pkey).unwrap();
+        signer.update(data).unwrap();
+        signer.sign(&mut out[..]).unwrap();
+        out
+    }
+
+    fn gcm_key(key: &[u8; 16]) -> Result<Self::GcmKey, BackendError> {
+        Ok(*key)
+    }
+
+    fn gcm_seal_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<(), BackendError> {
+        if in_out.len() < GCM_TAG_LEN {
+            return Err(BackendError);
+        }
+        let len = in_out.len() - GCM_TAG_LEN;
+        let (plaintext, tag) = in_out.split_at_mut(len);
+        let ciphertext = symm::encrypt_aead(Cipher::aes_128_gcm(), &key[..], Some(&nonce[..]),
+                                            aad, plaintext, tag)
+            .map_err(|_| BackendError)?;
+        plaintext.copy_from_slice(&ciphertext[..]);
+        Ok(())
+    }
+
+    fn gcm_open_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<usize, BackendError> {
+        if in_out.len() < GCM_TAG_LEN {
+            return Err(BackendError);
+        }
+        let len = in_out.len() - GCM_TAG_LEN;
+        let (ciphertext, tag) = in_out.split_at_mut(len);
+        let plaintext = symm::decrypt_aead(Cipher::aes_128_gcm(), &key[..], Some(&nonce[..]),
+                                           aad, ciphertext, tag)
+            .map_err(|_| BackendError)?;
+        ciphertext.copy_from_slice(&plaintext[..]);
+        Ok(len)
+    }
+
+    fn ecdh_generate(_rng: &Self::Rng)
+        -> Result<(Self::EcdhPrivateKey, [u8; ECDH_PUBKEY_LEN]), BackendError> {
+            let (private_key, public_key) = generate_p256()?;
+            let mut out = [0u8; ECDH_PUBKEY_LEN];
+            out.copy_from_slice(&public_key[..]);
+            Ok((private_key, out))
+        }
+
+    fn ecdh_agree(private_key: Self::EcdhPrivateKey, peer_public_key: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            let peer = p256_public_key(peer_public_key)?;
+            let mut deriver = Deriver::new(&private_key).map_err(|_| BackendError)?;
+            deriver.set_peer(&peer).map_err(|_| BackendError)?;
+            deriver.derive_to_vec().map_err(|_| BackendError)
+        }
+
+    fn rsa_key_pair(private_key_der: &[u8]) -> Result<Self::RsaKeyPair, BackendError> {
+        let rsa = Rsa::private_key_from_der(private_key_der).map_err(|_| BackendError)?;
+        if rsa.size() < RSA_MIN_LEN || rsa.size() > RSA_MAX_LEN {
+            return Err(BackendError);
+        }
+        PKey::from_rsa(rsa).map_err(|_| BackendError)
+    }
+
+    fn rsa_sign(key_pair: &Self::RsaKeyPair, _rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            sign(key_pair, msg)
+        }
+
+    fn rsa_verify(public_key_der: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError> {
+            let rsa = Rsa::public_key_from_der_pkcs1(public_key_der)
+                .map_err(|_| BackendError)?;
+            if rsa.size() < RSA_MIN_LEN || rsa.size() > RSA_MAX_LEN {
+                return Err(BackendError);
+            }
+            let public_key = PKey::from_rsa(rsa).map_err(|_| BackendError)?;
+            let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)
+                .map_err(|_| BackendError)?;
+            verifier.update(msg).map_err(|_| BackendError)?;
+            match verifier.verify(signature) {
+                Ok(true) => Ok(()),
+                _ => Err(BackendError),
+            }
+        }
+
+    fn ecdsa_generate(_rng: &Self::Rng) -> Result<(Self::EcdsaKeyPair, Vec<u8>), BackendError> {
+        let (key_pair, public_key) = generate_p256()?;
+        let private_key = key_pair.ec_key()
+            .and_then(|ec_key| ec_key.private_key().to_vec_padded(32))
+            .map_err(|_| BackendError)?;
+        let pkcs8 = p256_pkcs8(&private_key[..], &public_key[..]);
+        Ok((key_pair, pkcs8))
+    }
+
+    fn ecdsa_key_pair(pkcs8: &[u8]) -> Result<Self::EcdsaKeyPair, BackendError> {
+        let key_pair = PKey::private_key_from_pkcs8(pkcs8).map_err(|_| BackendError)?;
+        let curve = key_pair.ec_key().ok().and_then(|ec_key| ec_key.group().curve_name());
+        if curve != Some(Nid::X9_62_PRIME256V1) {
+            return Err(BackendError);
+        }
+        Ok(key_pair)
+    }
+
+    fn ecdsa_public_key(key_pair: &Self::EcdsaKeyPair) -> Vec<u8> {
+        // Can unwrap since the key pair is on P-256
+        let ec_key = key_pair.ec_key().unwrap();
+        encode_point(ec_key.group(), ec_key.public_key()).unwrap()
+    }
+
+    fn ecdsa_sign(key_pair: &Self::EcdsaKeyPair, _rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            sign(key_pair, msg)
+        }
+
+    fn ecdsa_verify(public_key: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError> {
+            let public_key = p256_public_key(public_key)?;
+            let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)
+                .map_err(|_| BackendError)?;
+            verifier.update(msg).map_err(|_| BackendError)?;
+            match verifier.verify(signature) {
+                Ok(true) => Ok(()),
+                _ => Err(BackendError),
+            }
+        }
+}
+
+// P-256 key pair and its public key as an uncompressed SEC1 point
+fn generate_p256() -> Result<(PKey<Private>, Vec<u8>), BackendError> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(|_| BackendError)?;
+    let ec_key = EcKey::generate(&group).map_err(|_| BackendError)?;
+    let public_key = encode_point(&group, ec_key.public_key())?;
+    let key_pair = PKey::from_ec_key(ec_key).map_err(|_| BackendError)?;
+    Ok((key_pair, public_key))
+}
+
+// P-256 public key of an uncompressed SEC1 point
+fn p256_public_key(point: &[u8]) -> Result<PKey<Public>, BackendError> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(|_| BackendError)?;
+    let mut ctx = BigNumContext::new().map_err(|_| BackendError)?;
+    EcPoint::from_bytes(&group, point, &mut ctx)
+        .and_then(|point| EcKey::from_public_key(&group, &point))
+        .and_then(PKey::from_ec_key)
+        .map_err(|_| BackendError)
+}
+
+fn encode_point(group: &openssl::ec::EcGroupRef, point: &openssl::ec::EcPointRef)
+    -> Result<Vec<u8>, BackendError> {
+        let mut ctx = BigNumContext::new().map_err(|_| BackendError)?;
+        point.to_bytes(group, PointConversionForm::UNCOMPRESSED, &mut ctx)
+            .map_err(|_| BackendError)
+    }
+
+// PKCS#1 v1.5 for RSA keys, ASN.1 DER ECDSA for EC keys, over SHA-256
+fn sign(key_pair: &PKey<Private>, msg: &[u8]) -> Result<Vec<u8>, BackendError> {
+    let mut signer = Signer::new(MessageDigest::sha256(), key_pair).map_err(|_| BackendError)?;
+    signer.update(msg).map_err(|_| BackendError)?;
+    signer.sign_to_vec().map_err(|_| BackendError)
+}
@@ -0,0 +1,148 @@
+use ring::{aead, agreement, constant_time, digest, rand, signature};
+use ring::rand::SecureRandom;
+use ring::signature::KeyPair;
+use untrusted::Input;
+use super::{CryptoBackend, BackendError, GCM_NONCE_LEN, ECDH_PUBKEY_LEN, soft_aes128_cmac};
+
+static RSA_VERIFY_ALG: &signature::RsaParameters = &signature::RSA_PKCS1_2048_8192_SHA256;
+static RSA_PADDING_ALG: &dyn signature::RsaEncoding = &signature::RSA_PKCS1_SHA256;
+static ECDSA_ALG: &signature::EcdsaSigningAlgorithm = &signature::ECDSA_P256_SHA256_ASN1_SIGNING;
+static ECDSA_VERIFY_ALG: &signature::EcdsaVerificationAlgorithm = &signature::ECDSA_P256_SHA256_ASN1;
+
+pub struct RingBackend;
+
+pub struct GcmKey {
+    sealing: aead::SealingKey,
+    opening: aead::OpeningKey,
+}
+
+impl CryptoBackend for RingBackend {
+    type Rng = rand::SystemRandom;
+    type GcmKey = GcmKey;
+    type EcdhPrivateKey = agreement::EphemeralPrivateKey;
+    type RsaKeyPair = signature::RsaKeyPair;
+    type EcdsaKeyPair = signature::EcdsaKeyPair;
+
+    fn new_rng() -> Self::Rng {
+        rand::SystemRandom::new()
+    }
+
+    fn fill_random(rng: &Self::Rng, dest: &mut [u8]) -> Result<(), BackendError> {
+        rng.fill(dest).map_err(|_| BackendError)
+    }
+
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        out.copy_from_slice(digest::digest(&digest::SHA256, data).as_ref());
+        out
+    }
+
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        constant_time::verify_slices_are_equal(a, b).is_ok()
+    }
+
+    fn aes128_cmac(key: &[u8; 16], data: &[u8]) -> [u8; 16] {
+        soft_aes128_cmac(key, data)
+    }
+
+    fn gcm_key(key: &[u8; 16]) -> Result<Self::GcmKey, BackendError> {
+        Ok(GcmKey {
+            sealing: aead::SealingKey::new(&aead::AES_128_GCM, &key[..])
+                .map_err(|_| BackendError)?,
+            opening: aead::OpeningKey::new(&aead::AES_128_GCM, &key[..])
+                .map_err(|_| BackendError)?,
+        })
+    }
+
+    fn gcm_seal_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<(), BackendError> {
+        aead::seal_in_place(&key.sealing, aead::Nonce::assume_unique_for_key(*nonce),
+                            aead::Aad::from(aad), in_out, aead::AES_128_GCM.tag_len())
+            .map(|_| ())
+            .map_err(|_| BackendError)
+    }
+
+    fn gcm_open_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<usize, BackendError> {
+        aead::open_in_place(&key.opening, aead::Nonce::assume_unique_for_key(*nonce),
+                            aead::Aad::from(aad), 0, in_out)
+            .map(|plaintext| plaintext.len())
+            .map_err(|_| BackendError)
+    }
+
+    fn ecdh_generate(rng: &Self::Rng)
+        -> Result<(Self::EcdhPrivateKey, [u8; ECDH_PUBKEY_LEN]), BackendError> {
+            let private_key = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, rng)
+                .map_err(|_| BackendError)?;
+            let mut public_key = [0u8; ECDH_PUBKEY_LEN];
+            public_key.copy_from_slice(private_key.compute_public_key()
+                                       .map_err(|_| BackendError)?
+                                       .as_ref());
+            Ok((private_key, public_key))
+        }
+
+    fn ecdh_agree(private_key: Self::EcdhPrivateKey, peer_public_key: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            agreement::agree_ephemeral(private_key,
+                                       &agreement::ECDH_P256,
+                                       Input::from(peer_public_key),
+                                       BackendError,
+                                       |shared_secret| Ok(shared_secret.to_vec()))
+        }
+
+    fn rsa_key_pair(private_key_der: &[u8]) -> Result<Self::RsaKeyPair, BackendError> {
+        signature::RsaKeyPair::from_der(Input::from(private_key_der))
+            .map_err(|_| BackendError)
+    }
+
+    fn rsa_sign(key_pair: &Self::RsaKeyPair, rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            let mut signature = vec![0; key_pair.public_modulus_len()];
+            key_pair.sign(RSA_PADDING_ALG, rng, msg, &mut signature)
+                .map_err(|_| BackendError)?;
+            Ok(signature)
+        }
+
+    fn rsa_verify(public_key_der: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError> {
+            signature::verify(RSA_VERIFY_ALG,
+                              Input::from(public_key_der),
+                              Input::from(msg),
+                              Input::from(signature))
+                .map_err(|_| BackendError)
+        }
+
+    fn ecdsa_generate(rng: &Self::Rng) -> Result<(Self::EcdsaKeyPair, Vec<u8>), BackendError> {
+        let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(ECDSA_ALG, rng)
+            .map_err(|_| BackendError)?;
+        let pkcs8 = pkcs8.as_ref().to_vec();
+        let key_pair = signature::EcdsaKeyPair::from_pkcs8(ECDSA_ALG, Input::from(&pkcs8[..]))
+            .map_err(|_| BackendError)?;
+        Ok((key_pair, pkcs8))
+    }
+
+    fn ecdsa_key_pair(pkcs8: &[u8]) -> Result<Self::EcdsaKeyPair, BackendError> {
+        signature::EcdsaKeyPair::from_pkcs8(ECDSA_ALG, Input::from(pkcs8))
+            .map_err(|_| BackendError)
+    }
+
+    fn ecdsa_public_key(key_pair: &Self::EcdsaKeyPair) -> Vec<u8> {
+        key_pair.public_key().as_ref().to_vec()
+    }
+
+    fn ecdsa_sign(key_pair: &Self::EcdsaKeyPair, rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            let signature = key_pair.sign(rng, Input::from(msg))
+                .map_err(|_| BackendError)?;
+            Ok(signature.as_ref().to_vec())
+        }
+
+    fn ecdsa_verify(public_key: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError> {
+            signature::verify(ECDSA_VERIFY_ALG,
+                              Input::from(public_key),
+                              Input::from(msg),
+                              Input::from(signature))
+                .map_err(|_| BackendError)
+        }
+}
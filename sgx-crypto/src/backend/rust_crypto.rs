@@ -0,0 +1,154 @@
+use aes_gcm::{Aes128Gcm, Key, Nonce, Tag};
+use aes_gcm::aead::{NewAead, AeadInPlace};
+use p256::{PublicKey, ecdh, ecdsa};
+use p256::ecdsa::signature::{RandomizedSigner, Verifier};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rand_core::{OsRng, RngCore};
+use rsa::{RsaPrivateKey, RsaPublicKey, PaddingScheme, Hash, PublicKey as _, PublicKeyParts};
+use rsa::pkcs1::{FromRsaPrivateKey, FromRsaPublicKey};
+use sha2::{Sha256, Digest};
+use subtle::ConstantTimeEq;
+use super::{CryptoBackend, BackendError, GCM_NONCE_LEN, GCM_TAG_LEN, ECDH_PUBKEY_LEN,
+            soft_aes128_cmac, p256_pkcs8, ec_pkcs8_private_key};
+
+// Modulus sizes accepted for RSA signatures, in bytes, as with ring's
+// RSA_PKCS1_2048_8192_SHA256
+const RSA_MIN_LEN: usize = 2048 / 8;
+const RSA_MAX_LEN: usize = 8192 / 8;
+
+pub struct RustCryptoBackend;
+
+impl CryptoBackend for RustCryptoBackend {
+    type Rng = OsRng;
+    type GcmKey = Aes128Gcm;
+    type EcdhPrivateKey = ecdh::EphemeralSecret;
+    type RsaKeyPair = RsaPrivateKey;
+    type EcdsaKeyPair = ecdsa::SigningKey;
+
+    fn new_rng() -> Self::Rng {
+        OsRng
+    }
+
+    fn fill_random(rng: &Self::Rng, dest: &mut [u8]) -> Result<(), BackendError> {
+        let mut rng = *rng;
+        rng.try_fill_bytes(dest).map_err(|_| BackendError)
+    }
+
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        Sha256::digest(data).into()
+    }
+
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.ct_eq(b).into()
+    }
+
+    fn aes128_cmac(key: &[u8; 16], data: &[u8]) -> [u8; 16] {
+        soft_aes128_cmac(key, data)
+    }
+
+    fn gcm_key(key: &[u8; 16]) -> Result<Self::GcmKey, BackendError> {
+        Ok(Aes128Gcm::new(Key::from_slice(&key[..])))
+    }
+
+    fn gcm_seal_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<(), BackendError> {
+        if in_out.len() < GCM_TAG_LEN {
+            return Err(BackendError);
+        }
+        let (plaintext, tag) = in_out.split_at_mut(in_out.len() - GCM_TAG_LEN);
+        let computed = key.encrypt_in_place_detached(Nonce::from_slice(&nonce[..]), aad, plaintext)
+            .map_err(|_| BackendError)?;
+        tag.copy_from_slice(computed.as_slice());
+        Ok(())
+    }
+
+    fn gcm_open_in_place(key: &Self::GcmKey, nonce: &[u8; GCM_NONCE_LEN], aad: &[u8],
+                         in_out: &mut [u8]) -> Result<usize, BackendError> {
+        if in_out.len() < GCM_TAG_LEN {
+            return Err(BackendError);
+        }
+        let len = in_out.len() - GCM_TAG_LEN;
+        let (ciphertext, tag) = in_out.split_at_mut(len);
+        key.decrypt_in_place_detached(Nonce::from_slice(&nonce[..]), aad, ciphertext,
+                                      Tag::from_slice(tag))
+            .map_err(|_| BackendError)?;
+        Ok(len)
+    }
+
+    fn ecdh_generate(rng: &Self::Rng)
+        -> Result<(Self::EcdhPrivateKey, [u8; ECDH_PUBKEY_LEN]), BackendError> {
+            let private_key = ecdh::EphemeralSecret::random(&mut rng.clone());
+            let point = private_key.public_key().to_encoded_point(false);
+            let mut public_key = [0u8; ECDH_PUBKEY_LEN];
+            public_key.copy_from_slice(point.as_bytes());
+            Ok((private_key, public_key))
+        }
+
+    fn ecdh_agree(private_key: Self::EcdhPrivateKey, peer_public_key: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            let peer_public_key = PublicKey::from_sec1_bytes(peer_public_key)
+                .map_err(|_| BackendError)?;
+            let shared_secret = private_key.diffie_hellman(&peer_public_key);
+            Ok(shared_secret.as_bytes().to_vec())
+        }
+
+    fn rsa_key_pair(private_key_der: &[u8]) -> Result<Self::RsaKeyPair, BackendError> {
+        let key_pair = RsaPrivateKey::from_pkcs1_der(private_key_der)
+            .map_err(|_| BackendError)?;
+        key_pair.validate().map_err(|_| BackendError)?;
+        Ok(key_pair)
+    }
+
+    fn rsa_sign(key_pair: &Self::RsaKeyPair, rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            key_pair.sign_blinded(&mut rng.clone(),
+                                  PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)),
+                                  &Self::sha256(msg)[..])
+                .map_err(|_| BackendError)
+        }
+
+    fn rsa_verify(public_key_der: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError> {
+            let public_key = RsaPublicKey::from_pkcs1_der(public_key_der)
+                .map_err(|_| BackendError)?;
+            if public_key.size() < RSA_MIN_LEN || public_key.size() > RSA_MAX_LEN {
+                return Err(BackendError);
+            }
+            public_key.verify(PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)),
+                              &Self::sha256(msg)[..],
+                              signature)
+                .map_err(|_| BackendError)
+        }
+
+    fn ecdsa_generate(rng: &Self::Rng) -> Result<(Self::EcdsaKeyPair, Vec<u8>), BackendError> {
+        let key_pair = ecdsa::SigningKey::random(&mut rng.clone());
+        let public_key = Self::ecdsa_public_key(&key_pair);
+        let pkcs8 = p256_pkcs8(key_pair.to_bytes().as_slice(), &public_key[..]);
+        Ok((key_pair, pkcs8))
+    }
+
+    fn ecdsa_key_pair(pkcs8: &[u8]) -> Result<Self::EcdsaKeyPair, BackendError> {
+        let private_key = ec_pkcs8_private_key(pkcs8).ok_or(BackendError)?;
+        ecdsa::SigningKey::from_bytes(private_key).map_err(|_| BackendError)
+    }
+
+    fn ecdsa_public_key(key_pair: &Self::EcdsaKeyPair) -> Vec<u8> {
+        key_pair.verifying_key().to_encoded_point(false).as_bytes().to_vec()
+    }
+
+    fn ecdsa_sign(key_pair: &Self::EcdsaKeyPair, rng: &Self::Rng, msg: &[u8])
+        -> Result<Vec<u8>, BackendError> {
+            let signature: ecdsa::Signature = key_pair.try_sign_with_rng(rng.clone(), msg)
+                .map_err(|_| BackendError)?;
+            Ok(signature.to_der().as_bytes().to_vec())
+        }
+
+    fn ecdsa_verify(public_key: &[u8], msg: &[u8], signature: &[u8])
+        -> Result<(), BackendError> {
+            let public_key = ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+                .map_err(|_| BackendError)?;
+            let signature = ecdsa::Signature::from_der(signature)
+                .map_err(|_| BackendError)?;
+            public_key.verify(msg, &signature).map_err(|_| BackendError)
+        }
+}
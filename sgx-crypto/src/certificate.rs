@@ -2,11 +2,17 @@ use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 use x509_parser::x509::X509Certificate;
+#[cfg(feature = "ring-backend")]
 use webpki::trust_anchor_util::cert_der_as_trust_anchor;
+#[cfg(feature = "ring-backend")]
 use untrusted::Input;
-use crate::pem_parser::pem_to_der;
+use crate::pem_parser::{pem_to_der_with_label, pem_to_der_blocks, PemError};
+use ra_verify::asn1;
 use crate::signature::VerificationKey;
 
+const CERTIFICATE_PEM_LABEL: &str = "CERTIFICATE";
+
+#[cfg(feature = "ring-backend")]
 static ALL_SIGALGS: &[&webpki::SignatureAlgorithm] = &[
     &webpki::RSA_PKCS1_2048_8192_SHA256,
 ];
@@ -14,6 +20,7 @@ static ALL_SIGALGS: &[&webpki::SignatureAlgorithm] = &[
 #[derive(Debug)]
 pub enum CertError {
     IO(std::io::Error),
+    Pem(PemError),
     BadCertificate,
     UnauthorizedCertificate,
 }
@@ -35,7 +42,8 @@ impl X509Cert {
     }
 
     pub fn new_from_pem(x509_pem: &str) -> Result<Self, CertError> {
-        let der = pem_to_der(x509_pem).map_err(|_| CertError::BadCertificate)?;
+        let der = pem_to_der_with_label(x509_pem, CERTIFICATE_PEM_LABEL)
+            .map_err(|e| CertError::Pem(e))?;
         Self::new_from_der(&der[..])
     }
 
@@ -45,12 +53,29 @@ impl X509Cert {
                            .map_err(|_| CertError::BadCertificate)?)
     }
 
+    /// Parse every CERTIFICATE block of a PEM bundle, in order. Blocks with
+    /// other labels (e.g. private keys) are ignored.
+    pub fn new_chain_from_pem(x509_pem: &str) -> Result<Vec<Self>, CertError> {
+        let blocks = pem_to_der_blocks(x509_pem).map_err(|e| CertError::Pem(e))?;
+        blocks.iter()
+            .filter(|b| b.label == CERTIFICATE_PEM_LABEL)
+            .map(|b| Self::new_from_der(&b.der[..]))
+            .collect()
+    }
+
+    pub fn new_chain_from_pem_file(x509_pem: &Path) -> Result<Vec<Self>, CertError> {
+        let pem = read_file(x509_pem)?;
+        Self::new_chain_from_pem(&String::from_utf8(pem)
+                                 .map_err(|_| CertError::BadCertificate)?)
+    }
+
     pub fn get_verification_key(&self) -> VerificationKey {
         let cert = Self::parse(&self.cert[..]).unwrap();
         VerificationKey::new_from_der(
             cert.tbs_certificate.subject_pki.subject_public_key.as_ref()).unwrap()
     }
 
+    #[cfg(feature = "ring-backend")]
     pub fn verify_cert(&self, immediate_cert: &Self) -> Result<(), CertError> {
         let anchors = vec![
             cert_der_as_trust_anchor(Input::from(immediate_cert.as_ref())).unwrap()
@@ -62,10 +87,35 @@ impl X509Cert {
             .map_err(|_| CertError::UnauthorizedCertificate)
     }
 
+    /// Check that `immediate_cert` issued this certificate: that its subject
+    /// is this certificate's issuer and that its key made the certificate's
+    /// sha256WithRSAEncryption signature, checked with the backend's RSA.
+    /// Unlike webpki, this does not check validity periods or extensions.
+    #[cfg(not(feature = "ring-backend"))]
+    pub fn verify_cert(&self, immediate_cert: &Self) -> Result<(), CertError> {
+        let cert = SignedParts::parse(self.as_ref())
+            .ok_or(CertError::BadCertificate)?;
+        let issuer = SignedParts::parse(immediate_cert.as_ref())
+            .ok_or(CertError::BadCertificate)?;
+        if cert.signature_alg != SHA256_WITH_RSA_ENCRYPTION ||
+            cert.issuer != issuer.subject {
+                return Err(CertError::UnauthorizedCertificate);
+            }
+        immediate_cert.get_verification_key()
+            .verify(cert.tbs, cert.signature)
+            .map_err(|_| CertError::UnauthorizedCertificate)
+    }
+
     pub fn as_ref(&self) -> &[u8] {
         &self.cert[..]
     }
 
+    /// DER of the certificate's SubjectPublicKeyInfo, e.g. to pin its key by
+    /// hash as in HPKP.
+    pub fn subject_public_key_info(&self) -> Option<&[u8]> {
+        subject_public_key_info(&self.cert[..])
+    }
+
     fn parse<'a>(x509_der: &'a [u8]) -> Result<X509Certificate<'a>, CertError> {
         match x509_parser::parse_x509_der(x509_der) {
             Ok((_, cert)) => Ok(cert),
@@ -74,9 +124,135 @@ impl X509Cert {
     }
 }
 
+// Certificate ::= SEQUENCE { tbsCertificate, ... } and TBSCertificate ::=
+// SEQUENCE { [0] version OPTIONAL, serialNumber, signature, issuer, validity,
+// subject, subjectPublicKeyInfo, ... }
+fn subject_public_key_info(cert_der: &[u8]) -> Option<&[u8]> {
+    let mut cert_der = cert_der;
+    let mut cert = asn1::expect(&mut cert_der, asn1::TAG_SEQUENCE)?;
+    let mut tbs = asn1::expect(&mut cert, asn1::TAG_SEQUENCE)?;
+    if tbs.first() == Some(&0xa0) {
+        asn1::next(&mut tbs)?;
+    }
+    for _ in 0..5 {
+        asn1::next(&mut tbs)?;
+    }
+    let (_, _, spki) = asn1::next(&mut tbs)?;
+    Some(spki)
+}
+
+// AlgorithmIdentifier ::= SEQUENCE { OID 1.2.840.113549.1.1.11, NULL }
+#[cfg(not(feature = "ring-backend"))]
+const SHA256_WITH_RSA_ENCRYPTION: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00,
+];
+
+#[cfg(not(feature = "ring-backend"))]
+const TAG_BIT_STRING: u8 = 0x03;
+
+// What verify_cert needs of Certificate ::= SEQUENCE { tbsCertificate,
+// signatureAlgorithm, signatureValue }
+#[cfg(not(feature = "ring-backend"))]
+struct SignedParts<'a> {
+    tbs: &'a [u8],
+    issuer: &'a [u8],
+    subject: &'a [u8],
+    signature_alg: &'a [u8],
+    signature: &'a [u8],
+}
+
+#[cfg(not(feature = "ring-backend"))]
+impl<'a> SignedParts<'a> {
+    fn parse(der: &'a [u8]) -> Option<Self> {
+        let mut d = der;
+        let mut cert = asn1::expect(&mut d, asn1::TAG_SEQUENCE)?;
+        let (mut tbs, tbs_tlv) = match asn1::next(&mut cert)? {
+            (asn1::TAG_SEQUENCE, content, tlv) => (content, tlv),
+            _ => return None,
+        };
+        let signature_alg = asn1::expect(&mut cert, asn1::TAG_SEQUENCE)?;
+        let signature = match asn1::expect(&mut cert, TAG_BIT_STRING)?.split_first()? {
+            (0, bits) => bits,
+            _ => return None,
+        };
+        if !d.is_empty() || !cert.is_empty() {
+            return None;
+        }
+
+        if tbs.first() == Some(&0xa0) {
+            asn1::next(&mut tbs)?;
+        }
+        asn1::expect(&mut tbs, asn1::TAG_INTEGER)?;
+        // The signed copy of the signature algorithm must match
+        if asn1::expect(&mut tbs, asn1::TAG_SEQUENCE)? != signature_alg {
+            return None;
+        }
+        let issuer = asn1::expect(&mut tbs, asn1::TAG_SEQUENCE)?;
+        asn1::expect(&mut tbs, asn1::TAG_SEQUENCE)?;
+        let subject = asn1::expect(&mut tbs, asn1::TAG_SEQUENCE)?;
+        Some(Self {
+            tbs: tbs_tlv,
+            issuer,
+            subject,
+            signature_alg,
+            signature,
+        })
+    }
+}
+
 fn read_file(path: &Path) -> Result<Vec<u8>, CertError> {
     let mut file = File::open(path).map_err(|e| CertError::IO(e))?;
     let mut contents: Vec<u8> = Vec::new();
     file.read_to_end(&mut contents).map_err(|e| CertError::IO(e))?;
     Ok(contents)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNING_CERT_PEM: &str = include_str!("../../ra-sp/data/loopback/ias_signing_cert.pem");
+    const ROOT_CA_PEM: &str = include_str!("../../ra-sp/data/loopback/ias_root_ca.pem");
+
+    // webpki's trust anchor holds the content of the SubjectPublicKeyInfo,
+    // without its SEQUENCE header
+    fn check_spki(cert_pem: &str) {
+        let cert = X509Cert::new_from_pem(cert_pem).unwrap();
+        let spki = cert.subject_public_key_info().unwrap();
+        // Both keys are RSA of 2048 bits or more, so the length takes two bytes
+        assert_eq!(&spki[..2], &[0x30, 0x82]);
+        assert_eq!(((spki[2] as usize) << 8) | spki[3] as usize, spki.len() - 4);
+        #[cfg(feature = "ring-backend")]
+        {
+            let anchor = cert_der_as_trust_anchor(Input::from(cert.as_ref())).unwrap();
+            assert_eq!(&spki[4..], anchor.spki);
+        }
+    }
+
+    #[test]
+    fn finds_spki_of_ias_signing_cert() {
+        check_spki(SIGNING_CERT_PEM);
+    }
+
+    #[test]
+    fn finds_spki_of_ias_root_ca() {
+        check_spki(ROOT_CA_PEM);
+    }
+
+    #[test]
+    #[cfg(not(feature = "ring-backend"))]
+    fn verifies_ias_signing_cert_against_root_ca() {
+        let cert = X509Cert::new_from_pem(SIGNING_CERT_PEM).unwrap();
+        let root = X509Cert::new_from_pem(ROOT_CA_PEM).unwrap();
+        assert!(cert.verify_cert(&root).is_ok());
+        assert!(root.verify_cert(&cert).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_certificate() {
+        let cert = X509Cert::new_from_pem(SIGNING_CERT_PEM).unwrap();
+        let der = cert.as_ref();
+        assert_eq!(subject_public_key_info(&der[..der.len() / 2]), None);
+        assert_eq!(subject_public_key_info(&[]), None);
+    }
+}
@@ -1,7 +1,5 @@
 // 128-bit AES-CMAC
-use crypto_mac::Mac as InnerMacTrait;
-use cmac::Cmac as InnerCmac;
-use aes::Aes128;
+use crate::backend::{Backend, CryptoBackend};
 
 const MAC_LEN: usize = 16;
 
@@ -20,15 +18,14 @@ impl Cmac {
     }
 
     pub fn sign(&self, data: &[u8]) -> MacTag {
-        let mut inner = InnerCmac::<Aes128>::new_varkey(&self.key[..]).unwrap();
-        inner.input(data);
-        let mac = inner.result_reset();
-        mac.code().into()
+        Backend::aes128_cmac(&self.key, data)
     }
 
     pub fn verify(&self, data: &[u8], tag: &MacTag) -> Result<(), MacError>{
-        let mut inner = InnerCmac::<Aes128>::new_varkey(&self.key[..]).unwrap();
-        inner.input(data);
-        inner.verify(&tag[..])
+        if Backend::constant_time_eq(&self.sign(data)[..], &tag[..]) {
+            Ok(())
+        } else {
+            Err(MacError)
+        }
     } 
 }
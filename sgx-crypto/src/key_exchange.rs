@@ -1,16 +1,25 @@
 use std::io::Write;
 use std::mem::size_of;
-use ring::agreement;
+use crate::backend::{Backend, CryptoBackend, ECDH_PUBKEY_LEN};
 use crate::random::RandomState;
 use crate::cmac::{MacTag, Cmac};
 use crate::signature::{SigningKey, VerificationKey, Signature, SigError};
 
-const DHKE_PUBKEY_LEN: usize = 65; 
+const DHKE_PUBKEY_LEN: usize = ECDH_PUBKEY_LEN; 
 const KDK_LEN: usize = size_of::<MacTag>(); 
-static KE_ALG: &agreement::Algorithm = &agreement::ECDH_P256;
+const SHARED_SECRET_LEN: usize = 32;
 
 pub type DHKEPublicKey = [u8; DHKE_PUBKEY_LEN];
 pub type KDK = [u8; KDK_LEN];
+/// x-coordinate of the shared ECDH point, big-endian as the crypto libraries
+/// return it. The Intel SGX SDK's `sgx_ec256_dh_shared_t` is little-endian.
+pub type SharedSecret = [u8; SHARED_SECRET_LEN];
+
+/// KDK = AES-CMAC(0^128, shared secret), as in the Intel SGX SDK.
+pub fn derive_kdk(shared_secret: &SharedSecret) -> KDK {
+    let cmac = Cmac::new(&[0; size_of::<MacTag>()]);
+    cmac.sign(&shared_secret[..])
+}
 
 #[derive(Debug)]
 pub enum KeError {
@@ -20,20 +29,14 @@ pub enum KeError {
 }
 
 pub struct DHKE {
-    private_key: agreement::EphemeralPrivateKey,
+    private_key: <Backend as CryptoBackend>::EcdhPrivateKey,
     public_key: DHKEPublicKey, 
 }
 
 impl DHKE {
     pub fn generate_keypair(rng: &RandomState) -> Result<Self, KeError> {
-        let private_key = 
-            agreement::EphemeralPrivateKey::generate(KE_ALG, rng.inner())
+        let (private_key, public_key) = Backend::ecdh_generate(rng.inner())
             .map_err(|_| KeError::KeyGenerationError)?;
-        let mut public_key: DHKEPublicKey = [0; DHKE_PUBKEY_LEN] ;
-        (&mut public_key[..]).copy_from_slice(private_key
-                                              .compute_public_key()
-                                              .map_err(|_| KeError::KeyGenerationError)?
-                                              .as_ref());
         Ok(Self { private_key, public_key })
     }
 
@@ -41,17 +44,19 @@ impl DHKE {
         &self.public_key
     }
 
+    pub fn agree(self, peer_public_key: &DHKEPublicKey) -> Result<SharedSecret, KeError> {
+        let ikm = Backend::ecdh_agree(self.private_key, &peer_public_key[..])
+            .map_err(|_| KeError::KeyDerivationError)?;
+        if ikm.len() != SHARED_SECRET_LEN {
+            return Err(KeError::KeyDerivationError);
+        }
+        let mut shared_secret = [0u8; SHARED_SECRET_LEN];
+        shared_secret.copy_from_slice(&ikm[..]);
+        Ok(shared_secret)
+    }
+
     pub fn derive_key(self, peer_public_key: &DHKEPublicKey) -> Result<KDK, KeError> {
-        agreement::agree_ephemeral(
-            self.private_key,
-            KE_ALG,
-            untrusted::Input::from(peer_public_key),
-            (),
-            |ikm| {
-                let cmac = Cmac::new(&[0; size_of::<MacTag>()]);
-                let kdk = cmac.sign(ikm);
-                Ok(kdk)
-            }).map_err(|_| KeError::KeyDerivationError)
+        Ok(derive_kdk(&self.agree(peer_public_key)?))
     }
 
 }
@@ -77,6 +82,17 @@ impl OneWayAuthenticatedDHKE {
                            signing_key: &SigningKey, 
                            rng: &RandomState) 
         -> Result<(KDK, Signature), KeError> {
+            let (shared_secret, sign_gb_ga) = self.sign_and_agree(g_a, signing_key, rng)?;
+            Ok((derive_kdk(&shared_secret), sign_gb_ga))
+        }
+
+    /// Like `sign_and_derive`, but returns the shared secret instead of the
+    /// KDK, e.g. for a custom key derivation.
+    pub fn sign_and_agree(self,
+                          g_a: &DHKEPublicKey,
+                          signing_key: &SigningKey,
+                          rng: &RandomState)
+        -> Result<(SharedSecret, Signature), KeError> {
 
             // Sign (g_b, g_a) with Bob's signing key 
             let mut gb_ga = Vec::new();
@@ -85,17 +101,33 @@ impl OneWayAuthenticatedDHKE {
             let sign_gb_ga = signing_key.sign(&gb_ga[..], rng)
                 .map_err(|e| KeError::SigError(e))?;
 
-            // Derive KDK
-            let kdk = self.dhke.derive_key(g_a)?;
-            Ok((kdk, sign_gb_ga))
+            let shared_secret = self.dhke.agree(g_a)?;
+            Ok((shared_secret, sign_gb_ga))
         } 
 
+    /// The shared secret without signing (g_b, g_a), for protocols that sign
+    /// it in an encoding or with a key of their own.
+    pub fn agree(self, g_a: &DHKEPublicKey) -> Result<SharedSecret, KeError> {
+        self.dhke.agree(g_a)
+    }
+
     /// Alice verifies the (g_b, g_a).
     pub fn verify_and_derive(self,
                              g_b: &DHKEPublicKey,
                              sign_gb_ga: &Signature,
                              verification_key: &VerificationKey) 
         -> Result<KDK, KeError> {
+            let shared_secret = self.verify_and_agree(g_b, sign_gb_ga, verification_key)?;
+            Ok(derive_kdk(&shared_secret))
+        }
+
+    /// Like `verify_and_derive`, but returns the shared secret instead of the
+    /// KDK, e.g. for a custom key derivation.
+    pub fn verify_and_agree(self,
+                            g_b: &DHKEPublicKey,
+                            sign_gb_ga: &Signature,
+                            verification_key: &VerificationKey)
+        -> Result<SharedSecret, KeError> {
 
             // Verify (g_b, g_a) with Bob's verification key 
             let mut gb_ga = Vec::new();
@@ -104,8 +136,7 @@ impl OneWayAuthenticatedDHKE {
             verification_key.verify(&gb_ga[..], &sign_gb_ga[..])
                 .map_err(|e| KeError::SigError(e))?;
 
-            // Derive KDK
-            self.dhke.derive_key(g_b)
+            self.dhke.agree(g_b)
 
         }
 }
@@ -0,0 +1,157 @@
+// 128-bit AES key wrap (RFC 3394)
+use aes::Aes128;
+use block_cipher_trait::BlockCipher;
+use block_cipher_trait::generic_array::GenericArray;
+use crate::backend::{Backend, CryptoBackend};
+
+const KEK_LEN: usize = 16;
+const SEMIBLOCK_LEN: usize = 8;
+const DEFAULT_IV: [u8; SEMIBLOCK_LEN] = [0xa6; SEMIBLOCK_LEN];
+
+pub type Kek = [u8; KEK_LEN];
+
+#[derive(Debug)]
+pub enum KeyWrapError {
+    /// Key data must be a multiple of 8 bytes and at least 16 bytes long.
+    InvalidLength,
+    IntegrityError,
+}
+
+pub struct KeyWrap {
+    kek: Kek,
+}
+
+impl KeyWrap {
+    pub fn new(kek: &Kek) -> Self {
+        Self {
+            kek: *kek,
+        }
+    }
+
+    /// Wrap `key_data`. The output is 8 bytes longer than the input.
+    pub fn wrap_key(&self, key_data: &[u8]) -> Result<Vec<u8>, KeyWrapError> {
+        if key_data.len() % SEMIBLOCK_LEN != 0 || key_data.len() < 2 * SEMIBLOCK_LEN {
+            return Err(KeyWrapError::InvalidLength);
+        }
+        let cipher = Aes128::new(GenericArray::from_slice(&self.kek[..]));
+        let n = key_data.len() / SEMIBLOCK_LEN;
+
+        let mut out = Vec::with_capacity(key_data.len() + SEMIBLOCK_LEN);
+        out.extend_from_slice(&DEFAULT_IV);
+        out.extend_from_slice(key_data);
+
+        let mut block = GenericArray::clone_from_slice(&[0u8; 2 * SEMIBLOCK_LEN]);
+        for j in 0..6 {
+            for i in 1..=n {
+                // B = AES(K, A | R[i])
+                block[..SEMIBLOCK_LEN].copy_from_slice(&out[..SEMIBLOCK_LEN]);
+                block[SEMIBLOCK_LEN..].copy_from_slice(r_i(&out, i));
+                cipher.encrypt_block(&mut block);
+
+                // A = MSB(64, B) ^ t, R[i] = LSB(64, B)
+                let t = ((n * j + i) as u64).to_be_bytes();
+                for k in 0..SEMIBLOCK_LEN {
+                    out[k] = block[k] ^ t[k];
+                }
+                r_i_mut(&mut out, i).copy_from_slice(&block[SEMIBLOCK_LEN..]);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Unwrap `wrapped` and check its integrity. The output is 8 bytes shorter
+    /// than the input.
+    pub fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, KeyWrapError> {
+        if wrapped.len() % SEMIBLOCK_LEN != 0 || wrapped.len() < 3 * SEMIBLOCK_LEN {
+            return Err(KeyWrapError::InvalidLength);
+        }
+        let cipher = Aes128::new(GenericArray::from_slice(&self.kek[..]));
+        let n = wrapped.len() / SEMIBLOCK_LEN - 1;
+
+        let mut out = wrapped.to_vec();
+        let mut block = GenericArray::clone_from_slice(&[0u8; 2 * SEMIBLOCK_LEN]);
+        for j in (0..6).rev() {
+            for i in (1..=n).rev() {
+                // B = AES-1(K, (A ^ t) | R[i])
+                let t = ((n * j + i) as u64).to_be_bytes();
+                for k in 0..SEMIBLOCK_LEN {
+                    block[k] = out[k] ^ t[k];
+                }
+                block[SEMIBLOCK_LEN..].copy_from_slice(r_i(&out, i));
+                cipher.decrypt_block(&mut block);
+
+                // A = MSB(64, B), R[i] = LSB(64, B)
+                out[..SEMIBLOCK_LEN].copy_from_slice(&block[..SEMIBLOCK_LEN]);
+                r_i_mut(&mut out, i).copy_from_slice(&block[SEMIBLOCK_LEN..]);
+            }
+        }
+
+        if !Backend::constant_time_eq(&out[..SEMIBLOCK_LEN], &DEFAULT_IV[..]) {
+            return Err(KeyWrapError::IntegrityError);
+        }
+        Ok(out.split_off(SEMIBLOCK_LEN))
+    }
+}
+
+fn r_i(buf: &[u8], i: usize) -> &[u8] {
+    &buf[(i * SEMIBLOCK_LEN)..((i + 1) * SEMIBLOCK_LEN)]
+}
+
+fn r_i_mut(buf: &mut [u8], i: usize) -> &mut [u8] {
+    &mut buf[(i * SEMIBLOCK_LEN)..((i + 1) * SEMIBLOCK_LEN)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 3394, section 4.1: wrap 128 bits of key data with a 128-bit KEK
+    const KEK: Kek = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    ];
+    const KEY_DATA: [u8; 16] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+        0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+    ];
+    const WRAPPED: [u8; 24] = [
+        0x1f, 0xa6, 0x8b, 0x0a, 0x81, 0x12, 0xb4, 0x47,
+        0xae, 0xf3, 0x4b, 0xd8, 0xfb, 0x5a, 0x7b, 0x82,
+        0x9d, 0x3e, 0x86, 0x23, 0x71, 0xd2, 0xcf, 0xe5,
+    ];
+
+    #[test]
+    fn wraps_rfc_3394_vector() {
+        assert_eq!(KeyWrap::new(&KEK).wrap_key(&KEY_DATA[..]).unwrap(), &WRAPPED[..]);
+    }
+
+    #[test]
+    fn unwraps_rfc_3394_vector() {
+        assert_eq!(KeyWrap::new(&KEK).unwrap_key(&WRAPPED[..]).unwrap(), &KEY_DATA[..]);
+    }
+
+    #[test]
+    fn rejects_modified_wrapped_key() {
+        let mut wrapped = WRAPPED;
+        wrapped[23] ^= 1;
+        match KeyWrap::new(&KEK).unwrap_key(&wrapped[..]) {
+            Err(KeyWrapError::IntegrityError) => {},
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_lengths() {
+        let key_wrap = KeyWrap::new(&KEK);
+        for len in &[0, 8, 15, 17] {
+            match key_wrap.wrap_key(&vec![0u8; *len][..]) {
+                Err(KeyWrapError::InvalidLength) => {},
+                r => panic!("{} bytes: unexpected {:?}", len, r),
+            }
+        }
+        match key_wrap.unwrap_key(&WRAPPED[..16]) {
+            Err(KeyWrapError::InvalidLength) => {},
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+}
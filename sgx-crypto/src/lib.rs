@@ -1,8 +1,12 @@
+pub mod backend;
 pub mod random;
 pub mod cmac;
+pub mod key_wrap;
+pub mod aead;
 pub mod digest;
 pub mod key_exchange;
 pub mod signature;
 pub mod certificate;
 pub mod secure_channel;
-mod pem_parser;
+pub mod pem_parser;
+pub mod locked;
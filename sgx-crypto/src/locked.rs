@@ -0,0 +1,194 @@
+// Secrets on the untrusted side kept in memory that is locked into RAM and
+// excluded from core dumps, so that they do not end up in swap or in a dump
+// of a crashed process. Locking and dump exclusion apply to whole pages, so
+// values of up to half a page share pages, in slots of a power of two bytes;
+// the keys of many sessions then fit in the RLIMIT_MEMLOCK of unprivileged
+// processes, often 64 KiB. Larger values get pages of their own. A value's
+// memory is zeroed when it is dropped, and freed slots are reused, their
+// pages staying locked for the life of the process. Only the bytes of the
+// value itself are protected; heap memory it owns, e.g. the key schedule of
+// a crypto library's key, is not, so only plain byte arrays belong here.
+// Outside Unix, e.g. inside an enclave where memory is protected anyway,
+// values are kept in ordinary memory and only zeroed.
+use std::io;
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{compiler_fence, Ordering};
+#[cfg(unix)]
+use std::sync::Mutex;
+#[cfg(unix)]
+use std::sync::atomic::AtomicPtr;
+
+#[cfg(unix)]
+const MIN_SLOT_LEN: usize = 16;
+
+pub struct Locked<T> {
+    ptr: NonNull<T>,
+    len: usize,
+    // Whether the memory is a slot of a shared page
+    pooled: bool,
+}
+
+unsafe impl<T: Send> Send for Locked<T> {}
+unsafe impl<T: Sync> Sync for Locked<T> {}
+
+impl<T> Locked<T> {
+    /// Fails if the memory cannot be locked, typically because of
+    /// RLIMIT_MEMLOCK.
+    #[cfg(unix)]
+    pub fn new(value: T) -> io::Result<Self> {
+        let page_size = page_size();
+        let slot_len = size_of::<T>().max(std::mem::align_of::<T>()).max(MIN_SLOT_LEN)
+            .next_power_of_two();
+        let (addr, len, pooled) = if slot_len <= page_size / 2 {
+            // Slots are aligned to their length within aligned pages
+            (pool().lock().unwrap().take(slot_len, page_size)?, slot_len, true)
+        } else {
+            let len = (size_of::<T>() + page_size - 1) / page_size * page_size;
+            // Pages are aligned beyond any alignment T may have
+            (map_locked(len)?, len, false)
+        };
+        unsafe {
+            let ptr = addr as *mut T;
+            ptr::write(ptr, value);
+            Ok(Self { ptr: NonNull::new_unchecked(ptr), len, pooled })
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(value: T) -> io::Result<Self> {
+        let ptr = Box::into_raw(Box::new(std::mem::MaybeUninit::new(value))) as *mut T;
+        // Can unwrap since Box never returns a null pointer
+        Ok(Self { ptr: NonNull::new(ptr).unwrap(), len: size_of::<T>(), pooled: false })
+    }
+}
+
+impl<T> Deref for Locked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for Locked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for Locked<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            ptr::write_bytes(self.ptr.as_ptr() as *mut u8, 0, self.len);
+            compiler_fence(Ordering::SeqCst);
+            self.release();
+        }
+    }
+}
+
+impl<T> Locked<T> {
+    #[cfg(unix)]
+    unsafe fn release(&mut self) {
+        if self.pooled {
+            pool().lock().unwrap().put(self.ptr.as_ptr() as usize, self.len);
+            return;
+        }
+        let addr = self.ptr.as_ptr() as *mut libc::c_void;
+        libc::munlock(addr, self.len);
+        libc::munmap(addr, self.len);
+    }
+
+    #[cfg(not(unix))]
+    unsafe fn release(&mut self) {
+        drop(Box::from_raw(self.ptr.as_ptr() as *mut std::mem::MaybeUninit<T>));
+    }
+}
+
+impl<T> std::fmt::Debug for Locked<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Locked(..)")
+    }
+}
+
+// Free slots of the shared pages, by the base-2 logarithm of their length
+#[cfg(unix)]
+#[derive(Default)]
+struct Pool {
+    free: Vec<Vec<usize>>,
+}
+
+#[cfg(unix)]
+impl Pool {
+    fn take(&mut self, slot_len: usize, page_size: usize) -> io::Result<usize> {
+        let class = slot_len.trailing_zeros() as usize;
+        if self.free.len() <= class {
+            self.free.resize(class + 1, Vec::new());
+        }
+        if self.free[class].is_empty() {
+            let page = map_locked(page_size)?;
+            self.free[class].extend((0..(page_size / slot_len)).rev()
+                                    .map(|i| page + i * slot_len));
+        }
+        // Can unwrap since a page was just added if there was no free slot
+        Ok(self.free[class].pop().unwrap())
+    }
+
+    fn put(&mut self, slot: usize, slot_len: usize) {
+        self.free[slot_len.trailing_zeros() as usize].push(slot);
+    }
+}
+
+#[cfg(unix)]
+fn pool() -> &'static Mutex<Pool> {
+    static POOL: AtomicPtr<Mutex<Pool>> = AtomicPtr::new(ptr::null_mut());
+    let mut pool = POOL.load(Ordering::Acquire);
+    if pool.is_null() {
+        let new = Box::into_raw(Box::new(Mutex::new(Pool::default())));
+        pool = match POOL.compare_exchange(ptr::null_mut(), new,
+                                           Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => new,
+            // Another thread created it first
+            Err(existing) => {
+                drop(unsafe { Box::from_raw(new) });
+                existing
+            },
+        };
+    }
+    // The pool is never freed
+    unsafe { &*pool }
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+// `len` bytes of fresh pages, locked and excluded from core dumps
+#[cfg(unix)]
+fn map_locked(len: usize) -> io::Result<usize> {
+    unsafe {
+        let addr = libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+                              libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0);
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::mlock(addr, len) != 0 {
+            let e = io::Error::last_os_error();
+            libc::munmap(addr, len);
+            return Err(e);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            if libc::madvise(addr, len, libc::MADV_DONTDUMP) != 0 {
+                let e = io::Error::last_os_error();
+                libc::munlock(addr, len);
+                libc::munmap(addr, len);
+                return Err(e);
+            }
+        }
+        Ok(addr as usize)
+    }
+}
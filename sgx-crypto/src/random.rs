@@ -1,16 +1,19 @@
-use ring::rand;
+use crate::backend::{Backend, CryptoBackend};
 
 pub struct RandomState {
-    inner: rand::SystemRandom,
+    inner: <Backend as CryptoBackend>::Rng,
 }
 
 impl RandomState {
     pub fn new() -> Self {
-        Self { inner: rand::SystemRandom::new() }
+        Self { inner: Backend::new_rng() }
     }
 
-    pub fn inner(&self) -> &rand::SystemRandom {
+    pub fn inner(&self) -> &<Backend as CryptoBackend>::Rng {
         &self.inner
     }
-}
 
+    pub fn fill(&self, dest: &mut [u8]) {
+        Backend::fill_random(&self.inner, dest).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use std::io::{Result, Error, ErrorKind};
+
+/// Compression applied to each data record before encryption. Which codecs are
+/// available depends on the `lz4` and `zstd` features; a peer receiving a
+/// record compressed with a codec it was built without fails to read it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None = 0,
+    #[cfg(feature = "lz4")]
+    Lz4 = 1,
+    #[cfg(feature = "zstd")]
+    Zstd = 2,
+}
+
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+pub(crate) fn compress(compression: Compression, data: &[u8]) -> Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::block::compress(data, ZSTD_LEVEL),
+    }
+}
+
+/// Decompress a record payload. `limit` bounds the decompressed size so that
+/// a peer cannot make us allocate more than a record's capacity.
+#[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+pub(crate) fn decompress(flag: u8, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    match flag {
+        0 => Ok(data.to_vec()),
+        #[cfg(feature = "lz4")]
+        1 => {
+            if data.len() < 4 {
+                return Err(decompression_error());
+            }
+            let size = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            if size as usize > limit {
+                return Err(decompression_error());
+            }
+            lz4_flex::decompress_size_prepended(data)
+                .map_err(|_| decompression_error())
+        },
+        #[cfg(feature = "zstd")]
+        2 => zstd::block::decompress(data, limit)
+            .map_err(|_| decompression_error()),
+        _ => Err(Error::new(ErrorKind::InvalidData,
+                            "Unsupported record compression")),
+    }
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+fn decompression_error() -> Error {
+    Error::new(ErrorKind::InvalidData, "Secure channel decompression error")
+}
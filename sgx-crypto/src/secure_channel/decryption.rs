@@ -1,118 +1,382 @@
-use std::io::{Result, Read, Error, ErrorKind};
-use std::rc::Rc;
-use std::cell::RefCell;
-use ring::aead::{OpeningKey, open_in_place, AES_128_GCM, Aad, Nonce};
+use std::io::{Result, Read, BufRead, Write, Error, ErrorKind};
+use std::sync::{Mutex, Weak};
+use std::time::{Duration, Instant};
+use crate::backend::{Backend, CryptoBackend, GCM_NONCE_LEN, GCM_TAG_LEN};
 use byteorder::{ReadBytesExt, NetworkEndian};
+use super::{IdleTimeout, RecordType, TransportTimeouts, SetTimeout, RECORD_HEADER_LEN,
+            FRAME_HEADER_LEN, is_timeout, operation_deadline};
+use super::compression::decompress;
+use super::encryption::{EncryptedWriter, ControlWriter};
 
-pub struct EncryptedReader {
-    inner: Rc<RefCell<dyn Read>>,
+pub struct EncryptedReader<R: Read> {
+    inner: R,
+    // Plaintext of the current data record
     buf: Vec<u8>,
+    // The record being read, kept across reads that time out so that the
+    // next read resumes it
+    frame_header: [u8; FRAME_HEADER_LEN],
+    frame_header_read: usize,
+    pending: Vec<u8>,
+    pending_read: usize,
     seq: u64,
     cursor: usize, 
-    key: OpeningKey,
+    key: <Backend as CryptoBackend>::GcmKey,
     tag_len: usize,
+    capacity: usize,
+    control_writer: Option<Weak<Mutex<dyn ControlWriter + Send>>>,
+    last_pong: Option<Instant>,
+    last_received: Instant,
+    last_ping: Option<Instant>,
+    idle_timeout: Option<IdleTimeout>,
+    closed: bool,
+    // Timeouts enforced by the reader, and the deadline of the current read
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    op_deadline: Option<Instant>,
+    set_inner_timeout: Option<SetTimeout<R>>,
+    inner_timeout_set: bool,
 }
 
-impl EncryptedReader {
-    pub fn with_capacity(capacity: usize, inner: Rc<RefCell<dyn Read>>, 
-                         key_bytes: &[u8]) -> Self {
+impl<R: Read> EncryptedReader<R> {
+    pub fn with_capacity(capacity: usize, inner: R, 
+                         key_bytes: &[u8; 16]) -> Self {
         Self {
             inner,
-            buf: Vec::with_capacity(capacity + AES_128_GCM.tag_len()),
+            buf: Vec::with_capacity(capacity + GCM_TAG_LEN),
+            frame_header: [0u8; FRAME_HEADER_LEN],
+            frame_header_read: 0,
+            pending: Vec::with_capacity(capacity + GCM_TAG_LEN),
+            pending_read: 0,
             seq: 0,
             cursor: 0,
-            key: OpeningKey::new(&AES_128_GCM, key_bytes).unwrap(),
-            tag_len: AES_128_GCM.tag_len(),
+            key: Backend::gcm_key(key_bytes).unwrap(),
+            tag_len: GCM_TAG_LEN,
+            capacity,
+            control_writer: None,
+            last_pong: None,
+            last_received: Instant::now(),
+            last_ping: None,
+            idle_timeout: None,
+            closed: false,
+            timeout: None,
+            deadline: None,
+            op_deadline: None,
+            set_inner_timeout: None,
+            inner_timeout_set: false,
         }
     }
 
-    fn fill_buf(&mut self) -> Result<()>{
-        assert!(self.buf.is_empty());
-        let r = self.inner.borrow_mut().read_u32::<NetworkEndian>();
-        let len: usize = match r {
-            Ok(n) if n as usize > self.buf.capacity() => {
-                return Err(Error::new(ErrorKind::InvalidInput,
-                                      "Input too large"));
+    /// Answer every ping received from the peer with a pong sent through
+    /// `writer`, which also sends the keepalives and close records of the
+    /// reader. Without it, pings are silently discarded.
+    pub fn reply_pings_with<W>(&mut self, writer: Weak<Mutex<EncryptedWriter<W>>>)
+        where W: Write + Send + 'static {
+        let writer: Weak<Mutex<dyn ControlWriter + Send>> = writer;
+        self.control_writer = Some(writer);
+    }
+
+    /// Tear the channel down once no record arrived from the peer for
+    /// `idle.timeout`, pinging it in between as `idle` says. Only takes
+    /// effect if reads from the inner stream time out, e.g. after
+    /// `TcpStream::set_read_timeout` with the keepalive interval: such
+    /// timeouts are then absorbed until the peer is deemed dead, at which
+    /// point the reader sends a close record and fails with `TimedOut`.
+    /// Counts from now.
+    pub fn set_idle_timeout(&mut self, idle: Option<IdleTimeout>) {
+        self.idle_timeout = idle;
+        self.last_received = Instant::now();
+    }
+
+    /// Fail every read that takes longer than `timeout` with `TimedOut`.
+    /// The record being read is kept, so that the next read resumes it.
+    /// None waits indefinitely, as do reads on a stream that times out on
+    /// its own, unless idle detection absorbs its timeouts.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) where R: TransportTimeouts {
+        self.timeout = timeout;
+        self.set_inner_timeout = Some(R::set_read_timeout);
+    }
+
+    /// Fail reads with `TimedOut` from `deadline` on, e.g. the end of a
+    /// request the application must answer in time, on top of the
+    /// per-read timeout.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) where R: TransportTimeouts {
+        self.deadline = deadline;
+        self.set_inner_timeout = Some(R::set_read_timeout);
+    }
+
+    /// When the last record of any type was read from the peer.
+    pub fn last_received(&self) -> Instant {
+        self.last_received
+    }
+
+    /// Whether the channel was closed by either side or torn down for being
+    /// idle. Reads then return end of stream.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Decrypt the records that follow with `key_bytes`, starting over at
+    /// sequence number 0. Plaintext already decrypted is still returned.
+    pub fn rekey(&mut self, key_bytes: &[u8; 16]) {
+        self.key = Backend::gcm_key(key_bytes).unwrap();
+        self.seq = 0;
+    }
+
+    /// When the last pong from the peer was read. Control records are only
+    /// processed while reading.
+    pub fn last_pong(&self) -> Option<Instant> {
+        self.last_pong
+    }
+
+    fn fill(&mut self) -> Result<()> {
+        while self.cursor == self.buf.len() && !self.closed {
+            self.buf.clear();
+            self.cursor = 0;
+            if let Err(e) = self.next_data_record() {
+                // Never hand out a record that failed its checks
+                self.buf.clear();
+                self.cursor = 0;
+                return Err(e);
             }
-            Ok(n) => n as usize, 
-            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    // Called at the start of every read that may block
+    fn start_operation(&mut self) {
+        self.op_deadline = operation_deadline(self.timeout, self.deadline);
+    }
+
+    fn write_control(&self, record_type: RecordType) -> Result<()> {
+        match self.control_writer.as_ref().and_then(|w| w.upgrade()) {
+            Some(w) => w.lock().unwrap().write_control(record_type),
+            None => Ok(()),
+        }
+    }
+
+    fn handle_control(&mut self, record_type: RecordType) -> Result<()> {
+        match record_type {
+            RecordType::Ping => self.write_control(RecordType::Pong)?,
+            RecordType::Pong => self.last_pong = Some(Instant::now()),
+            RecordType::Close => {
+                // Confirm, unless this side closed first
+                let _r = self.write_control(RecordType::Close);
+                self.closed = true;
+            },
+            RecordType::Data => {},
+        }
+        Ok(())
+    }
+
+    // Called when a read from the inner stream timed out
+    fn keep_alive(&mut self, idle: IdleTimeout) -> Result<()> {
+        let idle_for = self.last_received.elapsed();
+        if idle_for >= idle.timeout {
+            let _r = self.write_control(RecordType::Close);
+            self.closed = true;
+            return Err(Error::new(ErrorKind::TimedOut, "Peer stopped responding"));
+        }
+        if let Some(interval) = idle.keepalive_interval {
+            let ping_due = self.last_ping.map(|t| t.elapsed() >= interval).unwrap_or(true);
+            if idle_for >= interval && ping_due {
+                self.write_control(RecordType::Ping)?;
+                self.last_ping = Some(Instant::now());
+            }
+        }
+        Ok(())
+    }
+
+    // How long the next read from the inner stream may block: until the
+    // read's deadline or the next keepalive action, whichever comes first
+    fn inner_timeout(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let to_deadline = self.op_deadline.map(|d| d.saturating_duration_since(now));
+        let to_keepalive = self.idle_timeout.map(|idle| {
+            let idle_for = self.last_received.elapsed();
+            let to_dead = idle.timeout.saturating_sub(idle_for);
+            match idle.keepalive_interval {
+                // Once pinging, wake up every interval until the peer is dead
+                Some(interval) => to_dead.min(interval.checked_sub(idle_for).unwrap_or(interval)),
+                None => to_dead,
+            }
+        });
+        let timeout = match (to_deadline, to_keepalive) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
         };
-        self.buf.resize(len, 0);
+        // A zero timeout means none to sockets
+        timeout.map(|t| t.max(Duration::from_millis(1)))
+    }
 
-        let mut nonce = [0u8; 12];
-        let mut read = 0;
-        while read < nonce.len() {
-            let r = self.inner.borrow_mut().read(&mut nonce[read..]);
+    fn arm_inner_timeout(&mut self) -> Result<()> {
+        let set_inner_timeout = match self.set_inner_timeout {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        let timeout = self.inner_timeout();
+        if timeout.is_some() || self.inner_timeout_set {
+            set_inner_timeout(&mut self.inner, timeout)?;
+            self.inner_timeout_set = timeout.is_some();
+        }
+        Ok(())
+    }
+
+    fn read_record(&mut self) -> Result<()> {
+        loop {
+            if self.op_deadline.map_or(false, |d| Instant::now() >= d) {
+                return Err(Error::new(ErrorKind::TimedOut, "Secure channel read timed out"));
+            }
+            self.arm_inner_timeout()?;
+            match self.read_frame() {
+                Err(ref e) if is_timeout(e) && self.idle_timeout.is_some() => {
+                    let idle = self.idle_timeout.unwrap();
+                    self.keep_alive(idle)?;
+                },
+                // The channel's own timeout, checked at the top
+                Err(ref e) if is_timeout(e) && self.op_deadline.is_some() => {},
+                r => return r,
+            }
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<()>{
+        assert!(self.buf.is_empty());
+        while self.frame_header_read < FRAME_HEADER_LEN {
+            let r = self.inner.read(&mut self.frame_header[self.frame_header_read..]);
             match r {
                 Ok(0) => {
                     return Err(Error::new(ErrorKind::UnexpectedEof,
                                           "Failed to read"));
                 }
-                Ok(n) => read += n,
+                Ok(n) => self.frame_header_read += n,
                 Err(e) => { return Err(e); }
             }
         }
+        let len = (&self.frame_header[..4]).read_u32::<NetworkEndian>()? as usize;
+        if len > self.capacity + self.tag_len {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  "Input too large"));
+        }
+        self.pending.resize(len, 0);
 
-        let mut read = 0;
-        while read < len {
-            let r = self.inner.borrow_mut().read(&mut self.buf[read..]);
+        while self.pending_read < len {
+            let r = self.inner.read(&mut self.pending[self.pending_read..]);
             match r {
                 Ok(0) => {
                     return Err(Error::new(ErrorKind::UnexpectedEof,
                                           "Failed to read"));
                 }
-                Ok(n) => read += n,
+                Ok(n) => self.pending_read += n,
                 Err(e) => { return Err(e); }
             }
         }
+        self.frame_header_read = 0;
+        self.pending_read = 0;
+        self.last_received = Instant::now();
 
+        let mut nonce = [0u8; GCM_NONCE_LEN];
+        nonce.copy_from_slice(&self.frame_header[4..]);
+        std::mem::swap(&mut self.buf, &mut self.pending);
+        self.pending.clear();
         decrypt(&self.key, &nonce, &mut self.buf[..])?;
         self.buf.resize(len-self.tag_len, 0);
 
         self.cursor = 0;
         Ok(())
     }
+
+    // Decrypt records until a data record arrives, handling the control
+    // records read on the way.
+    fn next_data_record(&mut self) -> Result<()> {
+        loop {
+            self.read_record()?;
+            let seq = self.buf.as_slice().read_u64::<NetworkEndian>()?;
+            if seq != self.seq {
+                return Err(Error::new(ErrorKind::InvalidData,
+                                      "Secure channel integrity error"));
+            }
+            self.seq += 1;
+            self.cursor += std::mem::size_of::<u64>();
+            let record_type = self.buf.get(self.cursor).cloned()
+                .and_then(RecordType::from_u8)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                                          "Unknown record type"))?;
+            self.cursor += 1;
+            let compression = self.buf.get(self.cursor).cloned()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                                          "Truncated record header"))?;
+            self.cursor += 1;
+            if compression != 0 {
+                let limit = self.capacity.saturating_sub(RECORD_HEADER_LEN);
+                let payload = decompress(compression, &self.buf[self.cursor..], limit)?;
+                self.buf.truncate(self.cursor);
+                self.buf.extend_from_slice(&payload[..]);
+            }
+            if record_type == RecordType::Data {
+                return Ok(());
+            }
+            self.buf.clear();
+            self.cursor = 0;
+            self.handle_control(record_type)?;
+            if self.closed {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Plaintext is buffered one record at a time, so small or line-based reads
+/// only decrypt a new record once the current one is used up.
+impl<R: Read> BufRead for EncryptedReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.cursor == self.buf.len() && !self.closed {
+            self.start_operation();
+        }
+        self.fill()?;
+        Ok(&self.buf[self.cursor..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.cursor = usize::min(self.cursor + amt, self.buf.len());
+    }
 }
 
-impl Read for EncryptedReader {
+impl<R: Read> Read for EncryptedReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if buf.is_empty() {
             return Ok(0);
         }
 
+        self.start_operation();
         let mut read = 0;
         while read < buf.len() {
-            if self.buf.is_empty() {
-                self.fill_buf()?;
-                let seq = self.buf.as_slice().read_u64::<NetworkEndian>()?;
-                if seq != self.seq {
-                    return Err(Error::new(ErrorKind::InvalidData,
-                                          "Secure channel integrity error"));
-                }
-                self.seq += 1;
-                self.cursor += std::mem::size_of::<u64>();
-            };
-            let to_read = usize::min(self.buf.len()-self.cursor, buf.len()-read);
+            match self.fill() {
+                Ok(()) => {},
+                // Return what was read; the next read resumes the record
+                Err(ref e) if is_timeout(e) && read > 0 => break,
+                Err(e) => return Err(e),
+            }
+            let available = &self.buf[self.cursor..];
+            if available.is_empty() {
+                // Closed
+                break;
+            }
+            let to_read = usize::min(available.len(), buf.len()-read);
             (&mut buf[read..(read+to_read)])
-                .clone_from_slice(
-                    &self.buf[self.cursor..(self.cursor+to_read)]);
-            self.cursor += to_read;
+                .clone_from_slice(&available[..to_read]);
+            self.consume(to_read);
             read += to_read;
-            if self.cursor == self.buf.len() {
-                self.buf.clear();
-                self.cursor = 0;
-            }
         }
-        Ok(buf.len())
+        Ok(read)
     }
 }
 
-pub fn decrypt<'a>(key: &OpeningKey, nonce: &[u8; 12],
+pub fn decrypt<'a>(key: &<Backend as CryptoBackend>::GcmKey, nonce: &[u8; GCM_NONCE_LEN],
                    ciphertext_and_tag_modified_in_place: &'a mut [u8]) -> 
 Result<&'a mut [u8]> {
-    let nonce = Nonce::assume_unique_for_key(*nonce);
-    open_in_place(key, nonce, Aad::empty(), 0, 
-                  ciphertext_and_tag_modified_in_place)
+    let len = Backend::gcm_open_in_place(key, nonce, &[],
+                                         ciphertext_and_tag_modified_in_place)
         .map_err(|_| Error::new(ErrorKind::InvalidData,
-                                    "Secure channel integrity error"))
+                                    "Secure channel integrity error"))?;
+    Ok(&mut ciphertext_and_tag_modified_in_place[..len])
 }
@@ -1,60 +1,133 @@
 use std::io::{Write, Result, Error, ErrorKind};
-use std::rc::Rc;
-use std::cell::RefCell;
-use ring::aead::{SealingKey, Nonce, Aad, seal_in_place, AES_128_GCM};
-use ring::rand::{SystemRandom, SecureRandom};
+use std::time::{Duration, Instant};
+use crate::backend::{Backend, CryptoBackend, GCM_NONCE_LEN, GCM_TAG_LEN};
+use crate::random::RandomState;
 use byteorder::{WriteBytesExt, NetworkEndian};
+use super::{RecordType, TransportTimeouts, SetTimeout, RECORD_HEADER_LEN, FRAME_HEADER_LEN,
+            is_timeout, operation_deadline};
+use super::compression::{Compression, compress};
 
-pub struct EncryptedWriter {
-    inner: Rc<RefCell<dyn Write>>,
+pub struct EncryptedWriter<W: Write> {
+    inner: W,
     buf: Vec<u8>,
-    key: SealingKey,
-    rand: SystemRandom,
+    key: <Backend as CryptoBackend>::GcmKey,
+    rand: RandomState,
     seq: u64,
     tag_len: usize,
     capacity: usize,
+    compression: Compression,
+    // Set once a close record was sent; nothing may follow it
+    closed: bool,
+    // Encrypted record being sent, kept across calls that time out
+    frame: Vec<u8>,
+    frame_written: usize,
+    // Timeouts enforced by the writer, and the deadline of the current write
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    op_deadline: Option<Instant>,
+    set_inner_timeout: Option<SetTimeout<W>>,
+    inner_timeout_set: bool,
     // If the inner writer panics in a call to write, we don't want to
     // write the buffered data a second time in BufWriter's destructor. This
     // flag tells the Drop impl if it should skip the flush.
     panicked: bool,
 }
 
-impl EncryptedWriter {
-    pub fn with_capacity(capacity: usize, inner: Rc<RefCell<dyn Write>>, 
+impl<W: Write> EncryptedWriter<W> {
+    pub fn with_capacity(capacity: usize, inner: W, 
                          key_bytes: &[u8; 16]) -> Self {
         Self {
             inner,
-            buf: Vec::with_capacity(capacity + AES_128_GCM.tag_len()),
-            key: SealingKey::new(&AES_128_GCM, &key_bytes[..]).unwrap(),
-            rand: SystemRandom::new(),
+            buf: Vec::with_capacity(capacity + GCM_TAG_LEN),
+            key: Backend::gcm_key(key_bytes).unwrap(),
+            rand: RandomState::new(),
             seq: 0,
-            tag_len: AES_128_GCM.tag_len(),
+            tag_len: GCM_TAG_LEN,
             capacity,
+            compression: Compression::None,
+            closed: false,
+            frame: Vec::with_capacity(FRAME_HEADER_LEN + capacity + GCM_TAG_LEN),
+            frame_written: 0,
+            timeout: None,
+            deadline: None,
+            op_deadline: None,
+            set_inner_timeout: None,
+            inner_timeout_set: false,
             panicked: false,
         }
     }
 
+    /// Fail every write or flush that takes longer than `timeout` with
+    /// `TimedOut`. The record being sent is kept, so that the next write or
+    /// flush resumes it. None waits indefinitely.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) where W: TransportTimeouts {
+        self.timeout = timeout;
+        self.set_inner_timeout = Some(W::set_write_timeout);
+    }
+
+    /// Fail writes and flushes with `TimedOut` from `deadline` on, on top of
+    /// the per-write timeout.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) where W: TransportTimeouts {
+        self.deadline = deadline;
+        self.set_inner_timeout = Some(W::set_write_timeout);
+    }
+
+    /// Compress the payload of subsequent data records. A record is sent
+    /// uncompressed if compression does not make it smaller.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Flush the data buffered under the current key, then encrypt the
+    /// records that follow with `key_bytes`, starting over at sequence
+    /// number 0.
+    pub fn rekey(&mut self, key_bytes: &[u8; 16]) -> Result<()> {
+        self.flush()?;
+        self.key = Backend::gcm_key(key_bytes).unwrap();
+        self.seq = 0;
+        Ok(())
+    }
+
+    fn compress_buf(&mut self) -> Result<()> {
+        if self.compression == Compression::None || self.buf.len() <= RECORD_HEADER_LEN {
+            return Ok(());
+        }
+        let compressed = compress(self.compression, &self.buf[RECORD_HEADER_LEN..])?;
+        if compressed.len() < self.buf.len() - RECORD_HEADER_LEN {
+            self.buf.truncate(RECORD_HEADER_LEN);
+            self.buf[RECORD_HEADER_LEN - 1] = self.compression as u8;
+            self.buf.extend_from_slice(&compressed[..]);
+        }
+        Ok(())
+    }
+
+    // Send the pending frame, then the buffered record, if any
     fn flush_buf(&mut self) -> Result<()> {
+        self.write_frame()?;
         if self.buf.is_empty() {
             return Ok(());
         }
+        self.compress_buf()?;
         self.buf.resize(self.buf.len()+self.tag_len, 0);
-        let mut nonce = [0u8; 12];
+        let mut nonce = [0u8; GCM_NONCE_LEN];
         let len = encrypt(&self.key, &self.rand, &mut nonce,
                 &mut self.buf[..]).unwrap();
+        self.frame.clear();
+        self.frame.write_u32::<NetworkEndian>(len as u32)?;
+        self.frame.extend_from_slice(&nonce[..]);
+        self.frame.extend_from_slice(&self.buf[..]);
+        self.buf.clear();
+        self.write_frame()
+    }
 
-        self.panicked = true;
-        let r = self.inner.borrow_mut().write_u32::<NetworkEndian>(len as u32);
-        self.panicked = false;
-        match r {
-            Ok(_) => {}
-            Err(e) => return Err(e),
-        }
-
-        let mut written = 0;
-        while written < nonce.len() {
+    fn write_frame(&mut self) -> Result<()> {
+        while self.frame_written < self.frame.len() {
+            if self.op_deadline.map_or(false, |d| Instant::now() >= d) {
+                return Err(Error::new(ErrorKind::TimedOut, "Secure channel write timed out"));
+            }
+            self.arm_inner_timeout()?;
             self.panicked = true;
-            let r = self.inner.borrow_mut().write(&nonce[written..]);
+            let r = self.inner.write(&self.frame[self.frame_written..]);
             self.panicked = false;
 
             match r {
@@ -62,59 +135,122 @@ impl EncryptedWriter {
                     return Err(Error::new(ErrorKind::WriteZero,
                                          "Failed to write the buffered data"));
                 }
-                Ok(n) => written += n,
+                Ok(n) => self.frame_written += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                // The channel's own timeout, checked at the top
+                Err(ref e) if is_timeout(e) && self.op_deadline.is_some() => {},
                 Err(e) => { return Err(e); }
             }
         }
+        self.frame.clear();
+        self.frame_written = 0;
+        Ok(())
+    }
 
-        let mut written = 0;
-        while written < len {
-            self.panicked = true;
-            let r = self.inner.borrow_mut().write(&self.buf[written..]);
-            self.panicked = false;
+    // Bound the next write to the inner stream by what is left of the
+    // current write's time
+    fn arm_inner_timeout(&mut self) -> Result<()> {
+        let set_inner_timeout = match self.set_inner_timeout {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        // A zero timeout means none to sockets
+        let timeout = self.op_deadline
+            .map(|d| d.saturating_duration_since(now).max(Duration::from_millis(1)));
+        if timeout.is_some() || self.inner_timeout_set {
+            set_inner_timeout(&mut self.inner, timeout)?;
+            self.inner_timeout_set = timeout.is_some();
+        }
+        Ok(())
+    }
 
-            match r {
-                Ok(0) => {
-                    return Err(Error::new(ErrorKind::WriteZero,
-                                         "Failed to write the buffered data"));
-                }
-                Ok(n) => written += n,
-                Err(e) => { return Err(e); }
-            }
+    // Called at the start of every write or flush that may block
+    fn start_operation(&mut self) {
+        self.op_deadline = operation_deadline(self.timeout, self.deadline);
+    }
+
+    /// Whether a close record was sent. Writes then fail with `NotConnected`.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn start_record(&mut self, record_type: RecordType) -> Result<()> {
+        if self.closed {
+            return Err(Error::new(ErrorKind::NotConnected, "Secure channel closed"));
         }
-        self.buf.clear();
+        self.buf.write_u64::<NetworkEndian>(self.seq)?;
+        self.buf.write_u8(record_type as u8)?;
+        // Compression flag, set when the record is flushed
+        self.buf.write_u8(Compression::None as u8)?;
+        self.seq += 1;
         Ok(())
     }
+
+    /// Flush any buffered data, then send a record of the given type with no
+    /// payload. Closing a closed writer does nothing.
+    pub fn write_control(&mut self, record_type: RecordType) -> Result<()> {
+        if self.closed && record_type == RecordType::Close {
+            return Ok(());
+        }
+        self.start_operation();
+        self.flush_buf()?;
+        self.start_record(record_type)?;
+        let r = self.flush_buf().and_then(|()| self.inner.flush());
+        if record_type == RecordType::Close {
+            self.closed = true;
+        }
+        r
+    }
 }
 
-impl Write for EncryptedWriter {
+impl<W: Write> Write for EncryptedWriter<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         if buf.is_empty() {
             return Ok(0);
         }
+        self.start_operation();
         let mut written = 0;
         let len = buf.len();
         while written < len {
             if self.buf.len() == 0 {
-                self.buf.write_u64::<NetworkEndian>(self.seq)?;
-                self.seq += 1;
+                self.start_record(RecordType::Data)?;
             }
             let to_write = usize::min(self.capacity - self.buf.len(), 
                                       buf.len() - written);
             written += self.buf.write(&buf[written..(written+to_write)])?;
             if self.buf.len() == self.capacity {
-                self.flush_buf()?;
+                match self.flush_buf() {
+                    Ok(()) => {},
+                    // What was written is in a record; the next write or
+                    // flush sends it
+                    Err(ref e) if is_timeout(e) && written > 0 => break,
+                    Err(e) => return Err(e),
+                }
             }
         }
         Ok(written)
     }
 
     fn flush(&mut self) -> Result<()> {
-        self.flush_buf().and_then(|()| self.inner.borrow_mut().flush())
+        self.start_operation();
+        self.flush_buf().and_then(|()| self.inner.flush())
+    }
+}
+
+/// Lets an `EncryptedReader` answer pings without knowing the type of the
+/// stream the writer sends on.
+pub trait ControlWriter {
+    fn write_control(&mut self, record_type: RecordType) -> Result<()>;
+}
+
+impl<W: Write> ControlWriter for EncryptedWriter<W> {
+    fn write_control(&mut self, record_type: RecordType) -> Result<()> {
+        EncryptedWriter::write_control(self, record_type)
     }
 }
 
-impl Drop for EncryptedWriter {
+impl<W: Write> Drop for EncryptedWriter<W> {
     fn drop(&mut self) {
         if !self.panicked {
             let _r = self.flush_buf();
@@ -122,12 +258,11 @@ impl Drop for EncryptedWriter {
     }
 }
 
-fn encrypt(key: &SealingKey, rand: &SystemRandom, nonce: &mut [u8; 12],
-               in_out: &mut [u8]) -> Result<usize> {
-    rand.fill(nonce).unwrap();
-    let nonce = Nonce::assume_unique_for_key(*nonce);
-    seal_in_place(key, nonce, Aad::empty(), in_out, 
-                            key.algorithm().tag_len())
+fn encrypt(key: &<Backend as CryptoBackend>::GcmKey, rand: &RandomState,
+           nonce: &mut [u8; GCM_NONCE_LEN], in_out: &mut [u8]) -> Result<usize> {
+    rand.fill(nonce);
+    Backend::gcm_seal_in_place(key, nonce, &[], in_out)
+        .map(|()| in_out.len())
         .map_err(|_| Error::new(ErrorKind::InvalidData,
                                     "Secure channel encryption error"))
 }
@@ -1,44 +1,392 @@
 pub mod encryption;
 pub mod decryption;
+pub mod compression;
+pub mod mux;
 
-use std::io::{Read, Write, Result};
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::io::{Read, BufRead, Write, Result, Error, ErrorKind};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::backend::GCM_NONCE_LEN;
 use self::encryption::*;
 use self::decryption::*;
+use self::compression::Compression;
 
-pub struct SecureChannel {
-    w: EncryptedWriter,
-    r: EncryptedReader,
+/// Plaintext header of every record: sequence number, record type, and
+/// compression flag.
+pub(crate) const RECORD_HEADER_LEN: usize = 8 + 1 + 1;
+
+/// Length prefix and nonce that precede the ciphertext of a record.
+pub(crate) const FRAME_HEADER_LEN: usize = 4 + GCM_NONCE_LEN;
+
+/// Type of a record, authenticated along with its sequence number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordType {
+    /// Application data.
+    Data = 0,
+    /// Keepalive request; the peer answers with a `Pong`.
+    Ping = 1,
+    Pong = 2,
+    /// The sender will send nothing more; the peer answers with a `Close` of
+    /// its own and reads end of stream from then on.
+    Close = 3,
 }
 
-impl SecureChannel {
-    pub fn new(inner: impl Read + Write + 'static, key_bytes: &[u8; 16]) -> Self {
-        Self::with_capacity(0x100000, inner, key_bytes)
+impl RecordType {
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(RecordType::Data),
+            1 => Some(RecordType::Ping),
+            2 => Some(RecordType::Pong),
+            3 => Some(RecordType::Close),
+            _ => None,
+        }
     }
+}
+
+/// When a `SecureChannel` gives up on a silent peer, see
+/// `SecureChannel::set_idle_timeout`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdleTimeout {
+    /// Time without any record from the peer after which the channel is torn
+    /// down.
+    pub timeout: Duration,
+    /// Ping the peer after this much silence, so that a peer that is alive
+    /// but has nothing to send answers in time. None to rely on the peer's
+    /// own traffic or keepalives.
+    pub keepalive_interval: Option<Duration>,
+}
 
-    pub fn with_capacity(capacity: usize, inner: impl Read + Write + 'static,
-                         key_bytes: &[u8; 16]) -> Self {
-        let inner = Rc::new(RefCell::new(inner));
+impl IdleTimeout {
+    /// Pings after a third of `timeout`, so that two pings go unanswered
+    /// before the peer is deemed dead.
+    pub fn new(timeout: Duration) -> Self {
         Self {
-            w: EncryptedWriter::with_capacity(capacity, inner.clone(), key_bytes),
-            r: EncryptedReader::with_capacity(capacity, inner.clone(), key_bytes),
+            timeout,
+            keepalive_interval: Some(timeout / 3),
         }
     }
 }
 
-impl Write for SecureChannel {
+/// Streams whose blocking reads and writes can be bounded in time, e.g. a
+/// `TcpStream`. A `SecureChannel` on such a stream enforces its own read and
+/// write timeouts by setting the stream's before each blocking call. A
+/// timeout of `None` blocks indefinitely.
+pub trait TransportTimeouts {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()>;
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()>;
+}
+
+impl TransportTimeouts for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl TransportTimeouts for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        std::os::unix::net::UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+/// Sets the timeout of one direction of a stream, see `TransportTimeouts`.
+pub(crate) type SetTimeout<S> = fn(&mut S, Option<Duration>) -> Result<()>;
+
+/// When an operation starting now must be done by: the earlier of its
+/// timeout and the channel's deadline.
+pub(crate) fn operation_deadline(timeout: Option<Duration>,
+                                 deadline: Option<Instant>) -> Option<Instant> {
+    match (timeout.map(|t| Instant::now() + t), deadline) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Whether an error of the inner stream is one of its timeouts.
+pub(crate) fn is_timeout(e: &Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+}
+
+/// A stream that a `SecureChannel` can protect, split into the end records
+/// are read from and the end they are written to.
+pub trait SecureTransport {
+    type Reader: Read;
+    type Writer: Write + Send + 'static;
+
+    fn into_ends(self) -> (Self::Reader, Self::Writer);
+}
+
+/// Transports whose reading and writing ends do not share a lock, so that a
+/// read blocked on one thread does not hold up writes on another. Only these
+/// can be split, see `SecureChannel::into_split`.
+pub trait IndependentEnds: SecureTransport {}
+
+/// Any stream, e.g. a TCP connection, a serial port, or a pipe. Both ends
+/// share it behind a lock, which is only held for one read or write at a
+/// time; such a channel cannot be split, see `Halves`.
+impl<T: Read + Write + Send + 'static> SecureTransport for T {
+    type Reader = Shared<T>;
+    type Writer = Shared<T>;
+
+    fn into_ends(self) -> (Shared<T>, Shared<T>) {
+        let shared = Shared(Arc::new(Mutex::new(self)));
+        (shared.clone(), shared)
+    }
+}
+
+/// Separate reading and writing ends of one connection, e.g. a `TcpStream`
+/// and its `try_clone()`.
+pub struct Halves<R, W>(pub R, pub W);
+
+impl Halves<TcpStream, TcpStream> {
+    /// Both ends of `stream`, the writing one on a clone of its socket.
+    pub fn tcp(stream: TcpStream) -> Result<Self> {
+        let writer = stream.try_clone()?;
+        Ok(Halves(stream, writer))
+    }
+}
+
+impl<R: Read, W: Write + Send + 'static> SecureTransport for Halves<R, W> {
+    type Reader = R;
+    type Writer = W;
+
+    fn into_ends(self) -> (R, W) {
+        (self.0, self.1)
+    }
+}
+
+impl<R: Read, W: Write + Send + 'static> IndependentEnds for Halves<R, W> {}
+
+/// A stream used by both ends of a channel.
+pub struct Shared<T>(Arc<Mutex<T>>);
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T: Read> Read for Shared<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl<T: TransportTimeouts> TransportTimeouts for Shared<T> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.0.lock().unwrap().set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.0.lock().unwrap().set_write_timeout(timeout)
+    }
+}
+
+impl<T: Write> Write for Shared<T> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        self.w.write(buf)
+        self.0.lock().unwrap().write(buf)
     }
 
     fn flush(&mut self) -> Result<()> {
-        self.w.flush()
+        self.0.lock().unwrap().flush()
     }
 }
 
-impl Read for SecureChannel {
+pub struct SecureChannel<T: SecureTransport> {
+    w: Arc<Mutex<EncryptedWriter<T::Writer>>>,
+    r: EncryptedReader<T::Reader>,
+}
+
+impl<T: SecureTransport> SecureChannel<T> {
+    /// Protect `inner` with a key both sides already hold, e.g. the channel
+    /// key of an attestation. No handshake takes place: the first record is
+    /// application data.
+    pub fn new(inner: T, key_bytes: &[u8; 16]) -> Self {
+        Self::with_capacity(0x100000, inner, key_bytes)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: T, key_bytes: &[u8; 16]) -> Self {
+        let (reader, writer) = inner.into_ends();
+        let w = Arc::new(Mutex::new(
+                EncryptedWriter::with_capacity(capacity, writer, key_bytes)));
+        let mut r = EncryptedReader::with_capacity(capacity, reader, key_bytes);
+        r.reply_pings_with(Arc::downgrade(&w));
+        Self { w, r }
+    }
+
+    /// Split the channel into a reading and a writing half that can be moved
+    /// to different threads. Each half keeps its own key and sequence number;
+    /// the reader still answers pings through the writer as long as the
+    /// writer half is alive. The transport must have independent ends, e.g.
+    /// `Halves::tcp`, so that a read that blocks does not hold up writes.
+    pub fn into_split(self) -> (EncryptedReader<T::Reader>, WriteHalf<T::Writer>)
+        where T: IndependentEnds {
+            (self.r, WriteHalf { w: self.w })
+        }
+
+    /// Switch both directions to `key_bytes`, e.g. the channel key of a new
+    /// attestation. The peer must switch at the same point in the stream:
+    /// no record may be in flight in either direction, which is the case
+    /// right after a request-response exchange such as the attestation.
+    pub fn rekey(&mut self, key_bytes: &[u8; 16]) -> Result<()> {
+        self.w.lock().unwrap().rekey(key_bytes)?;
+        self.r.rekey(key_bytes);
+        Ok(())
+    }
+
+    /// Compress outgoing data records. The peer decompresses transparently as
+    /// long as it was built with the same codec feature.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.w.lock().unwrap().set_compression(compression);
+    }
+
+    /// Send an authenticated keepalive. The peer answers with a pong as soon
+    /// as it reads the ping, without any application data being exchanged.
+    pub fn ping(&mut self) -> Result<()> {
+        self.w.lock().unwrap().write_control(RecordType::Ping)
+    }
+
+    /// When the last pong was received, if any. Pongs are processed while
+    /// reading from the channel.
+    pub fn last_pong(&self) -> Option<Instant> {
+        self.r.last_pong()
+    }
+
+    /// Detect a dead peer: once nothing was read from the peer for
+    /// `idle.timeout`, send it a close record and fail reads with
+    /// `TimedOut`, so that the session can be dropped. The inner stream must
+    /// time out reads for this to work, at least as often as the keepalive
+    /// interval, e.g. with `TcpStream::set_read_timeout`; the channel absorbs
+    /// those timeouts until the peer is deemed dead. None turns detection
+    /// off, so that read timeouts reach the caller again.
+    pub fn set_idle_timeout(&mut self, idle: Option<IdleTimeout>) {
+        self.r.set_idle_timeout(idle);
+    }
+
+    /// When the last record of any type, e.g. a pong, was read from the
+    /// peer.
+    pub fn last_received(&self) -> Instant {
+        self.r.last_received()
+    }
+
+    /// Fail reads that take longer than `timeout` with `TimedOut`, whatever
+    /// the timeout of the inner stream. A read that times out halfway
+    /// through a record keeps what it got, and the next read resumes the
+    /// record, so that the channel stays usable. None waits indefinitely.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>)
+        where T::Reader: TransportTimeouts {
+            self.r.set_timeout(timeout);
+        }
+
+    /// Fail writes and flushes that take longer than `timeout` with
+    /// `TimedOut`. As with reads, the record being sent is resumed by the
+    /// next write or flush. None waits indefinitely.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>)
+        where T::Writer: TransportTimeouts {
+            self.w.lock().unwrap().set_timeout(timeout);
+        }
+
+    /// Fail reads with `TimedOut` from `deadline` on, e.g. to bound a whole
+    /// request-response exchange rather than each read. Applies on top of
+    /// the read timeout; None clears it.
+    pub fn set_read_deadline(&mut self, deadline: Option<Instant>)
+        where T::Reader: TransportTimeouts {
+            self.r.set_deadline(deadline);
+        }
+
+    /// Fail writes and flushes with `TimedOut` from `deadline` on. Applies
+    /// on top of the write timeout; None clears it.
+    pub fn set_write_deadline(&mut self, deadline: Option<Instant>)
+        where T::Writer: TransportTimeouts {
+            self.w.lock().unwrap().set_deadline(deadline);
+        }
+
+    /// Tell the peer that nothing more will be sent, flushing what is
+    /// buffered first. Reads still return what the peer sends until its own
+    /// close record, after which they return end of stream; writes fail.
+    pub fn close(&mut self) -> Result<()> {
+        self.w.lock().unwrap().write_control(RecordType::Close)
+    }
+
+    /// Whether either side closed the channel or it was torn down for being
+    /// idle.
+    pub fn is_closed(&self) -> bool {
+        self.r.is_closed() || self.w.lock().unwrap().is_closed()
+    }
+}
+
+impl<T: SecureTransport> Write for SecureChannel<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.w.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.w.lock().unwrap().flush()
+    }
+}
+
+impl<T: SecureTransport> Read for SecureChannel<T> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         self.r.read(buf)
     }
 }
+
+impl<T: SecureTransport> BufRead for SecureChannel<T> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.r.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.r.consume(amt)
+    }
+}
+
+/// Writing half of a `SecureChannel`, see `SecureChannel::into_split`.
+pub struct WriteHalf<W: Write> {
+    w: Arc<Mutex<EncryptedWriter<W>>>,
+}
+
+impl<W: Write> WriteHalf<W> {
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.w.lock().unwrap().set_compression(compression);
+    }
+
+    /// Send a keepalive. The pong is seen by the reading half.
+    pub fn ping(&mut self) -> Result<()> {
+        self.w.lock().unwrap().write_control(RecordType::Ping)
+    }
+
+    /// See `SecureChannel::close`.
+    pub fn close(&mut self) -> Result<()> {
+        self.w.lock().unwrap().write_control(RecordType::Close)
+    }
+
+    /// See `SecureChannel::set_write_timeout`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) where W: TransportTimeouts {
+        self.w.lock().unwrap().set_timeout(timeout);
+    }
+
+    /// See `SecureChannel::set_write_deadline`.
+    pub fn set_write_deadline(&mut self, deadline: Option<Instant>) where W: TransportTimeouts {
+        self.w.lock().unwrap().set_deadline(deadline);
+    }
+}
+
+impl<W: Write> Write for WriteHalf<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.w.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.w.lock().unwrap().flush()
+    }
+}
@@ -0,0 +1,326 @@
+// Lightweight stream multiplexing on top of a single channel. Each frame is
+// (stream id: u32, kind: u8, length: u32, payload) in network byte order.
+use std::io::{Read, Write, Result, Error, ErrorKind};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use byteorder::{ReadBytesExt, WriteBytesExt, NetworkEndian};
+
+/// Bytes a sender may have in flight on one stream before it must wait for
+/// the receiver to consume them.
+const INITIAL_WINDOW: u32 = 0x40000;
+const MAX_FRAME_LEN: u32 = 0x4000;
+/// Streams the peer may have open at a time unless set otherwise, see
+/// `Multiplexer::set_max_incoming_streams`.
+const DEFAULT_MAX_INCOMING_STREAMS: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FrameKind {
+    Data = 0,
+    WindowUpdate = 1,
+    Close = 2,
+}
+
+impl FrameKind {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(FrameKind::Data),
+            1 => Some(FrameKind::WindowUpdate),
+            2 => Some(FrameKind::Close),
+            _ => None,
+        }
+    }
+}
+
+struct StreamState {
+    recv_buf: VecDeque<u8>,
+    // Bytes consumed by the application but not yet announced to the peer
+    unacked: u32,
+    send_window: u32,
+    remote_closed: bool,
+}
+
+impl StreamState {
+    fn new() -> Self {
+        Self {
+            recv_buf: VecDeque::new(),
+            unacked: 0,
+            send_window: INITIAL_WINDOW,
+            remote_closed: false,
+        }
+    }
+}
+
+struct MuxState<T: Read + Write> {
+    channel: T,
+    streams: HashMap<u32, StreamState>,
+    incoming: VecDeque<u32>,
+    next_id: u32,
+    max_remote_id: Option<u32>,
+    // Streams opened by the peer that are not dropped yet, accepted or not
+    remote_streams: usize,
+    max_remote_streams: usize,
+}
+
+impl<T: Read + Write> MuxState<T> {
+    fn is_local(&self, id: u32) -> bool {
+        id % 2 == self.next_id % 2
+    }
+
+    fn write_frame(&mut self, id: u32, kind: FrameKind, payload: &[u8]) -> Result<()> {
+        self.channel.write_u32::<NetworkEndian>(id)?;
+        self.channel.write_u8(kind as u8)?;
+        self.channel.write_u32::<NetworkEndian>(payload.len() as u32)?;
+        self.channel.write_all(payload)?;
+        self.channel.flush()
+    }
+
+    /// Read one frame from the channel and dispatch it to its stream.
+    fn pump(&mut self) -> Result<()> {
+        let id = self.channel.read_u32::<NetworkEndian>()?;
+        let kind = FrameKind::from_u8(self.channel.read_u8()?)
+            .ok_or_else(|| protocol_error("Unknown frame kind"))?;
+        let len = self.channel.read_u32::<NetworkEndian>()?;
+        if len > MAX_FRAME_LEN {
+            return Err(protocol_error("Frame too large"));
+        }
+        let mut payload = vec![0u8; len as usize];
+        self.channel.read_exact(&mut payload[..])?;
+
+        if !self.streams.contains_key(&id) {
+            // Frames for streams we already dropped are discarded.
+            let is_new = !self.is_local(id) &&
+                self.max_remote_id.map_or(true, |max| id > max);
+            if !is_new {
+                return Ok(());
+            }
+            self.max_remote_id = Some(id);
+            if self.remote_streams >= self.max_remote_streams {
+                // Refuse the stream, whose later frames are then discarded
+                // like those of a dropped one
+                return self.write_frame(id, FrameKind::Close, &[]);
+            }
+            self.remote_streams += 1;
+            self.streams.insert(id, StreamState::new());
+            self.incoming.push_back(id);
+        }
+
+        let stream = self.streams.get_mut(&id).unwrap();
+        match kind {
+            FrameKind::Data => {
+                if stream.recv_buf.len() + payload.len() > INITIAL_WINDOW as usize {
+                    return Err(protocol_error("Flow control window exceeded"));
+                }
+                stream.recv_buf.extend(payload);
+            },
+            FrameKind::WindowUpdate => {
+                let increment = (&payload[..]).read_u32::<NetworkEndian>()?;
+                stream.send_window = stream.send_window.saturating_add(increment);
+            },
+            FrameKind::Close => stream.remote_closed = true,
+        }
+        Ok(())
+    }
+}
+
+/// Runs several independent, flow-controlled streams over one channel, e.g. a
+/// `SecureChannel`, so that a single attestation can serve many conversations.
+/// Both ends must agree on which one is the initiator so that their stream
+/// IDs do not collide.
+pub struct Multiplexer<T: Read + Write> {
+    state: Rc<RefCell<MuxState<T>>>,
+}
+
+impl<T: Read + Write> Multiplexer<T> {
+    pub fn new(channel: T, initiator: bool) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(MuxState {
+                channel,
+                streams: HashMap::new(),
+                incoming: VecDeque::new(),
+                next_id: if initiator { 1 } else { 2 },
+                max_remote_id: None,
+                remote_streams: 0,
+                max_remote_streams: DEFAULT_MAX_INCOMING_STREAMS,
+            })),
+        }
+    }
+
+    /// Limit the streams the peer may have open at a time, 64 by default, so
+    /// that it cannot make this end buffer data for any number of them. A
+    /// stream opened beyond the limit is closed right away, which the peer
+    /// sees as a closed stream.
+    pub fn set_max_incoming_streams(&self, max: usize) {
+        self.state.borrow_mut().max_remote_streams = max;
+    }
+
+    /// Open a new stream. The peer learns about it with the first frame sent.
+    pub fn open_stream(&self) -> MuxStream<T> {
+        let mut state = self.state.borrow_mut();
+        let id = state.next_id;
+        state.next_id += 2;
+        state.streams.insert(id, StreamState::new());
+        MuxStream { id, state: self.state.clone() }
+    }
+
+    /// Block until the peer opens a new stream.
+    pub fn accept_stream(&self) -> Result<MuxStream<T>> {
+        let mut state = self.state.borrow_mut();
+        loop {
+            if let Some(id) = state.incoming.pop_front() {
+                return Ok(MuxStream { id, state: self.state.clone() });
+            }
+            state.pump()?;
+        }
+    }
+}
+
+/// One logical stream of a `Multiplexer`. Dropping it closes the stream.
+pub struct MuxStream<T: Read + Write> {
+    id: u32,
+    state: Rc<RefCell<MuxState<T>>>,
+}
+
+impl<T: Read + Write> MuxStream<T> {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl<T: Read + Write> Read for MuxStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.state.borrow_mut();
+        loop {
+            let stream = state.streams.get(&self.id).unwrap();
+            if !stream.recv_buf.is_empty() || stream.remote_closed {
+                break;
+            }
+            state.pump()?;
+        }
+
+        let stream = state.streams.get_mut(&self.id).unwrap();
+        let n = usize::min(buf.len(), stream.recv_buf.len());
+        for (dst, src) in buf.iter_mut().zip(stream.recv_buf.drain(..n)) {
+            *dst = src;
+        }
+        stream.unacked += n as u32;
+
+        // Reopen the window once half of it has been consumed.
+        if stream.unacked >= INITIAL_WINDOW / 2 && !stream.remote_closed {
+            let increment = stream.unacked;
+            stream.unacked = 0;
+            let mut payload = Vec::new();
+            payload.write_u32::<NetworkEndian>(increment)?;
+            state.write_frame(self.id, FrameKind::WindowUpdate, &payload[..])?;
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Read + Write> Write for MuxStream<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.state.borrow_mut();
+        loop {
+            let stream = state.streams.get(&self.id).unwrap();
+            if stream.remote_closed {
+                return Err(Error::new(ErrorKind::BrokenPipe, "Stream closed by peer"));
+            }
+            if stream.send_window > 0 {
+                break;
+            }
+            state.pump()?;
+        }
+
+        let stream = state.streams.get_mut(&self.id).unwrap();
+        let n = *[buf.len() as u32, stream.send_window, MAX_FRAME_LEN]
+            .iter().min().unwrap();
+        stream.send_window -= n;
+        state.write_frame(self.id, FrameKind::Data, &buf[..(n as usize)])?;
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.state.borrow_mut().channel.flush()
+    }
+}
+
+impl<T: Read + Write> Drop for MuxStream<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.streams.remove(&self.id);
+        if !state.is_local(self.id) {
+            state.remote_streams -= 1;
+        }
+        let _r = state.write_frame(self.id, FrameKind::Close, &[]);
+    }
+}
+
+fn protocol_error(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One end of a channel whose input the test writes as it goes
+    struct Pipe {
+        input: Rc<RefCell<VecDeque<u8>>>,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.input.borrow_mut().read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.output.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(id: u32, kind: FrameKind, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.write_u32::<NetworkEndian>(id).unwrap();
+        frame.write_u8(kind as u8).unwrap();
+        frame.write_u32::<NetworkEndian>(payload.len() as u32).unwrap();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn refuses_streams_beyond_the_limit() {
+        let input = Rc::new(RefCell::new(VecDeque::new()));
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mux = Multiplexer::new(Pipe { input: input.clone(), output: output.clone() }, false);
+        mux.set_max_incoming_streams(2);
+        for id in &[1, 3, 5] {
+            input.borrow_mut().extend(frame(*id, FrameKind::Data, b"hello"));
+        }
+        let first = mux.accept_stream().unwrap();
+        let second = mux.accept_stream().unwrap();
+        assert_eq!((first.id(), second.id()), (1, 3));
+        // Stream 5 is closed, and then the input runs dry
+        assert!(mux.accept_stream().is_err());
+        assert_eq!(&output.borrow()[..], &frame(5, FrameKind::Close, &[])[..]);
+        assert_eq!(mux.state.borrow().streams.len(), 2);
+
+        // The refused stream stays closed, and a dropped one makes room
+        input.borrow_mut().extend(frame(5, FrameKind::Data, b"hello"));
+        input.borrow_mut().extend(frame(7, FrameKind::Data, b"hello"));
+        drop(first);
+        assert_eq!(mux.accept_stream().unwrap().id(), 7);
+    }
+}
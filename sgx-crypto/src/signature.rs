@@ -1,20 +1,23 @@
-// Adapted from Ring's documentation https://briansmith.org/rustdoc/ring/signature/index.html
+// RSA PKCS#1 v1.5 SHA-256 signatures with 2048- to 8192-bit keys, and ECDSA
+// P-256 signatures
 use std::path::Path;
 use std::io::Read;
 use std::fs::File;
-use ring::signature;
-use untrusted::Input;
+use ra_verify::asn1;
+use crate::backend::{Backend, CryptoBackend};
 use crate::random::RandomState;
-use crate::pem_parser::pem_to_der;
+use crate::pem_parser::{pem_to_der_with_label, PemError};
 
-static SIG_ALG: &signature::RsaParameters = &signature::RSA_PKCS1_2048_8192_SHA256;
-static PADDING_ALG: &dyn signature::RsaEncoding = &signature::RSA_PKCS1_SHA256;
+const PUBLIC_KEY_PEM_LABEL: &str = "RSA PUBLIC KEY";
+const PRIVATE_KEY_PEM_LABEL: &str = "RSA PRIVATE KEY";
+const PKCS8_PEM_LABEL: &str = "PRIVATE KEY";
 
 pub type Signature = Vec<u8>; // variable length, depending on RSA parameters
 
 #[derive(Debug)]
 pub enum SigError {
    IO(std::io::Error),
+   Pem(PemError),
    BadPrivateKey,
    BadPublicKey,
    BadSignature,
@@ -33,7 +36,8 @@ impl VerificationKey {
     }
 
     pub fn new_from_pem(public_key_pem: &str) -> Result<Self, SigError> {
-        let pem = pem_to_der(public_key_pem).map_err(|_| SigError::BadPublicKey)?;
+        let pem = pem_to_der_with_label(public_key_pem, PUBLIC_KEY_PEM_LABEL)
+            .map_err(|e| SigError::Pem(e))?;
         Self::new_from_der(&pem[..])
     }
 
@@ -47,10 +51,7 @@ impl VerificationKey {
     }
 
     pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), SigError> {
-        signature::verify(SIG_ALG, 
-                          Input::from(&self.key[..]), 
-                          Input::from(message), 
-                          Input::from(signature))
+        Backend::rsa_verify(&self.key[..], message, signature)
             .map_err(|_| SigError::BadSignature)
     }
 
@@ -60,37 +61,149 @@ impl VerificationKey {
 }
 
 pub struct SigningKey {
-    key_pair: signature::RsaKeyPair,
+    key_pair: <Backend as CryptoBackend>::RsaKeyPair,
+    public_key: Vec<u8>,
 }
 
 impl SigningKey {
-    pub fn new_from_der_file(private_key_der: &Path) ->  Result<Self, SigError> {
-        let private_key_der = read_file(&private_key_der)?;
-        let private_key_der = Input::from(&private_key_der[..]);
-        let key_pair = signature::RsaKeyPair::from_der(private_key_der)
+    pub fn new_from_der(private_key_der: &[u8]) -> Result<Self, SigError> {
+        let key_pair = Backend::rsa_key_pair(private_key_der)
             .map_err(|_| SigError::BadPrivateKey)?;
-        Ok( Self { key_pair } )
+        let public_key = rsa_public_key(private_key_der)
+            .ok_or(SigError::BadPrivateKey)?;
+        Ok( Self { key_pair, public_key } )
+    }
+
+    pub fn new_from_der_file(private_key_der: &Path) ->  Result<Self, SigError> {
+        Self::new_from_der(&read_file(&private_key_der)?[..])
+    }
+
+    pub fn new_from_pem(private_key_pem: &str) ->  Result<Self, SigError> {
+        let private_key_der = pem_to_der_with_label(private_key_pem, PRIVATE_KEY_PEM_LABEL)
+            .map_err(|e| SigError::Pem(e))?;
+        Self::new_from_der(&private_key_der[..])
     }
 
     pub fn new_from_pem_file(private_key_pem: &Path) ->  Result<Self, SigError> {
         let private_key_pem = read_file(&private_key_pem)?;
         let private_key_pem = String::from_utf8(private_key_pem)
             .map_err(|_| SigError::BadPrivateKey)?;
-        let private_key_der = pem_to_der(&private_key_pem)
-            .map_err(|_| SigError::BadPrivateKey)?;
-        let private_key_der = Input::from(&private_key_der[..]);
-        let key_pair = signature::RsaKeyPair::from_der(private_key_der)
-            .map_err(|_| SigError::BadPrivateKey)?;
-        Ok( Self { key_pair } )
+        Self::new_from_pem(&private_key_pem)
     }
 
     pub fn sign(&self, msg: &[u8], rng: &RandomState) 
         -> Result<Signature, SigError> {
-            let mut signature = vec![0; self.key_pair.public_modulus_len()];
-            self.key_pair.sign(PADDING_ALG, rng.inner(), msg, &mut signature)
-                .map_err(|_| SigError::OutOfMemory)?;
-            Ok(signature)
+            Backend::rsa_sign(&self.key_pair, rng.inner(), msg)
+                .map_err(|_| SigError::OutOfMemory)
+        }
+
+    /// The matching public key as a DER RSAPublicKey, as taken by
+    /// `VerificationKey::new_from_der`.
+    pub fn public_key_der(&self) -> &[u8] {
+        &self.public_key[..]
+    }
+
+    /// The matching public key in PEM, as taken by
+    /// `VerificationKey::new_from_pem`, e.g. to pin it in an enclave.
+    pub fn public_key_pem(&self) -> String {
+        let b64 = base64::encode(&self.public_key[..]);
+        let mut pem = format!("-----BEGIN {}-----\n", PUBLIC_KEY_PEM_LABEL);
+        for line in b64.as_bytes().chunks(64) {
+            pem.push_str(std::str::from_utf8(line).unwrap());
+            pem.push('\n');
         }
+        pem.push_str(&format!("-----END {}-----\n", PUBLIC_KEY_PEM_LABEL));
+        pem
+    }
+}
+
+// RSAPublicKey ::= SEQUENCE { modulus, publicExponent }, taken from the second
+// and third fields of RSAPrivateKey ::= SEQUENCE { version, modulus,
+// publicExponent, ... }
+fn rsa_public_key(private_key_der: &[u8]) -> Option<Vec<u8>> {
+    let mut private_key_der = private_key_der;
+    let mut content = asn1::expect(&mut private_key_der, asn1::TAG_SEQUENCE)?;
+    let mut fields = Vec::new();
+    for _ in 0..3 {
+        match asn1::next(&mut content)? {
+            (asn1::TAG_INTEGER, _, field) => fields.push(field),
+            _ => return None,
+        }
+    }
+    Some(asn1::der(asn1::TAG_SEQUENCE, &[fields[1], fields[2]].concat()))
+}
+
+/// ECDSA P-256 key pair, e.g. for a TLS certificate generated in an enclave.
+pub struct EcdsaSigningKey {
+    key_pair: <Backend as CryptoBackend>::EcdsaKeyPair,
+    public_key: Vec<u8>,
+    pkcs8: Vec<u8>,
+}
+
+impl EcdsaSigningKey {
+    pub fn generate(rng: &RandomState) -> Result<Self, SigError> {
+        let (key_pair, pkcs8) = Backend::ecdsa_generate(rng.inner())
+            .map_err(|_| SigError::BadPrivateKey)?;
+        let public_key = Backend::ecdsa_public_key(&key_pair);
+        Ok(Self { key_pair, public_key, pkcs8 })
+    }
+
+    /// `pkcs8` is the PKCS#8 document of a P-256 key, e.g. as written by
+    /// `openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256`.
+    pub fn new_from_pkcs8(pkcs8: &[u8]) -> Result<Self, SigError> {
+        let key_pair = Backend::ecdsa_key_pair(pkcs8)
+            .map_err(|_| SigError::BadPrivateKey)?;
+        let public_key = Backend::ecdsa_public_key(&key_pair);
+        Ok(Self { key_pair, public_key, pkcs8: pkcs8.to_owned() })
+    }
+
+    pub fn new_from_pem(pkcs8_pem: &str) -> Result<Self, SigError> {
+        let pkcs8 = pem_to_der_with_label(pkcs8_pem, PKCS8_PEM_LABEL)
+            .map_err(|e| SigError::Pem(e))?;
+        Self::new_from_pkcs8(&pkcs8[..])
+    }
+
+    pub fn new_from_pem_file(pkcs8_pem: &Path) -> Result<Self, SigError> {
+        let pem = read_file(pkcs8_pem)?;
+        Self::new_from_pem(&String::from_utf8(pem).map_err(|_| SigError::BadPrivateKey)?)
+    }
+
+    /// Uncompressed SEC1 point.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key[..]
+    }
+
+    /// The private key as a PKCS#8 document, e.g. to hand to a TLS library.
+    pub fn pkcs8_der(&self) -> &[u8] {
+        &self.pkcs8[..]
+    }
+
+    /// ASN.1 DER encoded signature.
+    pub fn sign(&self, msg: &[u8], rng: &RandomState) -> Result<Signature, SigError> {
+        Backend::ecdsa_sign(&self.key_pair, rng.inner(), msg)
+            .map_err(|_| SigError::OutOfMemory)
+    }
+}
+
+/// ECDSA P-256 public key, as an uncompressed SEC1 point.
+pub struct EcdsaVerificationKey {
+    key: Vec<u8>,
+}
+
+impl EcdsaVerificationKey {
+    pub fn new_from_sec1(public_key: &[u8]) -> Self {
+        Self { key: public_key.to_owned() }
+    }
+
+    /// `signature` is ASN.1 DER encoded.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), SigError> {
+        Backend::ecdsa_verify(&self.key[..], message, signature)
+            .map_err(|_| SigError::BadSignature)
+    }
+
+    pub fn as_ref(&self) -> &[u8] {
+        &self.key[..]
+    }
 }
 
 fn read_file(path: &Path) -> Result<Vec<u8>, SigError> {